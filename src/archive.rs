@@ -0,0 +1,413 @@
+//! Модуль для сравнения содержимого архивов.
+//!
+//! Архив описывается индексом — списком записей с размерами, правами доступа, временем
+//! изменения и контрольными суммами без самих данных. Два индекса можно сравнить функцией `diff`, которая сообщает
+//! о добавленных, удаленных и измененных записях с указанием причины изменения.
+//!
+//! Индекс можно построить прямо по дереву файлов и сохранить как архив без данных
+//! (`--index-only`), чтобы позже сверять с ним новые архивы.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use crate::checksum::{self, Crc32};
+use crate::io::{file_mtime, special_data, WalkOptions, DUPLICATE_FLAG};
+use crate::timestamp::TimeStyle;
+use crate::ArchiveData;
+
+/// Сигнатура, с которой начинается сериализованный индекс.
+pub const INDEX_MAGIC: &[u8; 4] = b"ARIX";
+
+/// Версия формата сериализованного индекса: со второй версии каждая запись хранит время изменения.
+const INDEX_VERSION: u8 = 2;
+
+/// Сведения об одной записи архива без ее содержимого.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    /// Относительный путь к файлу
+    pub path: String,
+    /// Размер содержимого в байтах
+    pub size: u64,
+    /// Права доступа к файлу
+    pub permissions: u32,
+    /// Контрольная сумма CRC32 содержимого
+    pub crc32: u32,
+    /// Время изменения в секундах от начала эпохи Unix (`None`, если оно не сохранено)
+    pub mtime: Option<u64>,
+}
+
+/// Индекс архива: записи, упорядоченные по пути.
+#[derive(Debug, Default, PartialEq)]
+pub struct ArchiveIndex {
+    /// Записи индекса
+    pub entries: Vec<IndexEntry>,
+}
+
+impl ArchiveIndex {
+    /// Строит индекс по распакованному архиву.
+    ///
+    /// Если путь встречается несколько раз (например, в склеенных архивах),
+    /// в индекс попадает последняя запись.
+    /// Жесткая ссылка или дубликат описываются размером и контрольной суммой файла, на который они указывают.
+    ///
+    /// # Аргументы
+    ///
+    /// * `archive` - Распакованные данные архива.
+    ///
+    /// # Возвращает
+    ///
+    /// Индекс архива.
+    pub fn from_archive(archive: &ArchiveData) -> Self {
+        let mut by_path = BTreeMap::new();
+        for e in &archive.entries {
+            let linked = e.hard_link_target().and_then(|target| by_path.get(target)).cloned();
+            let duplicated = e.duplicate_of().and_then(|source| by_path.get(source)).cloned();
+            let entry = match (linked, duplicated) {
+                (Some(target), _) => IndexEntry { path: e.path.clone(), ..target },
+                // Дубликат совпадает с исходной записью содержимым, но права и время изменения у него свои
+                (None, Some(source)) => IndexEntry { path: e.path.clone(), permissions: e.permissions & !DUPLICATE_FLAG, mtime: e.mtime, ..source },
+                (None, None) => IndexEntry {
+                    path: e.path.clone(),
+                    size: e.data.len() as u64,
+                    permissions: e.permissions,
+                    crc32: checksum::crc32(&e.data),
+                    mtime: e.mtime,
+                },
+            };
+            by_path.insert(e.path.clone(), entry);
+        }
+        ArchiveIndex { entries: by_path.into_values().collect() }
+    }
+
+    /// Строит индекс, обходя дерево файлов без загрузки их содержимого в память.
+    ///
+    /// # Аргументы
+    ///
+    /// * `root` - Файл или директория, для которой строится индекс.
+    /// * `options` - Параметры обхода.
+    ///
+    /// # Возвращает
+    ///
+    /// Индекс с путями относительно `root` или ошибку ввода/вывода.
+    pub fn from_dir(root: &Path, options: &WalkOptions) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        index_dir_recursive(root, root, options, options.root_device(root)?, &mut entries)?;
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(ArchiveIndex { entries })
+    }
+
+    /// Преобразует индекс в байты.
+    ///
+    /// # Возвращает
+    ///
+    /// Вектор байтов, начинающийся с `INDEX_MAGIC`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(INDEX_MAGIC);
+        buffer.push(INDEX_VERSION);
+        buffer.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for e in &self.entries {
+            buffer.extend_from_slice(&(e.path.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(e.path.as_bytes());
+            buffer.extend_from_slice(&e.size.to_le_bytes());
+            buffer.extend_from_slice(&e.permissions.to_le_bytes());
+            buffer.extend_from_slice(&e.crc32.to_le_bytes());
+            buffer.push(u8::from(e.mtime.is_some()));
+            buffer.extend_from_slice(&e.mtime.unwrap_or(0).to_le_bytes());
+        }
+        buffer
+    }
+
+    /// Преобразует байты, записанные `to_bytes`, в индекс.
+    ///
+    /// Индексы первой версии читаются без времени изменения.
+    ///
+    /// # Аргументы
+    ///
+    /// * `data` - Срез байтов сериализованного индекса.
+    ///
+    /// # Возвращает
+    ///
+    /// Индекс или ошибку при неверном формате.
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+        if !is_index(data) {
+            return Err(invalid("Данные не являются индексом архива"));
+        }
+        let version = data[4];
+        if version == 0 || version > INDEX_VERSION {
+            return Err(invalid("Неподдерживаемая версия индекса"));
+        }
+        let mut offset = 5;
+        let mut take = |len: usize| -> io::Result<&[u8]> {
+            let slice = data.get(offset..offset + len).ok_or_else(|| invalid("Индекс поврежден"))?;
+            offset += len;
+            Ok(slice)
+        };
+        let count = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let path_len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+            let path = String::from_utf8(take(path_len)?.to_vec()).map_err(|_| invalid("Неверный путь в индексе"))?;
+            let size = u64::from_le_bytes(take(8)?.try_into().unwrap());
+            let permissions = u32::from_le_bytes(take(4)?.try_into().unwrap());
+            let crc32 = u32::from_le_bytes(take(4)?.try_into().unwrap());
+            let mtime = if version >= 2 {
+                let present = take(1)?[0];
+                let mtime = u64::from_le_bytes(take(8)?.try_into().unwrap());
+                match present {
+                    0 => None,
+                    1 => Some(mtime),
+                    _ => return Err(invalid("Индекс поврежден")),
+                }
+            } else {
+                None
+            };
+            entries.push(IndexEntry { path, size, permissions, crc32, mtime });
+        }
+        Ok(ArchiveIndex { entries })
+    }
+
+    /// Ищет запись по пути.
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.entries.binary_search_by(|e| e.path.as_str().cmp(path)).ok().map(|i| &self.entries[i])
+    }
+}
+
+/// Проверяет, являются ли распакованные данные индексом (архивом без данных).
+pub fn is_index(data: &[u8]) -> bool {
+    data.len() >= 5 && &data[..4] == INDEX_MAGIC
+}
+
+/// Рекурсивно обходит дерево и добавляет в индекс сведения о каждом файле.
+///
+/// Содержимое файлов читается блоками только для вычисления контрольной суммы.
+fn index_dir_recursive(current_path: &Path, root_path: &Path, options: &WalkOptions, root_device: Option<u64>,
+                       entries: &mut Vec<IndexEntry>) -> io::Result<()> {
+    let rel_path = current_path.strip_prefix(root_path)
+        .unwrap_or(current_path)
+        .to_str().unwrap()
+        .to_owned();
+    if options.skips(current_path, &rel_path, root_device)? {
+        return Ok(());
+    }
+    if current_path.is_file() {
+        let metadata = fs::metadata(current_path)?;
+        let mut file = File::open(current_path)?;
+        let mut crc = Crc32::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            crc.update(&buffer[..n]);
+        }
+        entries.push(IndexEntry {
+            path: rel_path,
+            size: metadata.len(),
+            permissions: metadata.permissions().mode(),
+            crc32: crc.finish(),
+            mtime: Some(file_mtime(&metadata)),
+        });
+    } else if current_path.is_dir() {
+        for entry in fs::read_dir(current_path)? {
+            index_dir_recursive(&entry?.path(), root_path, options, root_device, entries)?;
+        }
+    } else if let Ok(metadata) = fs::metadata(current_path) {
+        // Каналы и устройства описываются так же, как их записи в архиве, без чтения
+        if let Some(data) = special_data(&metadata).filter(|_| !options.skip_special) {
+            entries.push(IndexEntry {
+                path: rel_path,
+                size: data.len() as u64,
+                permissions: metadata.permissions().mode(),
+                crc32: checksum::crc32(&data),
+                mtime: Some(file_mtime(&metadata)),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Причина, по которой запись считается измененной.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeReason {
+    /// Изменился размер содержимого
+    Size { old: u64, new: u64 },
+    /// Размер тот же, но содержимое отличается (по контрольной сумме)
+    Content,
+    /// Изменились права доступа
+    Permissions { old: u32, new: u32 },
+    /// Изменилось время изменения (сравнивается, только если оно известно в обоих индексах)
+    Mtime { old: u64, new: u64 },
+}
+
+impl ChangeReason {
+    /// Возвращает неизменное имя причины для вывода `--porcelain`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChangeReason::Size { .. } => "size",
+            ChangeReason::Content => "content",
+            ChangeReason::Permissions { .. } => "permissions",
+            ChangeReason::Mtime { .. } => "mtime",
+        }
+    }
+}
+
+impl fmt::Display for ChangeReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeReason::Size { old, new } => write!(f, "size {} -> {}", old, new),
+            ChangeReason::Content => write!(f, "content"),
+            ChangeReason::Permissions { old, new } => write!(f, "permissions {:o} -> {:o}", old & 0o7777, new & 0o7777),
+            ChangeReason::Mtime { old, new } => write!(f, "mtime {} -> {}", TimeStyle::Iso.format(*old, 0), TimeStyle::Iso.format(*new, 0)),
+        }
+    }
+}
+
+/// Измененная запись и причины изменения.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModifiedEntry {
+    /// Путь к записи
+    pub path: String,
+    /// Причины изменения
+    pub reasons: Vec<ChangeReason>,
+}
+
+/// Результат сравнения двух архивов.
+#[derive(Debug, Default, PartialEq)]
+pub struct DiffReport {
+    /// Пути, которые есть только во втором архиве
+    pub added: Vec<String>,
+    /// Пути, которые есть только в первом архиве
+    pub removed: Vec<String>,
+    /// Записи, которые есть в обоих архивах, но отличаются
+    pub modified: Vec<ModifiedEntry>,
+}
+
+impl DiffReport {
+    /// Возвращает `true`, если архивы не отличаются.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Сравнивает два индекса архивов.
+///
+/// # Аргументы
+///
+/// * `a` - Индекс исходного (старого) архива.
+/// * `b` - Индекс нового архива.
+///
+/// # Возвращает
+///
+/// Отчет с добавленными, удаленными и измененными записями, упорядоченными по пути.
+pub fn diff(a: &ArchiveIndex, b: &ArchiveIndex) -> DiffReport {
+    let mut report = DiffReport::default();
+    for old in &a.entries {
+        match b.get(&old.path) {
+            None => report.removed.push(old.path.clone()),
+            Some(new) => {
+                let mut reasons = Vec::new();
+                if old.size != new.size {
+                    reasons.push(ChangeReason::Size { old: old.size, new: new.size });
+                } else if old.crc32 != new.crc32 {
+                    reasons.push(ChangeReason::Content);
+                }
+                if old.permissions != new.permissions {
+                    reasons.push(ChangeReason::Permissions { old: old.permissions, new: new.permissions });
+                }
+                if let (Some(old_mtime), Some(new_mtime)) = (old.mtime, new.mtime) {
+                    if old_mtime != new_mtime {
+                        reasons.push(ChangeReason::Mtime { old: old_mtime, new: new_mtime });
+                    }
+                }
+                if !reasons.is_empty() {
+                    report.modified.push(ModifiedEntry { path: old.path.clone(), reasons });
+                }
+            }
+        }
+    }
+    for new in &b.entries {
+        if a.get(&new.path).is_none() {
+            report.added.push(new.path.clone());
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::io::DirEntry;
+
+    fn index(entries: &[(&str, &[u8], u32)]) -> ArchiveIndex {
+        ArchiveIndex::from_archive(&ArchiveData {
+            entries: entries.iter().map(|(p, d, m)| DirEntry {
+                path: p.to_string(),
+                data: d.to_vec(),
+                permissions: *m,
+                crc32: None,
+                owner: None,
+                metadata: BTreeMap::new(),
+                mtime: None,
+            }).collect(),
+        })
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = index(&[("same", b"x", 0o644), ("gone", b"x", 0o644), ("size", b"x", 0o644),
+            ("content", b"x", 0o644), ("mode", b"x", 0o644)]);
+        let b = index(&[("same", b"x", 0o644), ("new", b"x", 0o644), ("size", b"xy", 0o644),
+            ("content", b"y", 0o644), ("mode", b"x", 0o755)]);
+        let report = diff(&a, &b);
+        assert_eq!(report.added, vec!["new"]);
+        assert_eq!(report.removed, vec!["gone"]);
+        assert_eq!(report.modified, vec![
+            ModifiedEntry { path: "content".into(), reasons: vec![ChangeReason::Content] },
+            ModifiedEntry { path: "mode".into(), reasons: vec![ChangeReason::Permissions { old: 0o644, new: 0o755 }] },
+            ModifiedEntry { path: "size".into(), reasons: vec![ChangeReason::Size { old: 1, new: 2 }] },
+        ]);
+        let names: Vec<_> = report.modified.iter().flat_map(|m| &m.reasons).map(ChangeReason::name).collect();
+        assert_eq!(names, ["content", "permissions", "size"]);
+        assert!(diff(&a, &a).is_empty());
+
+        // Время изменения сравнивается, только если оно известно в обоих индексах
+        let mut old = index(&[("same", b"x", 0o644)]);
+        let mut new = index(&[("same", b"x", 0o644)]);
+        new.entries[0].mtime = Some(1_700_000_000);
+        assert!(diff(&old, &new).is_empty());
+        old.entries[0].mtime = Some(1_600_000_000);
+        let report = diff(&old, &new);
+        assert_eq!(report.modified[0].reasons, [ChangeReason::Mtime { old: 1_600_000_000, new: 1_700_000_000 }]);
+        assert_eq!(report.modified[0].reasons[0].name(), "mtime");
+    }
+
+    #[test]
+    fn test_index_round_trip() {
+        let mut a = index(&[("a/b.txt", b"hello", 0o100644), ("c", b"", 0o100755)]);
+        a.entries[0].mtime = Some(1_700_000_000);
+        let bytes = a.to_bytes();
+        assert!(is_index(&bytes));
+        assert_eq!(ArchiveIndex::from_bytes(&bytes).unwrap(), a);
+
+        // Индекс первой версии читается без времени изменения
+        let mut v1 = b"ARIX\x01".to_vec();
+        v1.extend_from_slice(&1u32.to_le_bytes());
+        v1.extend_from_slice(&1u32.to_le_bytes());
+        v1.push(b'c');
+        v1.extend_from_slice(&0u64.to_le_bytes());
+        v1.extend_from_slice(&0o100755u32.to_le_bytes());
+        v1.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(ArchiveIndex::from_bytes(&v1).unwrap().entries, a.entries[1..]);
+        let mut corrupt = bytes.clone();
+        corrupt[4] = 3;
+        assert!(ArchiveIndex::from_bytes(&corrupt).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! Модуль для сравнения алгоритмов на пользовательском наборе файлов.
+//!
+//! Каждый файл корпуса сжимается и распаковывается всеми алгоритмами, а результаты
+//! (степень сжатия и скорость) записываются в CSV с типом файла, чтобы можно было
+//! подбирать алгоритм по умолчанию на основе реальных данных.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+use crate::io::{read_dir_recursive, WalkOptions};
+use crate::processing::{self, Algorithm};
+
+/// Результат одного прогона алгоритма на одном файле.
+pub struct BenchResult {
+    /// Относительный путь к файлу корпуса
+    pub path: String,
+    /// Тип файла (расширение в нижнем регистре или `-`, если его нет)
+    pub file_type: String,
+    /// Имя алгоритма
+    pub algorithm: &'static str,
+    /// Исходный размер в байтах
+    pub original_size: usize,
+    /// Размер после сжатия в байтах
+    pub compressed_size: usize,
+    /// Скорость сжатия, МБ/с
+    pub compress_speed: f64,
+    /// Скорость распаковки, МБ/с
+    pub decompress_speed: f64,
+    /// Совпали ли распакованные данные с исходными
+    pub round_trip_ok: bool,
+}
+
+impl BenchResult {
+    /// Отношение сжатого размера к исходному.
+    pub fn ratio(&self) -> f64 {
+        if self.original_size == 0 {
+            1.0
+        } else {
+            self.compressed_size as f64 / self.original_size as f64
+        }
+    }
+}
+
+/// Вычисляет скорость обработки в МБ/с.
+fn speed(bytes: usize, seconds: f64) -> f64 {
+    if seconds > 0.0 {
+        bytes as f64 / (1024.0 * 1024.0) / seconds
+    } else {
+        0.0
+    }
+}
+
+/// Определяет тип файла по расширению.
+fn file_type(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+/// Прогоняет все алгоритмы по всем файлам корпуса.
+///
+/// # Аргументы
+///
+/// * `corpus` - Директория с представительными файлами.
+///
+/// # Возвращает
+///
+/// Результаты для каждой пары (файл, алгоритм) или ошибку ввода/вывода.
+pub fn run(corpus: &Path) -> io::Result<Vec<BenchResult>> {
+    let entries = read_dir_recursive(corpus, corpus, &WalkOptions::default())?;
+    let mut results = Vec::new();
+    for entry in &entries {
+        for algorithm in Algorithm::ALL.iter() {
+            let start = Instant::now();
+            let compressed = processing::compress(&entry.data, algorithm.clone(), false, false);
+            let compress_time = start.elapsed().as_secs_f64();
+
+            let start = Instant::now();
+            let restored = processing::decompress(&compressed, None, false, false, false);
+            let decompress_time = start.elapsed().as_secs_f64();
+
+            results.push(BenchResult {
+                path: entry.path.clone(),
+                file_type: file_type(&entry.path),
+                algorithm: algorithm.name(),
+                original_size: entry.data.len(),
+                compressed_size: compressed.len(),
+                compress_speed: speed(entry.data.len(), compress_time),
+                decompress_speed: speed(entry.data.len(), decompress_time),
+                round_trip_ok: restored.map(|r| r == entry.data).unwrap_or(false),
+            });
+        }
+    }
+    Ok(results)
+}
+
+/// Экранирует поле CSV, если оно содержит разделители или кавычки.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Формирует CSV с результатами прогона.
+///
+/// # Аргументы
+///
+/// * `results` - Результаты, полученные из `run`.
+///
+/// # Возвращает
+///
+/// Текст CSV с заголовком.
+pub fn to_csv(results: &[BenchResult]) -> String {
+    let mut csv = String::from("file,type,algorithm,original_size,compressed_size,ratio,compress_mb_s,decompress_mb_s,round_trip\n");
+    for r in results {
+        csv.push_str(&format!("{},{},{},{},{},{:.4},{:.2},{:.2},{}\n",
+            csv_field(&r.path), csv_field(&r.file_type), r.algorithm, r.original_size, r.compressed_size,
+            r.ratio(), r.compress_speed, r.decompress_speed, if r.round_trip_ok { "ok" } else { "FAILED" }));
+    }
+    csv
+}
+
+/// Формирует сводку по типам файлов: суммарная степень сжатия каждого алгоритма.
+///
+/// # Аргументы
+///
+/// * `results` - Результаты, полученные из `run`.
+///
+/// # Возвращает
+///
+/// Строки сводки для вывода пользователю.
+pub fn summary(results: &[BenchResult]) -> Vec<String> {
+    let mut totals: BTreeMap<(&str, &str), (usize, usize)> = BTreeMap::new();
+    for r in results {
+        let total = totals.entry((r.file_type.as_str(), r.algorithm)).or_insert((0, 0));
+        total.0 += r.original_size;
+        total.1 += r.compressed_size;
+    }
+    totals.iter().map(|((file_type, algorithm), (original, compressed))| {
+        let ratio = if *original == 0 { 1.0 } else { *compressed as f64 / *original as f64 };
+        format!("{:<8} {:<5} {:>12} -> {:>12} ({:.3})", file_type, algorithm, original, compressed, ratio)
+    }).collect()
+}
@@ -0,0 +1,116 @@
+//! Модуль для выбора границ блоков блочного режима (`--block-size`) по энтропии содержимого.
+//!
+//! Размер блока из командной строки — базовый. Перед каждым блоком оценивается энтропия
+//! Шеннона следующих байтов: почти случайные данные (уже сжатые файлы, шифротекст) алгоритм
+//! все равно не сожмет, поэтому там блок уменьшается в `SCALE` раз, и неудачная попытка сжатия
+//! и чтение диапазона затрагивают меньше байтов. В повторяющихся данных блок растет, пока
+//! следующие окна тоже повторяются, до `SCALE` базовых размеров: в длинном блоке алгоритм
+//! находит больше совпадений. Фактические длины блоков записываются в таблицу блоков
+//! центрального индекса (см. `entry_index`), поэтому при чтении их не нужно вычислять.
+
+/// Во сколько раз блок может уменьшиться или вырасти относительно базового размера.
+pub const SCALE: usize = 4;
+
+/// Энтропия (бит на байт), начиная с которой данные считаются несжимаемыми.
+pub const HIGH_ENTROPY: f64 = 7.5;
+
+/// Энтропия (бит на байт), до которой данные считаются повторяющимися.
+pub const LOW_ENTROPY: f64 = 4.0;
+
+/// Наименьший базовый размер, с которого блоки подстраиваются: по меньшему окну энтропия
+/// оценивается ненадежно, и блоки остаются фиксированными.
+pub const MIN_ADAPTIVE_BLOCK: usize = 4096;
+
+/// Наибольшее окно, по которому оценивается энтропия.
+const SAMPLE: usize = 64 * 1024;
+
+/// Вычисляет энтропию Шеннона данных в битах на байт (от 0 до 8).
+pub fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts.iter().filter(|&&count| count > 0).map(|&count| {
+        let p = count as f64 / len;
+        -p * p.log2()
+    }).sum()
+}
+
+/// Делит содержимое файла на блоки с учетом энтропии.
+///
+/// # Аргументы
+///
+/// * `data` - Содержимое файла.
+/// * `block_size` - Базовый размер блока (`--block-size`).
+///
+/// # Возвращает
+///
+/// Длины блоков по порядку; их сумма равна длине `data`. Для базового размера меньше
+/// `MIN_ADAPTIVE_BLOCK` все блоки, кроме последнего, имеют размер `block_size`.
+pub fn block_lengths(data: &[u8], block_size: usize) -> Vec<usize> {
+    let block_size = block_size.max(1);
+    if block_size < MIN_ADAPTIVE_BLOCK {
+        return data.chunks(block_size).map(<[u8]>::len).collect();
+    }
+    let window = |pos: usize| &data[pos..data.len().min(pos + block_size.min(SAMPLE))];
+    let mut lengths = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let rest = data.len() - pos;
+        let level = entropy(window(pos));
+        let len = if level >= HIGH_ENTROPY {
+            block_size / SCALE
+        } else if level <= LOW_ENTROPY {
+            // Блок растет на базовый размер, пока следующее окно тоже повторяется
+            let mut len = block_size;
+            while len < block_size * SCALE && len < rest && entropy(window(pos + len)) <= LOW_ENTROPY {
+                len += block_size;
+            }
+            len
+        } else {
+            block_size
+        };
+        let len = len.min(rest);
+        lengths.push(len);
+        pos += len;
+    }
+    lengths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_lengths() {
+        assert_eq!(entropy(b""), 0.0);
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        assert!((entropy(&(0..=255).collect::<Vec<u8>>()) - 8.0).abs() < 1e-9);
+
+        // Повторяющиеся данные собираются в крупные блоки
+        let zeros = vec![0u8; 10 * 4096];
+        assert_eq!(block_lengths(&zeros, 4096), [16384, 16384, 8192]);
+        // Псевдослучайные данные делятся на мелкие блоки
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..8192).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        assert_eq!(block_lengths(&noise, 4096), [1024; 8]);
+        // Данные средней энтропии (64 равновероятных значения, 6 бит) делятся на базовые блоки
+        let text: Vec<u8> = (0..10_000).map(|i| (i * 7 % 64) as u8).collect();
+        assert_eq!(block_lengths(&text, 4096), [4096, 4096, 1808]);
+        // Сжимаемый хвост после случайного начала
+        let mixed: Vec<u8> = noise.iter().copied().chain(zeros.iter().copied()).collect();
+        let lengths = block_lengths(&mixed, 4096);
+        assert_eq!(lengths.iter().sum::<usize>(), mixed.len());
+        assert_eq!((lengths[0], *lengths.iter().max().unwrap()), (1024, 16384));
+        // Мелкие блоки не подстраиваются
+        assert_eq!(block_lengths(&zeros[..40], 16), [16, 16, 8]);
+        assert!(block_lengths(b"", 4096).is_empty());
+    }
+}
@@ -0,0 +1,222 @@
+//! Модуль для кеша списков записей архивов (`~/.cache/archiver/`).
+//!
+//! Чтобы перечислить записи сплошного архива, нужно распаковать весь сжатый поток, а в архиве
+//! с центральным индексом — разобрать индекс и распаковать записи ссылок и дубликатов. Повторные
+//! `--list` и `--tree` того же архива берут разобранный список из кеша. Ключ записи кеша —
+//! SHA-256 содержимого архива, поэтому измененный архив получает новый ключ, а прежняя запись
+//! просто перестает использоваться. `--cat` и `--grep` распаковывают сами записи, и разбор
+//! индекса для них несуществен, поэтому кеш ими не используется.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::crypto;
+use crate::io::{read_varint, write_varint};
+use crate::processing::{self, ListedEntry};
+
+/// Сигнатура файла кеша; меняется вместе с форматом, и старые файлы не читаются.
+const CACHE_MAGIC: &[u8; 4] = b"RLC2";
+
+/// Запись списка: сведения о записи архива и ее сжатый размер, если он известен.
+pub type CachedEntry = (ListedEntry, Option<u64>);
+
+/// Возвращает директорию кеша: `$XDG_CACHE_HOME/archiver` или `~/.cache/archiver`.
+///
+/// # Возвращает
+///
+/// Путь к директории или `None`, если не задана ни одна из переменных окружения.
+pub fn default_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").filter(|dir| !dir.is_empty()).map(|home| Path::new(&home).join(".cache")))?;
+    Some(base.join("archiver"))
+}
+
+/// Вычисляет ключ записи кеша: SHA-256 архива в шестнадцатеричном виде.
+pub fn key(archive: &[u8]) -> String {
+    crypto::digest(archive).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Перечисляет записи архива как `processing::list_sizes`, используя кеш.
+///
+/// Ошибки чтения и записи кеша не мешают перечислению: испорченный файл кеша заменяется
+/// новым, а если записать его не удалось, список просто не сохраняется.
+///
+/// # Аргументы
+///
+/// * `dir` - Директория кеша (см. `default_dir`).
+/// * `input` - Срез байтов архива.
+/// * `strict` - Строгий режим разбора; при нем кеш не используется, чтобы архив проверялся целиком.
+///
+/// # Возвращает
+///
+/// Записи архива со сжатыми размерами или ошибку, если архив поврежден.
+pub fn list_sizes(dir: &Path, input: &[u8], strict: bool) -> io::Result<Vec<CachedEntry>> {
+    if strict {
+        return processing::list_sizes(input, strict);
+    }
+    let path = dir.join(format!("{}.list", key(input)));
+    if let Some(entries) = fs::read(&path).ok().and_then(|data| from_bytes(&data).ok()) {
+        log::debug!("listing of the archive read from {}", path.display());
+        return Ok(entries);
+    }
+    let entries = processing::list_sizes(input, strict)?;
+    if let Err(e) = store(&path, &entries) {
+        log::debug!("cannot write {}: {}", path.display(), e);
+    }
+    Ok(entries)
+}
+
+/// Записывает список в файл кеша через временный файл, чтобы параллельный запуск
+/// не прочитал его наполовину записанным.
+fn store(path: &Path, entries: &[CachedEntry]) -> io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+    let temp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&temp, to_bytes(entries))?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+/// Записывает строку с длиной в формате varint.
+fn push_str(buffer: &mut Vec<u8>, s: &str) {
+    write_varint(buffer, s.len() as u64);
+    buffer.extend_from_slice(s.as_bytes());
+}
+
+/// Преобразует список записей в байты файла кеша.
+fn to_bytes(entries: &[CachedEntry]) -> Vec<u8> {
+    let mut buffer = CACHE_MAGIC.to_vec();
+    write_varint(&mut buffer, entries.len() as u64);
+    for (entry, compressed) in entries {
+        push_str(&mut buffer, &entry.path);
+        write_varint(&mut buffer, entry.permissions as u64);
+        write_varint(&mut buffer, entry.size);
+        // Необязательные поля отмечаются байтом наличия
+        match &entry.link {
+            Some(link) => {
+                buffer.push(1);
+                push_str(&mut buffer, link);
+            }
+            None => buffer.push(0),
+        }
+        match entry.crc32 {
+            Some(crc) => {
+                buffer.push(1);
+                buffer.extend_from_slice(&crc.to_le_bytes());
+            }
+            None => buffer.push(0),
+        }
+        match compressed {
+            Some(size) => {
+                buffer.push(1);
+                write_varint(&mut buffer, *size);
+            }
+            None => buffer.push(0),
+        }
+        match entry.mtime {
+            Some(mtime) => {
+                buffer.push(1);
+                write_varint(&mut buffer, mtime);
+            }
+            None => buffer.push(0),
+        }
+    }
+    buffer
+}
+
+/// Разбирает файл кеша.
+///
+/// # Возвращает
+///
+/// Список записей или ошибку, если файл обрезан, поврежден или записан в другом формате.
+fn from_bytes(data: &[u8]) -> io::Result<Vec<CachedEntry>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid cache file");
+    if !data.starts_with(CACHE_MAGIC) {
+        return Err(invalid());
+    }
+    let mut offset = CACHE_MAGIC.len();
+    let take = |offset: &mut usize, len: usize| -> io::Result<&[u8]> {
+        let end = offset.checked_add(len).filter(|&end| end <= data.len()).ok_or_else(invalid)?;
+        let bytes = &data[*offset..end];
+        *offset = end;
+        Ok(bytes)
+    };
+    let read_str = |offset: &mut usize| -> io::Result<String> {
+        let len = usize::try_from(read_varint(data, offset)?).map_err(|_| invalid())?;
+        String::from_utf8(take(offset, len)?.to_vec()).map_err(|_| invalid())
+    };
+    let present = |offset: &mut usize| -> io::Result<bool> {
+        match take(offset, 1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid()),
+        }
+    };
+    let count = read_varint(data, &mut offset)?;
+    // Каждая запись занимает не меньше семи байтов, поэтому число записей ограничено размером файла
+    if count > (data.len() / 7) as u64 {
+        return Err(invalid());
+    }
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let path = read_str(&mut offset)?;
+        let permissions = u32::try_from(read_varint(data, &mut offset)?).map_err(|_| invalid())?;
+        let size = read_varint(data, &mut offset)?;
+        let link = if present(&mut offset)? { Some(read_str(&mut offset)?) } else { None };
+        let crc32 = if present(&mut offset)? {
+            Some(u32::from_le_bytes(take(&mut offset, 4)?.try_into().unwrap()))
+        } else {
+            None
+        };
+        let compressed = if present(&mut offset)? { Some(read_varint(data, &mut offset)?) } else { None };
+        let mtime = if present(&mut offset)? { Some(read_varint(data, &mut offset)?) } else { None };
+        entries.push((ListedEntry { path, permissions, size, link, crc32, mtime }, compressed));
+    }
+    if offset != data.len() {
+        return Err(invalid());
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::io::{DirEntry, HARD_LINK_FLAG};
+    use crate::processing::{compress_archive, Algorithm, CompressionOptions};
+    use crate::ArchiveData;
+
+    #[test]
+    fn test_list_cache() {
+        let dir = std::env::temp_dir().join(format!("list_cache_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let entry = |path: &str, permissions, data: &[u8]| DirEntry { path: path.into(), data: data.to_vec(), permissions, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None };
+        let archive = ArchiveData { entries: vec![
+            entry("docs/a.txt", 0o100644, &b"cached listing ".repeat(100)),
+            entry("docs/b.txt", 0o100644 | HARD_LINK_FLAG, b"docs/a.txt"),
+            entry("tool", 0o104755, b"#!/bin/sh\n"),
+        ] };
+        for options in [CompressionOptions::new(Algorithm::Lz4).block_size(Some(512)), CompressionOptions::new(Algorithm::Lz4).solid(true)] {
+            let packed = compress_archive(archive.clone(), &options);
+            let expected = processing::list_sizes(&packed, true).unwrap();
+            assert_eq!(from_bytes(&to_bytes(&expected)).unwrap(), expected);
+
+            // Первый вызов сохраняет список, второй читает его из файла кеша
+            assert_eq!(list_sizes(&dir, &packed, false).unwrap(), expected);
+            let cached = dir.join(format!("{}.list", key(&packed)));
+            assert!(cached.is_file());
+            assert_eq!(list_sizes(&dir, &packed, false).unwrap(), expected);
+
+            // Испорченный файл кеша не используется и перезаписывается
+            fs::write(&cached, b"RLC2\x05broken").unwrap();
+            assert_eq!(list_sizes(&dir, &packed, false).unwrap(), expected);
+            assert_eq!(from_bytes(&fs::read(&cached).unwrap()).unwrap(), expected);
+        }
+        // Другое содержимое архива дает другой ключ
+        let other = compress_archive(ArchiveData { entries: vec![entry("x", 0o100644, b"other")] }, &CompressionOptions::new(Algorithm::Lz4));
+        assert_eq!(list_sizes(&dir, &other, false).unwrap()[0].0.path, "x");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+        assert!(from_bytes(b"RLC1").is_err() && from_bytes(b"RLC2\xff\xff\xff\xff\x0f").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,244 @@
+//! Модуль для выбора алгоритма по замеру на этой машине (`--target-speed`, `--target-ratio`).
+//!
+//! Каждый алгоритм 1–2 мс (медленный — один кусок) сжимает встроенный образец (текст,
+//! структурированные записи, повторы и шум), и по замеренным скорости и степени сжатия
+//! выбирается алгоритм, который укладывается в заданную цель. Степень сжатия измеряется на образце, а не на данных
+//! пользователя, поэтому она лишь сравнивает алгоритмы между собой; выбор вместе с замером
+//! записывается в раздел сведений архива (см. `ArchiveInfo::selection`).
+
+use std::fmt;
+use std::time::{Duration, Instant};
+use crate::info::format_rate;
+use crate::processing::{self, Algorithm};
+
+/// Размер одного куска образца, который сжимается за раз.
+const PIECE_LEN: usize = 16 * 1024;
+
+/// Число кусков во встроенном образце.
+const PIECES: usize = 4;
+
+/// Время, за которое замеряется один алгоритм (хотя бы один кусок сжимается всегда).
+const BUDGET: Duration = Duration::from_millis(2);
+
+/// Цель выбора: наименьшая скорость сжатия и/или наибольшая доля сжатого размера.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Target {
+    /// Скорость сжатия в байтах исходных данных в секунду (`--target-speed`)
+    pub speed: Option<u64>,
+    /// Отношение сжатого размера к исходному (`--target-ratio`)
+    pub ratio: Option<f64>,
+}
+
+impl Target {
+    /// Задана ли хотя бы одна цель.
+    pub fn is_set(&self) -> bool {
+        self.speed.is_some() || self.ratio.is_some()
+    }
+}
+
+/// Замер одного алгоритма на встроенном образце.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    /// Алгоритм
+    pub algorithm: Algorithm,
+    /// Скорость сжатия в байтах исходных данных в секунду
+    pub speed: u64,
+    /// Отношение сжатого размера к исходному
+    pub ratio: f64,
+}
+
+impl Measurement {
+    /// Укладывается ли замер в цель.
+    fn meets(&self, target: &Target) -> bool {
+        target.speed.is_none_or(|speed| self.speed >= speed) && target.ratio.is_none_or(|ratio| self.ratio <= ratio)
+    }
+}
+
+/// Выбранный алгоритм, его замер и цель, по которой он выбран.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    /// Замер выбранного алгоритма
+    pub chosen: Measurement,
+    /// Цель выбора
+    pub target: Target,
+    /// Укладывается ли выбранный алгоритм в цель
+    pub met: bool,
+}
+
+impl fmt::Display for Selection {
+    /// Выводит выбор с замером и целью, например
+    /// `lz4 (measured ~350.2 MB/s, ratio 0.480; target speed ~200.0 MB/s)`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (measured {}, ratio {:.3}; target", self.chosen.algorithm.name(), format_rate(self.chosen.speed), self.chosen.ratio)?;
+        if let Some(speed) = self.target.speed {
+            write!(f, " speed {}", format_rate(speed))?;
+        }
+        if let Some(ratio) = self.target.ratio {
+            write!(f, " ratio {:.3}", ratio)?;
+        }
+        write!(f, "{})", if self.met { "" } else { ", not met by any algorithm" })
+    }
+}
+
+/// Разбирает скорость для `--target-speed`: число байтов в секунду с необязательной
+/// десятичной приставкой `K`, `M` или `G` и единицами `B/s` или `Bps` (`200MBps`, `1.5G`).
+pub fn parse_speed(spec: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid target speed {} (expected e.g. 200MBps or 50MB/s)", spec);
+    let lower = spec.trim().to_ascii_lowercase();
+    let unit = lower.strip_suffix("ps").or_else(|| lower.strip_suffix("/s")).unwrap_or(&lower);
+    let unit = unit.strip_suffix('b').unwrap_or(unit);
+    let (number, scale) = match unit.char_indices().last() {
+        Some((i, 'k')) => (&unit[..i], 1e3),
+        Some((i, 'm')) => (&unit[..i], 1e6),
+        Some((i, 'g')) => (&unit[..i], 1e9),
+        _ => (unit, 1.0),
+    };
+    number.trim().parse::<f64>().ok()
+        .map(|number| number * scale)
+        .filter(|speed| speed.is_finite() && *speed >= 1.0)
+        .map(|speed| speed as u64)
+        .ok_or_else(invalid)
+}
+
+/// Разбирает цель `--target-ratio`: отношение сжатого размера к исходному больше 0 и не больше 1.
+pub fn parse_ratio(spec: &str) -> Result<f64, String> {
+    spec.trim().parse::<f64>().ok()
+        .filter(|ratio| *ratio > 0.0 && *ratio <= 1.0)
+        .ok_or_else(|| format!("invalid target ratio {} (expected a number above 0 and at most 1, e.g. 0.5)", spec))
+}
+
+/// Строит встроенный образец: в каждом куске есть текст, записи, повторы и шум,
+/// поэтому и замер по одному куску похож на замер по всему образцу.
+fn sample() -> Vec<u8> {
+    // Линейный конгруэнтный генератор дает одинаковый образец на любой платформе
+    let mut state = 0x2545_f491u32;
+    let mut next = move || {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        state >> 16
+    };
+    let mut sample = Vec::with_capacity(PIECE_LEN * PIECES);
+    for piece in 0..PIECES {
+        let start = sample.len();
+        let words = ["error", "request", "served", "in", "ms", "user", "session", "cache", "miss", "the"];
+        let mut line = 0;
+        while sample.len() - start < PIECE_LEN / 2 {
+            line += 1;
+            let text = format!("{} {} {} {} {}\n", piece * 1000 + line, words[next() as usize % 10], words[next() as usize % 10], words[next() as usize % 10], next() % 50);
+            sample.extend_from_slice(text.as_bytes());
+        }
+        let mut id = piece as u32 * 1000;
+        while sample.len() - start < PIECE_LEN * 3 / 4 {
+            id += 1 + next() % 3;
+            sample.extend_from_slice(&id.to_le_bytes());
+            sample.extend_from_slice(&[0, 0, 1, (next() % 4) as u8, 0, 0, 0, 0]);
+        }
+        sample.extend(std::iter::repeat_n(0u8, PIECE_LEN / 8));
+        while sample.len() - start < PIECE_LEN {
+            sample.push(next() as u8);
+        }
+        sample.truncate(start + PIECE_LEN);
+    }
+    sample
+}
+
+/// Замеряет один алгоритм: куски образца сжимаются по кругу, пока не пройдет `BUDGET`.
+fn measure_one(sample: &[u8], algorithm: &Algorithm) -> Measurement {
+    let (mut original, mut compressed) = (0usize, 0usize);
+    let mut elapsed = Duration::ZERO;
+    for piece in sample.chunks(PIECE_LEN).cycle() {
+        let start = Instant::now();
+        compressed += processing::compress(piece, algorithm.clone(), false, false).len();
+        elapsed += start.elapsed();
+        original += piece.len();
+        if elapsed >= BUDGET {
+            break;
+        }
+    }
+    let nanos = elapsed.as_nanos().max(1);
+    Measurement {
+        algorithm: algorithm.clone(),
+        speed: u64::try_from(original as u128 * 1_000_000_000 / nanos).unwrap_or(u64::MAX),
+        ratio: compressed as f64 / original as f64,
+    }
+}
+
+/// Замеряет все алгоритмы на встроенном образце.
+///
+/// # Возвращает
+///
+/// Замеры в порядке `Algorithm::ALL`.
+pub fn measure() -> Vec<Measurement> {
+    let sample = sample();
+    Algorithm::ALL.iter().map(|algorithm| {
+        let measurement = measure_one(&sample, algorithm);
+        log::debug!("calibration: {} compresses at {}, ratio {:.3}", algorithm.name(), format_rate(measurement.speed), measurement.ratio);
+        measurement
+    }).collect()
+}
+
+/// Выбирает алгоритм по замерам.
+///
+/// Из алгоритмов, которые укладываются в цель, с `--target-speed` выбирается сжимающий
+/// лучше всех, а только с `--target-ratio` — самый быстрый. Если в цель не укладывается
+/// ни один, с `--target-speed` выбирается самый быстрый, иначе сжимающий лучше всех.
+///
+/// # Аргументы
+///
+/// * `measurements` - Замеры, как их возвращает `measure` (не пустые).
+/// * `target` - Цель выбора.
+///
+/// # Возвращает
+///
+/// Выбранный алгоритм с замером и отметкой, достигнута ли цель.
+pub fn choose(measurements: &[Measurement], target: &Target) -> Selection {
+    let fastest = |a: &&Measurement, b: &&Measurement| a.speed.cmp(&b.speed);
+    let smallest = |a: &&Measurement, b: &&Measurement| b.ratio.total_cmp(&a.ratio);
+    let candidates: Vec<&Measurement> = measurements.iter().filter(|m| m.meets(target)).collect();
+    let met = !candidates.is_empty();
+    let pool = if met { candidates } else { measurements.iter().collect() };
+    let prefer_speed = if met { target.speed.is_none() } else { target.speed.is_some() };
+    let chosen = if prefer_speed { pool.into_iter().max_by(fastest) } else { pool.into_iter().max_by(smallest) };
+    Selection { chosen: chosen.expect("no measurements").clone(), target: target.clone(), met }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_choose() {
+        assert_eq!(parse_speed("200MBps"), Ok(200_000_000));
+        assert_eq!(parse_speed("50MB/s"), Ok(50_000_000));
+        assert_eq!(parse_speed("1.5G"), Ok(1_500_000_000));
+        assert_eq!(parse_speed("800kbps"), Ok(800_000));
+        assert_eq!(parse_speed("4096"), Ok(4096));
+        assert!(parse_speed("fast").is_err() && parse_speed("0MBps").is_err() && parse_speed("MBps").is_err());
+        assert_eq!(parse_ratio("0.5"), Ok(0.5));
+        assert!(parse_ratio("0").is_err() && parse_ratio("1.5").is_err() && parse_ratio("half").is_err());
+
+        let m = |algorithm, speed, ratio| Measurement { algorithm, speed, ratio };
+        let measurements = [
+            m(Algorithm::Rle, 900_000_000, 0.9),
+            m(Algorithm::Lz4, 400_000_000, 0.5),
+            m(Algorithm::Lz77, 30_000_000, 0.3),
+        ];
+        let pick = |speed, ratio| {
+            let selection = choose(&measurements, &Target { speed, ratio });
+            (selection.chosen.algorithm, selection.met)
+        };
+        assert_eq!(pick(Some(200_000_000), None), (Algorithm::Lz4, true));
+        assert_eq!(pick(None, Some(0.6)), (Algorithm::Lz4, true));
+        assert_eq!(pick(Some(10_000_000), Some(0.95)), (Algorithm::Lz77, true));
+        assert_eq!(pick(Some(2_000_000_000), None), (Algorithm::Rle, false));
+        assert_eq!(pick(Some(500_000_000), Some(0.4)), (Algorithm::Rle, false));
+        assert_eq!(pick(None, Some(0.1)), (Algorithm::Lz77, false));
+        let selection = choose(&measurements, &Target { speed: Some(200_000_000), ratio: None });
+        assert_eq!(selection.to_string(), "lz4 (measured ~400.0 MB/s, ratio 0.500; target speed ~200.0 MB/s)");
+
+        // Замер на этой машине дает по одному результату на алгоритм
+        let measured = measure();
+        assert_eq!(measured.len(), Algorithm::ALL.len());
+        assert!(measured.iter().all(|m| m.speed > 0 && m.ratio > 0.0));
+        assert_eq!(sample().len(), PIECE_LEN * PIECES);
+    }
+}
@@ -0,0 +1,238 @@
+//! Модуль для ведения каталога созданных архивов.
+//!
+//! Каталог — это файл, в который при каждом сжатии дописывается запись об архиве:
+//! путь к архиву, время создания и список записей с размерами и контрольными суммами.
+//! По каталогу можно узнать, в каких поколениях резервных копий есть нужный файл,
+//! не открывая сами архивы. Запись дописывается под исключительной блокировкой файла
+//! каталога, поэтому одновременные задания не перемешивают свои записи.
+
+use std::convert::TryInto;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::checksum;
+use crate::lock::ArchiveLock;
+use crate::io::DirEntry;
+
+/// Сведения об одной записи архива, сохраненные в каталоге.
+#[derive(Debug, PartialEq)]
+pub struct CatalogEntry {
+    /// Относительный путь к файлу внутри архива
+    pub path: String,
+    /// Размер исходного файла в байтах
+    pub size: u64,
+    /// Контрольная сумма CRC32 содержимого файла
+    pub crc32: u32,
+}
+
+/// Запись каталога об одном созданном архиве.
+#[derive(Debug, PartialEq)]
+pub struct CatalogRecord {
+    /// Путь к файлу архива
+    pub archive: String,
+    /// Время создания архива в секундах от начала эпохи Unix
+    pub created: u64,
+    /// Записи, содержащиеся в архиве
+    pub entries: Vec<CatalogEntry>,
+}
+
+impl CatalogRecord {
+    /// Создает запись каталога для архива из его записей директории.
+    ///
+    /// Контрольные суммы берутся из записей; отсутствующие вычисляются на нескольких потоках.
+    ///
+    /// # Аргументы
+    ///
+    /// * `archive` - Путь к созданному архиву.
+    /// * `entries` - Записи, помещенные в архив.
+    ///
+    /// # Возвращает
+    ///
+    /// Новую запись каталога с текущим временем создания.
+    pub fn new(archive: &str, entries: &[DirEntry]) -> Self {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        // Сохраненные в записях суммы используются повторно, недостающие вычисляются параллельно
+        let missing: Vec<&[u8]> = entries.iter().filter(|e| e.crc32.is_none()).map(|e| e.data.as_slice()).collect();
+        let mut computed = checksum::crc32_parallel(&missing).into_iter();
+        CatalogRecord {
+            archive: archive.to_owned(),
+            created,
+            entries: entries.iter().map(|e| CatalogEntry {
+                path: e.path.clone(),
+                size: e.data.len() as u64,
+                crc32: e.crc32.or_else(|| computed.next()).unwrap(),
+            }).collect(),
+        }
+    }
+}
+
+/// Записывает строку с префиксом длины (4 байта).
+fn push_str(buffer: &mut Vec<u8>, s: &str) {
+    buffer.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(s.as_bytes());
+}
+
+/// Преобразует запись каталога в байты.
+fn record_to_bytes(record: &CatalogRecord) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_str(&mut body, &record.archive);
+    body.extend_from_slice(&record.created.to_le_bytes());
+    body.extend_from_slice(&(record.entries.len() as u32).to_le_bytes());
+    for e in &record.entries {
+        push_str(&mut body, &e.path);
+        body.extend_from_slice(&e.size.to_le_bytes());
+        body.extend_from_slice(&e.crc32.to_le_bytes());
+    }
+
+    // Запись предваряется своей длиной, чтобы читатель мог проверить ее целостность
+    let mut result = Vec::with_capacity(body.len() + 4);
+    result.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    result.extend_from_slice(&body);
+    result
+}
+
+/// Курсор для последовательного чтения полей записи каталога.
+struct Cursor<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// Читает заданное количество байтов.
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < self.offset + len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Каталог поврежден: неожиданный конец записи"));
+        }
+        let slice = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn read_str(&mut self) -> io::Result<String> {
+        let len = self.read_u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Каталог поврежден: неверная строка"))
+    }
+}
+
+/// Преобразует байты в запись каталога.
+fn bytes_to_record(data: &[u8]) -> io::Result<CatalogRecord> {
+    let mut cursor = Cursor { data, offset: 0 };
+    let archive = cursor.read_str()?;
+    let created = cursor.read_u64()?;
+    let count = cursor.read_u32()? as usize;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        let path = cursor.read_str()?;
+        let size = cursor.read_u64()?;
+        let crc32 = cursor.read_u32()?;
+        entries.push(CatalogEntry { path, size, crc32 });
+    }
+    Ok(CatalogRecord { archive, created, entries })
+}
+
+/// Дописывает запись в конец файла каталога, создавая его при необходимости.
+///
+/// # Аргументы
+///
+/// * `catalog_path` - Путь к файлу каталога.
+/// * `record` - Запись об архиве.
+///
+/// # Возвращает
+///
+/// Результат операции или ошибку ввода/вывода.
+pub fn append_record(catalog_path: &str, record: &CatalogRecord) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(catalog_path)?;
+    // Записи одновременных заданий не перемешиваются
+    let _lock = ArchiveLock::exclusive(Path::new(catalog_path))?;
+    file.write_all(&record_to_bytes(record))?;
+    Ok(())
+}
+
+/// Читает все записи каталога.
+///
+/// # Аргументы
+///
+/// * `catalog_path` - Путь к файлу каталога.
+///
+/// # Возвращает
+///
+/// Вектор записей каталога в порядке их добавления или ошибку ввода/вывода.
+pub fn read_catalog(catalog_path: &str) -> io::Result<Vec<CatalogRecord>> {
+    let data = ArchiveLock::shared(Path::new(catalog_path))?.read_all()?;
+
+    let mut cursor = Cursor { data: &data, offset: 0 };
+    let mut records = Vec::new();
+    while cursor.offset < data.len() {
+        let len = cursor.read_u32()? as usize;
+        records.push(bytes_to_record(cursor.take(len)?)?);
+    }
+    Ok(records)
+}
+
+/// Ищет в каталоге записи с указанным путем или именем файла.
+///
+/// Совпадением считается полный относительный путь или его последний компонент.
+///
+/// # Аргументы
+///
+/// * `records` - Записи каталога.
+/// * `name` - Искомый путь или имя файла.
+///
+/// # Возвращает
+///
+/// Пары (архив, запись) для каждого найденного вхождения.
+pub fn locate<'a>(records: &'a [CatalogRecord], name: &str) -> Vec<(&'a CatalogRecord, &'a CatalogEntry)> {
+    let mut found = Vec::new();
+    for record in records {
+        for entry in &record.entries {
+            let file_name = entry.path.rsplit('/').next().unwrap_or(&entry.path);
+            if entry.path == name || file_name == name {
+                found.push((record, entry));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn entry(path: &str, data: &[u8]) -> DirEntry {
+        DirEntry { path: path.to_owned(), data: data.to_vec(), permissions: 0o644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None }
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let record = CatalogRecord::new("backup.arc", &[entry("etc/config.yaml", b"a: 1"), entry("b.txt", b"")]);
+        let bytes = record_to_bytes(&record);
+        assert_eq!(bytes_to_record(&bytes[4..]).unwrap(), record);
+    }
+
+    #[test]
+    fn test_locate_by_name_and_path() {
+        let records = vec![
+            CatalogRecord::new("1.arc", &[entry("etc/config.yaml", b"a: 1")]),
+            CatalogRecord::new("2.arc", &[entry("other.txt", b"x")]),
+            CatalogRecord::new("3.arc", &[entry("etc/config.yaml", b"a: 2")]),
+        ];
+        let by_name: Vec<&str> = locate(&records, "config.yaml").iter().map(|(r, _)| r.archive.as_str()).collect();
+        assert_eq!(by_name, vec!["1.arc", "3.arc"]);
+        assert_eq!(locate(&records, "etc/config.yaml").len(), 2);
+        assert!(locate(&records, "config").is_empty());
+    }
+}
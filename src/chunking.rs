@@ -0,0 +1,171 @@
+//! Модуль для дедупликации по блокам переменной длины (`--chunk-dedup`).
+//!
+//! Поток данных делится на блоки по содержимому скользящим хешем (по схеме FastCDC):
+//! граница блока ставится там, где старшие биты хеша равны нулю, поэтому вставка или
+//! удаление байтов сдвигает только соседние границы, а остальные блоки совпадают с блоками
+//! прежней версии данных. Каждый уникальный блок (по SHA-256) сохраняется один раз, а
+//! повторы заменяются ссылками на него. Так образы виртуальных машин и последовательные
+//! дампы баз данных, в которых большая часть содержимого повторяется на расстояниях больше
+//! окна алгоритма сжатия, уменьшаются до размера их различий.
+//!
+//! Закодированный поток — последовательность элементов, каждый начинается с числа varint:
+//!
+//! | Младший бит | Остальные биты | Следом |
+//! |-------------|----------------|--------|
+//! | 0 | длина нового блока | байты блока |
+//! | 1 | номер ранее сохраненного уникального блока (с 0) | — |
+
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use crate::checksum;
+use crate::io::{read_varint, write_varint};
+
+/// Наименьший размер блока (кроме последнего).
+pub const MIN_CHUNK: usize = 2 * 1024;
+
+/// Средний размер блока, к которому стремится разбиение.
+pub const AVG_CHUNK: usize = 8 * 1024;
+
+/// Наибольший размер блока.
+pub const MAX_CHUNK: usize = 64 * 1024;
+
+/// Маска до среднего размера: на 2 бита строже средней, чтобы короткие блоки были редкими.
+const MASK_SMALL: u64 = !0 << (64 - 15);
+
+/// Маска после среднего размера: на 2 бита мягче средней, чтобы длинные блоки были редкими.
+const MASK_LARGE: u64 = !0 << (64 - 11);
+
+/// Таблица случайных чисел для скользящего хеша (gear), одинаковая во всех сборках.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < 256 {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Находит конец первого блока данных.
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK);
+    let normal = end.min(AVG_CHUNK);
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let mask = if i < normal { MASK_SMALL } else { MASK_LARGE };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Делит данные на блоки по содержимому.
+///
+/// # Возвращает
+///
+/// Диапазоны блоков подряд, покрывающие все данные.
+pub fn chunks(data: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = start + cut_point(&data[start..]);
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges
+}
+
+/// Заменяет повторяющиеся блоки данных ссылками на их первое вхождение.
+///
+/// # Аргументы
+///
+/// * `data` - Исходные данные.
+///
+/// # Возвращает
+///
+/// Закодированный поток (см. описание модуля).
+pub fn deduplicate(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut unique: HashMap<[u8; 32], u64> = HashMap::new();
+    for range in chunks(data) {
+        let chunk = &data[range];
+        let next = unique.len() as u64;
+        match *unique.entry(checksum::sha256(chunk)).or_insert(next) {
+            number if number < next => write_varint(&mut output, number << 1 | 1),
+            _ => {
+                write_varint(&mut output, (chunk.len() as u64) << 1);
+                output.extend_from_slice(chunk);
+            }
+        }
+    }
+    output
+}
+
+/// Восстанавливает данные из потока, созданного `deduplicate`.
+///
+/// # Возвращает
+///
+/// Исходные данные или ошибку, если поток обрезан или ссылается на несуществующий блок.
+pub fn reassemble(encoded: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut output = Vec::new();
+    let mut unique: Vec<Range<usize>> = Vec::new();
+    let mut offset = 0;
+    while offset < encoded.len() {
+        let value = read_varint(encoded, &mut offset)?;
+        if value & 1 == 1 {
+            let range = usize::try_from(value >> 1).ok().and_then(|number| unique.get(number)).cloned()
+                .ok_or_else(|| invalid(format!("chunk reference {} points past {} stored chunks", value >> 1, unique.len())))?;
+            output.extend_from_within(range);
+        } else {
+            let chunk = usize::try_from(value >> 1).ok()
+                .and_then(|len| offset.checked_add(len))
+                .and_then(|end| encoded.get(offset..end))
+                .ok_or_else(|| invalid("chunk data is truncated".to_owned()))?;
+            offset += chunk.len();
+            unique.push(output.len()..output.len() + chunk.len());
+            output.extend_from_slice(chunk);
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deduplicate_shifted_copy() {
+        // Псевдослучайные данные не сжимаются, поэтому уменьшение дает только дедупликация
+        let mut state = 12345u32;
+        let image: Vec<u8> = (0..300_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        // Вторая копия сдвинута вставкой в начало, и блоки фиксированного размера бы не совпали
+        let mut data = image.clone();
+        data.extend_from_slice(b"inserted bytes");
+        data.extend_from_slice(&image);
+
+        assert!(chunks(&data).iter().all(|r| r.len() <= MAX_CHUNK));
+        let encoded = deduplicate(&data);
+        assert!(encoded.len() < image.len() + image.len() / 5, "{} bytes", encoded.len());
+        assert_eq!(reassemble(&encoded).unwrap(), data);
+        assert!(reassemble(&deduplicate(b"")).unwrap().is_empty());
+
+        assert!(reassemble(&[0x03]).is_err());
+        assert!(reassemble(&[0x10, b'x']).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//! Модуль подключаемых реализаций хеширования и шифрования архивов.
+//!
+//! Хеш трейлера архива (по умолчанию SHA-256) вычисляется через `Hasher`, а файл архива
+//! при записи и чтении командной строкой проходит через `Cipher` (по умолчанию без изменений).
+//! Программа, использующая архиватор как библиотеку, может установить свои реализации
+//! функцией `install` — например, хеш или шифрование на аппаратном модуле безопасности, —
+//! не изменяя остальной код. Установленные реализации действуют на весь процесс.
+//!
+//! Шифр применяется к файлу архива целиком, вместе с заголовками и записями восстановления,
+//! поэтому `volume::read_archive` возвращает уже расшифрованные данные. Зашифрованный файл
+//! начинается с открытой метки `SEALED_MAGIC`, чтобы `--info` и программа без шифра отличали
+//! его от поврежденного файла. Хеш должен иметь длину `header::DIGEST_LEN`: размер трейлера
+//! задан форматом.
+
+use std::io;
+use std::sync::{Arc, RwLock};
+use crate::checksum;
+use crate::header::DIGEST_LEN;
+
+/// Метка, с которой начинается файл архива, зашифрованный установленным шифром.
+pub const SEALED_MAGIC: &[u8; 4] = b"RENC";
+
+/// Хеш, которым проверяется целостность сжатых данных архива.
+pub trait Hasher: Send + Sync {
+    /// Вычисляет хеш данных.
+    fn digest(&self, data: &[u8]) -> [u8; DIGEST_LEN];
+}
+
+/// Шифр, которым защищается файл архива.
+pub trait Cipher: Send + Sync {
+    /// Шифрует файл архива перед записью.
+    fn encrypt(&self, data: Vec<u8>) -> io::Result<Vec<u8>>;
+
+    /// Расшифровывает прочитанный файл архива.
+    fn decrypt(&self, data: Vec<u8>) -> io::Result<Vec<u8>>;
+
+    /// Возвращает `true`, если шифр оставляет данные без изменений: такой файл
+    /// не помечается `SEALED_MAGIC`.
+    fn is_plain(&self) -> bool {
+        false
+    }
+}
+
+/// Реализация по умолчанию: SHA-256 (см. `checksum::sha256`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256Hasher;
+
+impl Hasher for Sha256Hasher {
+    fn digest(&self, data: &[u8]) -> [u8; DIGEST_LEN] {
+        checksum::sha256(data)
+    }
+}
+
+/// Реализация по умолчанию: архив хранится без шифрования.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlainCipher;
+
+impl Cipher for PlainCipher {
+    fn encrypt(&self, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        Ok(data)
+    }
+
+    fn decrypt(&self, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        Ok(data)
+    }
+
+    fn is_plain(&self) -> bool {
+        true
+    }
+}
+
+/// Набор реализаций хеширования и шифрования.
+#[derive(Clone)]
+pub struct Providers {
+    /// Хеш трейлера архива
+    pub hasher: Arc<dyn Hasher>,
+    /// Шифр файла архива
+    pub cipher: Arc<dyn Cipher>,
+}
+
+impl Default for Providers {
+    fn default() -> Self {
+        Providers { hasher: Arc::new(Sha256Hasher), cipher: Arc::new(PlainCipher) }
+    }
+}
+
+/// Реализации, установленные `install`; `None` — реализации по умолчанию.
+static INSTALLED: RwLock<Option<Providers>> = RwLock::new(None);
+
+/// Устанавливает реализации хеширования и шифрования для всего процесса.
+///
+/// Архивы, записанные с одним хешем или шифром, читаются только с ними же, поэтому
+/// реализации следует устанавливать до первой операции с архивами.
+///
+/// # Аргументы
+///
+/// * `providers` - Новые реализации.
+pub fn install(providers: Providers) {
+    *INSTALLED.write().unwrap_or_else(|e| e.into_inner()) = Some(providers);
+}
+
+/// Возвращает установленные реализации или реализации по умолчанию.
+pub fn current() -> Providers {
+    INSTALLED.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+/// Вычисляет хеш трейлера установленным `Hasher`.
+pub fn digest(data: &[u8]) -> [u8; DIGEST_LEN] {
+    current().hasher.digest(data)
+}
+
+/// Шифрует файл архива установленным `Cipher` и помечает его `SEALED_MAGIC`.
+///
+/// # Возвращает
+///
+/// Данные для записи в файл или ошибку шифра.
+pub fn seal(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    seal_with(&*current().cipher, data)
+}
+
+/// Расшифровывает файл архива установленным `Cipher`.
+///
+/// Файл без `SEALED_MAGIC` (записанный без шифра или до появления метки) передается
+/// шифру целиком.
+///
+/// # Возвращает
+///
+/// Данные архива или ошибку шифра (например, при неверном ключе), а также ошибку,
+/// если файл зашифрован, но шифр не установлен.
+pub fn open(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    open_with(&*current().cipher, data)
+}
+
+/// Проверяет, помечен ли файл архива как зашифрованный (`SEALED_MAGIC`).
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(SEALED_MAGIC)
+}
+
+/// Шифрует файл архива шифром `cipher` (см. `seal`).
+fn seal_with(cipher: &dyn Cipher, data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if cipher.is_plain() {
+        return Ok(data);
+    }
+    let mut sealed = SEALED_MAGIC.to_vec();
+    sealed.extend(cipher.encrypt(data)?);
+    Ok(sealed)
+}
+
+/// Расшифровывает файл архива шифром `cipher` (см. `open`).
+fn open_with(cipher: &dyn Cipher, mut data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !is_sealed(&data) {
+        return cipher.decrypt(data);
+    }
+    if cipher.is_plain() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the archive is encrypted, and no cipher is installed to decrypt it"));
+    }
+    data.drain(..SEALED_MAGIC.len());
+    cipher.decrypt(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Шифр для проверки: добавляет метку и инвертирует байты.
+    struct Inverting;
+
+    impl Cipher for Inverting {
+        fn encrypt(&self, data: Vec<u8>) -> io::Result<Vec<u8>> {
+            Ok(b"INV".iter().copied().chain(data.into_iter().map(|b| !b)).collect())
+        }
+
+        fn decrypt(&self, data: Vec<u8>) -> io::Result<Vec<u8>> {
+            match data.strip_prefix(b"INV") {
+                Some(body) => Ok(body.iter().map(|b| !b).collect()),
+                None => Err(io::Error::new(io::ErrorKind::InvalidData, "not encrypted")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_providers() {
+        // Реализации по умолчанию не меняют формат архива
+        let defaults = Providers::default();
+        assert_eq!(defaults.hasher.digest(b"abc"), checksum::sha256(b"abc"));
+        assert_eq!(defaults.cipher.encrypt(b"RARC".to_vec()).unwrap(), b"RARC");
+        assert_eq!(digest(b"abc"), checksum::sha256(b"abc"));
+
+        let custom = Providers { cipher: Arc::new(Inverting), ..Providers::default() };
+        let sealed = custom.cipher.encrypt(b"RARC data".to_vec()).unwrap();
+        assert_ne!(&sealed[3..], b"RARC data");
+        assert_eq!(custom.cipher.decrypt(sealed).unwrap(), b"RARC data");
+        assert!(custom.cipher.decrypt(b"RARC data".to_vec()).is_err());
+
+        // Зашифрованный файл помечается, а без шифра не читается
+        let sealed = seal_with(&Inverting, b"RARC data".to_vec()).unwrap();
+        assert!(is_sealed(&sealed) && !is_sealed(&seal_with(&PlainCipher, b"RARC data".to_vec()).unwrap()));
+        assert_eq!(open_with(&Inverting, sealed.clone()).unwrap(), b"RARC data");
+        assert_eq!(open_with(&PlainCipher, sealed).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // Файлы, зашифрованные до появления метки, расшифровываются целиком
+        assert_eq!(open_with(&Inverting, Inverting.encrypt(b"RARC".to_vec()).unwrap()).unwrap(), b"RARC");
+    }
+}
@@ -0,0 +1,198 @@
+//! Модуль для поиска файлов с одинаковым содержимым (`--dupes`).
+//!
+//! Записи архива или файлы директории группируются по хешу SHA-256 содержимого, и для
+//! каждой группы считается, сколько байтов занимают лишние копии. Жесткие ссылки и записи,
+//! уже сохраненные дубликатами (`io::DUPLICATE_FLAG`), относятся к группе своего исходного
+//! файла, но лишнего места не занимают. Найденные копии в директории можно заменить жесткими
+//! ссылками на первую копию (`link_duplicates`), и тогда при сжатии они сохраняются ссылками.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::checksum;
+use crate::filter::EntryType;
+use crate::io::{DirEntry, DUPLICATE_FLAG, HARD_LINK_FLAG};
+
+/// Группа файлов с одинаковым содержимым.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    /// Размер содержимого в байтах
+    pub size: u64,
+    /// Пути копий, содержимое которых хранится полностью, в порядке записей
+    pub copies: Vec<String>,
+    /// Пути жестких ссылок и дубликатов, которые ссылаются на одну из копий
+    pub references: Vec<String>,
+}
+
+impl DuplicateGroup {
+    /// Возвращает число байтов, занятых копиями сверх первой.
+    pub fn wasted(&self) -> u64 {
+        self.size * self.copies.len().saturating_sub(1) as u64
+    }
+}
+
+/// Находит группы записей с одинаковым непустым содержимым.
+///
+/// # Аргументы
+///
+/// * `entries` - Записи архива или обхода директории в их порядке.
+///
+/// # Возвращает
+///
+/// Группы хотя бы из двух путей по убыванию лишних байтов, затем размера и пути первой копии.
+pub fn find(entries: &[DirEntry]) -> Vec<DuplicateGroup> {
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    let mut by_digest: HashMap<[u8; 32], usize> = HashMap::new();
+    // Группа каждой копии для ссылок, которые указывают на ее путь
+    let mut by_path: HashMap<&str, usize> = HashMap::new();
+    let files = entries.iter()
+        .filter(|e| EntryType::from_mode(e.permissions & !(HARD_LINK_FLAG | DUPLICATE_FLAG)) == EntryType::File);
+    fn source(e: &DirEntry) -> Option<&str> {
+        e.hard_link_target().or_else(|| e.duplicate_of())
+    }
+    for entry in files.clone().filter(|e| source(e).is_none() && !e.data.is_empty()) {
+        let group = *by_digest.entry(checksum::sha256(&entry.data)).or_insert_with(|| {
+            groups.push(DuplicateGroup { size: entry.data.len() as u64, copies: Vec::new(), references: Vec::new() });
+            groups.len() - 1
+        });
+        groups[group].copies.push(entry.path.clone());
+        by_path.insert(&entry.path, group);
+    }
+    // Ссылки разбираются после копий: после сортировки по пути ссылка может идти раньше файла
+    for entry in files {
+        if let Some(&group) = source(entry).and_then(|source| by_path.get(source)) {
+            groups[group].references.push(entry.path.clone());
+        }
+    }
+    groups.retain(|g| g.copies.len() + g.references.len() > 1);
+    groups.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then(b.size.cmp(&a.size)).then_with(|| a.copies[0].cmp(&b.copies[0])));
+    groups
+}
+
+/// Результат замены копий жесткими ссылками.
+#[derive(Debug, Default, PartialEq)]
+pub struct LinkReport {
+    /// Число копий, замененных ссылками
+    pub linked: usize,
+    /// Освобожденные байты
+    pub freed: u64,
+    /// Пропущенные копии и причина
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Заменяет копии в директории жесткими ссылками на первую копию группы.
+///
+/// Ссылка создается под временным именем и переименовывается поверх копии, поэтому копия
+/// не пропадает, если ссылку создать нельзя. Копии с другими правами, владельцем или
+/// на другой файловой системе пропускаются: у жестких ссылок они общие.
+///
+/// # Аргументы
+///
+/// * `root` - Директория, по которой найдены группы.
+/// * `groups` - Группы, найденные `find` по записям обхода `root`.
+///
+/// # Возвращает
+///
+/// Отчет о замене или ошибку ввода/вывода при чтении первой копии.
+pub fn link_duplicates(root: &Path, groups: &[DuplicateGroup]) -> io::Result<LinkReport> {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut report = LinkReport::default();
+    for group in groups {
+        let first = root.join(&group.copies[0]);
+        let original = fs::metadata(&first)?;
+        for copy in &group.copies[1..] {
+            let path = root.join(copy);
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    report.skipped.push((copy.clone(), e.to_string()));
+                    continue;
+                }
+            };
+            let reason = if metadata.dev() != original.dev() {
+                Some("on another file system")
+            } else if metadata.mode() != original.mode() {
+                Some("permissions differ")
+            } else if (metadata.uid(), metadata.gid()) != (original.uid(), original.gid()) {
+                Some("owner differs")
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                report.skipped.push((copy.clone(), reason.to_owned()));
+                continue;
+            }
+            let mut temp = path.as_os_str().to_owned();
+            temp.push(format!(".{}.link", std::process::id()));
+            let linked = fs::hard_link(&first, &temp).and_then(|()| fs::rename(&temp, &path));
+            if let Err(e) = linked {
+                let _ = fs::remove_file(&temp);
+                report.skipped.push((copy.clone(), e.to_string()));
+                continue;
+            }
+            report.linked += 1;
+            report.freed += group.size;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{read_dir_recursive, WalkOptions};
+    use std::collections::BTreeMap;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_find_and_link() {
+        let entry = |path: &str, permissions, data: &[u8]| DirEntry { path: path.into(), data: data.to_vec(), permissions, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None };
+        let text = b"the same content".repeat(10);
+        let entries = vec![
+            entry("a", 0o100644, &text),
+            entry("b", 0o100600, &text),
+            entry("c", 0o100644 | DUPLICATE_FLAG, b"a"),
+            entry("d", 0o100644 | HARD_LINK_FLAG, b"b"),
+            entry("small1", 0o100644, b"xy"),
+            entry("small2", 0o100644, b"xy"),
+            entry("empty1", 0o100644, b""),
+            entry("empty2", 0o100644, b""),
+            entry("link", 0o120777, b"a"),
+            entry("unique", 0o100644, b"only once"),
+        ];
+        let groups = find(&entries);
+        assert_eq!(groups, [
+            DuplicateGroup { size: 160, copies: vec!["a".into(), "b".into()], references: vec!["c".into(), "d".into()] },
+            DuplicateGroup { size: 2, copies: vec!["small1".into(), "small2".into()], references: vec![] },
+        ]);
+        assert_eq!(groups[0].wasted(), 160);
+
+        let root = std::env::temp_dir().join(format!("dupes_test_{}", std::process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        for (path, mode) in [("one", 0o644), ("sub/two", 0o644), ("three", 0o600)] {
+            fs::write(root.join(path), &text).unwrap();
+            fs::set_permissions(root.join(path), std::os::unix::fs::PermissionsExt::from_mode(mode)).unwrap();
+        }
+        let walk = WalkOptions { dedup: false, ..Default::default() };
+        let walked = || {
+            let mut entries = read_dir_recursive(&root, &root, &walk).unwrap();
+            entries.sort_by(|a, b| a.path.cmp(&b.path));
+            entries
+        };
+        let groups = find(&walked());
+        assert_eq!((groups.len(), groups[0].copies.len(), groups[0].wasted()), (1, 3, 320));
+        let report = link_duplicates(&root, &groups).unwrap();
+        assert_eq!((report.linked, report.freed, report.skipped.len()), (1, 160, 1));
+        assert_eq!(report.skipped[0].1, "permissions differ");
+        assert_eq!(fs::metadata(root.join("one")).unwrap().nlink(), 2);
+        assert_eq!(fs::read(root.join("sub/two")).unwrap(), text);
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 3);
+
+        // После замены копия становится жесткой ссылкой и лишнего места не занимает
+        let groups = find(&walked());
+        assert_eq!((groups[0].copies.len(), groups[0].references.len(), groups[0].wasted()), (2, 1, 160));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,526 @@
+//! Модуль центрального индекса архива с посжатыми записями.
+//!
+//! В таком архиве каждая запись `DirEntry` сжимается отдельно, а после сжатых записей
+//! располагается индекс: путь, права доступа, смещение, сжатый и исходный размер каждой
+//! записи. Последние 8 байтов данных содержат смещение индекса. Благодаря индексу
+//! для выборочной распаковки и просмотра не нужно распаковывать весь архив.
+//!
+//! В блочном режиме содержимое каждого файла делится на блоки, которые сжимаются независимо,
+//! а индекс дополнительно хранит базовый размер блока, CRC32 файла и таблицу сжатых размеров
+//! блоков. По этой таблице можно распаковать только блоки, покрывающие нужный диапазон байтов.
+//! Начиная с версии формата 11 таблица хранит и исходную длину каждого блока: размер блока
+//! подбирается по энтропии данных (см. модуль `blocks`), а прежде все блоки, кроме последнего,
+//! имели базовый размер. Начиная с флага `FLAG_OWNERS` запись индекса
+//! блочного режима содержит и владельца файла, а также пользовательские метаданные.
+//!
+//! Начиная с версии формата 5 путь каждой записи индекса хранится как длина общего
+//! с путем предыдущей записи префикса и окончание, что сильно уменьшает индекс глубоких
+//! деревьев, где соседние записи делят длинные пути директорий.
+//!
+//! Мелкие файлы могут быть упакованы в общие сегменты (флаг `FLAG_SEGMENTS`): байты
+//! нескольких записей записываются подряд и сжимаются одним потоком, а запись индекса
+//! хранит, кроме смещения сегмента, смещение и длину своих байтов в распакованном сегменте.
+//!
+//! Начиная с версии формата 10 каждая запись индекса хранит время изменения файла, поэтому
+//! список записей и сравнение с деревом файлов обходятся без распаковки их содержимого.
+//!
+//! В архивах с множеством мелких файлов индекс занимает больше места, чем сами данные,
+//! поэтому он сжимается LZ4 (флаг `FLAG_PACKED_INDEX`) отдельно от записей: для
+//! просмотра по-прежнему достаточно распаковать только индекс, и это быстро.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io;
+use std::ops::Range;
+use crate::header::Header;
+use crate::io::{read_metadata, shared_prefix_len, write_metadata};
+use crate::lz4;
+use crate::owner::Owner;
+
+/// Описание одной сжатой записи в центральном индексе.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexRecord {
+    /// Относительный путь к файлу
+    pub path: String,
+    /// Права доступа к файлу
+    pub permissions: u32,
+    /// Смещение сжатой записи от начала данных
+    pub offset: u64,
+    /// Размер сжатой записи в байтах
+    pub compressed_size: u64,
+    /// Исходный размер содержимого файла в байтах
+    pub size: u64,
+    /// Контрольная сумма CRC32 содержимого (только в блочном режиме)
+    pub crc32: Option<u32>,
+    /// Сжатые размеры блоков (только в блочном режиме)
+    pub blocks: Vec<u64>,
+    /// Исходные длины блоков (только в блочном режиме; до версии формата 11 вычисляются
+    /// по базовому размеру блока)
+    pub block_lengths: Vec<u64>,
+    /// Владелец файла (только в блочном режиме)
+    pub owner: Option<Owner>,
+    /// Пользовательские метаданные (только в блочном режиме)
+    pub metadata: BTreeMap<String, String>,
+    /// Время последнего изменения файла в секундах от начала эпохи Unix (с версии формата 10)
+    pub mtime: Option<u64>,
+    /// Смещение и длина байтов записи в распакованном сегменте (только для упакованных записей)
+    pub segment: Option<(u64, u64)>,
+}
+
+/// Бит байта наличия: за ним следует владелец.
+const HAS_OWNER: u8 = 0x01;
+
+/// Бит байта наличия: за владельцем следуют пользовательские метаданные.
+const HAS_METADATA: u8 = 0x02;
+
+/// Центральный индекс: записи в порядке их следования в архиве.
+#[derive(Debug, Default, PartialEq)]
+pub struct EntryIndex {
+    /// Базовый размер блока исходных данных; 0, если записи сжаты целиком
+    pub block_size: u64,
+    /// Записи блочного режима содержат владельца и метаданные
+    pub owners: bool,
+    /// Записи могут быть упакованы в общие сегменты
+    pub segments: bool,
+    /// Записи содержат время изменения (с версии формата 10)
+    pub mtimes: bool,
+    /// Таблица блоков содержит исходные длины блоков (с версии формата 11)
+    pub adaptive_blocks: bool,
+    /// Записи индекса
+    pub records: Vec<IndexRecord>,
+}
+
+impl EntryIndex {
+    /// Преобразует индекс в байты.
+    ///
+    /// Поля блочного режима записываются, только если задан `block_size`. Путь записи
+    /// хранится относительно пути предыдущей: длина общего префикса, длина окончания и само
+    /// окончание. Количества записей и блоков и длины хранятся в `u32`; их переполнение
+    /// считается ошибкой программы, а не усекается молча.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let count = |n: usize, what: &str| u32::try_from(n).unwrap_or_else(|_| panic!("{} does not fit the index ({})", what, n));
+        let mut buffer = Vec::new();
+        if self.block_size > 0 {
+            buffer.extend_from_slice(&self.block_size.to_le_bytes());
+        }
+        buffer.extend_from_slice(&count(self.records.len(), "entry count").to_le_bytes());
+        let mut previous = "";
+        for r in &self.records {
+            let shared = shared_prefix_len(previous, &r.path);
+            buffer.extend_from_slice(&count(shared, "path length").to_le_bytes());
+            buffer.extend_from_slice(&count(r.path.len() - shared, "path length").to_le_bytes());
+            buffer.extend_from_slice(&r.path.as_bytes()[shared..]);
+            previous = &r.path;
+            buffer.extend_from_slice(&r.permissions.to_le_bytes());
+            buffer.extend_from_slice(&r.offset.to_le_bytes());
+            buffer.extend_from_slice(&r.compressed_size.to_le_bytes());
+            buffer.extend_from_slice(&r.size.to_le_bytes());
+            if self.mtimes {
+                // Байт наличия времени изменения, затем само время
+                buffer.push(r.mtime.is_some() as u8);
+                if let Some(mtime) = r.mtime {
+                    buffer.extend_from_slice(&mtime.to_le_bytes());
+                }
+            }
+            if self.segments {
+                // Байт наличия сегмента, затем смещение и длина записи в нем
+                buffer.push(r.segment.is_some() as u8);
+                if let Some((offset, len)) = r.segment {
+                    buffer.extend_from_slice(&offset.to_le_bytes());
+                    buffer.extend_from_slice(&len.to_le_bytes());
+                }
+            }
+            if self.block_size > 0 {
+                buffer.extend_from_slice(&r.crc32.unwrap_or(0).to_le_bytes());
+                buffer.extend_from_slice(&count(r.blocks.len(), "block count").to_le_bytes());
+                for (i, b) in r.blocks.iter().enumerate() {
+                    buffer.extend_from_slice(&b.to_le_bytes());
+                    if self.adaptive_blocks {
+                        buffer.extend_from_slice(&r.block_lengths[i].to_le_bytes());
+                    }
+                }
+                if self.owners {
+                    // Байт наличия владельца и метаданных, затем сами поля
+                    let owner = if r.owner.is_some() { HAS_OWNER } else { 0 };
+                    let metadata = if r.metadata.is_empty() { 0 } else { HAS_METADATA };
+                    buffer.push(owner | metadata);
+                    if let Some(owner) = &r.owner {
+                        owner.write(&mut buffer);
+                    }
+                    if !r.metadata.is_empty() {
+                        write_metadata(&mut buffer, &r.metadata);
+                    }
+                }
+            }
+        }
+        buffer
+    }
+
+    /// Преобразует байты, записанные `to_bytes`, в индекс.
+    ///
+    /// # Аргументы
+    ///
+    /// * `data` - Срез байтов сериализованного индекса.
+    /// * `header` - Заголовок архива: версия и флаги определяют состав полей записи.
+    ///
+    /// # Возвращает
+    ///
+    /// Индекс или ошибку при неверном формате.
+    pub fn from_bytes(data: &[u8], header: &Header) -> io::Result<Self> {
+        let (framed, owners, segments) = (header.blocks(), header.owners(), header.segments());
+        let corrupted = || io::Error::new(io::ErrorKind::InvalidData, "Центральный индекс поврежден");
+        let mut pos = 0usize;
+        let take = |pos: &mut usize, len: usize| -> io::Result<&[u8]> {
+            let slice = data.get(*pos..pos.checked_add(len).ok_or_else(corrupted)?).ok_or_else(corrupted)?;
+            *pos += len;
+            Ok(slice)
+        };
+        let block_size = if framed {
+            let block_size = u64::from_le_bytes(take(&mut pos, 8)?.try_into().unwrap());
+            if block_size == 0 {
+                return Err(corrupted());
+            }
+            block_size
+        } else {
+            0
+        };
+        let count = u32::from_le_bytes(take(&mut pos, 4)?.try_into().unwrap());
+        let mut records: Vec<IndexRecord> = Vec::new();
+        for _ in 0..count {
+            // Общий префикс берется из пути предыдущей записи и не может разрывать символ
+            let previous = records.last().map_or("", |r| r.path.as_str());
+            let shared = if header.front_coded_index() {
+                let shared = u32::from_le_bytes(take(&mut pos, 4)?.try_into().unwrap()) as usize;
+                if !previous.is_char_boundary(shared) {
+                    return Err(corrupted());
+                }
+                shared
+            } else {
+                0
+            };
+            let path_len = u32::from_le_bytes(take(&mut pos, 4)?.try_into().unwrap()) as usize;
+            let suffix = std::str::from_utf8(take(&mut pos, path_len)?).map_err(|_| corrupted())?;
+            let path = format!("{}{}", &previous[..shared], suffix);
+            let permissions = u32::from_le_bytes(take(&mut pos, 4)?.try_into().unwrap());
+            let offset = u64::from_le_bytes(take(&mut pos, 8)?.try_into().unwrap());
+            let compressed_size = u64::from_le_bytes(take(&mut pos, 8)?.try_into().unwrap());
+            let size = u64::from_le_bytes(take(&mut pos, 8)?.try_into().unwrap());
+            let mtime = match if header.mtimes() { take(&mut pos, 1)?[0] } else { 0 } {
+                0 => None,
+                1 => Some(u64::from_le_bytes(take(&mut pos, 8)?.try_into().unwrap())),
+                _ => return Err(corrupted()),
+            };
+            let segment = match if segments { take(&mut pos, 1)?[0] } else { 0 } {
+                0 => None,
+                1 => {
+                    let offset = u64::from_le_bytes(take(&mut pos, 8)?.try_into().unwrap());
+                    let len = u64::from_le_bytes(take(&mut pos, 8)?.try_into().unwrap());
+                    Some((offset, len))
+                }
+                _ => return Err(corrupted()),
+            };
+            let (crc32, blocks, block_lengths, owner, metadata) = if framed {
+                let crc32 = u32::from_le_bytes(take(&mut pos, 4)?.try_into().unwrap());
+                let block_count = u32::from_le_bytes(take(&mut pos, 4)?.try_into().unwrap());
+                let mut blocks = Vec::new();
+                let mut block_lengths = Vec::new();
+                for _ in 0..block_count {
+                    blocks.push(u64::from_le_bytes(take(&mut pos, 8)?.try_into().unwrap()));
+                    if header.block_lengths() {
+                        block_lengths.push(u64::from_le_bytes(take(&mut pos, 8)?.try_into().unwrap()));
+                    }
+                }
+                if !header.block_lengths() {
+                    // Прежде все блоки, кроме последнего, имели базовый размер
+                    if u64::from(block_count) != size.div_ceil(block_size) {
+                        return Err(corrupted());
+                    }
+                    block_lengths = (0..u64::from(block_count)).map(|i| block_size.min(size - i * block_size)).collect();
+                }
+                let present = if owners { take(&mut pos, 1)?[0] } else { 0 };
+                if present & !(HAS_OWNER | HAS_METADATA) != 0 {
+                    return Err(corrupted());
+                }
+                let owner = if present & HAS_OWNER != 0 { Some(Owner::read(data, &mut pos)?) } else { None };
+                let metadata = if present & HAS_METADATA != 0 { read_metadata(data, &mut pos)? } else { BTreeMap::new() };
+                (Some(crc32), blocks, block_lengths, owner, metadata)
+            } else {
+                (None, Vec::new(), Vec::new(), None, BTreeMap::new())
+            };
+            records.push(IndexRecord { path, permissions, offset, compressed_size, size, crc32, blocks, block_lengths, owner, metadata, mtime, segment });
+        }
+        Ok(EntryIndex { block_size, owners: framed && owners, segments, mtimes: header.mtimes(), adaptive_blocks: framed && header.block_lengths(), records })
+    }
+}
+
+/// Собирает данные архива из сжатых записей и индекса.
+///
+/// # Аргументы
+///
+/// * `blobs` - Сжатые записи или сегменты вместе с описаниями записей в них (поля `offset`
+///   и `compressed_size` заполняются здесь).
+/// * `block_size` - Размер блока в блочном режиме или 0.
+///
+/// # Возвращает
+///
+/// Данные архива: сжатые записи, сжатый LZ4 индекс и смещение индекса.
+pub fn build_payload(blobs: Vec<(Vec<IndexRecord>, Vec<u8>)>, block_size: u64) -> Vec<u8> {
+    let mut payload = Vec::new();
+    let segments = blobs.iter().flat_map(|(records, _)| records).any(|r| r.segment.is_some());
+    let mut index = EntryIndex { block_size, owners: block_size > 0, segments, mtimes: true, adaptive_blocks: block_size > 0, records: Vec::new() };
+    for (records, blob) in blobs {
+        for mut record in records {
+            record.offset = payload.len() as u64;
+            record.compressed_size = blob.len() as u64;
+            index.records.push(record);
+        }
+        payload.extend_from_slice(&blob);
+    }
+    let index_offset = payload.len() as u64;
+    payload.extend_from_slice(&lz4::compress(&index.to_bytes()));
+    payload.extend_from_slice(&index_offset.to_le_bytes());
+    payload
+}
+
+/// Читает индекс из данных архива.
+///
+/// # Аргументы
+///
+/// * `payload` - Данные архива, собранные `build_payload`.
+/// * `header` - Заголовок архива с флагами, определяющими устройство индекса.
+///
+/// # Возвращает
+///
+/// Индекс, смещения и таблицы блоков которого проверены на выход за пределы данных, или ошибку.
+pub fn read_index(payload: &[u8], header: &Header) -> io::Result<EntryIndex> {
+    let corrupted = || io::Error::new(io::ErrorKind::InvalidData, "Центральный индекс поврежден");
+    let footer = payload.len().checked_sub(8).ok_or_else(corrupted)?;
+    let index_offset = usize::try_from(u64::from_le_bytes(payload[footer..].try_into().unwrap()))
+        .map_err(|_| corrupted())?;
+    let region = payload.get(index_offset..footer).ok_or_else(corrupted)?;
+    let index = if header.packed_index() {
+        EntryIndex::from_bytes(&lz4::decompress(region).map_err(|_| corrupted())?, header)?
+    } else {
+        EntryIndex::from_bytes(region, header)?
+    };
+    for r in &index.records {
+        if r.offset.checked_add(r.compressed_size).is_none_or(|end| end > index_offset as u64) {
+            return Err(corrupted());
+        }
+        if header.blocks() {
+            let total = r.blocks.iter().try_fold(0u64, |acc, b| acc.checked_add(*b));
+            let length = r.block_lengths.iter().try_fold(0u64, |acc, b| acc.checked_add(*b));
+            if total != Some(r.compressed_size) || length != Some(r.size) || r.block_lengths.len() != r.blocks.len()
+                || r.block_lengths.contains(&0) {
+                return Err(corrupted());
+            }
+        }
+    }
+    Ok(index)
+}
+
+/// Находит блоки записи, покрывающие диапазон исходных байтов.
+///
+/// Таблица блоков должна быть проверена `read_index`.
+///
+/// # Аргументы
+///
+/// * `record` - Запись блочного режима.
+/// * `range` - Непустой диапазон внутри содержимого записи.
+///
+/// # Возвращает
+///
+/// Номера первого и последнего блоков и смещение начала первого блока в содержимом.
+pub fn covering_blocks(record: &IndexRecord, range: Range<u64>) -> (usize, usize, u64) {
+    let (mut first, mut start) = (0, 0);
+    while start + record.block_lengths[first] <= range.start {
+        start += record.block_lengths[first];
+        first += 1;
+    }
+    let (mut last, mut end) = (first, start + record.block_lengths[first]);
+    while end < range.end {
+        last += 1;
+        end += record.block_lengths[last];
+    }
+    (first, last, start)
+}
+
+/// Возвращает сжатые байты блока записи с номером `block`.
+///
+/// Таблица блоков должна быть проверена `read_index`.
+pub fn block_blob<'a>(payload: &'a [u8], record: &IndexRecord, block: usize) -> &'a [u8] {
+    let start = record.offset + record.blocks[..block].iter().sum::<u64>();
+    &payload[start as usize..(start + record.blocks[block]) as usize]
+}
+
+/// Возвращает сжатые байты записи из данных архива.
+///
+/// Для упакованной записи это сжатый сегмент целиком (см. `segment_bytes`).
+/// Границы записи должны быть проверены `read_index`.
+pub fn entry_blob<'a>(payload: &'a [u8], record: &IndexRecord) -> &'a [u8] {
+    &payload[record.offset as usize..(record.offset + record.compressed_size) as usize]
+}
+
+/// Выделяет байты записи из распакованного потока записи.
+///
+/// # Аргументы
+///
+/// * `decoded` - Распакованный поток записи или ее сегмента.
+/// * `record` - Описание записи.
+///
+/// # Возвращает
+///
+/// Байты записи: весь поток для отдельно сжатой записи и ее часть для упакованной,
+/// либо ошибку, если часть выходит за пределы сегмента.
+pub fn segment_bytes<'a>(decoded: &'a [u8], record: &IndexRecord) -> io::Result<&'a [u8]> {
+    match record.segment {
+        None => Ok(decoded),
+        Some((offset, len)) => offset.checked_add(len)
+            .and_then(|end| decoded.get(usize::try_from(offset).ok()?..usize::try_from(end).ok()?))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                format!("{}: entry is outside of its data segment", record.path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{FORMAT_VERSION, LAYOUT_LITTLE_ENDIAN, FLAG_BLOCKS, FLAG_OWNERS, FLAG_PACKED_INDEX, FLAG_PER_ENTRY, FLAG_SEGMENTS};
+    use crate::processing::Algorithm;
+
+    /// Заголовок посжатого архива текущей версии с дополнительными флагами `flags`.
+    fn header(flags: u8) -> Header {
+        Header { version: FORMAT_VERSION, algorithm: Algorithm::Lz4, flags: FLAG_PER_ENTRY | flags, layout: LAYOUT_LITTLE_ENDIAN, payload_len: 0 }
+    }
+
+    fn record(path: &str, size: u64) -> IndexRecord {
+        IndexRecord { path: path.to_owned(), permissions: 0o100644, offset: 0, compressed_size: 0, size, crc32: None, blocks: Vec::new(), block_lengths: Vec::new(), owner: None, metadata: BTreeMap::new(), mtime: None, segment: None }
+    }
+
+    #[test]
+    fn test_payload_round_trip() {
+        let payload = build_payload(vec![
+            (vec![record("a.txt", 10)], b"first".to_vec()),
+            (vec![record("dir/b.bin", 3)], b"second blob".to_vec()),
+        ], 0);
+        let index = read_index(&payload, &header(FLAG_PACKED_INDEX)).unwrap();
+        assert_eq!(index.records.len(), 2);
+        assert_eq!(index.records[1].path, "dir/b.bin");
+        assert_eq!(index.records[1].size, 3);
+        assert_eq!(entry_blob(&payload, &index.records[0]), b"first");
+        assert_eq!(entry_blob(&payload, &index.records[1]), b"second blob");
+
+        // Пути похожих записей хранятся окончаниями, индекс сжимается, а несжатый индекс читается
+        let paths: Vec<_> = (0..1000).map(|i| format!("projects/archiver/logs/2024/november/ёлка-{:04}.log", i)).collect();
+        let records: Vec<_> = paths.iter().enumerate()
+            .map(|(i, path)| (vec![IndexRecord { mtime: (i % 2 == 0).then_some(1_700_000_000 + (i / 100) as u64), ..record(path, 1) }], Vec::new()))
+            .collect();
+        let index = EntryIndex { records: records.iter().map(|(r, _)| r[0].clone()).collect(), mtimes: true, ..Default::default() };
+        let raw = index.to_bytes();
+        assert!(raw.len() < paths.iter().map(|p| p.len()).sum::<usize>());
+        let packed = build_payload(records, 0);
+        assert!(packed.len() < raw.len() / 2);
+        let mut unpacked = raw;
+        unpacked.extend_from_slice(&0u64.to_le_bytes());
+        assert_eq!(read_index(&unpacked, &header(0)).unwrap(), index);
+        assert_eq!(read_index(&packed, &header(FLAG_PACKED_INDEX)).unwrap().records.len(), 1000);
+        assert_eq!(read_index(&packed, &header(FLAG_PACKED_INDEX)).unwrap().records[400].mtime, Some(1_700_000_004));
+
+        // До версии 10 времени изменения в индексе нет
+        let mut old = EntryIndex { mtimes: false, ..index }.to_bytes();
+        old.extend_from_slice(&0u64.to_le_bytes());
+        let previous = read_index(&old, &Header { version: 9, ..header(0) }).unwrap();
+        assert!(previous.records.iter().all(|r| r.mtime.is_none()) && !previous.mtimes);
+
+        // В версии 4 пути индекса записаны полностью
+        let mut full = 1u32.to_le_bytes().to_vec();
+        full.extend_from_slice(&(paths[7].len() as u32).to_le_bytes());
+        full.extend_from_slice(paths[7].as_bytes());
+        full.extend_from_slice(&[0; 4 + 8 + 8 + 8]);
+        full.extend_from_slice(&0u64.to_le_bytes());
+        let legacy = Header { version: 4, ..header(0) };
+        assert_eq!(read_index(&full, &legacy).unwrap().records[0].path, paths[7]);
+    }
+
+    #[test]
+    fn test_segments() {
+        let packed = |path: &str, offset, len| IndexRecord { segment: Some((offset, len)), ..record(path, len) };
+        let payload = build_payload(vec![
+            (vec![packed("a", 0, 2), packed("b", 2, 3)], b"segment".to_vec()),
+            (vec![record("big", 9)], b"big".to_vec()),
+        ], 0);
+        let index = read_index(&payload, &header(FLAG_PACKED_INDEX | FLAG_SEGMENTS)).unwrap();
+        assert!(index.segments);
+        assert_eq!(index.records[0].offset, index.records[1].offset);
+        assert_eq!(entry_blob(&payload, &index.records[1]), b"segment");
+        assert_eq!(segment_bytes(b"aabbb", &index.records[1]).unwrap(), b"bbb");
+        assert_eq!(segment_bytes(b"whole", &index.records[2]).unwrap(), b"whole");
+        assert!(segment_bytes(b"aab", &index.records[1]).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_index() {
+        let mut payload = build_payload(vec![(vec![record("a", 1)], b"x".to_vec())], 0);
+        assert!(read_index(&payload[..4], &header(FLAG_PACKED_INDEX)).is_err());
+        let len = payload.len();
+        payload[len - 8] = 0xFF;
+        assert!(read_index(&payload, &header(FLAG_PACKED_INDEX)).is_err());
+
+        // Общий префикс пути не может разрывать символ предыдущего пути или быть у первой записи
+        let index = |paths: &[&str]| EntryIndex { records: paths.iter().map(|p| record(p, 0)).collect(), mtimes: true, ..Default::default() }.to_bytes();
+        let mut raw = index(&["é", "éa"]);
+        raw.extend_from_slice(&0u64.to_le_bytes());
+        assert_eq!(read_index(&raw, &header(0)).unwrap().records[1].path, "éa");
+        let second = index(&["é"]).len();
+        assert_eq!(raw[second], 2);
+        raw[second] = 1;
+        assert!(read_index(&raw, &header(0)).is_err());
+        raw[second] = 2;
+        raw[4] = 1;
+        assert!(read_index(&raw, &header(0)).is_err());
+        raw[4] = 0;
+        // Байт наличия времени изменения может быть только 0 или 1
+        raw[second - 1] = 2;
+        assert!(read_index(&raw, &header(0)).is_err());
+    }
+
+    #[test]
+    fn test_block_table() {
+        let mut framed = record("big", 10);
+        framed.crc32 = Some(7);
+        framed.blocks = vec![2, 3, 1];
+        framed.block_lengths = vec![4, 2, 4];
+        framed.owner = Some(Owner { uid: 1000, gid: 100, user: Some("user".into()), group: None });
+        framed.metadata.insert("origin".into(), "https://example.com/big".into());
+        let payload = build_payload(vec![(vec![framed.clone()], b"aabbbc".to_vec())], 4);
+        let index = read_index(&payload, &header(FLAG_BLOCKS | FLAG_OWNERS | FLAG_PACKED_INDEX)).unwrap();
+        assert_eq!(index.block_size, 4);
+        assert_eq!(index.records[0].crc32, Some(7));
+        assert_eq!(index.records[0].owner, framed.owner);
+        assert_eq!(index.records[0].metadata, framed.metadata);
+        assert_eq!(block_blob(&payload, &index.records[0], 1), b"bbb");
+        assert_eq!(block_blob(&payload, &index.records[0], 2), b"c");
+        // Блоки разной длины: 0..4, 4..6 и 6..10
+        assert_eq!(index.records[0].block_lengths, [4, 2, 4]);
+        assert_eq!(covering_blocks(&index.records[0], 0..1), (0, 0, 0));
+        assert_eq!(covering_blocks(&index.records[0], 3..5), (0, 1, 0));
+        assert_eq!(covering_blocks(&index.records[0], 5..10), (1, 2, 4));
+        assert_eq!(covering_blocks(&index.records[0], 6..7), (2, 2, 6));
+
+        // Длины блоков должны складываться в размер файла
+        let mut wrong = record("big", 20);
+        wrong.blocks = vec![6];
+        wrong.block_lengths = vec![10];
+        assert!(read_index(&build_payload(vec![(vec![wrong.clone()], b"aabbbc".to_vec())], 4), &header(FLAG_BLOCKS | FLAG_OWNERS | FLAG_PACKED_INDEX)).is_err());
+        wrong.blocks = vec![3, 3];
+        wrong.block_lengths = vec![20, 0];
+        assert!(read_index(&build_payload(vec![(vec![wrong], b"aabbbc".to_vec())], 4), &header(FLAG_BLOCKS | FLAG_OWNERS | FLAG_PACKED_INDEX)).is_err());
+
+        // До версии 11 длины блоков вычисляются по базовому размеру
+        let mut old = b"aabbbc".to_vec();
+        old.extend(EntryIndex { adaptive_blocks: false, ..index }.to_bytes());
+        old.extend_from_slice(&6u64.to_le_bytes());
+        let previous = read_index(&old, &Header { version: 10, ..header(FLAG_BLOCKS | FLAG_OWNERS) }).unwrap();
+        assert_eq!(previous.records[0].block_lengths, [4, 4, 2]);
+    }
+}
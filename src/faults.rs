@@ -0,0 +1,113 @@
+//! Модуль для имитации сбоев ввода/вывода в тестах.
+//!
+//! `FailingWriter` и `FailingReader` оборачивают поток и возвращают ошибку (ENOSPC, EIO)
+//! после заданного числа байтов, а `FailingReader` может также отдавать данные короткими
+//! порциями. Так тесты проверяют, что сбой в любом месте приводит к понятной ошибке,
+//! а не к панике, и что незавершенные выходные файлы удаляются.
+
+use std::io::{self, Read, Write};
+
+/// Поток записи, который принимает `budget` байтов, а затем возвращает ошибку.
+pub struct FailingWriter<W: Write> {
+    /// Обернутый поток
+    inner: W,
+    /// Сколько байтов еще можно записать
+    budget: usize,
+    /// Код ошибки ОС, которая возвращается после исчерпания `budget`
+    errno: i32,
+}
+
+impl<W: Write> FailingWriter<W> {
+    /// Создает поток, на котором после `budget` байтов кончается место (ENOSPC).
+    pub fn enospc(inner: W, budget: usize) -> Self {
+        FailingWriter { inner, budget, errno: libc::ENOSPC }
+    }
+
+    /// Создает поток, который после `budget` байтов возвращает ошибку устройства (EIO).
+    pub fn eio(inner: W, budget: usize) -> Self {
+        FailingWriter { inner, budget, errno: libc::EIO }
+    }
+
+    /// Возвращает обернутый поток.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for FailingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.budget == 0 && !buf.is_empty() {
+            return Err(io::Error::from_raw_os_error(self.errno));
+        }
+        // Как настоящий диск, запись до ошибки может быть частичной
+        let written = self.inner.write(&buf[..buf.len().min(self.budget)])?;
+        self.budget -= written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Поток чтения, который отдает данные порциями не больше `chunk` байтов
+/// и после `budget` байтов (если он задан) возвращает ошибку EIO.
+pub struct FailingReader<R: Read> {
+    /// Обернутый поток
+    inner: R,
+    /// Сколько байтов еще можно прочитать до ошибки
+    budget: Option<usize>,
+    /// Наибольший размер одной порции
+    chunk: usize,
+}
+
+impl<R: Read> FailingReader<R> {
+    /// Создает поток, который после `budget` байтов возвращает ошибку устройства (EIO).
+    pub fn eio(inner: R, budget: usize) -> Self {
+        FailingReader { inner, budget: Some(budget), chunk: usize::MAX }
+    }
+
+    /// Создает поток без ошибок, который отдает данные порциями не больше `chunk` байтов.
+    pub fn short_reads(inner: R, chunk: usize) -> Self {
+        FailingReader { inner, budget: None, chunk: chunk.max(1) }
+    }
+}
+
+impl<R: Read> Read for FailingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let limit = buf.len().min(self.chunk).min(self.budget.unwrap_or(usize::MAX));
+        if limit == 0 && !buf.is_empty() {
+            return Err(io::Error::from_raw_os_error(libc::EIO));
+        }
+        let read = self.inner.read(&mut buf[..limit])?;
+        if let Some(budget) = &mut self.budget {
+            *budget -= read;
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failing_streams() {
+        let mut writer = FailingWriter::enospc(Vec::new(), 5);
+        assert_eq!(writer.write(b"abc").unwrap(), 3);
+        let error = writer.write_all(b"defgh").unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::ENOSPC));
+        assert_eq!(writer.into_inner(), b"abcde");
+
+        let mut reader = FailingReader::short_reads(&b"0123456789"[..], 3);
+        let mut buffer = [0u8; 8];
+        assert_eq!(reader.read(&mut buffer).unwrap(), 3);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"3456789");
+
+        let mut rest = Vec::new();
+        let error = FailingReader::eio(&b"0123456789"[..], 4).read_to_end(&mut rest).unwrap_err();
+        assert_eq!((error.raw_os_error(), rest.as_slice()), (Some(libc::EIO), &b"0123"[..]));
+    }
+}
@@ -0,0 +1,222 @@
+//! Модуль для отбора записей архива по типу и правам доступа.
+//!
+//! Тип записи определяется по битам типа в сохраненном режиме файла (`st_mode`),
+//! а условие на права записывается так же, как в `find -perm`: `/MODE` — установлен
+//! хотя бы один из битов, `-MODE` — установлены все биты, `MODE` — права совпадают точно.
+//! Например, `--perm /4000` находит файлы с битом setuid.
+
+use std::io;
+use crate::io::DirEntry;
+
+/// Маска битов типа файла.
+const S_IFMT: u32 = 0o170000;
+/// Директория.
+const S_IFDIR: u32 = 0o040000;
+/// Символическая ссылка.
+const S_IFLNK: u32 = 0o120000;
+/// Именованный канал (FIFO).
+const S_IFIFO: u32 = 0o010000;
+/// Символьное устройство.
+const S_IFCHR: u32 = 0o020000;
+/// Блочное устройство.
+const S_IFBLK: u32 = 0o060000;
+
+/// Тип записи архива.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryType {
+    /// Обычный файл (`f`)
+    File,
+    /// Директория (`d`)
+    Dir,
+    /// Символическая ссылка (`l`)
+    Symlink,
+    /// Именованный канал (`p`)
+    Fifo,
+    /// Символьное устройство (`c`)
+    CharDevice,
+    /// Блочное устройство (`b`)
+    BlockDevice,
+}
+
+impl EntryType {
+    /// Определяет тип по букве из командной строки (`f`, `d`, `l`, `p`, `c`, `b`).
+    pub fn from_letter(letter: &str) -> Option<EntryType> {
+        match letter {
+            "f" => Some(EntryType::File),
+            "d" => Some(EntryType::Dir),
+            "l" => Some(EntryType::Symlink),
+            "p" => Some(EntryType::Fifo),
+            "c" => Some(EntryType::CharDevice),
+            "b" => Some(EntryType::BlockDevice),
+            _ => None,
+        }
+    }
+
+    /// Определяет тип по режиму файла.
+    ///
+    /// Обычные файлы, а также записи без битов типа (например, из архивов, созданных
+    /// на других системах), считаются файлами.
+    pub fn from_mode(mode: u32) -> EntryType {
+        match mode & S_IFMT {
+            S_IFDIR => EntryType::Dir,
+            S_IFLNK => EntryType::Symlink,
+            S_IFIFO => EntryType::Fifo,
+            S_IFCHR => EntryType::CharDevice,
+            S_IFBLK => EntryType::BlockDevice,
+            _ => EntryType::File,
+        }
+    }
+
+    /// Возвращает `true` для специальных файлов: каналов и устройств, у которых нет содержимого.
+    pub fn is_special(&self) -> bool {
+        matches!(self, EntryType::Fifo | EntryType::CharDevice | EntryType::BlockDevice)
+    }
+
+    /// Возвращает имя типа для машиночитаемого вывода (`file`, `dir`, `symlink`, `fifo`,
+    /// `char_device`, `block_device`).
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryType::File => "file",
+            EntryType::Dir => "dir",
+            EntryType::Symlink => "symlink",
+            EntryType::Fifo => "fifo",
+            EntryType::CharDevice => "char_device",
+            EntryType::BlockDevice => "block_device",
+        }
+    }
+}
+
+/// Возвращает режим файла в виде `ls -l`, например `drwxr-xr-x`.
+pub fn mode_string(mode: u32) -> String {
+    let mut text = String::with_capacity(10);
+    text.push(match EntryType::from_mode(mode) {
+        EntryType::File => '-',
+        EntryType::Dir => 'd',
+        EntryType::Symlink => 'l',
+        EntryType::Fifo => 'p',
+        EntryType::CharDevice => 'c',
+        EntryType::BlockDevice => 'b',
+    });
+    // Для каждой тройки: бит setuid, setgid или sticky и символ, которым он показывается
+    for (shift, special, letter) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        text.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        text.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => letter,
+            (false, true) => letter.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    text
+}
+
+/// Условие на права доступа.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PermMatch {
+    /// Права совпадают точно
+    Exact(u32),
+    /// Установлены все указанные биты
+    All(u32),
+    /// Установлен хотя бы один из указанных битов
+    Any(u32),
+}
+
+impl PermMatch {
+    /// Разбирает условие в синтаксисе `find -perm` с восьмеричным режимом.
+    ///
+    /// # Аргументы
+    ///
+    /// * `spec` - Строка вида `/4000`, `-0755` или `644`.
+    ///
+    /// # Возвращает
+    ///
+    /// Условие или ошибку при неверной записи.
+    pub fn parse(spec: &str) -> io::Result<PermMatch> {
+        let (kind, digits): (fn(u32) -> PermMatch, &str) = match spec.as_bytes().first() {
+            Some(b'/') => (PermMatch::Any, &spec[1..]),
+            Some(b'-') => (PermMatch::All, &spec[1..]),
+            _ => (PermMatch::Exact, spec),
+        };
+        u32::from_str_radix(digits, 8).ok()
+            .filter(|mode| !digits.is_empty() && *mode <= 0o7777)
+            .map(kind)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid permission filter: {}", spec)))
+    }
+
+    /// Проверяет права доступа (младшие 12 битов режима).
+    pub fn matches(&self, mode: u32) -> bool {
+        let perm = mode & 0o7777;
+        match *self {
+            PermMatch::Exact(m) => perm == m,
+            PermMatch::All(m) => perm & m == m,
+            PermMatch::Any(m) => m == 0 || perm & m != 0,
+        }
+    }
+}
+
+/// Фильтр записей архива; пустой фильтр пропускает все записи.
+#[derive(Debug, Default, Clone)]
+pub struct EntryFilter {
+    /// Допустимый тип записи
+    pub entry_type: Option<EntryType>,
+    /// Условие на права доступа
+    pub perm: Option<PermMatch>,
+}
+
+impl EntryFilter {
+    /// Возвращает `true`, если фильтр не задает ни одного условия.
+    pub fn is_empty(&self) -> bool {
+        self.entry_type.is_none() && self.perm.is_none()
+    }
+
+    /// Проверяет режим файла (тип и права доступа) по условиям фильтра.
+    pub fn matches_mode(&self, mode: u32) -> bool {
+        self.entry_type.is_none_or(|t| EntryType::from_mode(mode) == t)
+            && self.perm.is_none_or(|p| p.matches(mode))
+    }
+
+    /// Возвращает `true`, если запись удовлетворяет всем условиям фильтра.
+    pub fn matches(&self, entry: &DirEntry) -> bool {
+        self.matches_mode(entry.permissions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn entry(permissions: u32) -> DirEntry {
+        DirEntry { path: "x".to_owned(), data: Vec::new(), permissions, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None }
+    }
+
+    #[test]
+    fn test_perm_match() {
+        assert_eq!(PermMatch::parse("/4000").unwrap(), PermMatch::Any(0o4000));
+        assert_eq!(PermMatch::parse("-0755").unwrap(), PermMatch::All(0o755));
+        assert_eq!(PermMatch::parse("644").unwrap(), PermMatch::Exact(0o644));
+        assert!(PermMatch::parse("/").is_err());
+        assert!(PermMatch::parse("89").is_err());
+        assert!(PermMatch::parse("/4000").unwrap().matches(0o104755));
+        assert!(!PermMatch::parse("/4000").unwrap().matches(0o100755));
+        assert!(PermMatch::parse("-0750").unwrap().matches(0o100755));
+        assert!(!PermMatch::parse("644").unwrap().matches(0o100755));
+    }
+
+    #[test]
+    fn test_entry_filter() {
+        let setuid = EntryFilter { entry_type: Some(EntryType::File), perm: Some(PermMatch::Any(0o4000)) };
+        assert!(setuid.matches(&entry(0o104755)));
+        assert!(!setuid.matches(&entry(0o100755)));
+        assert!(!setuid.matches(&entry(0o044755)));
+        assert!(EntryFilter::default().matches(&entry(0o120777)));
+        let links = EntryFilter { entry_type: Some(EntryType::Symlink), perm: None };
+        assert!(links.matches(&entry(0o120777)));
+        assert!(!links.matches(&entry(0o100644)));
+        assert_eq!(mode_string(0o104755), "-rwsr-xr-x");
+        assert_eq!(mode_string(0o041776), "drwxrwxrwT");
+        assert_eq!(mode_string(0o120777), "lrwxrwxrwx");
+    }
+}
@@ -0,0 +1,212 @@
+//! Спецификация двоичного формата архива.
+//!
+//! Все поля формата записываются одним способом: целые числа фиксированной длины — в порядке
+//! little-endian, длины и количества — в формате varint (LEB128: по 7 битов в байте, младшие
+//! группы первыми, старший бит байта означает продолжение), строки — длиной в формате varint
+//! и байтами UTF-8. Формат версионируется одним числом — версией в заголовке
+//! (`header::FORMAT_VERSION`); возможности внутри версии отмечаются флагами заголовка.
+//!
+//! # Архив
+//!
+//! Архив состоит из одного или нескольких членов, записанных подряд (`cat a.arc b.arc`).
+//! За членами может следовать запись восстановления с данными четности (`--recovery`,
+//! сигнатура `RREC`, устройство описано в модуле `recovery`); при чтении она пропускается.
+//! Член архива:
+//!
+//! | Поле | Размер | Содержимое |
+//! |------|--------|------------|
+//! | сигнатура | 4 | `RARC` (`header::ARCHIVE_MAGIC`) |
+//! | версия | 1 | версия формата, сейчас 11 |
+//! | алгоритм | 1 | 1 — RLE, 2 — LZ77, 3 — LZ4, 4 — LZW, 5 — Хаффман |
+//! | флаги | 1 | `FLAG_SHORT_MATCHES`, `FLAG_PER_ENTRY`, `FLAG_BLOCKS`, `FLAG_OWNERS`, `FLAG_INFO`, `FLAG_PACKED_INDEX`, `FLAG_SEGMENTS`, `FLAG_CHUNKED` |
+//! | устройство данных | 1 | `LAYOUT_LITTLE_ENDIAN` (начиная с версии 8) |
+//! | длина данных | 8 | `u64`, число байтов данных после заголовка |
+//! | данные | длина данных | блок полей (начиная с версии 9), раздел сведений (с `FLAG_INFO`) и сжатые данные |
+//! | трейлер | 32 | SHA-256 данных (начиная с версии 2) |
+//!
+//! Раздел сведений (`info::ArchiveInfo`) начинается со своей длины в формате varint, за которой
+//! следуют время создания (varint, секунды от начала эпохи Unix), имя компьютера, версия
+//! программы и комментарий (строки; пустой комментарий означает его отсутствие), а затем
+//! необязательные параметры сжатия (`processing::CompressionOptions`): их длина (varint),
+//! идентификатор алгоритма, байт флагов (0x01 — `-m`, 0x02 — `--short-matches`, 0x04 — `--solid`,
+//! 0x08 — `--chunk-dedup`), размер блока и порог упаковки (varint, 0 — не задан); нулевая длина
+//! означает, что параметры не записаны. За ними может следовать скорость распаковки, измеренная
+//! при создании архива (varint, байтов исходных данных в секунду; 0 — не измерена), а за ней —
+//! выбор алгоритма по замеру (`--target-speed`, `--target-ratio`; строка).
+//!
+//! # Поля заголовка
+//!
+//! Начиная с версии 9 данные начинаются с блока полей (`header::write_fields`): длина блока
+//! (varint), затем для каждого поля его тип (varint), длина значения (varint) и значение.
+//! Читатель пропускает поля неизвестных ему типов, поэтому новые необязательные сведения
+//! добавляются без новой версии формата, и старые программы по-прежнему распаковывают такие
+//! архивы. Поле с битом `header::FIELD_CRITICAL` в типе меняет смысл данных: если его тип
+//! неизвестен, архив отклоняется с просьбой обновить программу. Сейчас поля не записываются.
+//!
+//! Числовые идентификаторы в формате закреплены и не меняются между версиями: алгоритмы
+//! (`processing::Algorithm`, 1–5), флаги заголовка, байт устройства данных, способы хранения
+//! потока и виды записей — биты типа файла `S_IFMT` в правах записи.
+//!
+//! # Сжатые данные
+//!
+//! Без `FLAG_PER_ENTRY` сжатые данные — один поток выбранного алгоритма, который
+//! распаковывается в набор записей (см. ниже) или, для архивов `--index-only`, в индекс
+//! `archive::ArchiveIndex` с сигнатурой `ARIX`. С `FLAG_CHUNKED` (только без `FLAG_PER_ENTRY`)
+//! поток распаковывается в последовательность новых блоков и ссылок на ранее сохраненные
+//! (см. модуль `chunking`), из которой собираются эти данные; блоки выбираются по содержимому
+//! (`--chunk-dedup`) или кадрами, повторяющими ранее записанные (модуль `frames`).
+//!
+//! С `FLAG_PER_ENTRY` каждая запись сжата отдельным потоком; за потоками следуют центральный
+//! индекс (`entry_index::EntryIndex`) и `u64` со смещением индекса от начала сжатых данных.
+//! Без `FLAG_BLOCKS` поток записи распаковывается в байты одной записи, а с `FLAG_BLOCKS`
+//! содержимое файла разбито на блоки, сжатые независимо, и их сжатые размеры хранятся в индексе.
+//! Начиная с версии 11 за сжатым размером каждого блока следует `u64` его исходная длина:
+//! размер блока подбирается по энтропии данных вокруг базового `block_size` (см. модуль `blocks`). Путь в записи индекса хранится относительно пути предыдущей
+//! записи: `u32` длина общего префикса (в байтах, не разрывая символы UTF-8), `u32` длина
+//! окончания и окончание. С `FLAG_PACKED_INDEX` байты индекса сжаты LZ4 (а смещение
+//! указывает на начало сжатого индекса); без него индекс записан как есть.
+//!
+//! Начиная с версии 10 за исходным размером каждой записи индекса следует байт наличия
+//! времени изменения; если он равен 1, за ним идет `u64` время изменения файла в секундах
+//! от начала эпохи Unix.
+//!
+//! С `FLAG_SEGMENTS` (только без `FLAG_BLOCKS`) за исходным размером (и временем изменения)
+//! каждой записи индекса следует байт наличия сегмента; если он равен 1, за ним идут `u64` смещение и `u64` длина
+//! байтов записи в распакованном потоке. Такой поток — сегмент: байты нескольких записей
+//! подряд, сжатые вместе, и смещение и сжатый размер в индексе у этих записей общие.
+//!
+//! Начиная с версии 7 каждый поток записи, блока или сегмента начинается с байта способа
+//! хранения: 0 — далее поток алгоритма, 1 — далее исходные байты, которые при сжатии
+//! не уменьшились. Сжатые размеры в индексе учитывают этот байт.
+//!
+//! Потоки алгоритмов устроены каждый по-своему; числа в них (например, длины в потоке
+//! Хаффмана) записываются в порядке байтов, объявленном байтом устройства данных, — сейчас
+//! всегда little-endian. Архивы без заголовка, которые по-прежнему распаковываются с `-a`,
+//! хранят эти числа в порядке big-endian.
+//!
+//! # Набор записей
+//!
+//! | Поле | Размер | Содержимое |
+//! |------|--------|------------|
+//! | сигнатура | 4 | `RDIP` (`io::PREFIX_CONTAINER_MAGIC`) |
+//! | количество | varint | число записей |
+//! | записи | | для каждой записи: длина префикса пути, общего с предыдущей записью (varint), длина записи (varint) и байты записи |
+//!
+//! Поле пути в записи набора содержит только окончание пути после общего префикса;
+//! префикс считается в байтах и не разрывает символы UTF-8.
+//!
+//! Запись (`io::DirEntry`):
+//!
+//! | Поле | Размер | Содержимое |
+//! |------|--------|------------|
+//! | права | 4 | `u32`, режим файла; бит `io::HARD_LINK_FLAG` отмечает жесткую ссылку, бит `io::DUPLICATE_FLAG` — дубликат |
+//! | путь | varint + N | относительный путь |
+//! | данные | varint + N | содержимое файла, цель символической или жесткой ссылки, путь исходной записи дубликата; у канала пусто, у устройства — `u64` номер устройства |
+//! | CRC32 | 4 | `u32`, контрольная сумма данных |
+//! | владелец | | UID и GID (varint), имена пользователя и группы (строки) |
+//! | метаданные | | количество пар (varint), затем ключи и значения (строки) |
+//! | время изменения | varint | секунды от начала эпохи Unix (начиная с версии 10) |
+//!
+//! Последние четыре поля необязательны и записываются по порядку: поле присутствует, если
+//! задано оно или любое следующее. Отсутствующий владелец перед метаданными записывается
+//! как UID `2^32`, который не помещается в `u32`.
+//!
+//! # Старые версии
+//!
+//! До версии 11 таблица блоков хранит только сжатые размеры, а все блоки, кроме последнего,
+//! имеют исходную длину `block_size`.
+//! До версии 10 записи и центральный индекс не хранят время изменения.
+//! До версии 9 в данных нет блока полей.
+//! До версии 8 в заголовке нет байта устройства данных (заголовок занимает 15 байтов),
+//! а числа в потоках алгоритмов записаны в порядке big-endian.
+//! До версии 6 в архивах нет дубликатов (`io::DUPLICATE_FLAG`), а до версии 7 — байтов способа хранения.
+//! В версии 4 путь в записи центрального индекса хранится полностью: `u32` длина и байты пути.
+//! В версии 3 набор записей начинается с сигнатуры `RDIR` (`io::CONTAINER_MAGIC`), перед
+//! записями нет длины общего префикса, а каждая запись хранит полный путь. В версиях 1 и 2 набор записей не имеет сигнатуры, а количество записей, их размеры
+//! и длины пути и данных хранятся в `u32`. В версии 1 нет трейлера с SHA-256.
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use crate::info::ArchiveInfo;
+    use crate::io::{self, DirEntry, HARD_LINK_FLAG};
+    use crate::owner::Owner;
+    use crate::processing::{self, Algorithm};
+    use crate::ArchiveData;
+
+    /// Записи со всеми сочетаниями необязательных полей.
+    fn sample_entries() -> Vec<DirEntry> {
+        let entry = |path: &str, data: &[u8], permissions: u32| DirEntry {
+            path: path.into(),
+            data: data.to_vec(),
+            permissions,
+            crc32: None,
+            owner: None,
+            metadata: BTreeMap::new(),
+            mtime: None,
+        };
+        let owner = Owner { uid: 1000, gid: 100, user: Some("alice".into()), group: None };
+        let metadata = BTreeMap::from([("content-type".to_owned(), "text/plain".to_owned())]);
+        let mut entries = vec![
+            entry("docs", b"", 0o40755),
+            entry("docs/plain.txt", b"plain text plain text plain text", 0o100644),
+            DirEntry { owner: Some(owner.clone()), ..entry("docs/owned.bin", &[7; 3000], 0o100600) },
+            DirEntry { metadata: metadata.clone(), ..entry("docs/tagged.txt", b"tagged", 0o100644) },
+            DirEntry { owner: Some(owner.clone()), metadata: metadata.clone(), ..entry("docs/both.txt", "юникод".as_bytes(), 0o100640) },
+            DirEntry { mtime: Some(1_700_000_000), ..entry("docs/dated.txt", b"dated", 0o100644) },
+            DirEntry { owner: Some(owner), metadata, mtime: Some(0), ..entry("docs/all.txt", b"all fields", 0o100600) },
+            entry("docs/link", b"plain.txt", 0o120777),
+            entry("docs/hard.txt", b"docs/plain.txt", 0o100644 | HARD_LINK_FLAG),
+            // Общие байты префикса обрываются посреди символа: «ж» и «л» начинаются с 0xD0
+            entry("docs/ёж.txt", b"1", 0o100644),
+            entry("docs/ёлка.txt", b"2", 0o100644),
+        ];
+        io::fill_checksums(&mut entries);
+        entries
+    }
+
+    #[test]
+    fn test_container_round_trip() {
+        let entries = sample_entries();
+        for e in &entries {
+            assert_eq!(&io::bytes_to_dir_entry(&io::dir_entry_to_bytes(e)).unwrap(), e);
+        }
+        let archive = ArchiveData { entries };
+        let bytes = io::archive_data_to_bytes(&archive);
+        assert!(bytes.starts_with(io::PREFIX_CONTAINER_MAGIC));
+        assert_eq!(io::bytes_to_archive_data(&bytes, true).unwrap(), archive);
+        assert!(io::bytes_to_archive_data(&bytes[..bytes.len() - 1], true).is_err());
+
+        // Набор версии 3 с полными путями по-прежнему читается
+        let mut full = io::CONTAINER_MAGIC.to_vec();
+        io::write_varint(&mut full, archive.entries.len() as u64);
+        for e in &archive.entries {
+            let entry = io::dir_entry_to_bytes(e);
+            io::write_varint(&mut full, entry.len() as u64);
+            full.extend(entry);
+        }
+        assert!(full.len() > bytes.len());
+        assert_eq!(io::bytes_to_archive_data(&full, true).unwrap(), archive);
+    }
+
+    #[test]
+    fn test_archive_round_trip() {
+        let entries = sample_entries();
+        let info = ArchiveInfo::current(Some("round trip".into()));
+        for algorithm in Algorithm::ALL {
+            let archives = [
+                processing::compress(&io::archive_data_to_bytes(&ArchiveData { entries: entries.clone() }),
+                    algorithm.clone(), false, false),
+                processing::compress_entries(&entries, algorithm.clone(), false, false, None, None),
+                processing::compress_entries(&entries, algorithm.clone(), false, false, Some(1024), None),
+                processing::compress_entries(&entries, algorithm.clone(), false, false, None, Some(usize::MAX)),
+            ];
+            for archive in archives {
+                let archive = processing::set_info(&archive, &info).unwrap();
+                let payload = processing::decompress(&archive, None, false, false, true).unwrap();
+                let parsed = io::bytes_to_archive_data(&payload, true).unwrap();
+                assert_eq!(parsed.entries, entries, "{}", algorithm.name());
+            }
+        }
+    }
+}
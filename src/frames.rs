@@ -0,0 +1,145 @@
+//! Модуль для дедупликации кадров общего потока сплошного архива.
+//!
+//! Поток записывается кадрами по `FRAME` байтов, и каждый записанный кадр попадает в индекс
+//! по полиномиальному хешу. Перед записью очередного байта скользящий хеш следующих `FRAME`
+//! байтов ищется в индексе; при совпадении (после сравнения байтов) кадр заменяется ссылкой
+//! на ранее записанный. Так второй экземпляр файла в общем потоке сохраняется ссылками
+//! независимо от смещения, на котором он начинается, — без разбиения всего потока на блоки
+//! по содержимому (`--chunk-dedup`), а выигрыш не ограничен окном алгоритма сжатия.
+//!
+//! Результат записывается в формате потока модуля `chunking` (новые блоки и ссылки на них)
+//! и восстанавливается `chunking::reassemble`, поэтому в заголовке архива он отмечается
+//! тем же флагом `FLAG_CHUNKED`.
+
+use std::collections::HashMap;
+use crate::io::write_varint;
+
+/// Размер кадра: повторы короче двух кадров могут не найтись.
+pub const FRAME: usize = 1024;
+
+/// Основание полиномиального хеша.
+const BASE: u64 = 0x100_0000_01B3;
+
+/// Число битов фильтра, отсеивающего позиции без кадра в индексе до поиска в нем.
+const FILTER_BITS: u32 = 22;
+
+/// Вычисляет хеш кадра.
+fn frame_hash(frame: &[u8]) -> u64 {
+    frame.iter().fold(0u64, |hash, &byte| hash.wrapping_mul(BASE).wrapping_add(byte as u64 + 1))
+}
+
+/// Индекс записанных кадров: хеш — начало кадра в исходных данных и номер блока в потоке.
+struct FrameIndex {
+    filter: Vec<u64>,
+    frames: HashMap<u64, (usize, u64)>,
+}
+
+impl FrameIndex {
+    fn new() -> Self {
+        FrameIndex { filter: vec![0; 1 << (FILTER_BITS - 6)], frames: HashMap::new() }
+    }
+
+    fn insert(&mut self, hash: u64, start: usize, number: u64) {
+        let bit = (hash >> (64 - FILTER_BITS)) as usize;
+        self.filter[bit / 64] |= 1 << (bit % 64);
+        self.frames.entry(hash).or_insert((start, number));
+    }
+
+    fn get(&self, hash: u64) -> Option<(usize, u64)> {
+        let bit = (hash >> (64 - FILTER_BITS)) as usize;
+        if self.filter[bit / 64] & (1 << (bit % 64)) == 0 {
+            return None;
+        }
+        self.frames.get(&hash).copied()
+    }
+}
+
+/// Заменяет кадры, повторяющие ранее записанные, ссылками на них.
+///
+/// # Аргументы
+///
+/// * `data` - Исходные данные.
+///
+/// # Возвращает
+///
+/// Закодированный поток (см. модуль `chunking`) или `None`, если ни один кадр не повторился
+/// и данные лучше сжимать как есть.
+pub fn deduplicate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut index = FrameIndex::new();
+    // Номер следующего блока потока: он растет на каждый записанный новый блок
+    let mut stored = 0u64;
+    let emit = |output: &mut Vec<u8>, literal: &[u8], stored: &mut u64| {
+        if !literal.is_empty() {
+            write_varint(output, (literal.len() as u64) << 1);
+            output.extend_from_slice(literal);
+            *stored += 1;
+        }
+    };
+    // Множитель байта, выходящего из окна скользящего хеша
+    let leading = (1..FRAME).fold(1u64, |power, _| power.wrapping_mul(BASE));
+    let mut repeated = false;
+    let mut literal_start = 0;
+    let mut literal_hash = 0;
+    let mut pos = 0;
+    let mut hash = None;
+    while pos + FRAME <= data.len() {
+        let current = *hash.get_or_insert_with(|| frame_hash(&data[pos..pos + FRAME]));
+        if pos == literal_start {
+            literal_hash = current;
+        }
+        if let Some((_, number)) = index.get(current).filter(|&(start, _)| data[start..start + FRAME] == data[pos..pos + FRAME]) {
+            emit(&mut output, &data[literal_start..pos], &mut stored);
+            write_varint(&mut output, number << 1 | 1);
+            repeated = true;
+            pos += FRAME;
+            literal_start = pos;
+            hash = None;
+            continue;
+        }
+        if pos + FRAME < data.len() {
+            let outgoing = (data[pos] as u64 + 1).wrapping_mul(leading);
+            hash = Some(current.wrapping_sub(outgoing).wrapping_mul(BASE).wrapping_add(data[pos + FRAME] as u64 + 1));
+        }
+        pos += 1;
+        // Накопленный кадр новых байтов записывается и становится доступен для ссылок
+        if pos - literal_start == FRAME {
+            index.insert(literal_hash, literal_start, stored);
+            emit(&mut output, &data[literal_start..pos], &mut stored);
+            literal_start = pos;
+        }
+    }
+    emit(&mut output, &data[literal_start..], &mut stored);
+    repeated.then_some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunking::reassemble;
+
+    #[test]
+    fn test_deduplicate_frames() {
+        let mut state = 99u32;
+        let file: Vec<u8> = (0..20_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        // Вторая копия начинается не на границе кадра первой
+        let mut data = b"header of the first file".to_vec();
+        data.extend_from_slice(&file);
+        data.extend_from_slice(b"metadata between the copies");
+        data.extend_from_slice(&file);
+        let encoded = deduplicate(&data).unwrap();
+        assert!(encoded.len() < file.len() + 3 * FRAME, "{} bytes", encoded.len());
+        assert_eq!(reassemble(&encoded).unwrap(), data);
+
+        // Скользящий хеш совпадает с хешем кадра, вычисленным заново
+        assert_eq!(reassemble(&deduplicate(&vec![7u8; 10 * FRAME + 5]).unwrap()).unwrap(), vec![7u8; 10 * FRAME + 5]);
+
+        // Без повторов кадров поток не меняется
+        assert!(deduplicate(&file).is_none());
+        assert!(deduplicate(&file[..FRAME / 2]).is_none());
+        assert!(deduplicate(b"").is_none());
+    }
+}
@@ -0,0 +1,259 @@
+//! Модуль для исключения файлов из архива по шаблонам.
+//!
+//! Шаблоны записываются так же, как в `tar --exclude` и `rsync`: `*` — любая
+//! последовательность символов внутри имени, `?` — один символ, `[a-z]` и `[!a-z]` —
+//! класс символов, `**` — любое число уровней директорий, `\` экранирует следующий символ.
+//! Шаблон без `/` сравнивается с именем файла или директории на любом уровне
+//! (`*.o`, `target`), шаблон с `/` — с относительным путем от корня (`docs/*.pdf`).
+//! Шаблон, оканчивающийся на `/`, исключает только директории. Исключенная
+//! директория не обходится; шаблон, оканчивающийся на `/**` (`target/**`), исключает
+//! и саму директорию, чтобы не обходить дерево, все файлы которого все равно исключены.
+//!
+//! Файлы `.archiverignore` и `.gitignore` разбираются так же (`add_ignore_lines`), но
+//! в них, как в git, шаблон с `!` возвращает исключенный ранее путь, а решение принимает
+//! последний подходящий шаблон.
+
+use std::fs;
+use std::io;
+
+/// Один шаблон исключения.
+#[derive(Debug, Clone, PartialEq)]
+struct Pattern {
+    /// Символы шаблона без ведущего и завершающего `/`
+    glob: Vec<char>,
+    /// Шаблон сравнивается с полным относительным путем, а не с именем
+    anchored: bool,
+    /// Шаблон относится только к директориям
+    dir_only: bool,
+    /// Шаблон возвращает путь, исключенный предыдущими шаблонами (`!` в файле игнорирования)
+    negated: bool,
+}
+
+impl Pattern {
+    /// Проверяет относительный путь по шаблону.
+    fn matches(&self, rel_path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let subject = if self.anchored { rel_path } else { rel_path.rsplit('/').next().unwrap_or(rel_path) };
+        let subject: Vec<char> = subject.chars().collect();
+        if is_dir && self.glob.ends_with(&['/', '*', '*']) && glob_match(&self.glob[..self.glob.len() - 3], &subject) {
+            return true;
+        }
+        glob_match(&self.glob, &subject)
+    }
+}
+
+/// Набор шаблонов исключения; пустой набор ничего не исключает.
+#[derive(Debug, Default, Clone)]
+pub struct ExcludeSet {
+    /// Шаблоны в порядке добавления
+    patterns: Vec<Pattern>,
+}
+
+impl ExcludeSet {
+    /// Добавляет шаблон (значение `--exclude`).
+    pub fn add(&mut self, pattern: &str) {
+        self.push(pattern, false);
+    }
+
+    /// Добавляет шаблон; `negated` — шаблон возвращает путь, а не исключает его.
+    fn push(&mut self, pattern: &str, negated: bool) {
+        let dir_only = pattern.len() > 1 && pattern.ends_with('/');
+        let trimmed = pattern.trim_end_matches('/');
+        let anchored = trimmed.contains('/');
+        let glob = trimmed.trim_start_matches('/').chars().collect();
+        self.patterns.push(Pattern { glob, anchored, dir_only, negated });
+    }
+
+    /// Добавляет шаблоны из текста: по одному на строку, пустые строки и строки,
+    /// начинающиеся с `#`, пропускаются.
+    pub fn add_lines(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.trim_end();
+            if !line.is_empty() && !line.starts_with('#') {
+                self.add(line);
+            }
+        }
+    }
+
+    /// Добавляет шаблоны из файла игнорирования в синтаксисе `.gitignore`: как `add_lines`,
+    /// но шаблон, начинающийся с `!`, возвращает путь (`\!` — обычный `!` в начале имени).
+    pub fn add_ignore_lines(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix('!') {
+                Some(pattern) => self.push(pattern, true),
+                None => self.push(line, false),
+            }
+        }
+    }
+
+    /// Добавляет шаблоны из файла (значение `--exclude-from`).
+    ///
+    /// # Аргументы
+    ///
+    /// * `path` - Путь к файлу шаблонов.
+    ///
+    /// # Возвращает
+    ///
+    /// Результат операции или ошибку ввода/вывода.
+    pub fn read_from(&mut self, path: &str) -> io::Result<()> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot read exclude file {}: {}", path, e)))?;
+        self.add_lines(&text);
+        Ok(())
+    }
+
+    /// Возвращает `true`, если набор не содержит шаблонов.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Возвращает `true`, если относительный путь исключен хотя бы одним шаблоном.
+    ///
+    /// # Аргументы
+    ///
+    /// * `rel_path` - Путь относительно корня архивируемого дерева с разделителями `/`.
+    /// * `is_dir` - Путь является директорией.
+    pub fn matches(&self, rel_path: &str, is_dir: bool) -> bool {
+        self.verdict(rel_path, is_dir).unwrap_or(false)
+    }
+
+    /// Возвращает решение последнего подходящего шаблона: `Some(true)` — путь исключен,
+    /// `Some(false)` — возвращен шаблоном с `!`, `None` — ни один шаблон не подошел.
+    pub fn verdict(&self, rel_path: &str, is_dir: bool) -> Option<bool> {
+        self.patterns.iter().rev().find(|p| p.matches(rel_path, is_dir)).map(|p| !p.negated)
+    }
+}
+
+/// Сравнивает строку с шаблоном.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let mut rest = &pattern[2..];
+            while rest.first() == Some(&'*') {
+                rest = &rest[1..];
+            }
+            // `**/` может соответствовать и нулю директорий
+            if rest.first() == Some(&'/') && glob_match(&rest[1..], text) {
+                return true;
+            }
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        Some('*') => {
+            let mut i = 0;
+            loop {
+                if glob_match(&pattern[1..], &text[i..]) {
+                    return true;
+                }
+                if i == text.len() || text[i] == '/' {
+                    return false;
+                }
+                i += 1;
+            }
+        }
+        Some('?') => text.first().is_some_and(|&c| c != '/') && glob_match(&pattern[1..], &text[1..]),
+        Some('[') => match (text.first(), match_class(&pattern[1..], text.first().copied().unwrap_or('/'))) {
+            (Some(&c), Some((matched, len))) => c != '/' && matched && glob_match(&pattern[1 + len..], &text[1..]),
+            (None, Some(_)) => false,
+            // Незакрытая скобка сравнивается как обычный символ
+            (_, None) => text.first() == Some(&'[') && glob_match(&pattern[1..], &text[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..]),
+        Some(&c) => text.first() == Some(&c) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Сравнивает символ с классом `[...]`, начинающимся после `[`.
+///
+/// # Возвращает
+///
+/// Результат сравнения и длину класса вместе с `]` или `None`, если класс не закрыт.
+fn match_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    let negated = matches!(class.first(), Some('!') | Some('^'));
+    let mut i = usize::from(negated);
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if class.get(i + 1) == Some(&'-') && class.get(i + 2).is_some_and(|&end| end != ']') {
+            matched |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(patterns: &[&str]) -> ExcludeSet {
+        let mut set = ExcludeSet::default();
+        for p in patterns {
+            set.add(p);
+        }
+        set
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        let names = set(&["*.o", "target", "file?.[ch]", "[!a-c]*.log"]);
+        assert!(names.matches("src/deep/main.o", false));
+        assert!(names.matches("target", true));
+        assert!(names.matches("sub/target", true));
+        assert!(names.matches("file1.c", false));
+        assert!(!names.matches("file10.c", false));
+        assert!(names.matches("x/debug.log", false));
+        assert!(!names.matches("x/build.log", false));
+        assert!(!names.matches("main.rs", false));
+
+        let paths = set(&["/docs/*.pdf", "assets/**/*.png", "cache/"]);
+        assert!(paths.matches("docs/a.pdf", false));
+        assert!(!paths.matches("docs/sub/a.pdf", false));
+        assert!(!paths.matches("other/docs/a.pdf", false));
+        assert!(paths.matches("assets/a.png", false));
+        assert!(paths.matches("assets/x/y/a.png", false));
+        assert!(paths.matches("cache", true));
+        assert!(!paths.matches("cache", false));
+
+        // Директория под `dir/**` исключается целиком и не обходится
+        let trees = set(&["target/**", "*.o"]);
+        assert!(trees.matches("target", true));
+        assert!(trees.matches("target/debug/deps/lib.rlib", false));
+        assert!(!trees.matches("target", false));
+        assert!(!trees.matches("src/target", true));
+        assert!(trees.matches("src/x.o", false));
+    }
+
+    #[test]
+    fn test_exclude_lines() {
+        let mut set = ExcludeSet::default();
+        set.add_lines("# build output\n*.tmp\r\n\n  \nnode_modules/\n");
+        assert!(set.matches("a/b.tmp", false));
+        assert!(set.matches("web/node_modules", true));
+        assert!(!set.matches("# build output", false));
+        assert!(!set.matches("readme", false));
+
+        // В файле игнорирования решает последний подходящий шаблон
+        let mut ignore = ExcludeSet::default();
+        ignore.add_ignore_lines("*.log\n!keep.log\n/build/\n\\!bang\n");
+        assert_eq!(ignore.verdict("logs/app.log", false), Some(true));
+        assert_eq!(ignore.verdict("logs/keep.log", false), Some(false));
+        assert!(!ignore.matches("logs/keep.log", false));
+        assert!(ignore.matches("build", true) && !ignore.matches("src/build", true));
+        assert!(ignore.matches("!bang", false));
+        assert_eq!(ignore.verdict("readme", false), None);
+    }
+}
@@ -0,0 +1,154 @@
+//! Модуль для поиска по содержимому файлов архива (`--grep`).
+//!
+//! Файлы распаковываются по одной записи (см. `processing::stream_files`), а строки
+//! собираются из частей по мере распаковки, поэтому архив не нужно распаковывать на диск
+//! и держать в памяти целиком. Шаблон — регулярное выражение в синтаксисе крейта `regex`
+//! или, с `--fixed-strings`, обычная строка. Строки сравниваются как байты, поэтому поиск
+//! работает и в файлах не в UTF-8.
+
+use std::io;
+use regex::bytes::{Regex, RegexBuilder};
+use crate::processing;
+
+/// Найденная строка.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    /// Путь записи в архиве
+    pub path: String,
+    /// Номер строки, начиная с 1
+    pub line: usize,
+    /// Строка без перевода строки; байты не в UTF-8 заменены на `U+FFFD`
+    pub text: String,
+}
+
+/// Собирает шаблон поиска.
+///
+/// # Аргументы
+///
+/// * `pattern` - Шаблон.
+/// * `fixed` - Шаблон — обычная строка, а не регулярное выражение (`--fixed-strings`).
+/// * `ignore_case` - Не учитывать регистр (`--ignore-case`).
+///
+/// # Возвращает
+///
+/// Регулярное выражение или описание ошибки в шаблоне.
+pub fn build_pattern(pattern: &str, fixed: bool, ignore_case: bool) -> Result<Regex, String> {
+    let pattern = if fixed { regex::escape(pattern) } else { pattern.to_owned() };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| format!("invalid pattern: {}", e))
+}
+
+/// Ищет шаблон в строках файлов архива.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов, содержащий архив с заголовком.
+/// * `pattern` - Шаблон, собранный `build_pattern`.
+/// * `strict` - Строгий режим распаковки.
+/// * `select` - Условие отбора записей по пути и режиму.
+/// * `found` - Получатель найденных строк в порядке архива; `false` прекращает поиск
+///   в текущей записи (например, когда нужны только пути).
+///
+/// # Возвращает
+///
+/// Число найденных строк или ошибку, если архив поврежден.
+pub fn search(input: &[u8], pattern: &Regex, strict: bool, select: &dyn Fn(&str, u32) -> bool,
+              found: &mut dyn FnMut(Match) -> bool) -> io::Result<usize> {
+    let mut count = 0;
+    // Начало строки, которая продолжится в следующей части, и номер текущей строки
+    let mut partial = Vec::new();
+    let mut line = 0;
+    let mut current = String::new();
+    processing::stream_files(input, strict, select, &mut |path, chunk| {
+        if current != path {
+            current = path.to_owned();
+            partial.clear();
+            line = 0;
+        }
+        let mut check = |text: &[u8]| {
+            line += 1;
+            if !pattern.is_match(text) {
+                return true;
+            }
+            count += 1;
+            let text = String::from_utf8_lossy(text.strip_suffix(b"\r").unwrap_or(text)).into_owned();
+            found(Match { path: path.to_owned(), line, text })
+        };
+        // Пустая часть завершает запись: последняя строка может быть без перевода строки
+        if chunk.is_empty() {
+            let last = std::mem::take(&mut partial);
+            return last.is_empty() || check(&last);
+        }
+        let mut rest = chunk;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            let keep_going = if partial.is_empty() {
+                check(&rest[..end])
+            } else {
+                partial.extend_from_slice(&rest[..end]);
+                let text = std::mem::take(&mut partial);
+                check(&text)
+            };
+            if !keep_going {
+                partial.clear();
+                return false;
+            }
+            rest = &rest[end + 1..];
+        }
+        partial.extend_from_slice(rest);
+        true
+    })?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::io::{DirEntry, HARD_LINK_FLAG};
+    use crate::processing::{compress_archive, Algorithm, CompressionOptions};
+    use crate::ArchiveData;
+
+    #[test]
+    fn test_search() {
+        let entry = |path: &str, permissions, data: &[u8]| DirEntry { path: path.into(), data: data.to_vec(), permissions, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None };
+        let log: Vec<u8> = (0..3000).flat_map(|i| format!("line {} {}\n", i, if i % 1000 == 7 { "ERROR" } else { "ok" }).into_bytes()).collect();
+        let archive = ArchiveData { entries: vec![
+            entry("logs/app.log", 0o100644, &log),
+            entry("logs/copy.log", 0o100644 | HARD_LINK_FLAG, b"logs/app.log"),
+            entry("notes.txt", 0o100644, b"first\r\nan error here\r\nlast error"),
+            entry("link", 0o120777, b"notes.txt"),
+        ] };
+        let packed = compress_archive(archive.clone(), &CompressionOptions::new(Algorithm::Lz4).block_size(Some(1024)));
+        let solid = compress_archive(archive, &CompressionOptions::new(Algorithm::Lz4).solid(true));
+
+        let grep = |pattern: &Regex| {
+            let mut matches = Vec::new();
+            let count = search(&packed, pattern, true, &|_, _| true, &mut |m| {
+                matches.push(format!("{}:{}:{}", m.path, m.line, m.text));
+                true
+            }).unwrap();
+            assert_eq!(count, matches.len());
+            // Общий сжатый поток дает те же строки
+            assert_eq!(search(&solid, pattern, true, &|_, _| true, &mut |_| true).unwrap(), count);
+            matches
+        };
+        // Строки, разрезанные границей блока, собираются целиком
+        assert_eq!(grep(&build_pattern("ERROR", true, true).unwrap()), [
+            "logs/app.log:8:line 7 ERROR", "logs/app.log:1008:line 1007 ERROR", "logs/app.log:2008:line 2007 ERROR",
+            "logs/copy.log:8:line 7 ERROR", "logs/copy.log:1008:line 1007 ERROR", "logs/copy.log:2008:line 2007 ERROR",
+            "notes.txt:2:an error here", "notes.txt:3:last error",
+        ]);
+        assert_eq!(grep(&build_pattern(r"^line 29\d\d ERR|^LAST", false, true).unwrap()), ["notes.txt:3:last error"]);
+        assert!(build_pattern("(", false, false).is_err() && build_pattern("(", true, false).is_ok());
+
+        // Получатель может прекратить поиск в записи после первой строки
+        let mut first = Vec::new();
+        search(&packed, &build_pattern("error", false, true).unwrap(), true, &|path, _| path != "logs/copy.log", &mut |m| {
+            first.push((m.path, m.line));
+            false
+        }).unwrap();
+        assert_eq!(first, [("logs/app.log".to_owned(), 8), ("notes.txt".to_owned(), 2)]);
+    }
+}
@@ -0,0 +1,465 @@
+//! Модуль для работы с заголовком архива.
+//!
+//! Каждый сжатый поток начинается с заголовка: сигнатура `RARC`, версия формата,
+//! идентификатор алгоритма, флаги и длина сжатых данных. По заголовку распаковка
+//! сама определяет алгоритм и отличает архивы от произвольных файлов. Благодаря
+//! длине данных несколько архивов, записанных подряд, разбираются по отдельности.
+//!
+//! Начиная с версии 2 за сжатыми данными следует трейлер — SHA-256 этих данных,
+//! по которому обрезанный или поврежденный архив обнаруживается до распаковки.
+//! В версии 3 длины внутри записей хранятся в формате varint (до 64 битов),
+//! а записи версий 1 и 2 читаются с длинами `u32`. В версии 4 пути в наборе записей
+//! хранятся относительно пути предыдущей записи (см. модуль `format`), а в версии 5 так же
+//! хранятся пути центрального индекса, а в версии 6 появились записи-дубликаты
+//! (`io::DUPLICATE_FLAG`), которые прежние версии распаковали бы как файлы с путем
+//! вместо содержимого. В версии 7 каждая сжатая запись, блок и сегмент архива с посжатыми
+//! записями начинается с байта способа хранения: данные, которые при сжатии не уменьшились,
+//! сохраняются как есть. В версии 8 после флагов появился байт устройства данных: он объявляет
+//! порядок байтов чисел в потоках алгоритмов (`LAYOUT_LITTLE_ENDIAN`), а потоки прежних версий
+//! читаются в порядке big-endian, в котором их записывали. В версии 9 данные начинаются с блока
+//! полей заголовка (`Field`): новые необязательные сведения добавляются полями новых типов без
+//! новой версии формата, а программа пропускает поля, которых не знает, если у их типа не
+//! установлен бит `FIELD_CRITICAL`. Так старая программа распаковывает архивы более новой,
+//! пока понимает их алгоритм и устройство данных. В версии 10 записи и центральный индекс
+//! хранят время изменения файлов, а в версии 11 таблица блоков хранит исходную длину каждого
+//! блока, которая подбирается по энтропии данных (см. модуль `blocks`). С флагом `FLAG_PACKED_INDEX` центральный индекс
+//! посжатых архивов сжат LZ4, а с флагом `FLAG_SEGMENTS` мелкие файлы упакованы в общие
+//! сегменты, а с флагом `FLAG_CHUNKED` повторы блоков общего потока заменены ссылками.
+//! С флагом `FLAG_INFO` сжатым данным
+//! предшествует раздел сведений о происхождении архива (см. модуль `info`); длина
+//! и хеш в этом случае охватывают раздел вместе с данными.
+
+use std::convert::TryInto;
+use std::io;
+use crate::io::{read_varint, write_varint};
+use crate::processing::Algorithm;
+
+/// Сигнатура, с которой начинается архив.
+pub const ARCHIVE_MAGIC: &[u8; 4] = b"RARC";
+
+/// Текущая версия формата архива.
+pub const FORMAT_VERSION: u8 = 11;
+
+/// Первая версия формата, в которой за данными следует трейлер с SHA-256.
+const DIGEST_VERSION: u8 = 2;
+
+/// Первая версия формата, в которой длины внутри записей имеют 64 бита.
+const VARINT_VERSION: u8 = 3;
+
+/// Первая версия формата, в которой пути центрального индекса хранятся относительно предыдущих.
+const FRONT_CODED_INDEX_VERSION: u8 = 5;
+
+/// Первая версия формата, в которой сжатые записи, блоки и сегменты начинаются с байта способа хранения.
+const STORED_BLOBS_VERSION: u8 = 7;
+
+/// Первая версия формата, в заголовке которой есть байт устройства данных (`LAYOUT_*`).
+const LAYOUT_VERSION: u8 = 8;
+
+/// Первая версия формата, в которой данные начинаются с блока полей заголовка.
+const FIELDS_VERSION: u8 = 9;
+
+/// Первая версия формата, в которой записи и центральный индекс хранят время изменения файлов.
+const MTIME_VERSION: u8 = 10;
+
+/// Первая версия формата, в которой таблица блоков хранит исходную длину каждого блока.
+const BLOCK_LENGTHS_VERSION: u8 = 11;
+
+/// Бит типа поля заголовка: поле обязательно, и программа, которая его не знает, не может
+/// прочитать архив. Поля без этого бита незнакомая с ними программа пропускает.
+pub const FIELD_CRITICAL: u64 = 0x01;
+
+/// Типы полей заголовка, которые понимает эта версия программы. Типы назначаются навсегда:
+/// номер однажды выпущенного типа не меняется и не переиспользуется.
+pub const KNOWN_FIELDS: &[u64] = &[];
+
+/// Размер трейлера с хешем SHA-256 в байтах.
+pub const DIGEST_LEN: usize = 32;
+
+/// Флаг: данные LZ77 содержат короткие токены совпадений (`--short-matches`).
+pub const FLAG_SHORT_MATCHES: u8 = 0x01;
+
+/// Флаг: записи сжаты по отдельности и описаны центральным индексом (см. `entry_index`, `--no-solid`,
+/// по умолчанию); без него архив сплошной (`--solid`): все записи сжаты одним потоком.
+pub const FLAG_PER_ENTRY: u8 = 0x02;
+
+/// Флаг: содержимое записей разбито на независимо сжатые блоки с таблицей блоков.
+pub const FLAG_BLOCKS: u8 = 0x04;
+
+/// Флаг: записи индекса блочного режима содержат владельца файла.
+pub const FLAG_OWNERS: u8 = 0x08;
+
+/// Флаг: данные начинаются с раздела сведений об архиве (время создания, компьютер, комментарий).
+pub const FLAG_INFO: u8 = 0x10;
+
+/// Флаг: центральный индекс посжатого архива сжат LZ4 независимо от алгоритма данных.
+pub const FLAG_PACKED_INDEX: u8 = 0x20;
+
+/// Флаг: мелкие файлы упакованы в общие сжатые сегменты, а записи индекса хранят свое место в сегменте.
+pub const FLAG_SEGMENTS: u8 = 0x40;
+
+/// Флаг: общий поток перед сжатием разбит на блоки, и повторы блоков заменены ссылками
+/// (блоки по содержимому с `--chunk-dedup`, см. модуль `chunking`, или кадры, см. модуль
+/// `frames`). Это последний свободный бит флагов: прежние версии отклоняют его как
+/// неизвестный, а новым режимам понадобится новая версия формата.
+pub const FLAG_CHUNKED: u8 = 0x80;
+
+/// Байт устройства данных: числа в потоках алгоритмов (заголовки блоков Хаффмана) записаны
+/// в порядке little-endian, как и все остальные числа архива. Без него — в порядке big-endian.
+pub const LAYOUT_LITTLE_ENDIAN: u8 = 0x01;
+
+/// Размер заголовка в байтах: сигнатура, версия, алгоритм, флаги, устройство данных и длина данных.
+pub const HEADER_LEN: usize = 4 + 1 + 1 + 1 + 1 + 8;
+
+/// Размер заголовка версий до 8, в которых нет байта устройства данных.
+pub const LEGACY_HEADER_LEN: usize = HEADER_LEN - 1;
+
+/// Порядок байтов в числах, записанных потоками алгоритмов.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteOrder {
+    /// Младший байт первым
+    Little,
+    /// Старший байт первым (потоки Хаффмана до версии 8 и архивы без заголовка)
+    Big,
+}
+
+impl ByteOrder {
+    /// Записывает `u16` в этом порядке байтов.
+    pub fn write_u16(self, buffer: &mut Vec<u8>, value: u16) {
+        buffer.extend_from_slice(&match self {
+            ByteOrder::Little => value.to_le_bytes(),
+            ByteOrder::Big => value.to_be_bytes(),
+        });
+    }
+
+    /// Записывает `u32` в этом порядке байтов.
+    pub fn write_u32(self, buffer: &mut Vec<u8>, value: u32) {
+        buffer.extend_from_slice(&match self {
+            ByteOrder::Little => value.to_le_bytes(),
+            ByteOrder::Big => value.to_be_bytes(),
+        });
+    }
+
+    /// Читает `u16` из двух байтов в этом порядке.
+    pub fn read_u16(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    /// Читает `u32` из четырех байтов в этом порядке.
+    pub fn read_u32(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+/// Поле заголовка: тип (`FIELD_CRITICAL` в младшем бите) и значение.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// Тип поля
+    pub kind: u64,
+    /// Значение поля
+    pub value: Vec<u8>,
+}
+
+/// Записывает блок полей заголовка: длина блока (varint), затем для каждого поля тип (varint),
+/// длина значения (varint) и значение.
+pub fn write_fields(fields: &[Field]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for field in fields {
+        write_varint(&mut body, field.kind);
+        write_varint(&mut body, field.value.len() as u64);
+        body.extend_from_slice(&field.value);
+    }
+    let mut buffer = Vec::with_capacity(body.len() + 1);
+    write_varint(&mut buffer, body.len() as u64);
+    buffer.extend(body);
+    buffer
+}
+
+/// Разбирает блок полей заголовка в начале данных.
+///
+/// # Аргументы
+///
+/// * `data` - Данные архива, начинающиеся с блока полей.
+/// * `known` - Типы полей, которые понимает программа (`KNOWN_FIELDS`).
+///
+/// # Возвращает
+///
+/// Поля известных типов и длину блока в байтах или ошибку, если блок поврежден
+/// или в нем есть обязательное поле неизвестного типа.
+pub fn read_fields(data: &[u8], known: &[u64]) -> io::Result<(Vec<Field>, usize)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "archive header fields are damaged");
+    let mut offset = 0;
+    let len = usize::try_from(read_varint(data, &mut offset)?).map_err(|_| invalid())?;
+    let end = offset.checked_add(len).filter(|&end| end <= data.len()).ok_or_else(invalid)?;
+    let body = &data[offset..end];
+    let mut pos = 0;
+    let mut fields = Vec::new();
+    while pos < body.len() {
+        let kind = read_varint(body, &mut pos)?;
+        let len = usize::try_from(read_varint(body, &mut pos)?).map_err(|_| invalid())?;
+        let value = pos.checked_add(len).and_then(|e| body.get(pos..e)).ok_or_else(invalid)?;
+        pos += len;
+        if known.contains(&kind) {
+            fields.push(Field { kind, value: value.to_vec() });
+        } else if kind & FIELD_CRITICAL != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("archive requires header field {} unknown to this version, upgrade the program to read it", kind)));
+        }
+    }
+    Ok((fields, end))
+}
+
+/// Заголовок одного сжатого потока.
+#[derive(Debug, PartialEq)]
+pub struct Header {
+    /// Версия формата
+    pub version: u8,
+    /// Алгоритм, которым сжаты данные
+    pub algorithm: Algorithm,
+    /// Флаги режима сжатия
+    pub flags: u8,
+    /// Устройство данных (`LAYOUT_*`); в версиях до 8 не записывается и равно 0
+    pub layout: u8,
+    /// Длина сжатых данных после заголовка
+    pub payload_len: u64,
+}
+
+impl Header {
+    /// Возвращает `true`, если установлен флаг коротких токенов LZ77.
+    pub fn short_matches(&self) -> bool {
+        self.flags & FLAG_SHORT_MATCHES != 0
+    }
+
+    /// Возвращает `true`, если записи сжаты по отдельности.
+    pub fn per_entry(&self) -> bool {
+        self.flags & FLAG_PER_ENTRY != 0
+    }
+
+    /// Возвращает `true`, если содержимое записей разбито на блоки.
+    pub fn blocks(&self) -> bool {
+        self.flags & FLAG_BLOCKS != 0
+    }
+
+    /// Возвращает `true`, если записи индекса блочного режима содержат владельца.
+    pub fn owners(&self) -> bool {
+        self.flags & FLAG_OWNERS != 0
+    }
+
+    /// Возвращает `true`, если данные начинаются с раздела сведений об архиве.
+    pub fn has_info(&self) -> bool {
+        self.flags & FLAG_INFO != 0
+    }
+
+    /// Возвращает `true`, если центральный индекс сжат LZ4.
+    pub fn packed_index(&self) -> bool {
+        self.flags & FLAG_PACKED_INDEX != 0
+    }
+
+    /// Возвращает `true`, если мелкие файлы упакованы в общие сегменты.
+    pub fn segments(&self) -> bool {
+        self.flags & FLAG_SEGMENTS != 0
+    }
+
+    /// Возвращает `true`, если повторы блоков общего потока заменены ссылками.
+    pub fn chunked(&self) -> bool {
+        self.flags & FLAG_CHUNKED != 0
+    }
+
+    /// Возвращает `true`, если за данными следует трейлер с SHA-256.
+    pub fn has_digest(&self) -> bool {
+        self.version >= DIGEST_VERSION
+    }
+
+    /// Возвращает `true`, если длины внутри записей хранятся в формате varint.
+    pub fn varint_lengths(&self) -> bool {
+        self.version >= VARINT_VERSION
+    }
+
+    /// Возвращает `true`, если пути центрального индекса хранятся относительно предыдущих.
+    pub fn front_coded_index(&self) -> bool {
+        self.version >= FRONT_CODED_INDEX_VERSION
+    }
+
+    /// Возвращает `true`, если сжатые записи, блоки и сегменты начинаются с байта способа
+    /// хранения (сжаты или сохранены как есть).
+    pub fn stored_blobs(&self) -> bool {
+        self.version >= STORED_BLOBS_VERSION
+    }
+
+    /// Возвращает `true`, если данные начинаются с блока полей заголовка.
+    pub fn has_fields(&self) -> bool {
+        self.version >= FIELDS_VERSION
+    }
+
+    /// Возвращает `true`, если записи центрального индекса содержат время изменения файлов.
+    pub fn mtimes(&self) -> bool {
+        self.version >= MTIME_VERSION
+    }
+
+    /// Возвращает `true`, если таблица блоков хранит исходную длину каждого блока, а не только
+    /// общий размер блока.
+    pub fn block_lengths(&self) -> bool {
+        self.version >= BLOCK_LENGTHS_VERSION
+    }
+
+    /// Возвращает порядок байтов в потоках алгоритмов.
+    pub fn byte_order(&self) -> ByteOrder {
+        if self.layout & LAYOUT_LITTLE_ENDIAN != 0 {
+            ByteOrder::Little
+        } else {
+            ByteOrder::Big
+        }
+    }
+
+    /// Возвращает размер заголовка этой версии в байтах.
+    pub fn encoded_len(&self) -> usize {
+        if self.version >= LAYOUT_VERSION { HEADER_LEN } else { LEGACY_HEADER_LEN }
+    }
+
+    /// Преобразует заголовок в байты.
+    ///
+    /// # Возвращает
+    ///
+    /// Вектор из `encoded_len()` байтов.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(HEADER_LEN);
+        buffer.extend_from_slice(ARCHIVE_MAGIC);
+        buffer.push(self.version);
+        buffer.push(self.algorithm.id());
+        buffer.push(self.flags);
+        if self.version >= LAYOUT_VERSION {
+            buffer.push(self.layout);
+        }
+        buffer.extend_from_slice(&self.payload_len.to_le_bytes());
+        buffer
+    }
+
+    /// Разбирает заголовок в начале данных.
+    ///
+    /// # Аргументы
+    ///
+    /// * `data` - Байты, начинающиеся с заголовка.
+    ///
+    /// # Возвращает
+    ///
+    /// Заголовок или ошибку, если данные не являются архивом, версия или алгоритм
+    /// не поддерживаются, флаги несовместимы либо устройство данных неизвестно.
+    pub fn parse(data: &[u8]) -> io::Result<Header> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if !has_magic(data) {
+            return Err(invalid("not an archive (missing RARC signature)".to_owned()));
+        }
+        if data.len() < LEGACY_HEADER_LEN {
+            return Err(invalid("truncated archive header".to_owned()));
+        }
+        let version = data[4];
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(invalid(format!("unsupported archive format version {}", version)));
+        }
+        let (layout, len) = if version >= LAYOUT_VERSION {
+            let layout = *data.get(7).filter(|_| data.len() >= HEADER_LEN)
+                .ok_or_else(|| invalid("truncated archive header".to_owned()))?;
+            if layout & !LAYOUT_LITTLE_ENDIAN != 0 {
+                return Err(invalid(format!("unknown data layout {:#04x}", layout)));
+            }
+            (layout, HEADER_LEN)
+        } else {
+            (0, LEGACY_HEADER_LEN)
+        };
+        let algorithm = Algorithm::from_id(data[5])
+            .ok_or_else(|| invalid(format!("unknown algorithm id {}", data[5])))?;
+        let flags = data[6];
+        if flags & FLAG_BLOCKS != 0 && flags & FLAG_PER_ENTRY == 0 {
+            return Err(invalid("block framing requires per-entry compression".to_owned()));
+        }
+        if flags & FLAG_OWNERS != 0 && flags & FLAG_BLOCKS == 0 {
+            return Err(invalid("owner records require block framing".to_owned()));
+        }
+        if flags & FLAG_PACKED_INDEX != 0 && flags & FLAG_PER_ENTRY == 0 {
+            return Err(invalid("packed index requires per-entry compression".to_owned()));
+        }
+        if flags & FLAG_SEGMENTS != 0 && (flags & FLAG_PER_ENTRY == 0 || flags & FLAG_BLOCKS != 0) {
+            return Err(invalid("data segments require per-entry compression without block framing".to_owned()));
+        }
+        if flags & FLAG_CHUNKED != 0 && flags & FLAG_PER_ENTRY != 0 {
+            return Err(invalid("chunk deduplication requires a single compressed stream".to_owned()));
+        }
+        let payload_len = u64::from_le_bytes(data[len - 8..len].try_into().unwrap());
+        Ok(Header { version, algorithm, flags, layout, payload_len })
+    }
+}
+
+/// Проверяет, начинаются ли данные с сигнатуры архива.
+pub fn has_magic(data: &[u8]) -> bool {
+    data.starts_with(ARCHIVE_MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = Header { version: FORMAT_VERSION, algorithm: Algorithm::Lz77, flags: FLAG_SHORT_MATCHES, layout: LAYOUT_LITTLE_ENDIAN, payload_len: 1234 };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN);
+        let parsed = Header::parse(&bytes).unwrap();
+        assert!(parsed.short_matches());
+        assert_eq!(parsed, header);
+        assert_eq!(parsed.byte_order(), ByteOrder::Little);
+
+        // Заголовок версии 7 короче на байт устройства данных, а потоки в нем big-endian
+        let legacy = Header { version: 7, layout: 0, ..header };
+        let bytes = legacy.to_bytes();
+        assert_eq!(bytes.len(), LEGACY_HEADER_LEN);
+        let parsed = Header::parse(&bytes).unwrap();
+        assert_eq!((parsed.encoded_len(), parsed.payload_len, parsed.byte_order()), (LEGACY_HEADER_LEN, 1234, ByteOrder::Big));
+    }
+
+    #[test]
+    fn test_header_fields() {
+        // Поля неизвестных типов пропускаются, а обязательное неизвестное поле — ошибка
+        let optional = Field { kind: 4, value: b"from a newer version".to_vec() };
+        let known = Field { kind: 6, value: vec![1, 2, 3] };
+        let mut bytes = write_fields(&[optional.clone(), known.clone()]);
+        let len = bytes.len();
+        bytes.extend_from_slice(b"payload");
+        assert_eq!(read_fields(&bytes, &[6]).unwrap(), (vec![known.clone()], len));
+        assert_eq!(read_fields(&bytes, KNOWN_FIELDS).unwrap(), (vec![], len));
+        let critical = write_fields(&[Field { kind: 5, value: vec![] }]);
+        assert!(read_fields(&critical, KNOWN_FIELDS).unwrap_err().to_string().contains("header field 5"));
+        assert_eq!(read_fields(&critical, &[5]).unwrap().0.len(), 1);
+        assert!(read_fields(&bytes[..len - 1], &[6]).is_err());
+        assert_eq!(write_fields(&[]), [0]);
+
+        // Идентификаторы, записанные в архивы, не меняются
+        let ids: Vec<u8> = Algorithm::ALL.iter().map(Algorithm::id).collect();
+        assert_eq!(ids, [1, 2, 3, 4, 5]);
+        assert_eq!([FLAG_SHORT_MATCHES, FLAG_PER_ENTRY, FLAG_BLOCKS, FLAG_OWNERS, FLAG_INFO, FLAG_PACKED_INDEX, FLAG_SEGMENTS, FLAG_CHUNKED],
+                   [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80]);
+        assert_eq!((LAYOUT_LITTLE_ENDIAN, FIELD_CRITICAL), (0x01, 0x01));
+    }
+
+    #[test]
+    fn test_header_rejects_invalid() {
+        let mut bytes = Header { version: FORMAT_VERSION, algorithm: Algorithm::Hf, flags: 0, layout: LAYOUT_LITTLE_ENDIAN, payload_len: 0 }.to_bytes();
+        assert!(Header::parse(b"hello, world!!!!").is_err());
+        assert!(Header::parse(&bytes[..HEADER_LEN - 1]).is_err());
+        bytes[6] = FLAG_CHUNKED | FLAG_PER_ENTRY;
+        assert!(Header::parse(&bytes).is_err());
+        bytes[6] = 0;
+        bytes[5] = 99;
+        assert!(Header::parse(&bytes).is_err());
+        bytes[5] = Algorithm::Hf.id();
+        bytes[7] = 0x02;
+        assert!(Header::parse(&bytes).is_err());
+        bytes[7] = LAYOUT_LITTLE_ENDIAN;
+        bytes[4] = FORMAT_VERSION + 1;
+        assert!(Header::parse(&bytes).is_err());
+    }
+}
@@ -0,0 +1,479 @@
+//! Модуль для HTTP-сервиса архивации (`--http`, возможность сборки `server`).
+//!
+//! Сервис позволяет внутренним инструментам архивировать и распаковывать данные по HTTP,
+//! не устанавливая программу рядом с данными. Каждое подключение обслуживает один запрос
+//! (`Connection: close`); тело запроса передается с заголовком `Content-Length`.
+//!
+//! | Запрос | Тело | Ответ |
+//! |--------|------|-------|
+//! | `POST /archive?algorithm=lz4` | поток tar с деревом файлов | архив |
+//! | `POST /list` | архив | по строке на запись: права (восьмеричные), размер и путь через табуляцию |
+//! | `POST /extract` | архив | поток tar со всеми записями |
+//! | `POST /extract?path=docs/a.txt` | архив | содержимое одного файла |
+//!
+//! Ошибки возвращаются с кодом 4xx или 5xx и текстом ошибки в теле.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+use crate::info::ArchiveInfo;
+use crate::io::{self as archive_io, DirEntry, HARD_LINK_FLAG};
+use crate::partial;
+use crate::processing::{self, Algorithm};
+use crate::server::ServeOptions;
+use crate::archive;
+
+/// Наибольший размер тела запроса.
+const MAX_BODY_LEN: u64 = 1 << 32;
+
+/// Наибольший размер строки запроса и заголовков.
+const MAX_HEAD_LEN: usize = 64 * 1024;
+
+/// Наибольшее число заголовков запроса.
+const MAX_HEADERS: usize = 64;
+
+/// Как часто сервис проверяет, не пора ли остановиться, пока нет новых подключений.
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+
+/// Ответ на запрос: код состояния, тип содержимого и тело.
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// Успешный ответ с двоичным телом.
+    fn binary(body: Vec<u8>) -> Response {
+        Response { status: 200, content_type: "application/octet-stream", body }
+    }
+
+    /// Ответ с текстом ошибки.
+    fn error(status: u16, message: impl Into<String>) -> Response {
+        Response { status, content_type: "text/plain; charset=utf-8", body: message.into().into_bytes() }
+    }
+
+    /// Ответ на ошибку обработки архива: отсутствующая запись — 404, поврежденные данные — 422.
+    fn from_io_error(e: io::Error) -> Response {
+        let status = match e.kind() {
+            io::ErrorKind::NotFound => 404,
+            io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::UnexpectedEof => 422,
+            _ => 500,
+        };
+        Response::error(status, e.to_string())
+    }
+}
+
+/// Запускает HTTP-сервис на адресе `addr` и обслуживает запросы до SIGINT/SIGTERM.
+///
+/// # Аргументы
+///
+/// * `addr` - Адрес и порт, например `127.0.0.1:8080`.
+/// * `options` - Параметры сжатия и распаковки.
+///
+/// # Возвращает
+///
+/// `Ok(())` после остановки или ошибку ввода/вывода, если адрес не удалось занять.
+pub fn serve(addr: &str, options: ServeOptions) -> io::Result<()> {
+    let addrs: Vec<_> = addr.to_socket_addrs()?.collect();
+    let listener = TcpListener::bind(&addrs[..])
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot listen on {}: {}", addr, e)))?;
+    // Подключения принимаются без блокировки, чтобы сервис замечал сигнал остановки
+    listener.set_nonblocking(true)?;
+    while !partial::interrupted() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, options) {
+                        log::warn!("connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+            Err(e) => log::warn!("failed to accept a connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Читает один HTTP-запрос из подключения, выполняет его и записывает ответ.
+pub fn handle_connection<S: Read + Write>(mut stream: S, options: ServeOptions) -> io::Result<()> {
+    let response = match read_request(&mut stream) {
+        Ok(Ok((method, target, body))) => route(&method, &target, &body, options),
+        Ok(Err(response)) => response,
+        Err(e) => return Err(e),
+    };
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status, reason, response.content_type, response.body.len())?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+/// Запрос: метод, путь с параметрами и тело.
+type Request = (String, String, Vec<u8>);
+
+/// Читает строку запроса, заголовки и тело длиной `Content-Length`.
+///
+/// # Возвращает
+///
+/// Запрос, ответ с ошибкой для неправильного запроса или ошибку чтения из подключения.
+fn read_request<S: Read>(stream: &mut S) -> io::Result<Result<Request, Response>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+        match request.parse(&buffer) {
+            Ok(httparse::Status::Complete(head_len)) => {
+                let method = request.method.unwrap_or_default().to_owned();
+                let target = request.path.unwrap_or_default().to_owned();
+                let header = |name: &str| request.headers.iter()
+                    .find(|h| h.name.eq_ignore_ascii_case(name))
+                    .map(|h| String::from_utf8_lossy(h.value).trim().to_owned());
+                if header("Transfer-Encoding").is_some() {
+                    return Ok(Err(Response::error(411, "chunked uploads are not supported, send Content-Length")));
+                }
+                let len = match header("Content-Length").map(|v| v.parse::<u64>()) {
+                    None => 0,
+                    Some(Ok(len)) if len <= MAX_BODY_LEN => len,
+                    Some(Ok(_)) => return Ok(Err(Response::error(413, format!("request body exceeds {} bytes", MAX_BODY_LEN)))),
+                    Some(Err(_)) => return Ok(Err(Response::error(400, "invalid Content-Length"))),
+                };
+                let mut body = buffer.split_off(head_len);
+                body.truncate(len as usize);
+                stream.take(len - body.len() as u64).read_to_end(&mut body)?;
+                if body.len() as u64 != len {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "request body is truncated"));
+                }
+                return Ok(Ok((method, target, body)));
+            }
+            Ok(httparse::Status::Partial) if buffer.len() < MAX_HEAD_LEN => {}
+            Ok(httparse::Status::Partial) => return Ok(Err(Response::error(431, "request headers are too large"))),
+            Err(e) => return Ok(Err(Response::error(400, format!("malformed request: {}", e)))),
+        }
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before the request was complete"));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Выполняет запрос по его методу и пути.
+fn route(method: &str, target: &str, body: &[u8], options: ServeOptions) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let params = parse_query(query);
+    if !matches!(path, "/archive" | "/list" | "/extract") {
+        return Response::error(404, format!("unknown endpoint {}", path));
+    }
+    if method != "POST" {
+        return Response::error(405, format!("{} expects POST", path));
+    }
+    let result = match path {
+        "/archive" => {
+            let Some(algorithm) = params.get("algorithm") else {
+                return Response::error(400, "missing algorithm parameter (rle, lz77, lz4, lzw, hf)");
+            };
+            let Some(algorithm) = Algorithm::from_name(algorithm) else {
+                return Response::error(400, format!("unknown algorithm {}", algorithm));
+            };
+            archive_tar(body, algorithm, options).map(Response::binary)
+        }
+        "/list" => read_entries(body, options).map(|entries| {
+            let lines: String = entries.iter()
+                .map(|e| format!("{:o}\t{}\t{}\n", e.permissions & !HARD_LINK_FLAG, e.data.len(), e.path))
+                .collect();
+            Response { status: 200, content_type: "text/plain; charset=utf-8", body: lines.into_bytes() }
+        }),
+        _ => match params.get("path") {
+            Some(entry) => processing::read_range(body, entry, 0..u64::MAX, options.strict).map(Response::binary),
+            None => read_entries(body, options).and_then(|entries| write_tar(&entries)).map(Response::binary),
+        },
+    };
+    result.unwrap_or_else(Response::from_io_error)
+}
+
+/// Разбирает параметры запроса `a=1&b=2`, раскодируя `%XX` и `+`.
+fn parse_query(query: &str) -> BTreeMap<String, String> {
+    let decode = |s: &str| {
+        let bytes = s.as_bytes();
+        let hex = |pos: usize| bytes.get(pos..pos + 2)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            match (bytes[i], hex(i + 1)) {
+                (b'+', _) => out.push(b' '),
+                (b'%', Some(byte)) => {
+                    out.push(byte);
+                    i += 2;
+                }
+                (b, _) => out.push(b),
+            }
+            i += 1;
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    };
+    query.split('&').filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .map(|(key, value)| (decode(key), decode(value)))
+        .collect()
+}
+
+/// Сжимает дерево файлов из потока tar.
+///
+/// Обычные файлы, директории, символические и жесткие ссылки переносятся в записи архива
+/// с правами из заголовков tar; остальные типы записей tar пропускаются. Файлы с одинаковым
+/// содержимым сохраняются один раз, как при сжатии дерева.
+fn archive_tar(tar_stream: &[u8], algorithm: Algorithm, options: ServeOptions) -> io::Result<Vec<u8>> {
+    let mut entries = Vec::new();
+    for item in tar::Archive::new(tar_stream).entries()? {
+        let mut item = item?;
+        let path = item.path()?.to_string_lossy().trim_start_matches("./").trim_end_matches('/').to_owned();
+        if path.is_empty() || path == "." {
+            continue;
+        }
+        let mode = item.header().mode()? & 0o7777;
+        let link = || -> io::Result<Vec<u8>> {
+            Ok(item.link_name_bytes().map(|name| name.into_owned()).unwrap_or_default())
+        };
+        let (permissions, data) = match item.header().entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let mut data = Vec::with_capacity(item.size() as usize);
+                item.read_to_end(&mut data)?;
+                (0o100000 | mode, data)
+            }
+            tar::EntryType::Directory => (0o40000 | mode, Vec::new()),
+            tar::EntryType::Symlink => (0o120000 | mode, link()?),
+            tar::EntryType::Link => {
+                let target = String::from_utf8_lossy(&link()?).trim_start_matches("./").to_owned();
+                (0o100000 | mode | HARD_LINK_FLAG, target.into_bytes())
+            }
+            _ => continue,
+        };
+        let mtime = item.header().mtime().ok();
+        entries.push(DirEntry { path, data, permissions, crc32: None, owner: None, metadata: BTreeMap::new(), mtime });
+    }
+    archive_io::deduplicate(&mut entries);
+    archive_io::fill_checksums(&mut entries);
+    let compressed = processing::compress_entries(&entries, algorithm, options.use_multithreading, options.short_matches, None, None);
+    processing::set_info(&compressed, &ArchiveInfo::current(None))
+}
+
+/// Распаковывает все записи архива; дубликаты заменяются копиями исходных записей.
+fn read_entries(archive_data: &[u8], options: ServeOptions) -> io::Result<Vec<DirEntry>> {
+    let decompressed = processing::decompress(archive_data, None, options.use_multithreading, options.short_matches, options.strict)?;
+    if archive::is_index(&decompressed) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "index-only archive contains no file data"));
+    }
+    let entries = archive_io::bytes_to_archive_data(&decompressed, options.strict)?.entries;
+    for entry in &entries {
+        entry.verify()?;
+    }
+    let sources: HashMap<&str, &DirEntry> = entries.iter()
+        .filter(|e| e.duplicate_of().is_none())
+        .map(|e| (e.path.as_str(), e))
+        .collect();
+    entries.iter().map(|e| match e.duplicate_of() {
+        None => Ok(e.clone()),
+        Some(source) => sources.get(source).map(|s| e.resolve_duplicate(s)).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+            format!("duplicate source {} of {} is missing", source, e.path))),
+    }).collect()
+}
+
+/// Записывает записи архива потоком tar.
+fn write_tar(entries: &[DirEntry]) -> io::Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(entry.permissions & 0o7777);
+        header.set_mtime(0);
+        let (entry_type, data): (_, &[u8]) = match entry.permissions & 0o170000 {
+            _ if entry.hard_link_target().is_some() => (tar::EntryType::Link, &[]),
+            0o40000 => (tar::EntryType::Directory, &[]),
+            0o120000 => (tar::EntryType::Symlink, &[]),
+            0o100000 => (tar::EntryType::Regular, &entry.data),
+            // Каналы и устройства в поток tar не переносятся
+            _ => continue,
+        };
+        header.set_entry_type(entry_type);
+        header.set_size(data.len() as u64);
+        match entry_type {
+            tar::EntryType::Link | tar::EntryType::Symlink => {
+                let target = String::from_utf8_lossy(&entry.data).into_owned();
+                builder.append_link(&mut header, &entry.path, target)?
+            }
+            _ => builder.append_data(&mut header, &entry.path, data)?,
+        }
+    }
+    builder.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Выполняет запрос через буфер в памяти и возвращает код состояния и тело ответа.
+    fn call(request: &[u8]) -> (u16, Vec<u8>) {
+        let mut exchange = Exchange { input: io::Cursor::new(request.to_vec()), output: Vec::new() };
+        handle_connection(&mut exchange, ServeOptions::default()).unwrap();
+        let output = exchange.output;
+        let head_end = output.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let status = std::str::from_utf8(&output[9..12]).unwrap().parse().unwrap();
+        (status, output[head_end + 4..].to_vec())
+    }
+
+    /// Подключение, которое читает запрос из буфера и записывает ответ в другой буфер.
+    struct Exchange {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Exchange {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Exchange {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Составляет запрос `POST` с телом.
+    fn post(target: &str, body: &[u8]) -> Vec<u8> {
+        let mut request = format!("POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", target, body.len()).into_bytes();
+        request.extend_from_slice(body);
+        request
+    }
+
+    #[test]
+    fn test_archive_list_extract() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_size(0);
+        builder.append_data(&mut header, "docs/", &[][..]).unwrap();
+        let text = b"remote archiving remote archiving".repeat(10);
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o640);
+        header.set_size(text.len() as u64);
+        builder.append_data(&mut header, "docs/a b.txt", &text[..]).unwrap();
+        let upload = builder.into_inner().unwrap();
+
+        let (status, archive) = call(&post("/archive?algorithm=lz4", &upload));
+        assert_eq!(status, 200);
+        let (status, listing) = call(&post("/list", &archive));
+        assert_eq!(status, 200);
+        assert_eq!(String::from_utf8(listing).unwrap(), format!("40755\t0\tdocs\n100640\t{}\tdocs/a b.txt\n", text.len()));
+        assert_eq!(call(&post("/extract?path=docs%2Fa+b.txt", &archive)), (200, text.clone()));
+
+        let (status, tar_stream) = call(&post("/extract", &archive));
+        assert_eq!(status, 200);
+        let mut extracted = tar::Archive::new(&tar_stream[..]);
+        let paths: Vec<String> = extracted.entries().unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(paths, ["docs", "docs/a b.txt"]);
+
+        assert_eq!(call(&post("/archive", &upload)).0, 400);
+        assert_eq!(call(&post("/extract?path=missing", &archive)).0, 404);
+        assert_eq!(call(&post("/list", b"not an archive")).0, 422);
+        assert_eq!(call(b"GET /list HTTP/1.1\r\n\r\n").0, 405);
+    }
+
+    #[test]
+    fn test_archive_dedup() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let text = b"identical uploaded file ".repeat(50);
+        for (path, data) in [("a/first.txt", &text[..]), ("b/second.txt", &text[..]), ("other.txt", &b"other"[..])] {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            header.set_size(data.len() as u64);
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+        let (status, archive) = call(&post("/archive?algorithm=lz4", &builder.into_inner().unwrap()));
+        assert_eq!(status, 200);
+        // Повторное содержимое хранится ссылкой на первый файл, как при сжатии дерева
+        let decompressed = processing::decompress(&archive, None, false, false, true).unwrap();
+        let stored = archive_io::bytes_to_archive_data(&decompressed, true).unwrap().entries;
+        assert_eq!(stored[1].duplicate_of(), Some("a/first.txt"));
+        assert!(stored[0].duplicate_of().is_none() && stored[2].duplicate_of().is_none());
+
+        // При выдаче дубликат снова получает содержимое
+        assert_eq!(call(&post("/extract?path=b%2Fsecond.txt", &archive)), (200, text.clone()));
+        let (status, tar_stream) = call(&post("/extract", &archive));
+        assert_eq!(status, 200);
+        let files: Vec<(String, Vec<u8>)> = tar::Archive::new(&tar_stream[..]).entries().unwrap().map(|e| {
+            let mut e = e.unwrap();
+            let mut data = Vec::new();
+            e.read_to_end(&mut data).unwrap();
+            (e.path().unwrap().to_string_lossy().into_owned(), data)
+        }).collect();
+        assert_eq!(files, [("a/first.txt".into(), text.clone()), ("b/second.txt".into(), text), ("other.txt".into(), b"other".to_vec())]);
+    }
+
+    /// Подключение из отдельных потоков чтения и записи, которые могут давать сбои.
+    struct Faulty<R: Read, W: Write>(R, W);
+
+    impl<R: Read, W: Write> Read for Faulty<R, W> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl<R: Read, W: Write> Write for Faulty<R, W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.1.flush()
+        }
+    }
+
+    #[test]
+    fn test_connection_faults() {
+        use crate::faults::{FailingReader, FailingWriter};
+
+        let request = post("/list", b"not an archive");
+        // Запрос, пришедший по одному байту, разбирается так же
+        let mut connection = Faulty(FailingReader::short_reads(&request[..], 1), Vec::new());
+        handle_connection(&mut connection, ServeOptions::default()).unwrap();
+        assert!(connection.1.starts_with(b"HTTP/1.1 422 "));
+
+        // Обрыв чтения в заголовках или в теле и ошибка записи ответа возвращаются как ошибки
+        for budget in [0, 10, request.len() - 3] {
+            let connection = Faulty(FailingReader::eio(&request[..], budget), Vec::new());
+            let error = handle_connection(connection, ServeOptions::default()).unwrap_err();
+            assert_eq!(error.raw_os_error(), Some(libc::EIO));
+        }
+        for budget in [0, 20, 60] {
+            let connection = Faulty(&request[..], FailingWriter::enospc(Vec::new(), budget));
+            let error = handle_connection(connection, ServeOptions::default()).unwrap_err();
+            assert_eq!(error.raw_os_error(), Some(libc::ENOSPC));
+        }
+    }
+}
@@ -2,7 +2,15 @@
 //!
 //! Этот модуль предоставляет функции для сжатия и распаковки данных с использованием алгоритма Хаффмана. 
 //! Алгоритм Хаффмана используется для создания оптимальных префиксных кодов для символов на основе их частоты появления в данных.
+//!
+//! Числа в заголовках блоков записываются в порядке little-endian, как и остальные числа архива.
+//! Потоки архивов до версии 8 и архивов без заголовка записаны в порядке big-endian и читаются
+//! `decompress_ordered` с `ByteOrder::Big`.
 use std::collections::{BinaryHeap, HashMap};
+use std::convert::TryInto;
+use std::io;
+use crate::header::ByteOrder;
+use crate::warnings::{self, Warning};
 
 /// Структура узла дерева Хаффмана.
 #[derive(Eq, PartialEq)]
@@ -102,20 +110,149 @@ fn build_codes(node: &Option<Box<Node>>, prefix: Vec<bool>, codes: &mut HashMap<
     }
 }
 
+/// Значение поля размера словаря, означающее, что вместо частот записана структура дерева.
+const TREE_HEADER_MARKER: u16 = 0xFFFF;
+
+/// Наибольшая длина исходных данных одного блока. При таком размере частоты символов
+/// и длина упакованных битов (коды короче 48 битов) гарантированно помещаются в `u32`.
+const MAX_MEMBER_LEN: usize = 1 << 29;
+
+/// Наибольшее число узлов дерева: 256 листьев и 255 внутренних узлов. Ограничивает глубину
+/// рекурсии `read_tree` на поврежденной или подделанной структуре дерева.
+const MAX_TREE_NODES: usize = 511;
+
+/// Записывает структуру дерева в прямом порядке обхода.
+///
+/// Внутренний узел кодируется битом `0`, за которым следуют левое и правое поддеревья,
+/// лист — битом `1` и восемью битами значения байта.
+///
+/// # Аргументы
+///
+/// * `node` - Текущий узел дерева Хаффмана.
+/// * `bits` - Вектор, в который дописываются биты структуры.
+fn write_tree(node: &Node, bits: &mut Vec<bool>) {
+    match (node.byte, &node.left, &node.right) {
+        (Some(b), _, _) => {
+            bits.push(true);
+            for i in 0..8 {
+                bits.push(b & (1 << (7 - i)) != 0);
+            }
+        }
+        (None, Some(left), Some(right)) => {
+            bits.push(false);
+            write_tree(left, bits);
+            write_tree(right, bits);
+        }
+        _ => {}
+    }
+}
+
+/// Восстанавливает дерево из битов структуры, записанных `write_tree`.
+///
+/// # Аргументы
+///
+/// * `bits` - Биты структуры дерева.
+/// * `pos` - Текущая позиция чтения.
+/// * `nodes` - Число уже прочитанных узлов; больше `MAX_TREE_NODES` — ошибка.
+///
+/// # Возвращает
+///
+/// Корень поддерева или `None`, если биты структуры повреждены.
+fn read_tree(bits: &[bool], pos: &mut usize, nodes: &mut usize) -> Option<Box<Node>> {
+    *nodes += 1;
+    if *nodes > MAX_TREE_NODES {
+        return None;
+    }
+    let is_leaf = *bits.get(*pos)?;
+    *pos += 1;
+    if is_leaf {
+        if *pos + 8 > bits.len() {
+            return None;
+        }
+        let mut b = 0u8;
+        for &bit in &bits[*pos..*pos + 8] {
+            b = (b << 1) | bit as u8;
+        }
+        *pos += 8;
+        Some(Box::new(Node { freq: 0, byte: Some(b), left: None, right: None }))
+    } else {
+        let left = read_tree(bits, pos, nodes)?;
+        let right = read_tree(bits, pos, nodes)?;
+        Some(Box::new(Node { freq: 0, byte: None, left: Some(left), right: Some(right) }))
+    }
+}
+
+/// Упаковывает биты в байты, дополняя последний байт нулями.
+fn pack_bits(bits: &[bool]) -> Vec<u8> {
+    let mut packed = Vec::with_capacity(bits.len().div_ceil(8));
+    let mut byte = 0u8;
+    let mut bit_index = 0;
+    for &bit in bits {
+        byte <<= 1;
+        if bit {
+            byte |= 1;
+        }
+        bit_index += 1;
+        if bit_index == 8 {
+            packed.push(byte);
+            byte = 0;
+            bit_index = 0;
+        }
+    }
+    if bit_index != 0 {
+        packed.push(byte << (8 - bit_index));
+    }
+    packed
+}
+
+/// Распаковывает байты в последовательность битов.
+fn unpack_bits(packed: &[u8]) -> Vec<bool> {
+    let mut bits = Vec::with_capacity(packed.len() * 8);
+    for &p in packed {
+        for i in 0..8 {
+            bits.push((p & (1 << (7 - i))) != 0);
+        }
+    }
+    bits
+}
+
 /// Сжимает входные данные с использованием алгоритма Хаффмана.
 ///
 /// # Аргументы
 ///
 /// * `input` - Срез байтов, которые требуется сжать.
+/// * `tree_header` - Записывать в заголовок структуру дерева вместо таблицы частот.
 ///
 /// # Возвращает
 ///
 /// Вектор байтов, представляющий сжатые данные.
-pub fn compress(input: &[u8]) -> Vec<u8> {
+///
+/// # Примечания
+///
+/// Заголовок со структурой дерева компактнее (около 10 бит на символ вместо 5 байтов)
+/// и избавляет распаковщик от повторного построения дерева по частотам.
+/// Данные длиннее `MAX_MEMBER_LEN` сжимаются несколькими блоками, записанными подряд,
+/// чтобы длины и частоты в заголовке блока помещались в `u32`.
+pub fn compress(input: &[u8], tree_header: bool) -> Vec<u8> {
+    compress_ordered(input, tree_header, ByteOrder::Little)
+}
+
+/// Сжимает данные, как `compress`, записывая числа заголовков в порядке `order`.
+pub fn compress_ordered(input: &[u8], tree_header: bool, order: ByteOrder) -> Vec<u8> {
+    compress_members(input, tree_header, MAX_MEMBER_LEN, order)
+}
+
+/// Сжимает данные блоками не длиннее `member_len`, записанными друг за другом.
+fn compress_members(input: &[u8], tree_header: bool, member_len: usize, order: ByteOrder) -> Vec<u8> {
+    input.chunks(member_len).flat_map(|member| compress_member(member, tree_header, order)).collect()
+}
+
+/// Сжимает один блок не длиннее `MAX_MEMBER_LEN`.
+fn compress_member(input: &[u8], tree_header: bool, order: ByteOrder) -> Vec<u8> {
     if input.is_empty() {
         return vec![];
     }
-    let original_len = input.len() as u32;
+    let original_len = u32::try_from(input.len()).expect("Huffman member exceeds u32 length");
     let mut freq_map = HashMap::new();
     for &b in input {
         *freq_map.entry(b).or_insert(0) += 1;
@@ -123,17 +260,32 @@ pub fn compress(input: &[u8]) -> Vec<u8> {
     let root = build_huffman_tree(&freq_map);
     let mut codes = HashMap::new();
     build_codes(&root, vec![], &mut codes);
+    // Дерево из одного листа дает пустой код, поэтому каждому байту назначается один бит
+    if let Some(code) = codes.values_mut().find(|c| c.is_empty()) {
+        code.push(false);
+    }
 
     let mut header = Vec::new();
-    header.extend_from_slice(&original_len.to_be_bytes());
+    order.write_u32(&mut header, original_len);
 
-    // Записываем размер словаря (u16 вместо u8)
-    let dict_len = freq_map.len() as u16;
-    header.extend_from_slice(&dict_len.to_be_bytes());
+    if tree_header {
+        // Маркер, количество битов структуры и сами биты
+        let mut tree_bits = Vec::new();
+        if let Some(root) = &root {
+            write_tree(root, &mut tree_bits);
+        }
+        order.write_u16(&mut header, TREE_HEADER_MARKER);
+        order.write_u16(&mut header, tree_bits.len() as u16);
+        header.extend_from_slice(&pack_bits(&tree_bits));
+    } else {
+        // Записываем размер словаря (u16 вместо u8)
+        let dict_len = freq_map.len() as u16;
+        order.write_u16(&mut header, dict_len);
 
-    for (b, f) in freq_map {
-        header.push(b);
-        header.extend_from_slice(&(f as u32).to_be_bytes());
+        for (b, f) in freq_map {
+            header.push(b);
+            order.write_u32(&mut header, f as u32);
+        }
     }
 
     // Формируем биты согласно кодам Хаффмана
@@ -145,91 +297,122 @@ pub fn compress(input: &[u8]) -> Vec<u8> {
     }
 
     // Упаковываем биты в байты
-    let mut packed = Vec::new();
-    let mut byte = 0u8;
-    let mut bit_index = 0;
-    for bit in bits {
-        byte <<= 1;
-        if bit {
-            byte |= 1;
-        }
-        bit_index += 1;
-        if bit_index == 8 {
-            packed.push(byte);
-            byte = 0;
-            bit_index = 0;
-        }
-    }
-    if bit_index != 0 {
-        packed.push(byte << (8 - bit_index));
-    }
+    let packed = pack_bits(&bits);
 
     // Добавляем длину упакованных данных (4 байта) и сами данные
     let mut compressed = header;
-    compressed.extend_from_slice(&(packed.len() as u32).to_be_bytes());
+    order.write_u32(&mut compressed, packed.len() as u32);
     compressed.extend_from_slice(&packed);
     compressed
 }
 
+/// Читает из входных данных срез заданной длины, сдвигая позицию.
+fn take<'a>(input: &'a [u8], idx: &mut usize, len: usize) -> io::Result<&'a [u8]> {
+    if input.len() < *idx + len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Huffman: неожиданный конец данных"));
+    }
+    let slice = &input[*idx..*idx + len];
+    *idx += len;
+    Ok(slice)
+}
+
 /// Распаковывает сжатые данные, используя алгоритм Хаффмана.
 ///
+/// Формат заголовка (таблица частот или структура дерева) определяется автоматически.
+/// Несколько сжатых блоков, записанных друг за другом, распаковываются последовательно.
+///
 /// # Аргументы
 ///
 /// * `input` - Срез байтов, которые требуется распаковать.
+/// * `strict` - Строгий режим: ненулевые биты дополнения и лишние байты после данных считаются ошибкой.
 ///
 /// # Возвращает
 ///
-/// Вектор байтов, представляющий распакованные данные.
-pub fn decompress(input: &[u8]) -> Vec<u8> {
-    if input.is_empty() {
-        return vec![];
+/// Вектор байтов, представляющий распакованные данные, или ошибку при некорректном потоке.
+pub fn decompress(input: &[u8], strict: bool) -> io::Result<Vec<u8>> {
+    decompress_ordered(input, ByteOrder::Little, strict)
+}
+
+/// Распаковывает данные, как `decompress`, читая числа заголовков в порядке `order`.
+pub fn decompress_ordered(input: &[u8], order: ByteOrder, strict: bool) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    let mut idx = 0;
+    while idx < input.len() {
+        match decompress_member(&input[idx..], order, strict) {
+            Ok((data, consumed)) => {
+                decompressed.extend(data);
+                idx += consumed;
+            }
+            Err(e) if idx == 0 || strict => return Err(e),
+            Err(_) => {
+                warnings::report(Warning::TrailingStreamBytes { algorithm: "Huffman", bytes: input.len() - idx });
+                break;
+            }
+        }
     }
+    Ok(decompressed)
+}
 
+/// Распаковывает один сжатый блок, начинающийся в начале `input`.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов, начинающийся с заголовка блока.
+/// * `order` - Порядок байтов в числах заголовка.
+/// * `strict` - Строгий режим: ненулевые биты дополнения считаются ошибкой.
+///
+/// # Возвращает
+///
+/// Распакованные данные и количество прочитанных байтов или ошибку при некорректном блоке.
+fn decompress_member(input: &[u8], order: ByteOrder, strict: bool) -> io::Result<(Vec<u8>, usize)> {
     let mut idx = 0;
-    let mut buf = [0u8; 4];
-    buf.copy_from_slice(&input[idx..idx + 4]);
-    idx += 4;
-    let original_len = u32::from_be_bytes(buf) as usize;
+    let original_len = order.read_u32(take(input, &mut idx, 4)?.try_into().unwrap()) as usize;
 
     // Читаем размер словаря (2 байта)
-    let mut buf2 = [0u8; 2];
-    buf2.copy_from_slice(&input[idx..idx + 2]);
-    idx += 2;
-    let dict_len = u16::from_be_bytes(buf2) as usize;
+    let dict_len = order.read_u16(take(input, &mut idx, 2)?.try_into().unwrap());
 
-    let mut freq_map = HashMap::new();
-    for _ in 0..dict_len {
-        let b = input[idx];
-        idx += 1;
-        let mut freq_buf = [0u8; 4];
-        freq_buf.copy_from_slice(&input[idx..idx + 4]);
-        idx += 4;
-        let f = u32::from_be_bytes(freq_buf) as usize;
-        freq_map.insert(b, f);
-    }
+    let root = if dict_len == TREE_HEADER_MARKER {
+        // Заголовок со структурой дерева
+        let tree_len = order.read_u16(take(input, &mut idx, 2)?.try_into().unwrap()) as usize;
+        let tree_bits = unpack_bits(take(input, &mut idx, tree_len.div_ceil(8))?);
+        let mut pos = 0;
+        let root = read_tree(&tree_bits[..tree_len], &mut pos, &mut 0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Huffman: поврежденная структура дерева"))?;
+        if strict && (pos != tree_len || tree_bits[tree_len..].iter().any(|&b| b)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Huffman: лишние биты в структуре дерева"));
+        }
+        Some(root)
+    } else {
+        let mut freq_map = HashMap::new();
+        for _ in 0..dict_len {
+            let b = take(input, &mut idx, 1)?[0];
+            let f = order.read_u32(take(input, &mut idx, 4)?.try_into().unwrap()) as usize;
+            freq_map.insert(b, f);
+        }
+        build_huffman_tree(&freq_map)
+    };
 
     // Читаем длину упакованных данных
-    let data_len = {
-        let mut buf = [0u8; 4];
-        buf.copy_from_slice(&input[idx..idx + 4]);
-        idx += 4;
-        u32::from_be_bytes(buf) as usize
-    };
+    let data_len = order.read_u32(take(input, &mut idx, 4)?.try_into().unwrap()) as usize;
     // Извлекаем упакованные биты
-    let packed = &input[idx..(idx + data_len)];
-    let root = build_huffman_tree(&freq_map);
-
-    let mut bits = Vec::new();
-    for &p in packed {
-        for i in 0..8 {
-            bits.push((p & (1 << (7 - i))) != 0);
-        }
+    let packed = take(input, &mut idx, data_len)?;
+    // Код каждого байта занимает хотя бы один бит, поэтому длина из заголовка не может
+    // превышать число упакованных битов; иначе память под нее не выделяется
+    if original_len > packed.len().saturating_mul(8) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Huffman: данные оборваны"));
     }
+    if let Some(b) = root.as_ref().and_then(|n| n.byte) {
+        return Ok((vec![b; original_len], idx));
+    }
+
+    let bits = unpack_bits(packed);
 
     let mut node = &root;
     let mut decompressed = Vec::with_capacity(original_len);
+    let mut consumed = 0;
     // Распаковываем, пока не достигнем исходной длины
-    for bit in bits {
+    for &bit in &bits {
+        consumed += 1;
         if let Some(n) = node {
             if bit {
                 node = &n.right;
@@ -247,7 +430,13 @@ pub fn decompress(input: &[u8]) -> Vec<u8> {
             }
         }
     }
-    decompressed
+    if decompressed.len() != original_len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Huffman: данные оборваны"));
+    }
+    if strict && bits[consumed..].iter().any(|&b| b) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Huffman: ненулевые биты дополнения"));
+    }
+    Ok((decompressed, idx))
 }
 
 
@@ -258,32 +447,99 @@ mod tests {
     #[test]
     fn test_compress_decompress() {
         let input = b"AAAABBBCCDAA";
-        let compressed = compress(input);
-        let decompressed = decompress(&compressed);
+        let compressed = compress(input, false);
+        let decompressed = decompress(&compressed, true).unwrap();
         assert_eq!(decompressed, input);
     }
 
+    #[test]
+    fn test_split_members() {
+        let input: Vec<u8> = (0..1000u32).map(|i| (i % 7) as u8 + b'a').collect();
+        let compressed = compress_members(&input, true, 300, ByteOrder::Little);
+        assert_eq!(decompress(&compressed, true).unwrap(), input);
+    }
+
     #[test]
     fn test_empty_input() {
         let input: &[u8] = &[];
-        let compressed = compress(input);
-        let decompressed = decompress(&compressed);
+        let compressed = compress(input, false);
+        let decompressed = decompress(&compressed, true).unwrap();
         assert_eq!(decompressed, input);
     }
 
     #[test]
     fn test_single_byte() {
         let input = b"A";
-        let compressed = compress(input);
-        let decompressed = decompress(&compressed);
+        let compressed = compress(input, false);
+        let decompressed = decompress(&compressed, true).unwrap();
         assert_eq!(decompressed, input);
     }
 
     #[test]
     fn test_varied_input() {
         let input = b"The quick brown fox jumps over the lazy dog";
-        let compressed = compress(input);
-        let decompressed = decompress(&compressed);
+        let compressed = compress(input, false);
+        let decompressed = decompress(&compressed, true).unwrap();
         assert_eq!(decompressed, input);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tree_header_round_trip() {
+        for input in [&b"AAAABBBCCDAA"[..], b"A", b"The quick brown fox jumps over the lazy dog"] {
+            let compressed = compress(input, true);
+            assert_eq!(decompress(&compressed, true).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_tree_header_is_smaller() {
+        let input = b"The quick brown fox jumps over the lazy dog";
+        assert!(compress(input, true).len() < compress(input, false).len());
+    }
+
+    #[test]
+    fn test_strict_trailing_bytes() {
+        let mut compressed = compress(b"AAAABBBCCDAA", true);
+        compressed.push(0);
+        assert!(decompress(&compressed, false).is_ok());
+        assert!(decompress(&compressed, true).is_err());
+    }
+
+    #[test]
+    fn test_legacy_byte_order() {
+        let input = b"The quick brown fox jumps over the lazy dog";
+        for tree_header in [false, true] {
+            let legacy = compress_ordered(input, tree_header, ByteOrder::Big);
+            assert_eq!(&legacy[..4], &(input.len() as u32).to_be_bytes());
+            assert_eq!(&compress(input, tree_header)[..4], &(input.len() as u32).to_le_bytes());
+            assert_eq!(decompress_ordered(&legacy, ByteOrder::Big, true).unwrap(), input);
+            assert!(decompress(&legacy, true).is_err());
+        }
+    }
+
+    #[test]
+    fn test_concatenated_members() {
+        let mut compressed = compress(b"first part ", true);
+        compressed.extend(compress(b"second part", false));
+        assert_eq!(decompress(&compressed, true).unwrap(), b"first part second part".to_vec());
+    }
+
+    #[test]
+    fn test_malicious_headers() {
+        // Структура дерева из одних внутренних узлов: без ограничения рекурсия шла бы на 65535 уровней
+        let mut chain = 100u32.to_le_bytes().to_vec();
+        chain.extend_from_slice(&TREE_HEADER_MARKER.to_le_bytes());
+        chain.extend_from_slice(&u16::MAX.to_le_bytes());
+        chain.extend(std::iter::repeat_n(0u8, 8192));
+        chain.extend_from_slice(&16u32.to_le_bytes());
+        chain.extend([0u8; 16]);
+        assert!(decompress(&chain, false).is_err());
+
+        // Длина из заголовка больше, чем могут дать упакованные биты: ошибка без выделения памяти
+        for input in [&b"A"[..], b"AAAABBBCCDAA"] {
+            let mut forged = compress(input, true);
+            forged[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(decompress(&forged, false).is_err());
+        }
+    }
+}
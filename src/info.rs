@@ -0,0 +1,231 @@
+//! Модуль для сведений о происхождении архива.
+//!
+//! Если в заголовке установлен флаг `FLAG_INFO`, перед сжатыми данными архива
+//! располагается раздел сведений: время создания, имя компьютера, версия программы
+//! и произвольный комментарий (`--comment`), а за ними — параметры сжатия
+//! (`processing::CompressionOptions`), скорость распаковки, измеренная при создании архива,
+//! и выбор алгоритма по замеру (`--target-speed`, `--target-ratio`), если они известны. Раздел начинается со своей длины в формате
+//! varint, поэтому поля, добавленные в будущих версиях, пропускаются старыми программами.
+//! Хеш SHA-256 в трейлере архива покрывает и этот раздел.
+
+use std::ffi::CStr;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::io::{read_varint, write_varint};
+use crate::processing::CompressionOptions;
+
+/// Скорость распаковки (байтов в секунду), ниже которой `--info` предупреждает о долгом восстановлении.
+pub const SLOW_RESTORE_RATE: u64 = 20_000_000;
+
+/// Сведения о происхождении архива.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveInfo {
+    /// Время создания в секундах от начала эпохи Unix
+    pub created: u64,
+    /// Имя компьютера, на котором создан архив
+    pub hostname: String,
+    /// Имя и версия программы, создавшей архив
+    pub tool_version: String,
+    /// Комментарий пользователя
+    pub comment: Option<String>,
+    /// Параметры, с которыми архив был сжат
+    pub options: Option<CompressionOptions>,
+    /// Скорость распаковки в байтах исходных данных в секунду, измеренная при создании
+    /// (см. `processing::measure_decode_rate`); для слишком малых архивов не измеряется
+    pub decode_rate: Option<u64>,
+    /// Алгоритм, выбранный по замеру на машине, где создан архив, с замером и целью
+    /// (см. `calibrate::Selection`)
+    pub selection: Option<String>,
+}
+
+impl ArchiveInfo {
+    /// Создает сведения для архива, создаваемого сейчас на этом компьютере.
+    ///
+    /// # Аргументы
+    ///
+    /// * `comment` - Комментарий (`--comment`) или `None`.
+    pub fn current(comment: Option<String>) -> ArchiveInfo {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        ArchiveInfo {
+            created,
+            hostname: hostname().unwrap_or_default(),
+            tool_version: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            comment,
+            options: None,
+            decode_rate: None,
+            selection: None,
+        }
+    }
+
+    /// Создает сведения для воспроизводимого архива (`--reproducible`): время создания берется
+    /// из `SOURCE_DATE_EPOCH` (или равно началу эпохи), а имя компьютера не сохраняется.
+    pub fn reproducible(comment: Option<String>) -> ArchiveInfo {
+        let created = std::env::var("SOURCE_DATE_EPOCH").ok()
+            .and_then(|epoch| epoch.trim().parse().ok())
+            .unwrap_or(0);
+        ArchiveInfo { created, hostname: String::new(), ..ArchiveInfo::current(comment) }
+    }
+
+    /// Преобразует сведения в раздел архива: длина раздела, затем время создания,
+    /// строки с длинами в формате varint (пустой комментарий означает его отсутствие)
+    /// и, если они известны, параметры сжатия с длиной в формате varint (нулевая длина
+    /// означает их отсутствие), скорость распаковки (varint, нулевая скорость означает ее
+    /// отсутствие) и выбор алгоритма (строка с длиной в формате varint).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        write_varint(&mut body, self.created);
+        for s in [&self.hostname, &self.tool_version, self.comment.as_deref().unwrap_or("")] {
+            write_varint(&mut body, s.len() as u64);
+            body.extend_from_slice(s.as_bytes());
+        }
+        if self.options.is_some() || self.decode_rate.is_some() || self.selection.is_some() {
+            let options = self.options.as_ref().map(CompressionOptions::to_bytes).unwrap_or_default();
+            write_varint(&mut body, options.len() as u64);
+            body.extend(options);
+        }
+        if self.decode_rate.is_some() || self.selection.is_some() {
+            write_varint(&mut body, self.decode_rate.unwrap_or(0));
+        }
+        if let Some(selection) = &self.selection {
+            write_varint(&mut body, selection.len() as u64);
+            body.extend_from_slice(selection.as_bytes());
+        }
+        let mut buffer = Vec::with_capacity(body.len() + 2);
+        write_varint(&mut buffer, body.len() as u64);
+        buffer.extend(body);
+        buffer
+    }
+
+    /// Разбирает раздел сведений в начале данных архива.
+    ///
+    /// # Аргументы
+    ///
+    /// * `data` - Данные архива, начинающиеся с раздела сведений.
+    ///
+    /// # Возвращает
+    ///
+    /// Сведения и длину раздела в байтах или ошибку при неверном формате.
+    pub fn from_bytes(data: &[u8]) -> io::Result<(ArchiveInfo, usize)> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Раздел сведений архива поврежден");
+        let mut offset = 0;
+        let len = usize::try_from(read_varint(data, &mut offset)?).map_err(|_| invalid())?;
+        let end = offset.checked_add(len).filter(|&end| end <= data.len()).ok_or_else(invalid)?;
+        let body = &data[offset..end];
+        let mut pos = 0;
+        let created = read_varint(body, &mut pos)?;
+        let mut strings = [String::new(), String::new(), String::new()];
+        for s in strings.iter_mut() {
+            let len = usize::try_from(read_varint(body, &mut pos)?).map_err(|_| invalid())?;
+            let bytes = pos.checked_add(len).and_then(|e| body.get(pos..e)).ok_or_else(invalid)?;
+            pos += len;
+            *s = String::from_utf8(bytes.to_vec()).map_err(|_| invalid())?;
+        }
+        let [hostname, tool_version, comment] = strings;
+        let comment = if comment.is_empty() { None } else { Some(comment) };
+        // Параметров сжатия нет в архивах, созданных до их появления
+        let options = if pos < body.len() {
+            let len = usize::try_from(read_varint(body, &mut pos)?).map_err(|_| invalid())?;
+            let bytes = pos.checked_add(len).and_then(|e| body.get(pos..e)).ok_or_else(invalid)?;
+            pos += len;
+            if len == 0 { None } else { Some(CompressionOptions::from_bytes(bytes)?.0) }
+        } else {
+            None
+        };
+        // Скорости распаковки нет в архивах, созданных до ее появления
+        let decode_rate = if pos < body.len() { Some(read_varint(body, &mut pos)?).filter(|&rate| rate != 0) } else { None };
+        // Выбора алгоритма нет в архивах, созданных до его появления или с заданным -a
+        let selection = if pos < body.len() {
+            let len = usize::try_from(read_varint(body, &mut pos)?).map_err(|_| invalid())?;
+            let bytes = pos.checked_add(len).and_then(|e| body.get(pos..e)).ok_or_else(invalid)?;
+            Some(String::from_utf8(bytes.to_vec()).map_err(|_| invalid())?)
+        } else {
+            None
+        };
+        Ok((ArchiveInfo { created, hostname, tool_version, comment, options, decode_rate, selection }, end))
+    }
+}
+
+/// Возвращает имя компьютера.
+fn hostname() -> Option<String> {
+    let mut buffer = [0 as libc::c_char; 256];
+    // SAFETY: буфер действителен, а его длина передается вместе с ним; последний байт
+    // остается нулевым, поэтому строка всегда завершена
+    unsafe {
+        if libc::gethostname(buffer.as_mut_ptr(), buffer.len() - 1) != 0 {
+            return None;
+        }
+        CStr::from_ptr(buffer.as_ptr()).to_str().ok().map(str::to_owned)
+    }
+}
+
+/// Форматирует скорость в байтах в секунду как мегабайты в секунду (`~120.5 MB/s`).
+pub fn format_rate(bytes_per_sec: u64) -> String {
+    format!("~{:.1} MB/s", bytes_per_sec as f64 / 1e6)
+}
+
+/// Форматирует время в секундах от начала эпохи Unix как дату и время UTC.
+pub fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rest = secs % 86400;
+    // Преобразование номера дня в дату григорианского календаря (алгоритм Хиннанта)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, rest / 3600, rest / 60 % 60, rest % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::Algorithm;
+
+    #[test]
+    fn test_info_round_trip() {
+        let mut info = ArchiveInfo::current(Some("nightly backup".into()));
+        assert!(info.tool_version.starts_with("rle_archiver "));
+        let mut bytes = info.to_bytes();
+        bytes.extend_from_slice(b"payload");
+        let (parsed, len) = ArchiveInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, info);
+        assert_eq!(&bytes[len..], b"payload");
+        assert!(ArchiveInfo::from_bytes(&bytes[..len - 1]).is_err());
+
+        info.comment = None;
+        assert_eq!(ArchiveInfo::from_bytes(&info.to_bytes()).unwrap().0, info);
+
+        let options = CompressionOptions::new(Algorithm::Lz77).solid(true).short_matches(true).pack_below(Some(4096));
+        assert_eq!(options.to_string(), "-a lz77 --solid --short-matches --pack-below 4096");
+        info.options = Some(options);
+        let bytes = info.to_bytes();
+        assert_eq!(ArchiveInfo::from_bytes(&bytes).unwrap(), (info.clone(), bytes.len()));
+
+        // Скорость распаковки записывается и без параметров сжатия
+        info.decode_rate = Some(85_000_000);
+        assert_eq!(ArchiveInfo::from_bytes(&info.to_bytes()).unwrap().0, info);
+        info.options = None;
+        assert_eq!(ArchiveInfo::from_bytes(&info.to_bytes()).unwrap().0, info);
+
+        // Выбор алгоритма записывается и без скорости распаковки
+        info.selection = Some("lz4 (measured ~400.0 MB/s, ratio 0.500; target speed ~200.0 MB/s)".into());
+        assert_eq!(ArchiveInfo::from_bytes(&info.to_bytes()).unwrap().0, info);
+        info.decode_rate = None;
+        assert_eq!(ArchiveInfo::from_bytes(&info.to_bytes()).unwrap().0, info);
+        assert_eq!(format_rate(85_000_000), "~85.0 MB/s");
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(951782400 + 3723), "2000-02-29 01:02:03 UTC");
+        assert_eq!(format_time(1790000000), "2026-09-21 14:13:20 UTC");
+    }
+}
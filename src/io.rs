@@ -4,16 +4,39 @@
 //! рекурсивного чтения директорий, а также сериализации и десериализации
 //! данных для архивации.
 
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{self, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
-use std::os::unix::fs::PermissionsExt;
-use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use crate::checksum;
+use crate::filter::EntryType;
+use crate::glob::ExcludeSet;
+use crate::names;
+use crate::owner::{self, Owner};
+use crate::partial;
+use crate::progress;
+use crate::warnings::{self, Warning};
 use crate::ArchiveData;
 
+/// Бит режима записи, которым помечается жесткая ссылка: содержимое такой записи —
+/// путь предыдущей записи с тем же inode. Биты выше `0o177777` в `st_mode` не используются.
+pub const HARD_LINK_FLAG: u32 = 0x8000_0000;
+
+/// Бит режима записи, которым помечается дубликат: содержимое такой записи — путь предыдущей
+/// записи с тем же содержимым. В отличие от жесткой ссылки, дубликат распаковывается
+/// отдельным файлом со своими правами и владельцем.
+pub const DUPLICATE_FLAG: u32 = 0x4000_0000;
+
 /// Представляет запись директории с путем, данными и правами доступа.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DirEntry {
     /// Относительный путь к файлу или директории
     pub path: String,  
@@ -21,6 +44,64 @@ pub struct DirEntry {
     pub data: Vec<u8>,
     /// Права доступа к файлу
     pub permissions: u32,
+    /// Контрольная сумма CRC32 исходного содержимого (`None` для архивов, записанных без нее)
+    pub crc32: Option<u32>,
+    /// Владелец файла (`None` для архивов, записанных без него)
+    pub owner: Option<Owner>,
+    /// Пользовательские метаданные записи (например, тип содержимого или исходный URL)
+    pub metadata: BTreeMap<String, String>,
+    /// Время последнего изменения файла в секундах от начала эпохи Unix (`None` для архивов,
+    /// записанных без него, и для потоков)
+    pub mtime: Option<u64>,
+}
+
+impl DirEntry {
+    /// Возвращает путь записи, на которую указывает жесткая ссылка, или `None`,
+    /// если запись не является ссылкой.
+    pub fn hard_link_target(&self) -> Option<&str> {
+        if self.permissions & HARD_LINK_FLAG == 0 {
+            return None;
+        }
+        std::str::from_utf8(&self.data).ok()
+    }
+
+    /// Возвращает путь записи, содержимое которой повторяет запись-дубликат, или `None`,
+    /// если запись не является дубликатом.
+    pub fn duplicate_of(&self) -> Option<&str> {
+        if self.permissions & DUPLICATE_FLAG == 0 {
+            return None;
+        }
+        std::str::from_utf8(&self.data).ok()
+    }
+
+    /// Возвращает копию записи-дубликата с содержимым и контрольной суммой записи `source`.
+    pub fn resolve_duplicate(&self, source: &DirEntry) -> DirEntry {
+        DirEntry {
+            data: source.data.clone(),
+            permissions: self.permissions & !DUPLICATE_FLAG,
+            crc32: source.crc32,
+            ..self.clone()
+        }
+    }
+
+    /// Сверяет содержимое записи с сохраненной контрольной суммой.
+    ///
+    /// # Возвращает
+    ///
+    /// `Ok(())`, если сумма совпадает или не сохранена, иначе ошибку с путем записи.
+    pub fn verify(&self) -> io::Result<()> {
+        match self.crc32 {
+            Some(expected) => {
+                let actual = checksum::crc32(&self.data);
+                if actual != expected {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                        format!("CRC32 mismatch for {}: expected {:08x}, got {:08x}", self.path, expected, actual)));
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
 }
 
 /// Читает содержимое файла по указанному пути и возвращает его как вектор байтов.
@@ -55,66 +136,1200 @@ pub fn write_file(path: &str, data: &[u8]) -> io::Result<()> {
     Ok(())
 }
 
+/// Путь `-`: стандартный ввод вместо входного файла или стандартный вывод вместо выходного.
+pub const STDIO_PATH: &str = "-";
+
+/// Права записи, прочитанной из потока: у потока нет своих прав, поэтому он
+/// сохраняется как обычный файл с правами `0644`.
+const STREAM_MODE: u32 = 0o100644;
+
+/// Читает поток (например, стандартный ввод) целиком как единственную запись архива.
+///
+/// Запись получает пустой путь, как одиночный файл, поэтому при распаковке
+/// она записывается в выходной файл или в стандартный вывод.
+///
+/// # Аргументы
+///
+/// * `reader` - Поток для чтения.
+///
+/// # Возвращает
+///
+/// Запись с содержимым потока или ошибку чтения.
+pub fn read_stream<R: Read>(reader: &mut R) -> io::Result<DirEntry> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(DirEntry {
+        path: String::new(),
+        data,
+        permissions: STREAM_MODE,
+        crc32: None,
+        owner: None,
+        metadata: BTreeMap::new(),
+        mtime: None,
+    })
+}
+
+/// Параметры обхода дерева файлов при сжатии.
+#[derive(Debug, Default, Clone)]
+pub struct WalkOptions {
+    /// Шаблоны исключения (`--exclude`, `--exclude-from`)
+    pub exclude: ExcludeSet,
+    /// Не переходить на другие файловые системы (`--one-file-system`)
+    pub one_file_system: bool,
+    /// Пропускать директории кеша с файлом `CACHEDIR.TAG` (`--exclude-caches`)
+    pub exclude_caches: bool,
+    /// Сохранять только числовые UID и GID без имен (`--numeric-owner`)
+    pub numeric_owner: bool,
+    /// Не добавлять в архив каналы и устройства (`--skip-special`)
+    pub skip_special: bool,
+    /// Сохранять повторяющееся содержимое файлов один раз (по умолчанию; `--no-dedup` отключает)
+    pub dedup: bool,
+    /// Учитывать файлы `IGNORE_FILE` в обходимых директориях (по умолчанию; `--no-ignore-files` отключает)
+    pub ignore_files: bool,
+    /// В директориях без `IGNORE_FILE` учитывать `.gitignore` (`--gitignore`)
+    pub gitignore: bool,
+}
+
+/// Параметры записи распакованных файлов на диск.
+#[derive(Debug, Default, Clone)]
+pub struct ExtractOptions {
+    /// Восстанавливать владельца только по числовым UID и GID (`--numeric-owner`)
+    pub numeric_owner: bool,
+    /// Число потоков записи (`--threads`); 0 и 1 означают запись в одном потоке
+    pub threads: usize,
+    /// Не создавать каналы и устройства (`--skip-special`)
+    pub skip_special: bool,
+    /// Когда сбрасывать записанные данные на диск (`--fsync`)
+    pub fsync: FsyncPolicy,
+    /// В каком порядке записывать файлы (`--extract-order`)
+    pub order: ExtractOrder,
+    /// Перечитывать записанные файлы и сверять их CRC32 с сохраненной (`--report`)
+    pub verify_written: bool,
+    /// Переименовывать и имена, недопустимые в файловых системах Windows (`--portable-names`)
+    pub portable_names: bool,
+    /// Что делать с файлами, которые уже есть на месте записей (`--overwrite`)
+    pub overwrite: OverwritePolicy,
+}
+
+/// Результат записи распакованных файлов на диск.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ExtractReport {
+    /// Результаты проверки записанных файлов (пустые без `ExtractOptions::verify_written`), по пути
+    pub checks: Vec<WriteCheck>,
+    /// Пути записей, которые не записаны, потому что на их месте оставлен существующий файл
+    pub skipped: Vec<String>,
+}
+
+/// Результат проверки файла, записанного при распаковке с `ExtractOptions::verify_written`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteCheck {
+    /// Путь записи
+    pub path: String,
+    /// CRC32 из архива (или распакованного содержимого, если в архиве ее нет)
+    pub expected: u32,
+    /// CRC32 байтов, прочитанных из записанного файла
+    pub written: u32,
+}
+
+impl WriteCheck {
+    /// Возвращает `true`, если записанные байты совпали с ожидаемыми.
+    pub fn is_ok(&self) -> bool {
+        self.expected == self.written
+    }
+}
+
+/// Политика сброса распакованных данных на диск (`--fsync`).
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum FsyncPolicy {
+    /// Не сбрасывать: данные попадают на диск, когда это решит система
+    #[default]
+    Off,
+    /// Сбрасывать каждый файл сразу после записи, а в конце — директории с новыми именами
+    PerFile,
+    /// Сбросить всю файловую систему назначения один раз после распаковки
+    AtEnd,
+}
+
+impl FsyncPolicy {
+    /// Определяет политику по имени из командной строки (`per-file`, `at-end`, `off`).
+    pub fn from_name(name: &str) -> Option<FsyncPolicy> {
+        match name {
+            "off" => Some(FsyncPolicy::Off),
+            "per-file" => Some(FsyncPolicy::PerFile),
+            "at-end" => Some(FsyncPolicy::AtEnd),
+            _ => None,
+        }
+    }
+}
+
+/// Что делать при распаковке с файлом, который уже есть на месте записи (`--overwrite`).
+///
+/// Политика касается файлов, ссылок и специальных файлов; существующие директории
+/// дополняются содержимым архива.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum OverwritePolicy {
+    /// Заменять существующий файл
+    #[default]
+    Force,
+    /// Оставлять существующий файл
+    Skip,
+    /// Спрашивать о каждом существующем файле (ответы читаются из стандартного ввода)
+    Prompt,
+    /// Оставлять файл, измененный позже, чем записанный в архив
+    KeepNewer,
+}
+
+impl OverwritePolicy {
+    /// Определяет политику по имени из командной строки (`force`, `skip`, `prompt`, `keep-newer`).
+    pub fn from_name(name: &str) -> Option<OverwritePolicy> {
+        match name {
+            "force" => Some(OverwritePolicy::Force),
+            "skip" => Some(OverwritePolicy::Skip),
+            "prompt" => Some(OverwritePolicy::Prompt),
+            "keep-newer" => Some(OverwritePolicy::KeepNewer),
+            _ => None,
+        }
+    }
+}
+
+/// Решения о существующих файлах по `OverwritePolicy`.
+pub struct ExistingFiles<'a> {
+    /// Политика
+    policy: OverwritePolicy,
+    /// Ответ «для всех» на вопрос `Prompt`: заменять (`true`) или оставлять (`false`)
+    all: Option<bool>,
+    /// Откуда читаются ответы на вопросы `Prompt`
+    answers: &'a mut dyn io::BufRead,
+}
+
+impl<'a> ExistingFiles<'a> {
+    /// Создает решения по политике; ответы на вопросы читаются из `answers`.
+    pub fn new(policy: OverwritePolicy, answers: &'a mut dyn io::BufRead) -> Self {
+        ExistingFiles { policy, all: None, answers }
+    }
+
+    /// Решает, оставить ли существующий файл на месте записи.
+    ///
+    /// С `Prompt` вопрос выводится в stderr; ответ `y` заменяет файл, `a` — этот и все
+    /// следующие, `s` оставляет этот и все следующие, а любой другой ответ и конец ввода
+    /// оставляют файл. С `KeepNewer` остается файл, измененный позже записи.
+    ///
+    /// # Аргументы
+    ///
+    /// * `path` - Путь файла на месте записи.
+    /// * `mtime` - Время изменения записи в секундах от начала эпохи Unix.
+    ///
+    /// # Возвращает
+    ///
+    /// `true`, если файл есть и его нужно оставить, или ошибку ввода/вывода; с `KeepNewer`
+    /// ошибку, если время изменения записи не сохранено.
+    pub fn keeps(&mut self, path: &Path, mtime: Option<u64>) -> io::Result<bool> {
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return Ok(false);
+        };
+        match self.policy {
+            OverwritePolicy::Force => Ok(false),
+            OverwritePolicy::Skip => Ok(true),
+            OverwritePolicy::KeepNewer => {
+                let mtime = mtime.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("{}: --overwrite keep-newer needs the modification time of the entry, which the archive does not store", path.display())))?;
+                Ok(metadata.mtime() > i64::try_from(mtime).unwrap_or(i64::MAX))
+            }
+            OverwritePolicy::Prompt => {
+                if let Some(overwrite) = self.all {
+                    return Ok(!overwrite);
+                }
+                eprint!("{} exists, overwrite? [y]es, [N]o, [a]ll, [s]kip all: ", path.display());
+                let mut answer = String::new();
+                self.answers.read_line(&mut answer)?;
+                match answer.trim() {
+                    "y" | "Y" | "yes" => Ok(false),
+                    "a" | "A" | "all" => {
+                        self.all = Some(true);
+                        Ok(false)
+                    }
+                    "s" | "S" => {
+                        self.all = Some(false);
+                        Ok(true)
+                    }
+                    _ => Ok(true),
+                }
+            }
+        }
+    }
+}
+
+/// Порядок записи файлов при распаковке (`--extract-order`).
+///
+/// Лучший порядок зависит от носителя: на жестком диске запись в порядке архива
+/// не прыгает по данным архива, а запись по пути назначения собирает файлы одной
+/// директории вместе, и файловая система размещает их рядом.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ExtractOrder {
+    /// В порядке записей архива (по смещению данных)
+    #[default]
+    Archive,
+    /// По пути назначения: файлы одной директории подряд, директории в глубину
+    Path,
+}
+
+impl ExtractOrder {
+    /// Определяет порядок по имени из командной строки (`archive`, `path`).
+    pub fn from_name(name: &str) -> Option<ExtractOrder> {
+        match name {
+            "archive" => Some(ExtractOrder::Archive),
+            "path" => Some(ExtractOrder::Path),
+            _ => None,
+        }
+    }
+
+    /// Упорядочивает файлы для записи; в порядке архива срез не меняется.
+    pub fn sort(&self, files: &mut [&DirEntry]) {
+        if *self == ExtractOrder::Path {
+            files.sort_by(|a, b| {
+                let (a, b) = (Path::new(&a.path), Path::new(&b.path));
+                a.parent().cmp(&b.parent()).then_with(|| a.file_name().cmp(&b.file_name()))
+            });
+        }
+    }
+}
+
+/// Убирает из пути первые `count` компонентов (`--strip-components`).
+///
+/// # Возвращает
+///
+/// Оставшийся путь или `None`, если в пути не больше `count` компонентов.
+pub fn strip_path(path: &str, count: usize) -> Option<&str> {
+    let mut rest = path.trim_start_matches('/');
+    for _ in 0..count {
+        rest = rest.split_once('/')?.1.trim_start_matches('/');
+    }
+    (!rest.is_empty()).then_some(rest)
+}
+
+/// Убирает первые `count` компонентов из путей записей перед распаковкой, как `tar --strip-components`.
+///
+/// Записи, в пути которых не больше `count` компонентов, удаляются (см. `rename_entries`).
+///
+/// # Аргументы
+///
+/// * `entries` - Записи архива.
+/// * `count` - Число убираемых компонентов.
+pub fn strip_components(entries: &mut Vec<DirEntry>, count: usize) {
+    if count > 0 {
+        rename_entries(entries, |path| strip_path(path, count).map(str::to_owned));
+    }
+}
+
+/// Переименовывает записи перед распаковкой (`--strip-components`, `--transform`).
+///
+/// Записи, для пути которых `rename` возвращает `None` или пустой путь, удаляются. Путь,
+/// на который указывает жесткая ссылка или дубликат, переименовывается так же, а контрольная
+/// сумма такой записи пересчитывается; цели символических ссылок не меняются. Запись
+/// с пустым путем (архив одного файла) остается как есть.
+///
+/// # Аргументы
+///
+/// * `entries` - Записи архива.
+/// * `rename` - Новый путь записи по старому.
+pub fn rename_entries(entries: &mut Vec<DirEntry>, mut rename: impl FnMut(&str) -> Option<String>) {
+    entries.retain_mut(|e| {
+        if e.path.is_empty() {
+            return true;
+        }
+        let Some(path) = rename(&e.path).filter(|path| !path.is_empty()) else {
+            return false;
+        };
+        e.path = path;
+        // Поврежденная запись не исправляется: ошибка CRC32 сообщается при записи
+        let source = e.hard_link_target().or_else(|| e.duplicate_of()).filter(|_| e.verify().is_ok());
+        if let Some(source) = source.and_then(&mut rename) {
+            e.data = source.into_bytes();
+            e.crc32 = e.crc32.map(|_| checksum::crc32(&e.data));
+        }
+        true
+    });
+}
+
+/// Возвращает содержимое записи для специального файла: пустое для канала и номер
+/// устройства (`st_rdev`, `u64`) для символьного и блочного устройства.
+///
+/// # Возвращает
+///
+/// Содержимое записи или `None`, если файл не является каналом или устройством
+/// (сокеты не сохраняются: их создает программа, которая их слушает).
+pub fn special_data(metadata: &fs::Metadata) -> Option<Vec<u8>> {
+    let file_type = metadata.file_type();
+    if file_type.is_fifo() {
+        Some(Vec::new())
+    } else if file_type.is_char_device() || file_type.is_block_device() {
+        Some(metadata.rdev().to_le_bytes().to_vec())
+    } else {
+        None
+    }
+}
+
+/// Имя файла с шаблонами игнорирования в синтаксисе `.gitignore`, которые действуют
+/// на содержимое его директории (см. `WalkOptions::ignore_files`).
+pub const IGNORE_FILE: &str = ".archiverignore";
+
+/// Имя файла игнорирования git, который читается вместо `IGNORE_FILE` с `WalkOptions::gitignore`.
+const GITIGNORE_FILE: &str = ".gitignore";
+
+/// Имя файла, которым помечаются директории кеша (Cache Directory Tagging Specification).
+pub const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+
+/// Сигнатура, с которой должен начинаться файл `CACHEDIR.TAG`.
+const CACHEDIR_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// Проверяет, помечена ли директория как кеш: в ней есть `CACHEDIR.TAG`
+/// с правильной сигнатурой.
+pub fn is_cache_dir(path: &Path) -> bool {
+    let mut signature = [0u8; CACHEDIR_SIGNATURE.len()];
+    File::open(path.join(CACHEDIR_TAG))
+        .and_then(|mut f| f.read_exact(&mut signature))
+        .is_ok() && signature == CACHEDIR_SIGNATURE
+}
+
+impl WalkOptions {
+    /// Возвращает устройство корня обхода, если обход ограничен одной файловой системой.
+    pub fn root_device(&self, root_path: &Path) -> io::Result<Option<u64>> {
+        if self.one_file_system {
+            Ok(Some(fs::metadata(root_path)?.dev()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Читает шаблоны файла игнорирования директории: `IGNORE_FILE` или, если его нет
+    /// и задан `gitignore`, `.gitignore`.
+    ///
+    /// # Возвращает
+    ///
+    /// Шаблоны, `None`, если файлы игнорирования не учитываются или их нет, или ошибку чтения.
+    fn ignore_set(&self, dir: &Path) -> io::Result<Option<ExcludeSet>> {
+        if !self.ignore_files {
+            return Ok(None);
+        }
+        let names: &[&str] = if self.gitignore { &[IGNORE_FILE, GITIGNORE_FILE] } else { &[IGNORE_FILE] };
+        for name in names {
+            let path = dir.join(name);
+            match fs::read_to_string(&path) {
+                Ok(text) => {
+                    let mut set = ExcludeSet::default();
+                    set.add_ignore_lines(&text);
+                    return Ok(Some(set));
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(io::Error::new(e.kind(), format!("cannot read ignore file {}: {}", path.display(), e))),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Проверяет, нужно ли пропустить путь при обходе.
+    ///
+    /// # Аргументы
+    ///
+    /// * `path` - Путь к файлу или директории.
+    /// * `rel_path` - Тот же путь относительно корня; корень (пустой путь) не пропускается.
+    /// * `root_device` - Устройство корня, полученное `root_device`.
+    ///
+    /// # Возвращает
+    ///
+    /// `true`, если путь исключен шаблоном, является директорией кеша или находится
+    /// на другой файловой системе.
+    pub fn skips(&self, path: &Path, rel_path: &str, root_device: Option<u64>) -> io::Result<bool> {
+        if rel_path.is_empty() {
+            return Ok(false);
+        }
+        let is_dir = path.is_dir();
+        if self.exclude.matches(rel_path, is_dir) || (self.exclude_caches && is_dir && is_cache_dir(path)) {
+            return Ok(true);
+        }
+        match root_device {
+            Some(device) => Ok(fs::metadata(path)?.dev() != device),
+            None => Ok(false),
+        }
+    }
+}
+
 /// Рекурсивно читает директорию и собирает информацию о каждом файле.
 ///
+/// Файлы и директории, которые пропускает `WalkOptions::skips` или исключают файлы
+/// игнорирования (`IGNORE_FILE`, с `WalkOptions::gitignore` — `.gitignore`) обходимых
+/// директорий, не читаются; пропущенные директории не обходятся. Шаблоны файла
+/// игнорирования задаются относительно его директории, а вложенный файл переопределяет внешний. Каналы и устройства сохраняются без чтения
+/// (см. `special_data`), а сокеты пропускаются. Файл, уже встреченный под другим именем
+/// (жесткая ссылка с тем же устройством и inode), не читается повторно, а сохраняется
+/// как ссылка на первую запись (см. `HARD_LINK_FLAG`). С `WalkOptions::dedup` файл с тем же
+/// содержимым (по SHA-256), что и у прочитанного ранее, сохраняется как дубликат первой
+/// записи (см. `DUPLICATE_FLAG`), если путь короче содержимого.
+///
 /// # Аргументы
 ///
 /// * `current_path` - Текущий путь для чтения.
 /// * `root_path` - Корневой путь для определения относительных путей.
+/// * `options` - Параметры обхода.
 ///
 /// # Возвращает
 ///
 /// Вектор записей `DirEntry` или ошибку ввода/вывода.
-pub fn read_dir_recursive(current_path: &Path, root_path: &Path) -> io::Result<Vec<DirEntry>> {
+pub fn read_dir_recursive(current_path: &Path, root_path: &Path, options: &WalkOptions) -> io::Result<Vec<DirEntry>> {
+    let root_device = options.root_device(root_path)?;
+    let mut entries = Vec::new();
+    walk_dir(current_path, root_path, options, root_device, &mut WalkState::default(), &mut entries)?;
+    Ok(entries)
+}
+
+/// Разбирает список путей для `--files-from`: по пути на строку или, с `null`, пути,
+/// разделенные нулевыми байтами (вывод `find -print0`), в которых может быть и перевод строки.
+/// Пустые пути пропускаются.
+///
+/// Пути задаются относительно корня архивируемого дерева: начальные `/` и компоненты `.`
+/// отбрасываются, поэтому список абсолютных путей читается с корнем `/`. Повторы пути
+/// пропускаются, а путь с компонентом `..` считается ошибкой: он вышел бы за пределы корня.
+///
+/// # Аргументы
+///
+/// * `text` - Содержимое файла списка.
+/// * `null` - Пути разделены нулевыми байтами (`--null`), а не переводами строк.
+///
+/// # Возвращает
+///
+/// Нормализованные пути в порядке списка (пустой путь — сам корень) или ошибку.
+pub fn parse_file_list(text: &str, null: bool) -> io::Result<Vec<String>> {
+    let mut seen = HashSet::new();
+    let mut paths = Vec::new();
+    let lines: Box<dyn Iterator<Item = &str>> = if null { Box::new(text.split('\0')) } else { Box::new(text.lines()) };
+    for line in lines.filter(|line| !line.is_empty()) {
+        let components: Vec<&str> = line.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+        if components.contains(&"..") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("listed path {} leaves the base directory", line)));
+        }
+        let path = components.join("/");
+        if seen.insert(path.clone()) {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Читает записи только для перечисленных путей, не обходя остальное дерево (`--files-from`).
+///
+/// Файлы читаются как при `read_dir_recursive`, перечисленные директории обходятся
+/// целиком, а жесткие ссылки и одинаковое содержимое распознаются по всем путям списка.
+/// Запись, попавшая в архив через директорию и перечисленная еще раз, сохраняется один раз.
+///
+/// # Аргументы
+///
+/// * `root` - Корень, относительно которого заданы пути и от которого отсчитываются пути записей.
+/// * `paths` - Пути, разобранные `parse_file_list`.
+/// * `options` - Параметры обхода.
+///
+/// # Возвращает
+///
+/// Вектор записей `DirEntry` или ошибку ввода/вывода, в том числе для отсутствующего пути.
+pub fn read_listed(root: &Path, paths: &[String], options: &WalkOptions) -> io::Result<Vec<DirEntry>> {
+    let root_device = options.root_device(root)?;
+    let mut state = WalkState::default();
     let mut entries = Vec::new();
+    for path in paths {
+        let full = root.join(path);
+        fs::symlink_metadata(&full)
+            .map_err(|e| io::Error::new(e.kind(), format!("listed path {}: {}", path, e)))?;
+        walk_dir(&full, root, options, root_device, &mut state, &mut entries)?;
+    }
+    let mut seen = HashSet::new();
+    entries.retain(|e| seen.insert(e.path.clone()));
+    Ok(entries)
+}
+
+/// Возвращает путь первого файла с тем же содержимым, если файл стоит сохранить как его дубликат.
+///
+/// `contents` сопоставляет хеш SHA-256 содержимого пути первого файла с ним и пополняется.
+/// Пустые файлы и файлы с содержимым не длиннее пути первого файла дубликатами не становятся.
+fn duplicate_source(contents: &mut HashMap<[u8; 32], String>, path: &str, data: &[u8]) -> Option<String> {
+    if data.is_empty() {
+        return None;
+    }
+    let first = contents.entry(checksum::sha256(data)).or_insert_with(|| path.to_owned());
+    (first != path && first.len() < data.len()).then(|| first.clone())
+}
+
+/// Заменяет обычные файлы, содержимое которых повторяет более ранний файл, дубликатами
+/// (см. `DUPLICATE_FLAG`), как обход дерева с `WalkOptions::dedup`.
+///
+/// Используется для записей, полученных не обходом дерева (например, из потока tar),
+/// чтобы и в общем сжатом потоке одинаковые файлы сохранялись один раз.
+///
+/// # Аргументы
+///
+/// * `entries` - Записи в порядке архива; контрольные суммы дубликатов сбрасываются.
+pub fn deduplicate(entries: &mut [DirEntry]) {
+    let mut contents = HashMap::new();
+    for entry in entries.iter_mut() {
+        if entry.permissions & (HARD_LINK_FLAG | DUPLICATE_FLAG) != 0 || EntryType::from_mode(entry.permissions) != EntryType::File {
+            continue;
+        }
+        if let Some(first) = duplicate_source(&mut contents, &entry.path, &entry.data) {
+            entry.data = first.into_bytes();
+            entry.permissions |= DUPLICATE_FLAG;
+            entry.crc32 = None;
+        }
+    }
+}
+
+/// Состояние обхода, общее для всех путей одного архива.
+#[derive(Default)]
+struct WalkState {
+    /// Устройство и inode файлов с несколькими жесткими ссылками -> путь их первой записи
+    links: HashMap<(u64, u64), String>,
+    /// Хеш SHA-256 содержимого -> путь первого файла с ним
+    contents: HashMap<[u8; 32], String>,
+    /// Шаблоны файлов игнорирования директорий, в которых идет обход, от внешней к вложенной,
+    /// с путем директории относительно корня
+    ignores: Vec<(String, ExcludeSet)>,
+}
+
+impl WalkState {
+    /// Проверяет, исключен ли путь файлами игнорирования: решает самый вложенный файл,
+    /// в котором подошел хотя бы один шаблон.
+    fn ignored(&self, rel_path: &str, is_dir: bool) -> bool {
+        self.ignores.iter().rev().find_map(|(dir, set)| {
+            let sub = if dir.is_empty() { Some(rel_path) } else { rel_path.strip_prefix(dir.as_str())?.strip_prefix('/') };
+            set.verdict(sub?, is_dir)
+        }).unwrap_or(false)
+    }
+}
+
+/// Рекурсивно добавляет в `entries` файлы из `current_path`.
+fn walk_dir(current_path: &Path, root_path: &Path, options: &WalkOptions, root_device: Option<u64>,
+            state: &mut WalkState, entries: &mut Vec<DirEntry>) -> io::Result<()> {
+    if partial::interrupted() {
+        return Err(partial::interrupted_error("reading of the input was stopped".to_owned()));
+    }
+    let rel_path = current_path.strip_prefix(root_path)
+        .unwrap_or(current_path)
+        .to_str().unwrap()
+        .to_owned(); // Относительный путь
+    if options.skips(current_path, &rel_path, root_device)? || state.ignored(&rel_path, current_path.is_dir()) {
+        log::debug!("skipping {}", current_path.display());
+        return Ok(());
+    }
     if current_path.is_file() {
+        let metadata = fs::metadata(current_path)?;
+        let perm = metadata.permissions().mode(); // Получение прав доступа
+        let owner = Some(Owner::from_ids(metadata.uid(), metadata.gid(), options.numeric_owner));
+        let mtime = Some(file_mtime(&metadata));
+        // Символическая ссылка на файл сохраняется копией, поэтому inode берется у самой ссылки
+        let link_metadata = fs::symlink_metadata(current_path)?;
+        if link_metadata.is_file() && link_metadata.nlink() > 1 {
+            let key = (link_metadata.dev(), link_metadata.ino());
+            if let Some(target) = state.links.get(&key) {
+                entries.push(DirEntry {
+                    path: rel_path,
+                    data: target.clone().into_bytes(),
+                    permissions: perm | HARD_LINK_FLAG,
+                    crc32: None,
+                    owner,
+                    metadata: BTreeMap::new(),
+                    mtime,
+                });
+                return Ok(());
+            }
+            state.links.insert(key, rel_path.clone());
+        }
         let data = read_file(current_path.to_str().unwrap())?; // Чтение файла
-        let perm = fs::metadata(current_path)?.permissions().mode(); // Получение прав доступа
-        let rel_path = current_path.strip_prefix(root_path)
-            .unwrap_or(current_path)
-            .to_str().unwrap()
-            .to_owned(); // Относительный путь
+        // Без дедупликации содержимое не хешируется вовсе
+        let first = if options.dedup { duplicate_source(&mut state.contents, &rel_path, &data) } else { None };
+        if let Some(first) = first {
+            entries.push(DirEntry {
+                path: rel_path,
+                data: first.into_bytes(),
+                permissions: perm | DUPLICATE_FLAG,
+                crc32: None,
+                owner,
+                metadata: BTreeMap::new(),
+                mtime,
+            });
+            return Ok(());
+        }
         entries.push(DirEntry {
             path: rel_path,
             data,
             permissions: perm,
+            crc32: None,
+            owner,
+            metadata: BTreeMap::new(),
+            mtime,
         });
     } else if current_path.is_dir() {
+        // Шаблоны файла игнорирования действуют на все содержимое директории
+        let ignore = options.ignore_set(current_path)?;
+        let pushed = ignore.is_some();
+        state.ignores.extend(ignore.map(|set| (rel_path, set)));
         for entry in fs::read_dir(current_path)? { // Чтение содержимого директории
             let entry = entry?;
-            let path = entry.path();
-            let mut sub_entries = read_dir_recursive(&path, root_path)?; // Рекурсивный вызов
-            entries.append(&mut sub_entries);
+            walk_dir(&entry.path(), root_path, options, root_device, state, entries)?; // Рекурсивный вызов
+        }
+        if pushed {
+            state.ignores.pop();
+        }
+    } else if let Ok(metadata) = fs::metadata(current_path) {
+        // Каналы и устройства не читаются: чтение канала блокируется до появления писателя
+        if let Some(data) = special_data(&metadata).filter(|_| !options.skip_special) {
+            entries.push(DirEntry {
+                path: rel_path,
+                data,
+                permissions: metadata.mode(),
+                crc32: None,
+                owner: Some(Owner::from_ids(metadata.uid(), metadata.gid(), options.numeric_owner)),
+                metadata: BTreeMap::new(),
+                mtime: Some(file_mtime(&metadata)),
+            });
         }
     }
-    Ok(entries)
+    Ok(())
+}
+
+/// Возвращает время изменения файла в секундах от начала эпохи Unix; время до начала эпохи
+/// сохраняется как 0.
+pub fn file_mtime(metadata: &fs::Metadata) -> u64 {
+    u64::try_from(metadata.mtime()).unwrap_or(0)
+}
+
+/// Приводит записи к воспроизводимому виду (`--reproducible`), чтобы одно и то же дерево
+/// давало побайтно одинаковый архив на любой машине.
+///
+/// Записи сортируются по пути, права доступа сводятся к `0644` или `0755` (по биту
+/// исполнения), владелец и время изменения не сохраняются, а из группы жестких ссылок или дубликатов файлом
+/// становится запись с наименьшим путем, а не первая в порядке обхода файловой системы.
+///
+/// # Аргументы
+///
+/// * `entries` - Записи, прочитанные `read_dir_recursive`.
+pub fn make_reproducible(entries: &mut [DirEntry]) {
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    for e in entries.iter_mut() {
+        e.permissions = reproducible_mode(e.permissions);
+        e.owner = None;
+        e.mtime = None;
+    }
+
+    // Группы жестких ссылок: путь файла, выбранного при обходе, -> индексы записей группы
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, e) in entries.iter().enumerate() {
+        if let Some(target) = e.hard_link_target() {
+            groups.entry(target.to_owned()).or_default().push(i);
+        }
+    }
+    for (target, links) in groups {
+        let Ok(file) = entries.binary_search_by(|e| e.path.as_str().cmp(&target)) else { continue };
+        let first = links[0];
+        if first > file {
+            continue;
+        }
+        // Файл меняется местами с первой ссылкой, а пути остаются на своих местах
+        entries.swap(first, file);
+        let (head, tail) = entries.split_at_mut(file);
+        std::mem::swap(&mut head[first].path, &mut tail[0].path);
+        let primary = head[first].path.clone();
+        for i in links.into_iter().map(|i| if i == first { file } else { i }) {
+            entries[i].data = primary.clone().into_bytes();
+            entries[i].crc32 = None;
+        }
+    }
+
+    // Дубликаты сохраняют свои права, поэтому между записями переносится только содержимое
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, e) in entries.iter().enumerate() {
+        if let Some(source) = e.duplicate_of() {
+            groups.entry(source.to_owned()).or_default().push(i);
+        }
+    }
+    for (source, duplicates) in groups {
+        let Ok(file) = entries.binary_search_by(|e| e.path.as_str().cmp(&source)) else { continue };
+        let first = duplicates[0];
+        if first > file {
+            continue;
+        }
+        let data = std::mem::take(&mut entries[file].data);
+        entries[first].data = data;
+        entries[first].crc32 = None;
+        entries[first].permissions &= !DUPLICATE_FLAG;
+        entries[file].permissions |= DUPLICATE_FLAG;
+        let primary = entries[first].path.clone();
+        for i in duplicates.into_iter().skip(1).chain([file]) {
+            entries[i].data = primary.clone().into_bytes();
+            entries[i].crc32 = None;
+        }
+    }
+}
+
+/// Сводит права доступа режима к `0644` или `0755` (по биту исполнения), сохраняя биты типа.
+pub fn reproducible_mode(mode: u32) -> u32 {
+    let perm = if mode & 0o111 != 0 { 0o755 } else { 0o644 };
+    (mode & !0o7777) | perm
+}
+
+/// Вычисляет недостающие контрольные суммы записей на нескольких потоках и сохраняет их в записях.
+///
+/// # Аргументы
+///
+/// * `entries` - Записи, прочитанные `read_dir_recursive`.
+pub fn fill_checksums(entries: &mut [DirEntry]) {
+    let blocks: Vec<&[u8]> = entries.iter().filter(|e| e.crc32.is_none()).map(|e| e.data.as_slice()).collect();
+    let mut checksums = checksum::crc32_parallel(&blocks).into_iter();
+    for e in entries.iter_mut().filter(|e| e.crc32.is_none()) {
+        e.crc32 = checksums.next();
+    }
 }
 
 /// Записывает записи директории на диск по базовому пути.
 ///
+/// Перед записью каждого файла его содержимое сверяется с сохраненной контрольной суммой.
+/// При запуске от имени root восстанавливается сохраненный владелец; если это не удалось,
+/// выводится предупреждение. Каналы и устройства создаются `mkfifo`/`mknod`; если это
+/// не удалось (устройства может создавать только root), выводится предупреждение.
+/// Файлы записываются в порядке `ExtractOptions::order` (потоки берут их из общей очереди
+/// по порядку). Файлы независимы, поэтому с `threads > 1` записываются параллельно; дубликаты (см.
+/// `DUPLICATE_FLAG`) записываются такими же файлами с содержимым исходной записи. Жесткие ссылки
+/// создаются после всех файлов и разделяют с ними inode, поэтому владелец и права у них
+/// не восстанавливаются отдельно. Директории из записей архива создаются заранее, а их
+/// владелец и права восстанавливаются в самом конце, начиная с самых глубоких: иначе
+/// в директорию без права записи нельзя было бы распаковать ее содержимое.
+///
+/// Если путь встречается несколько раз (например, в склеенных архивах `cat a.arc b.arc`),
+/// записывается только последняя запись: иначе параллельные потоки записывали бы один
+/// файл одновременно, и результат зависел бы от их порядка.
+///
+/// Запись с именем, недопустимым в системе назначения, распаковывается под именем из
+/// `names::target_path`, и о замене сообщается предупреждением `Warning::PathRenamed`.
+///
+/// С `ExtractOptions::verify_written` каждый записанный файл перечитывается и его CRC32
+/// сравнивается с сохраненной: так обнаруживается порча данных между распаковкой и диском.
+/// Несовпадение не прерывает распаковку, а попадает в результат. Перечитывание может
+/// обслуживаться из кеша страниц, поэтому ошибки самого носителя видны не всегда.
+///
+/// Существующие файлы на месте записей заменяются или остаются по `ExtractOptions::overwrite`;
+/// решения принимаются до записи, поэтому вопросы `Prompt` задаются по одному.
+///
 /// # Аргументы
 ///
 /// * `entries` - Срез записей `DirEntry`.
 /// * `base_path` - Базовый путь для создания файлов.
+/// * `options` - Параметры записи.
 ///
 /// # Возвращает
 ///
-/// Результат операции или ошибку ввода/вывода.
-pub fn write_dir_entries(entries: &[DirEntry], base_path: &Path) -> io::Result<()> {
-    for e in entries {
-        let real_path = base_path.join(&e.path); // Формирование полного пути
+/// Результаты проверки записанных файлов и пропущенные записи или ошибку ввода/вывода
+/// (в том числе при несовпадении контрольной суммы распакованного содержимого,
+/// отсутствии файла, на который указывает жесткая ссылка или дубликат, или прерывании с числом уже
+/// записанных файлов).
+pub fn write_dir_entries(entries: &[DirEntry], base_path: &Path, options: &ExtractOptions) -> io::Result<ExtractReport> {
+    let threads = options.threads;
+    let latest: HashMap<&str, usize> = entries.iter().enumerate().map(|(i, e)| (e.path.as_str(), i)).collect();
+    let (mut dirs, entries_rest): (Vec<&DirEntry>, Vec<&DirEntry>) = entries.iter().enumerate()
+        .filter(|(i, e)| latest[e.path.as_str()] == *i)
+        .map(|(_, e)| e)
+        .partition(|e| EntryType::from_mode(e.permissions) == EntryType::Dir);
+    for e in dirs.iter().chain(&entries_rest) {
+        if let Cow::Owned(renamed) = names::target_path(&e.path, options.portable_names) {
+            warnings::report(Warning::PathRenamed { path: e.path.clone(), renamed });
+        }
+    }
+    let (mut hard_links, files): (Vec<&DirEntry>, Vec<&DirEntry>) = entries_rest.into_iter().partition(|e| e.hard_link_target().is_some());
+    let (duplicates, mut files): (Vec<&DirEntry>, Vec<&DirEntry>) = files.into_iter().partition(|e| e.duplicate_of().is_some());
+    // Дубликаты записываются обычными файлами с содержимым исходной записи
+    let resolved = duplicates.iter().map(|e| {
+        e.verify()?;
+        let source = e.duplicate_of().unwrap_or_default();
+        match latest.get(source).map(|&i| &entries[i]) {
+            Some(s) if s.duplicate_of().is_none() && s.hard_link_target().is_none() => Ok(e.resolve_duplicate(s)),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound,
+                format!("duplicate source {} of {} was not extracted", source, e.path))),
+        }
+    }).collect::<io::Result<Vec<DirEntry>>>()?;
+    files.extend(&resolved);
+    options.order.sort(&mut files);
+    let mut skipped = Vec::new();
+    if options.overwrite != OverwritePolicy::Force {
+        let stdin = io::stdin();
+        let mut answers: Box<dyn io::BufRead> = if options.overwrite == OverwritePolicy::Prompt { Box::new(stdin.lock()) } else { Box::new(io::empty()) };
+        let mut existing = ExistingFiles::new(options.overwrite, &mut *answers);
+        for list in [&mut files, &mut hard_links] {
+            let mut kept = Vec::with_capacity(list.len());
+            for e in list.drain(..) {
+                if existing.keeps(&target_path(base_path, &e.path, options), e.mtime)? {
+                    progress::advance(&e.path, e.data.len() as u64);
+                    skipped.push(e.path.clone());
+                } else {
+                    kept.push(e);
+                }
+            }
+            *list = kept;
+        }
+    }
+    for e in &dirs {
+        e.verify()?;
+        fs::create_dir_all(target_path(base_path, &e.path, options))?;
+    }
+
+    // Потоки берут следующий файл из общей очереди; после первой ошибки остальные останавливаются
+    let next = AtomicUsize::new(0);
+    let written = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let worker = || -> io::Result<(Vec<Warning>, Vec<WriteCheck>)> {
+        let mut warnings = Vec::new();
+        let mut checks = Vec::new();
+        while let Some(e) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+            if failed.load(Ordering::Relaxed) {
+                break;
+            }
+            if partial::interrupted() {
+                failed.store(true, Ordering::Relaxed);
+                let done = written.load(Ordering::Relaxed);
+                return Err(partial::interrupted_error(format!("extracted {} of {} entries", done, entries.len())));
+            }
+            match write_file_entry(e, base_path, options, &mut warnings) {
+                Ok(check) => checks.extend(check),
+                Err(err) => {
+                    failed.store(true, Ordering::Relaxed);
+                    return Err(err);
+                }
+            }
+            written.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok((warnings, checks))
+    };
+    let results = if threads > 1 && files.len() > 1 {
+        thread::scope(|s| {
+            let handles: Vec<_> = (0..threads.min(files.len())).map(|_| s.spawn(worker)).collect();
+            handles.into_iter().map(|h| h.join().expect("Thread failed")).collect()
+        })
+    } else {
+        vec![worker()]
+    };
+    // Предупреждения сообщаются из вызывающего потока, чтобы их собирал `warnings::capture`
+    let mut first_error = None;
+    let mut checks = Vec::new();
+    for result in results {
+        match result {
+            Ok((list, written)) => {
+                list.into_iter().for_each(warnings::report);
+                checks.extend(written);
+            }
+            Err(err) => {
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+    if let Some(err) = first_error {
+        return Err(err);
+    }
+
+    for e in hard_links {
+        e.verify()?;
+        let target = e.hard_link_target().unwrap_or_default();
+        let real_path = target_path(base_path, &e.path, options);
         if let Some(parent) = real_path.parent() {
-            fs::create_dir_all(parent)?; // Создание всех родительских директорий
+            fs::create_dir_all(parent)?;
+        }
+        progress::advance(&e.path, e.data.len() as u64);
+        let target_path = target_path(base_path, target, options);
+        if !target_path.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound,
+                format!("hard link target {} of {} was not extracted", target, real_path.display())));
+        }
+        // Ссылка заменяет файл, оставшийся от прежней распаковки
+        if fs::symlink_metadata(&real_path).is_ok() {
+            fs::remove_file(&real_path)?;
+        }
+        fs::hard_link(&target_path, &real_path)?;
+    }
+
+    // Права и время изменения директорий восстанавливаются после содержимого, от вложенных к внешним
+    dirs.sort_by_key(|e| std::cmp::Reverse(Path::new(&e.path).components().count()));
+    for e in dirs {
+        let real_path = target_path(base_path, &e.path, options);
+        if let Some(owner) = &e.owner {
+            if let Err(err) = owner::restore(&real_path, owner, options.numeric_owner) {
+                warnings::report(Warning::OwnerNotRestored { path: e.path.clone(), error: err.to_string() });
+            }
+        }
+        fs::set_permissions(&real_path, fs::Permissions::from_mode(e.permissions))?;
+        if let Some(mtime) = e.mtime {
+            restore_mtime(&real_path, mtime)?;
+        }
+        progress::advance(&e.path, 0);
+    }
+    sync_tree(entries, base_path, options)?;
+    checks.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(ExtractReport { checks, skipped })
+}
+
+/// Сбрасывает на диск результат распаковки по политике `--fsync`.
+///
+/// С `PerFile` файлы уже сброшены, и остается сбросить директории, в которых появились
+/// новые имена; с `AtEnd` один вызов `syncfs` сбрасывает всю файловую систему назначения.
+fn sync_tree(entries: &[DirEntry], base_path: &Path, options: &ExtractOptions) -> io::Result<()> {
+    match options.fsync {
+        FsyncPolicy::Off => Ok(()),
+        FsyncPolicy::PerFile => {
+            let mut dirs: Vec<_> = entries.iter()
+                .filter_map(|e| target_path(base_path, &e.path, options).parent().map(Path::to_path_buf))
+                .collect();
+            dirs.sort();
+            dirs.dedup();
+            // Директорий пропущенных записей (`--skip-special`) может не быть
+            for dir in dirs.into_iter().filter(|dir| dir.is_dir()) {
+                File::open(dir)?.sync_all()?;
+            }
+            Ok(())
+        }
+        FsyncPolicy::AtEnd => {
+            let dir = File::open(base_path)?;
+            // SAFETY: дескриптор принадлежит открытому файлу и действителен во время вызова
+            if unsafe { libc::syncfs(dir.as_raw_fd()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
         }
-        let mut file = File::create(&real_path)?; // Создание файла
+    }
+}
+
+/// Возвращает путь, по которому запись создается при распаковке (см. `names::target_path`).
+fn target_path(base_path: &Path, path: &str, options: &ExtractOptions) -> PathBuf {
+    base_path.join(&*names::target_path(path, options.portable_names))
+}
+
+/// Записывает на диск одну запись-файл для `write_dir_entries`.
+///
+/// # Аргументы
+///
+/// * `e` - Запись `DirEntry`.
+/// * `base_path` - Базовый путь для создания файлов.
+/// * `options` - Параметры записи.
+/// * `warnings` - Список, в который добавляются некритичные проблемы.
+///
+/// # Возвращает
+///
+/// Результат проверки записанного файла (с `verify_written`) или ошибку ввода/вывода.
+fn write_file_entry(e: &DirEntry, base_path: &Path, options: &ExtractOptions, warnings: &mut Vec<Warning>) -> io::Result<Option<WriteCheck>> {
+    e.verify()?; // Проверка целостности содержимого
+    let special = EntryType::from_mode(e.permissions).is_special();
+    if special && options.skip_special {
+        return Ok(None);
+    }
+    let real_path = target_path(base_path, &e.path, options); // Формирование полного пути
+    if let Some(parent) = real_path.parent() {
+        fs::create_dir_all(parent)?; // Создание всех родительских директорий
+    }
+    if special {
+        if let Err(err) = make_special(&real_path, e) {
+            warnings.push(Warning::SpecialNotRestored { path: e.path.clone(), error: err.to_string() });
+            return Ok(None);
+        }
+    }
+    let mut check = None;
+    let file = if special {
+        None
+    } else {
+        // Файл открывается и на чтение, чтобы перечитать записанное до смены прав
+        let mut file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&real_path)?; // Создание файла
         file.write_all(&e.data)?; // Запись данных в файл
-        fs::set_permissions(&real_path, fs::Permissions::from_mode(e.permissions))?; // Установка прав доступа
+        if options.verify_written {
+            let expected = e.crc32.unwrap_or_else(|| checksum::crc32(&e.data));
+            check = Some(WriteCheck { path: e.path.clone(), expected, written: reread_crc32(&mut file)? });
+        }
+        Some(file)
+    };
+    if let Some(owner) = &e.owner {
+        // Владелец восстанавливается до прав доступа: chown сбрасывает биты setuid/setgid
+        if let Err(err) = owner::restore(&real_path, owner, options.numeric_owner) {
+            warnings.push(Warning::OwnerNotRestored { path: e.path.clone(), error: err.to_string() });
+        }
+    }
+    fs::set_permissions(&real_path, fs::Permissions::from_mode(e.permissions))?; // Установка прав доступа
+    if let Some(mtime) = e.mtime {
+        restore_mtime(&real_path, mtime)?;
+    }
+    if let Some(file) = file.filter(|_| options.fsync == FsyncPolicy::PerFile) {
+        file.sync_all()?;
+    }
+    progress::advance(&e.path, e.data.len() as u64);
+    Ok(check)
+}
+
+/// Перечитывает файл с начала и возвращает CRC32 его содержимого.
+pub fn reread_crc32(file: &mut File) -> io::Result<u32> {
+    file.flush()?;
+    io::Seek::seek(file, io::SeekFrom::Start(0))?;
+    let mut crc = checksum::Crc32::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(crc.finish()),
+            n => crc.update(&buffer[..n]),
+        }
+    }
+}
+
+/// Создает канал или устройство для записи, сохраненной `special_data`, заменяя
+/// файл, оставшийся от прежней распаковки.
+fn make_special(path: &Path, e: &DirEntry) -> io::Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+    let rdev = match EntryType::from_mode(e.permissions) {
+        EntryType::Fifo => 0,
+        _ => u64::from_le_bytes(e.data.as_slice().try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid device number for {}", e.path)))?),
+    };
+    // SAFETY: путь — действительная строка C, остальные аргументы передаются по значению
+    if unsafe { libc::mknod(c_path.as_ptr(), e.permissions as libc::mode_t, rdev as libc::dev_t) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Устанавливает время изменения файла, канала, устройства или директории, не меняя время
+/// доступа и не переходя по символической ссылке.
+///
+/// # Аргументы
+///
+/// * `path` - Путь к созданному при распаковке объекту.
+/// * `mtime` - Время изменения в секундах от начала эпохи Unix.
+pub fn restore_mtime(path: &Path, mtime: u64) -> io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+    let times = [
+        libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_OMIT },
+        libc::timespec { tv_sec: mtime.min(i64::MAX as u64) as libc::time_t, tv_nsec: 0 },
+    ];
+    // SAFETY: путь — действительная строка C, массив из двух элементов живет до конца вызова
+    if unsafe { libc::utimensat(libc::AT_FDCWD, c_path.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW) } != 0 {
+        return Err(io::Error::last_os_error());
     }
     Ok(())
 }
 
+/// Сигнатура, с которой начинается сериализованный набор записей с 64-битными длинами.
+///
+/// Наборы без сигнатуры записаны старым форматом с длинами `u32`.
+pub const CONTAINER_MAGIC: &[u8; 4] = b"RDIR";
+
+/// Сигнатура набора записей, в котором путь каждой записи хранится относительно пути
+/// предыдущей: длина общего префикса в формате varint и окончание пути.
+pub const PREFIX_CONTAINER_MAGIC: &[u8; 4] = b"RDIP";
+
+/// Возвращает длину общего префикса двух путей в байтах, не разрывая символы UTF-8.
+pub fn shared_prefix_len(previous: &str, path: &str) -> usize {
+    let mut len = previous.bytes().zip(path.bytes()).take_while(|(a, b)| a == b).count();
+    while !path.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+/// Дописывает число в формате varint (LEB128): по 7 битов в байте, младшие первыми.
+pub fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Читает число в формате varint, сдвигая позицию.
+///
+/// # Возвращает
+///
+/// Число или ошибку, если данные обрезаны или число не помещается в `u64`.
+pub fn read_varint(data: &[u8], offset: &mut usize) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*offset)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Недостаточно данных для чтения длины"))?;
+        *offset += 1;
+        if shift == 63 && byte > 1 {
+            break;
+        }
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "Длина не помещается в 64 бита"))
+}
+
+/// Функция чтения длины: varint в текущем формате или `u32` в старом.
+type ReadLen = fn(&[u8], &mut usize) -> io::Result<usize>;
+
+/// Читает длину в формате varint и проверяет, что она помещается в `usize`.
+fn read_varint_len(data: &[u8], offset: &mut usize) -> io::Result<usize> {
+    usize::try_from(read_varint(data, offset)?)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Длина превышает адресное пространство"))
+}
+
+/// Читает длину старого формата (`u32`), сдвигая позицию.
+fn read_u32_len(data: &[u8], offset: &mut usize) -> io::Result<usize> {
+    use std::convert::TryInto;
+    let bytes = data.get(*offset..*offset + 4)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Недостаточно данных для чтения длины"))?;
+    *offset += 4;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+/// Значение, которое записывается вместо отсутствующего владельца перед метаданными:
+/// UID в формате varint, не помещающийся в `u32`.
+const NO_OWNER: u64 = 1 << 32;
+
+/// Дописывает пользовательские метаданные: количество пар, затем ключи и значения
+/// с длинами в формате varint.
+pub fn write_metadata(buffer: &mut Vec<u8>, metadata: &BTreeMap<String, String>) {
+    write_varint(buffer, metadata.len() as u64);
+    for (key, value) in metadata {
+        for s in [key, value] {
+            write_varint(buffer, s.len() as u64);
+            buffer.extend_from_slice(s.as_bytes());
+        }
+    }
+}
+
+/// Читает метаданные, записанные `write_metadata`, сдвигая позицию.
+///
+/// # Возвращает
+///
+/// Метаданные или ошибку при неверном формате.
+pub fn read_metadata(data: &[u8], offset: &mut usize) -> io::Result<BTreeMap<String, String>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Неверный формат метаданных записи");
+    let count = read_varint(data, offset)?;
+    let mut metadata = BTreeMap::new();
+    for _ in 0..count {
+        let mut pair = [String::new(), String::new()];
+        for s in pair.iter_mut() {
+            let len = read_varint_len(data, offset)?;
+            let bytes = offset.checked_add(len).and_then(|end| data.get(*offset..end)).ok_or_else(invalid)?;
+            *offset += len;
+            *s = String::from_utf8(bytes.to_vec()).map_err(|_| invalid())?;
+        }
+        let [key, value] = pair;
+        metadata.insert(key, value);
+    }
+    Ok(metadata)
+}
+
 /// Преобразует `DirEntry` в байты для сериализации.
 ///
+/// Длины пути и данных записываются в формате varint, поэтому размер файла не ограничен 4 ГиБ.
+/// Необязательные поля следуют за данными по порядку: контрольная сумма, владелец,
+/// метаданные и время изменения (varint, с версии формата 10); поле записывается,
+/// если оно задано или за ним следует заданное поле.
+///
 /// # Аргументы
 ///
 /// * `entry` - Ссылка на запись `DirEntry`.
@@ -123,22 +1338,56 @@ pub fn write_dir_entries(entries: &[DirEntry], base_path: &Path) -> io::Result<(
 ///
 /// Вектор байтов, представляющих `DirEntry`.
 pub fn dir_entry_to_bytes(entry: &DirEntry) -> Vec<u8> {
+    entry_to_bytes(entry, &entry.path, false)
+}
+
+/// Преобразует запись в байты, как `dir_entry_to_bytes`, но всегда записывает контрольную
+/// сумму, вычисляя ее, если она не задана.
+///
+/// Так сумма записи считается в той же задаче, которая ее сжимает, без отдельного прохода.
+pub fn dir_entry_to_bytes_with_crc(entry: &DirEntry) -> Vec<u8> {
+    entry_to_bytes(entry, &entry.path, true)
+}
+
+/// Преобразует запись в байты, записывая вместо ее пути `path` (в наборе с общими
+/// префиксами — только окончание пути); с `always_crc` контрольная сумма записывается всегда.
+fn entry_to_bytes(entry: &DirEntry, path: &str, always_crc: bool) -> Vec<u8> {
     let mut result = Vec::new();
 
     // Запись прав доступа (4 байта)
     result.extend_from_slice(&entry.permissions.to_le_bytes());
 
     // Запись пути
-    let path_bytes = entry.path.as_bytes();
-    let path_len = path_bytes.len() as u32;
-    result.extend_from_slice(&path_len.to_le_bytes());
+    let path_bytes = path.as_bytes();
+    write_varint(&mut result, path_bytes.len() as u64);
     result.extend_from_slice(path_bytes);
 
     // Запись данных файла
-    let data_len = entry.data.len() as u32;
-    result.extend_from_slice(&data_len.to_le_bytes());
+    write_varint(&mut result, entry.data.len() as u64);
     result.extend_from_slice(&entry.data);
 
+    // Запись контрольной суммы (4 байта), если она вычислена или за ней следуют другие поля
+    let has_metadata = !entry.metadata.is_empty() || entry.mtime.is_some();
+    let crc = entry.crc32.or_else(|| (always_crc || entry.owner.is_some() || has_metadata).then(|| checksum::crc32(&entry.data)));
+    if let Some(crc) = crc {
+        result.extend_from_slice(&crc.to_le_bytes());
+    }
+
+    // Запись владельца; перед метаданными отсутствующий владелец отмечается `NO_OWNER`
+    match &entry.owner {
+        Some(owner) => owner.write(&mut result),
+        None if has_metadata => write_varint(&mut result, NO_OWNER),
+        None => {}
+    }
+
+    // Запись метаданных (пустых перед временем изменения) и времени изменения
+    if has_metadata {
+        write_metadata(&mut result, &entry.metadata);
+    }
+    if let Some(mtime) = entry.mtime {
+        write_varint(&mut result, mtime);
+    }
+
     result
 }
 
@@ -152,48 +1401,90 @@ pub fn dir_entry_to_bytes(entry: &DirEntry) -> Vec<u8> {
 ///
 /// Результат с `DirEntry` или ошибкой ввода/вывода.
 pub fn bytes_to_dir_entry(data: &[u8]) -> std::io::Result<DirEntry> {
+    parse_dir_entry(data, read_varint_len)
+}
+
+/// Преобразует в `DirEntry` байты старого формата с длинами `u32`
+/// (записи архивов версии 2 и более ранних).
+pub fn bytes_to_dir_entry_legacy(data: &[u8]) -> std::io::Result<DirEntry> {
+    parse_dir_entry(data, read_u32_len)
+}
+
+/// Разбирает запись, читая длины пути и данных функцией `read_len`.
+fn parse_dir_entry(data: &[u8], read_len: ReadLen) -> io::Result<DirEntry> {
     use std::convert::TryInto;
     let mut offset = 0;
+    let truncated = || io::Error::new(io::ErrorKind::InvalidData, "Недостаточно данных для чтения записи");
 
     // Чтение прав доступа
-    let permissions = u32::from_le_bytes(data[offset..offset+4].try_into().unwrap());
+    let permissions = u32::from_le_bytes(data.get(offset..offset+4).ok_or_else(truncated)?.try_into().unwrap());
     offset += 4;
 
     // Чтение пути
-    let path_len = u32::from_le_bytes(data[offset..offset+4].try_into().unwrap()) as usize;
-    offset += 4;
-    let path_bytes = &data[offset..offset+path_len];
+    let path_len = read_len(data, &mut offset)?;
+    let path_bytes = data.get(offset..offset.checked_add(path_len).ok_or_else(truncated)?).ok_or_else(truncated)?;
     offset += path_len;
     let path_str = String::from_utf8(path_bytes.to_vec())
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Неверный формат"))?;
 
     // Чтение данных файла
-    let data_len = u32::from_le_bytes(data[offset..offset+4].try_into().unwrap()) as usize;
+    let data_len = read_len(data, &mut offset)?;
+    let file_data = data.get(offset..offset.checked_add(data_len).ok_or_else(truncated)?).ok_or_else(truncated)?.to_vec();
+    offset += data_len;
+
+    // Чтение контрольной суммы: в записях старого формата ее нет
+    let crc32 = data.get(offset..offset+4).map(|b| u32::from_le_bytes(b.try_into().unwrap()));
     offset += 4;
-    let file_data = data[offset..offset+data_len].to_vec();
+
+    // Чтение владельца: он записывается после контрольной суммы, если известен
+    let mut peek = offset;
+    let owner = if offset >= data.len() {
+        None
+    } else if read_varint(data, &mut peek)? == NO_OWNER {
+        offset = peek;
+        None
+    } else {
+        Some(Owner::read(data, &mut offset)?)
+    };
+
+    // Чтение метаданных, если они записаны после владельца, и времени изменения после них
+    let metadata = if offset < data.len() { read_metadata(data, &mut offset)? } else { BTreeMap::new() };
+    let mtime = if offset < data.len() { Some(read_varint(data, &mut offset)?) } else { None };
 
     Ok(DirEntry {
         path: path_str,
         data: file_data,
         permissions,
+        crc32,
+        owner,
+        metadata,
+        mtime,
     })
 }
 
 /// Преобразование ArchiveData в байты
+///
+/// Набор начинается с `PREFIX_CONTAINER_MAGIC`, а количество записей и их размеры
+/// записываются в формате varint. Перед каждой записью хранится длина префикса,
+/// общего с путем предыдущей записи, а в самой записи — только окончание пути:
+/// в глубоких деревьях соседние записи разделяют длинные пути директорий.
 pub fn archive_data_to_bytes(archive: &ArchiveData) -> Vec<u8> {
     let mut buffer = Vec::new();
+    buffer.extend_from_slice(PREFIX_CONTAINER_MAGIC);
 
     // Запись количества записей DirEntry
-    let entries_len = archive.entries.len() as u32;
-    buffer.extend_from_slice(&entries_len.to_le_bytes());
+    write_varint(&mut buffer, archive.entries.len() as u64);
 
     // Запись каждой записи DirEntry
+    let mut previous = "";
     for entry in &archive.entries {
-        let entry_bytes = dir_entry_to_bytes(entry);
-        let entry_size = entry_bytes.len() as u32;
+        let shared = shared_prefix_len(previous, &entry.path);
+        write_varint(&mut buffer, shared as u64);
+        let entry_bytes = entry_to_bytes(entry, &entry.path[shared..], false);
+        previous = &entry.path;
 
         // Сначала записываем размер записи
-        buffer.extend_from_slice(&entry_size.to_le_bytes());
+        write_varint(&mut buffer, entry_bytes.len() as u64);
         // Затем сами байты записи
         buffer.extend_from_slice(&entry_bytes);
     }
@@ -201,47 +1492,753 @@ pub fn archive_data_to_bytes(archive: &ArchiveData) -> Vec<u8> {
     buffer
 }
 
+/// Разбирает один архив (член потока), начинающийся в начале `data`.
+///
+/// Наборы с `PREFIX_CONTAINER_MAGIC` читаются с общими префиксами путей, с `CONTAINER_MAGIC` —
+/// с полными путями и 64-битными длинами, наборы без сигнатуры — в старом формате с длинами `u32`.
+///
+/// # Аргументы
+///
+/// * `data` - Срез байтов, начинающийся с сигнатуры или количества записей.
+/// * `entries` - Вектор, в который добавляются прочитанные записи; при ошибке в нем
+///   остаются записи, прочитанные до нее.
+///
+/// # Возвращает
+///
+/// Количество прочитанных байтов или ошибку ввода/вывода.
+fn parse_archive_member(data: &[u8], entries: &mut Vec<DirEntry>) -> io::Result<usize> {
+    let prefixed = data.starts_with(PREFIX_CONTAINER_MAGIC);
+    let (mut offset, read_len): (usize, ReadLen) = if prefixed || data.starts_with(CONTAINER_MAGIC) {
+        (CONTAINER_MAGIC.len(), read_varint_len)
+    } else {
+        (0, read_u32_len)
+    };
+    let mut previous = String::new();
+
+    // Чтение количества записей `DirEntry`
+    let entries_len = read_len(data, &mut offset)?;
+
+    // Емкость не резервируется заранее: количество записей может быть прочитано из мусора
+    for _ in 0..entries_len {
+        let dir_entry = read_framed(data, &mut offset, prefixed, read_len, &previous)??;
+        if prefixed {
+            previous.clone_from(&dir_entry.path);
+        }
+        entries.push(dir_entry);
+    }
+
+    Ok(offset)
+}
+
+/// Читает одну запись набора: длину префикса пути, общего с предыдущей записью, размер
+/// записи и ее байты.
+///
+/// # Возвращает
+///
+/// Внешняя ошибка означает, что границы записи прочитать не удалось. Иначе позиция
+/// сдвигается за запись, а внутренний результат — запись с полным путем или ошибка ее разбора.
+fn read_framed(data: &[u8], offset: &mut usize, prefixed: bool, read_len: ReadLen, previous: &str) -> io::Result<io::Result<DirEntry>> {
+    // Длина префикса, общего с путем предыдущей записи
+    let shared = if prefixed { read_varint_len(data, offset)? } else { 0 };
+
+    // Чтение размера и байтов записи `DirEntry`
+    let entry_size = read_len(data, offset)?;
+    let entry_bytes = offset.checked_add(entry_size).and_then(|end| data.get(*offset..end))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Недостаточно данных для чтения записи"))?;
+    *offset += entry_size;
+
+    if !previous.is_char_boundary(shared) {
+        return Ok(Err(io::Error::new(io::ErrorKind::InvalidData, "Неверная длина общего префикса пути")));
+    }
+    // Восстановление записи `DirEntry` из байтов
+    Ok(parse_dir_entry(entry_bytes, read_len).map(|mut dir_entry| {
+        dir_entry.path.insert_str(0, &previous[..shared]);
+        dir_entry
+    }))
+}
+
+/// Читает записи поврежденных данных, не останавливаясь на первой ошибке (для `--salvage`).
+///
+/// Запись, которую не удалось разобрать, пропускается по ее размеру. Если испорчены сами
+/// размеры, следующая запись ищется побайтно: подходит место, с которого читается запись
+/// с верной CRC32. Ее путь дополняется префиксом последнего прочитанного пути, поэтому
+/// после такого участка пути могут быть неточными.
+///
+/// # Возвращает
+///
+/// Прочитанные записи (их CRC32 проверяет вызывающий код) и описания поврежденных участков.
+pub fn salvage_archive_data(data: &[u8]) -> (Vec<DirEntry>, Vec<String>) {
+    let mut entries = Vec::new();
+    let mut lost = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let rest = &data[offset..];
+        let prefixed = rest.starts_with(PREFIX_CONTAINER_MAGIC);
+        let (mut pos, read_len): (usize, ReadLen) = if prefixed || rest.starts_with(CONTAINER_MAGIC) {
+            (offset + CONTAINER_MAGIC.len(), read_varint_len)
+        } else {
+            (offset, read_u32_len)
+        };
+        let mut remaining = match read_len(data, &mut pos) {
+            Ok(count) => count,
+            Err(e) => {
+                lost.push(format!("{} bytes at offset {} are not an entry set ({})", rest.len(), offset, e));
+                break;
+            }
+        };
+        let mut previous = String::new();
+        while remaining > 0 && pos < data.len() {
+            let start = pos;
+            match read_framed(data, &mut pos, prefixed, read_len, &previous) {
+                Ok(Ok(entry)) => {
+                    if prefixed {
+                        previous.clone_from(&entry.path);
+                    }
+                    entries.push(entry);
+                }
+                Ok(Err(e)) => lost.push(format!("entry at offset {}: {}", start, e)),
+                Err(e) => match resync(data, start + 1, prefixed, read_len, &previous) {
+                    Some(next) => {
+                        lost.push(format!("{} damaged bytes at offset {} skipped ({})", next - start, start, e));
+                        pos = next;
+                        // Сколько записей пропущено, неизвестно: записи читаются до конца данных
+                        remaining = usize::MAX;
+                        continue;
+                    }
+                    None => {
+                        lost.push(format!("entries after offset {} are lost ({})", start, e));
+                        return (entries, lost);
+                    }
+                },
+            }
+            remaining -= 1;
+        }
+        offset = pos;
+    }
+    (entries, lost)
+}
+
+/// Ищет начало следующей неповрежденной записи: с него читается запись с верной CRC32.
+fn resync(data: &[u8], from: usize, prefixed: bool, read_len: ReadLen, previous: &str) -> Option<usize> {
+    (from..data.len()).find(|&start| {
+        let mut pos = start;
+        matches!(read_framed(data, &mut pos, prefixed, read_len, previous),
+            Ok(Ok(entry)) if entry.crc32.is_some() && entry.verify().is_ok())
+    })
+}
+
 /// Преобразует байты в `ArchiveData` для десериализации.
 ///
+/// Поддерживает несколько архивов, записанных друг за другом (например, `cat a.arc b.arc`
+/// для потоковых алгоритмов): их записи объединяются в порядке следования.
+///
 /// # Аргументы
 ///
 /// * `data` - Срез байтов для преобразования.
+/// * `strict` - Строгий режим: байты после последнего архива, которые не удается разобрать,
+///   считаются ошибкой, а не отбрасываются с предупреждением.
 ///
 /// # Возвращает
 ///
 /// Результат с `ArchiveData` или ошибкой ввода/вывода.
-pub fn bytes_to_archive_data(data: &[u8]) -> io::Result<ArchiveData> {
-    use std::convert::TryInto;
-    let mut offset = 0;
+pub fn bytes_to_archive_data(data: &[u8], strict: bool) -> io::Result<ArchiveData> {
+    let mut entries = Vec::new();
+    let mut offset = parse_archive_member(data, &mut entries)?;
 
-    // Чтение количества записей `DirEntry`
-    if data.len() < 4 {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "Недостаточно данных для чтения количества записей"));
+    while offset < data.len() {
+        let mut member = Vec::new();
+        match parse_archive_member(&data[offset..], &mut member) {
+            Ok(consumed) => {
+                entries.extend(member);
+                offset += consumed;
+            }
+            Err(_) if strict => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Лишние данные после последней записи"));
+            }
+            Err(_) => {
+                warnings::report(Warning::TrailingBytes { bytes: data.len() - offset });
+                break;
+            }
+        }
     }
-    let entries_len = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
-    offset += 4;
 
-    let mut entries = Vec::with_capacity(entries_len);
+    Ok(ArchiveData { entries })
+}
 
-    for _ in 0..entries_len {
-        if data.len() < offset + 4 {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Недостаточно данных для чтения размера записи"));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(paths: &[&str]) -> Vec<u8> {
+        let entries = paths.iter().map(|p| DirEntry {
+            path: p.to_string(),
+            data: p.as_bytes().to_vec(),
+            permissions: 0o644,
+            crc32: Some(checksum::crc32(p.as_bytes())),
+            owner: None,
+            metadata: BTreeMap::new(),
+            mtime: None,
+        }).collect();
+        archive_data_to_bytes(&ArchiveData { entries })
+    }
+
+    #[test]
+    fn test_concatenated_archives() {
+        let mut data = archive(&["a.txt"]);
+        data.extend(archive(&["b.txt", "c/d.txt"]));
+        let parsed = bytes_to_archive_data(&data, true).unwrap();
+        let paths: Vec<&str> = parsed.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.txt", "b.txt", "c/d.txt"]);
+
+        // При распаковке объединения повторяющийся путь получает версию из последнего архива
+        let mut union = parsed.entries.clone();
+        union.push(DirEntry { data: b"newer".to_vec(), crc32: None, ..parsed.entries[0].clone() });
+        let out = std::env::temp_dir().join(format!("union_test_{}", std::process::id()));
+        write_dir_entries(&union, &out, &ExtractOptions { threads: 4, ..Default::default() }).unwrap();
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"newer");
+        assert_eq!(fs::read(out.join("c/d.txt")).unwrap(), b"c/d.txt");
+        fs::remove_dir_all(&out).unwrap();
+    }
+
+    #[test]
+    fn test_trailing_garbage() {
+        let mut data = archive(&["a.txt"]);
+        data.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(bytes_to_archive_data(&data, false).unwrap().entries.len(), 1);
+        assert!(bytes_to_archive_data(&data, true).is_err());
+    }
+
+    #[test]
+    fn test_salvage_resynchronizes() {
+        let paths = ["a.txt", "b.txt", "c.txt", "d.txt"];
+        let data = archive(&paths);
+        let position = |path: &str| data.windows(path.len()).position(|w| w == path.as_bytes()).unwrap();
+        let mut damaged = data.clone();
+        // Испорчен размер записи b.txt (байт перед ее правами) и содержимое записи d.txt
+        damaged[position("b.txt") - 6] = 0xFF;
+        let last = data.len() - 6;
+        damaged[last] ^= 0x01;
+        assert!(bytes_to_archive_data(&damaged, false).is_err());
+
+        let (entries, lost) = salvage_archive_data(&damaged);
+        let intact: Vec<&str> = entries.iter().filter(|e| e.verify().is_ok()).map(|e| e.path.as_str()).collect();
+        assert_eq!(intact, vec!["a.txt", "c.txt"]);
+        assert_eq!(entries.len(), 3);
+        assert!(lost[0].contains("damaged bytes"), "{:?}", lost);
+    }
+
+    #[test]
+    fn test_entry_checksum() {
+        let mut entries = vec![DirEntry { path: "a".into(), data: b"payload".to_vec(), permissions: 0o644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None }];
+        fill_checksums(&mut entries);
+        let bytes = dir_entry_to_bytes(&entries[0]);
+        let mut restored = bytes_to_dir_entry(&bytes).unwrap();
+        assert_eq!(restored.crc32, Some(checksum::crc32(b"payload")));
+        assert!(restored.verify().is_ok());
+        restored.data[0] ^= 1;
+        assert!(restored.verify().is_err());
+
+        // Записи старого формата без контрольной суммы читаются и не проверяются
+        let legacy = bytes_to_dir_entry(&bytes[..bytes.len() - 4]).unwrap();
+        assert_eq!(legacy.crc32, None);
+        assert!(legacy.verify().is_ok());
+
+        // Владелец записывается после контрольной суммы, которая вычисляется при необходимости
+        let owner = Owner { uid: 1000, gid: 1000, user: Some("user".into()), group: None };
+        let owned = DirEntry { path: "b".into(), data: b"data".to_vec(), permissions: 0o644, crc32: None, owner: Some(owner.clone()), metadata: BTreeMap::new(), mtime: None };
+        let restored = bytes_to_dir_entry(&dir_entry_to_bytes(&owned)).unwrap();
+        assert_eq!(restored.owner, Some(owner));
+        assert_eq!(restored.crc32, Some(checksum::crc32(b"data")));
+    }
+
+    #[test]
+    fn test_entry_metadata() {
+        let metadata = BTreeMap::from([
+            ("content-type".to_owned(), "text/html".to_owned()),
+            ("url".to_owned(), "https://example.com/".to_owned()),
+        ]);
+        let owner = Owner { uid: u32::MAX, gid: 0, user: None, group: None };
+        for owner in [None, Some(owner)] {
+            let entry = DirEntry { path: "page".into(), data: b"<html>".to_vec(), permissions: 0o644, crc32: None, owner, metadata: metadata.clone(), mtime: None };
+            let restored = bytes_to_dir_entry(&dir_entry_to_bytes(&entry)).unwrap();
+            assert!(restored.verify().is_ok());
+            assert_eq!((restored.owner, restored.metadata), (entry.owner, entry.metadata));
         }
-        // Чтение размера записи `DirEntry`
-        let entry_size = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
-        offset += 4;
+        let plain = DirEntry { path: "a".into(), data: Vec::new(), permissions: 0o644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None };
+        assert!(bytes_to_dir_entry(&dir_entry_to_bytes(&plain)).unwrap().metadata.is_empty());
 
-        if data.len() < offset + entry_size {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Недостаточно данных для чтения записи"));
+        // Время изменения следует за метаданными, даже пустыми, и за отсутствующим владельцем
+        for metadata in [BTreeMap::new(), metadata] {
+            let dated = DirEntry { mtime: Some(1_700_000_000), metadata, ..plain.clone() };
+            let restored = bytes_to_dir_entry(&dir_entry_to_bytes(&dated)).unwrap();
+            assert_eq!((restored.owner, restored.metadata, restored.mtime), (None, dated.metadata, dated.mtime));
         }
-        // Чтение байтов записи `DirEntry`
-        let entry_bytes = &data[offset..offset + entry_size];
-        offset += entry_size;
+        assert_eq!(bytes_to_dir_entry(&dir_entry_to_bytes(&plain)).unwrap().mtime, None);
+    }
 
-        // Восстановление записи `DirEntry` из байтов
-        let dir_entry = bytes_to_dir_entry(entry_bytes)?;
-        entries.push(dir_entry);
+    #[test]
+    fn test_exclude_caches() {
+        let root = std::env::temp_dir().join(format!("cachedir_test_{}", std::process::id()));
+        fs::create_dir_all(root.join("cache")).unwrap();
+        fs::create_dir_all(root.join("fake")).unwrap();
+        fs::write(root.join("keep.txt"), b"keep").unwrap();
+        fs::write(root.join("cache").join(CACHEDIR_TAG), b"Signature: 8a477f597d28d172789f06886806bc55\n# cache").unwrap();
+        fs::write(root.join("cache/blob"), b"cached").unwrap();
+        fs::write(root.join("fake").join(CACHEDIR_TAG), b"not a tag").unwrap();
+
+        let options = WalkOptions { exclude_caches: true, ..Default::default() };
+        let mut paths: Vec<String> = read_dir_recursive(&root, &root, &options).unwrap().into_iter().map(|e| e.path).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["fake/CACHEDIR.TAG", "keep.txt"]);
+        assert_eq!(read_dir_recursive(&root, &root, &WalkOptions::default()).unwrap().len(), 4);
+        fs::remove_dir_all(&root).unwrap();
     }
 
-    Ok(ArchiveData { entries })
-}
\ No newline at end of file
+    #[test]
+    fn test_hard_links() {
+        let root = std::env::temp_dir().join(format!("hardlink_test_{}", std::process::id()));
+        let out = root.with_extension("out");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("data.bin"), b"shared content").unwrap();
+        fs::hard_link(root.join("data.bin"), root.join("sub/copy.bin")).unwrap();
+        restore_mtime(&root.join("data.bin"), 1_600_000_000).unwrap();
+
+        // Какое из имен станет файлом, а какое ссылкой, зависит от порядка обхода
+        let mut entries = read_dir_recursive(&root, &root, &WalkOptions::default()).unwrap();
+        let target = entries.iter().find(|e| e.hard_link_target().is_none()).unwrap();
+        let link = entries.iter().find(|e| e.hard_link_target().is_some()).unwrap();
+        assert_eq!(target.data, b"shared content");
+        assert_eq!((target.mtime, link.mtime), (Some(1_600_000_000), Some(1_600_000_000)));
+        assert_eq!(link.hard_link_target(), Some(target.path.as_str()));
+
+        // Ссылка переживает сериализацию и создается после файла, даже если стоит раньше него
+        fill_checksums(&mut entries);
+        let mut restored = bytes_to_archive_data(&archive_data_to_bytes(&ArchiveData { entries }), true).unwrap().entries;
+        restored.sort_by_key(|e| e.hard_link_target().is_none());
+        write_dir_entries(&restored, &out, &ExtractOptions { threads: 2, ..Default::default() }).unwrap();
+        let a = fs::metadata(out.join("data.bin")).unwrap();
+        let b = fs::metadata(out.join("sub/copy.bin")).unwrap();
+        assert_eq!((a.ino(), a.nlink()), (b.ino(), 2));
+        assert_eq!(file_mtime(&a), 1_600_000_000);
+        assert_eq!(fs::read(out.join("sub/copy.bin")).unwrap(), b"shared content");
+
+        assert!(write_dir_entries(&restored[..1], &root.with_extension("lost"), &ExtractOptions::default()).is_err());
+        for dir in [&root, &out, &root.with_extension("lost")] {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let root = std::env::temp_dir().join(format!("duplicate_test_{}", std::process::id()));
+        let out = root.with_extension("out");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        let content = b"identical content of two files".repeat(4);
+        fs::write(root.join("first.txt"), &content).unwrap();
+        fs::write(root.join("sub/second.txt"), &content).unwrap();
+        fs::set_permissions(root.join("sub/second.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+        // Содержимое короче пути не заменяется ссылкой
+        fs::write(root.join("a"), b"x").unwrap();
+        fs::write(root.join("b"), b"x").unwrap();
+
+        let mut plain = read_dir_recursive(&root, &root, &WalkOptions::default()).unwrap();
+        assert!(plain.iter().all(|e| e.duplicate_of().is_none()));
+        let mut entries = read_dir_recursive(&root, &root, &WalkOptions { dedup: true, ..Default::default() }).unwrap();
+        // Записи, полученные не обходом дерева, дедуплицируются так же
+        deduplicate(&mut plain);
+        assert_eq!(plain, entries);
+        let duplicates: Vec<_> = entries.iter().filter_map(|e| e.duplicate_of()).collect();
+        assert_eq!(duplicates.len(), 1);
+        assert!(["first.txt", "sub/second.txt"].contains(&duplicates[0]));
+
+        // Исходной записью после приведения к воспроизводимому виду становится наименьший путь
+        make_reproducible(&mut entries);
+        let second = entries.iter().find(|e| e.path == "sub/second.txt").unwrap();
+        assert_eq!(second.duplicate_of(), Some("first.txt"));
+
+        fill_checksums(&mut entries);
+        let restored = bytes_to_archive_data(&archive_data_to_bytes(&ArchiveData { entries }), true).unwrap().entries;
+        write_dir_entries(&restored, &out, &ExtractOptions { threads: 2, ..Default::default() }).unwrap();
+        assert_eq!(fs::read(out.join("sub/second.txt")).unwrap(), content);
+        let (a, b) = (fs::metadata(out.join("first.txt")).unwrap(), fs::metadata(out.join("sub/second.txt")).unwrap());
+        assert_ne!(a.ino(), b.ino());
+        assert_eq!(b.permissions().mode() & 0o777, 0o644);
+
+        let duplicate_only: Vec<_> = restored.into_iter().filter(|e| e.duplicate_of().is_some()).collect();
+        assert!(write_dir_entries(&duplicate_only, &root.with_extension("lost"), &ExtractOptions::default()).is_err());
+        for dir in [&root, &out, &root.with_extension("lost")] {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+
+    #[test]
+    fn test_verify_written() {
+        let root = std::env::temp_dir().join(format!("verify_written_test_{}", std::process::id()));
+        let out = root.with_extension("out");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), b"first file").unwrap();
+        fs::write(root.join("sub/b.txt"), b"second file".repeat(100)).unwrap();
+        let mut entries = read_dir_recursive(&root, &root, &WalkOptions::default()).unwrap();
+        fill_checksums(&mut entries);
+
+        assert!(write_dir_entries(&entries, &out, &ExtractOptions::default()).unwrap().checks.is_empty());
+        let checks = write_dir_entries(&entries, &out, &ExtractOptions { verify_written: true, threads: 2, ..Default::default() }).unwrap().checks;
+        let paths: Vec<_> = checks.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, ["a.txt", "sub/b.txt"]);
+        assert!(checks.iter().all(WriteCheck::is_ok));
+        assert_eq!(checks[0].written, checksum::crc32(b"first file"));
+        for dir in [&root, &out] {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+
+    #[test]
+    fn test_read_stream() {
+        use crate::faults::FailingReader;
+
+        let data = b"piped input ".repeat(1000);
+        let entry = read_stream(&mut FailingReader::short_reads(&data[..], 7)).unwrap();
+        assert_eq!((entry.path.as_str(), entry.data.as_slice(), entry.permissions), ("", &data[..], 0o100644));
+        assert_eq!(EntryType::from_mode(entry.permissions), EntryType::File);
+        let error = read_stream(&mut FailingReader::eio(&data[..], 5000)).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EIO));
+    }
+
+    #[test]
+    fn test_files_from() {
+        let root = std::env::temp_dir().join(format!("files_from_test_{}", std::process::id()));
+        fs::create_dir_all(root.join("sub/deep")).unwrap();
+        fs::write(root.join("a.txt"), b"listed").unwrap();
+        fs::write(root.join("b.txt"), b"not listed").unwrap();
+        fs::write(root.join("sub/c.txt"), b"in a listed directory").unwrap();
+        fs::write(root.join("sub/deep/d.txt"), b"listed").unwrap();
+
+        let paths = parse_file_list("a.txt\n\n./sub\n/sub/c.txt\nsub//c.txt\n", false).unwrap();
+        assert_eq!(paths, ["a.txt", "sub", "sub/c.txt"]);
+        assert!(parse_file_list("sub/../../etc/passwd\n", false).is_err());
+
+        let mut entries = read_listed(&root, &paths, &WalkOptions { dedup: true, ..Default::default() }).unwrap();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let listed: Vec<(&str, bool)> = entries.iter().map(|e| (e.path.as_str(), e.duplicate_of().is_some())).collect();
+        assert_eq!(listed, [("a.txt", false), ("sub/c.txt", false), ("sub/deep/d.txt", true)]);
+        let missing = read_listed(&root, &["gone".to_owned()], &WalkOptions::default()).unwrap_err();
+        assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+
+        // Список `find -print0`: перевод строки — часть имени
+        fs::write(root.join("two\nlines"), b"odd name").unwrap();
+        let paths = parse_file_list("./two\nlines\0./a.txt\0", true).unwrap();
+        assert_eq!(paths, ["two\nlines", "a.txt"]);
+        let entries = read_listed(&root, &paths, &WalkOptions::default()).unwrap();
+        assert_eq!(entries[0].data, b"odd name");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_renamed_paths() {
+        let out = std::env::temp_dir().join(format!("renamed_paths_test_{}", std::process::id()));
+        let entry = |path: &str| DirEntry { path: path.to_owned(), data: path.as_bytes().to_vec(), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None };
+        let entries = vec![entry("docs/aux.txt"), entry("../escape"), entry("docs/plain")];
+        let options = ExtractOptions { portable_names: true, ..Default::default() };
+        let (result, reported) = warnings::capture(|| write_dir_entries(&entries, &out, &options));
+        result.unwrap();
+        assert_eq!(fs::read(out.join("docs/%61ux.txt")).unwrap(), b"docs/aux.txt");
+        assert_eq!(fs::read(out.join("%2E%2E/escape")).unwrap(), b"../escape");
+        assert!(out.join("docs/plain").is_file());
+        assert_eq!(reported, vec![
+            Warning::PathRenamed { path: "docs/aux.txt".into(), renamed: "docs/%61ux.txt".into() },
+            Warning::PathRenamed { path: "../escape".into(), renamed: "%2E%2E/escape".into() },
+        ]);
+        let _ = fs::remove_dir_all(&out);
+    }
+
+    #[test]
+    fn test_special_files() {
+        let root = std::env::temp_dir().join(format!("special_test_{}", std::process::id()));
+        let out = root.with_extension("out");
+        fs::create_dir_all(&root).unwrap();
+        let fifo = CString::new(root.join("pipe").as_os_str().as_bytes()).unwrap();
+        // SAFETY: путь — действительная строка C
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o640) }, 0);
+
+        // Канал сохраняется без чтения, иначе обход ждал бы писателя
+        let entries = read_dir_recursive(&root, &root, &WalkOptions::default()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(EntryType::from_mode(entries[0].permissions), EntryType::Fifo);
+        assert!(entries[0].data.is_empty());
+        let skipping = WalkOptions { skip_special: true, ..Default::default() };
+        assert!(read_dir_recursive(&root, &root, &skipping).unwrap().is_empty());
+
+        write_dir_entries(&entries, &out, &ExtractOptions::default()).unwrap();
+        let restored = fs::symlink_metadata(out.join("pipe")).unwrap();
+        assert!(restored.file_type().is_fifo());
+        assert_eq!(restored.permissions().mode() & 0o777, 0o640);
+        fs::remove_file(out.join("pipe")).unwrap();
+        write_dir_entries(&entries, &out, &ExtractOptions { skip_special: true, ..Default::default() }).unwrap();
+        assert!(fs::symlink_metadata(out.join("pipe")).is_err());
+        for dir in [&root, &out] {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+
+    #[test]
+    fn test_reproducible_entries() {
+        let entry = |path: &str, data: &[u8], permissions: u32| DirEntry {
+            path: path.into(),
+            data: data.to_vec(),
+            permissions,
+            crc32: None,
+            owner: Some(Owner { uid: 1000, gid: 1000, user: None, group: None }),
+            metadata: BTreeMap::new(),
+            mtime: Some(u64::from(permissions)),
+        };
+        // Обход нашел сначала b.txt, поэтому ссылкой стала запись a.txt
+        let mut first = vec![
+            entry("tool", b"#!/bin/sh", 0o100750),
+            entry("b.txt", b"shared", 0o100640),
+            entry("a.txt", b"b.txt", 0o100640 | HARD_LINK_FLAG),
+        ];
+        let mut second = vec![
+            entry("a.txt", b"shared", 0o100600),
+            entry("b.txt", b"a.txt", 0o100600 | HARD_LINK_FLAG),
+            entry("tool", b"#!/bin/sh", 0o100700),
+        ];
+        make_reproducible(&mut first);
+        make_reproducible(&mut second);
+        assert_eq!(first, second);
+        let paths: Vec<_> = first.iter().map(|e| (e.path.as_str(), e.hard_link_target(), e.permissions & 0o7777)).collect();
+        assert_eq!(paths, [("a.txt", None, 0o644), ("b.txt", Some("a.txt"), 0o644), ("tool", None, 0o755)]);
+        assert_eq!(first[0].data, b"shared");
+        assert!(first.iter().all(|e| e.owner.is_none() && e.mtime.is_none()));
+    }
+
+    #[test]
+    fn test_directory_entries() {
+        let out = std::env::temp_dir().join(format!("dir_entries_test_{}", std::process::id()));
+        let entry = |path: &str, data: &[u8], permissions: u32| DirEntry {
+            path: path.into(),
+            data: data.to_vec(),
+            permissions,
+            crc32: None,
+            owner: None,
+            metadata: BTreeMap::new(),
+            mtime: None,
+        };
+        // Директории стоят раньше содержимого, но права без записи получают только в конце
+        let entries = vec![
+            entry("ro", b"", 0o40555),
+            entry("ro/inner", b"", 0o40500),
+            entry("empty", b"", 0o40700),
+            entry("ro/inner/file.txt", b"inside", 0o100644),
+        ];
+        write_dir_entries(&entries, &out, &ExtractOptions::default()).unwrap();
+        assert_eq!(fs::read(out.join("ro/inner/file.txt")).unwrap(), b"inside");
+        let mode = |path: &str| fs::metadata(out.join(path)).unwrap().permissions().mode();
+        assert_eq!((mode("ro"), mode("ro/inner"), mode("empty")), (0o40555, 0o40500, 0o40700));
+
+        for dir in ["ro/inner", "ro"] {
+            fs::set_permissions(out.join(dir), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        fs::remove_dir_all(&out).unwrap();
+    }
+
+    #[test]
+    fn test_fsync_policies() {
+        assert_eq!(FsyncPolicy::from_name("per-file"), Some(FsyncPolicy::PerFile));
+        assert_eq!(FsyncPolicy::from_name("never"), None);
+        let root = std::env::temp_dir().join(format!("fsync_test_{}", std::process::id()));
+        let entries = vec![DirEntry {
+            path: "deep/dir/file.txt".into(),
+            data: b"durable".to_vec(),
+            permissions: 0o100644,
+            crc32: None,
+            owner: None,
+            metadata: BTreeMap::new(),
+            mtime: None,
+        }];
+        for fsync in [FsyncPolicy::PerFile, FsyncPolicy::AtEnd] {
+            let out = root.join(format!("{:?}", fsync));
+            write_dir_entries(&entries, &out, &ExtractOptions { fsync, threads: 2, ..Default::default() }).unwrap();
+            assert_eq!(fs::read(out.join("deep/dir/file.txt")).unwrap(), b"durable");
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_ignore_files() {
+        let root = std::env::temp_dir().join(format!("ignore_files_test_{}", std::process::id()));
+        for path in ["src/main.rs", "src/gen/out.rs", "target/debug/app", "web/node_modules/x.js", "web/app.log", "web/keep.log", "notes.tmp"] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, path.to_str().unwrap()).unwrap();
+        }
+        fs::write(root.join(IGNORE_FILE), "/target/\nnode_modules\n*.log\n").unwrap();
+        fs::write(root.join("web").join(IGNORE_FILE), "!keep.log\n").unwrap();
+        fs::write(root.join("src").join(GITIGNORE_FILE), "gen/\n").unwrap();
+        fs::write(root.join(GITIGNORE_FILE), "*.tmp\n").unwrap();
+        let paths = |options: &WalkOptions| {
+            let mut paths: Vec<String> = read_dir_recursive(&root, &root, options).unwrap().into_iter().map(|e| e.path).collect();
+            paths.sort();
+            paths
+        };
+        let ignoring = WalkOptions { ignore_files: true, ..Default::default() };
+        assert_eq!(paths(&ignoring), [".archiverignore", ".gitignore", "notes.tmp", "src/.gitignore", "src/gen/out.rs", "src/main.rs", "web/.archiverignore", "web/keep.log"]);
+        // `.gitignore` читается только в директориях без `.archiverignore`
+        let with_git = WalkOptions { gitignore: true, ..ignoring.clone() };
+        assert_eq!(paths(&with_git), [".archiverignore", ".gitignore", "notes.tmp", "src/.gitignore", "src/main.rs", "web/.archiverignore", "web/keep.log"]);
+        assert_eq!(paths(&WalkOptions::default()).len(), 11);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_extract_order() {
+        assert_eq!(ExtractOrder::from_name("path"), Some(ExtractOrder::Path));
+        assert_eq!(ExtractOrder::from_name("offset"), None);
+        let entry = |path: &str| DirEntry { path: path.into(), data: path.as_bytes().to_vec(), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None };
+        let entries: Vec<DirEntry> = ["src/main.rs", "README", "src/lib/a.rs", "src-old/x", "src/build.rs", "docs/b"].into_iter().map(entry).collect();
+        let sorted = |order: ExtractOrder| {
+            let mut files: Vec<&DirEntry> = entries.iter().collect();
+            order.sort(&mut files);
+            files.iter().map(|e| e.path.as_str()).collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(ExtractOrder::Archive), ["src/main.rs", "README", "src/lib/a.rs", "src-old/x", "src/build.rs", "docs/b"]);
+        // Файлы одной директории идут подряд, поддиректории — после них
+        assert_eq!(sorted(ExtractOrder::Path), ["README", "docs/b", "src/build.rs", "src/main.rs", "src/lib/a.rs", "src-old/x"]);
+
+        let out = std::env::temp_dir().join(format!("extract_order_test_{}", std::process::id()));
+        write_dir_entries(&entries, &out, &ExtractOptions { order: ExtractOrder::Path, threads: 2, ..Default::default() }).unwrap();
+        assert_eq!(fs::read(out.join("src/lib/a.rs")).unwrap(), b"src/lib/a.rs");
+        fs::remove_dir_all(&out).unwrap();
+    }
+
+    #[test]
+    fn test_strip_components() {
+        assert_eq!(strip_path("project-1.2.3/src/main.rs", 1), Some("src/main.rs"));
+        assert_eq!(strip_path("a//b/c", 2), Some("c"));
+        assert_eq!((strip_path("project-1.2.3", 1), strip_path("a/b/", 2), strip_path("a/b", 0)), (None, None, Some("a/b")));
+        let entry = |path: &str, data: &[u8], permissions| DirEntry {
+            path: path.into(), data: data.to_vec(), permissions, crc32: Some(checksum::crc32(data)), owner: None, metadata: BTreeMap::new(), mtime: None,
+        };
+        let mut entries = vec![
+            entry("p", b"", 0o40755),
+            entry("p/src/main.rs", b"fn main() {}", 0o100644),
+            entry("p/src/copy.rs", b"p/src/main.rs", 0o100644 | DUPLICATE_FLAG),
+            entry("p/link", b"p/src/main.rs", 0o100644 | HARD_LINK_FLAG),
+            entry("p/soft", b"../p/x", 0o120777),
+            entry("top", b"top", 0o100644),
+        ];
+        strip_components(&mut entries, 1);
+        let stripped: Vec<(&str, &[u8])> = entries.iter().map(|e| (e.path.as_str(), e.data.as_slice())).collect();
+        assert_eq!(stripped, [
+            ("src/main.rs", &b"fn main() {}"[..]), ("src/copy.rs", b"src/main.rs"), ("link", b"src/main.rs"), ("soft", b"../p/x"),
+        ]);
+        assert!(entries.iter().all(|e| e.verify().is_ok()));
+        let out = std::env::temp_dir().join(format!("strip_test_{}", std::process::id()));
+        write_dir_entries(&entries, &out, &ExtractOptions::default()).unwrap();
+        assert_eq!(fs::read(out.join("src/copy.rs")).unwrap(), b"fn main() {}");
+        assert_eq!(fs::metadata(out.join("link")).unwrap().nlink(), 2);
+        fs::remove_dir_all(&out).unwrap();
+    }
+
+    #[test]
+    fn test_overwrite_policy() {
+        assert_eq!(OverwritePolicy::from_name("keep-newer"), Some(OverwritePolicy::KeepNewer));
+        assert_eq!(OverwritePolicy::from_name("never"), None);
+        let out = std::env::temp_dir().join(format!("overwrite_test_{}", std::process::id()));
+        let entry = |path: &str, data: &[u8], permissions| DirEntry { path: path.into(), data: data.to_vec(), permissions, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: Some(1_600_000_000) };
+        let mut entries = vec![entry("old", b"from archive", 0o100644), entry("new", b"fresh", 0o100644), entry("link", b"new", 0o100644 | HARD_LINK_FLAG)];
+        fs::create_dir_all(&out).unwrap();
+        fs::write(out.join("old"), b"local edit").unwrap();
+        fs::write(out.join("link"), b"local link").unwrap();
+        let extract = |entries: &[DirEntry], overwrite| write_dir_entries(entries, &out, &ExtractOptions { overwrite, threads: 2, ..Default::default() });
+
+        // Существующие файлы остаются, а новые записываются
+        assert_eq!(extract(&entries, OverwritePolicy::Skip).unwrap().skipped, ["old", "link"]);
+        assert_eq!((fs::read(out.join("old")).unwrap(), fs::read(out.join("new")).unwrap()), (b"local edit".to_vec(), b"fresh".to_vec()));
+        // Файлы старше записей заменяются и получают их время, а измененный позже файл остается
+        for path in ["old", "link"] {
+            File::options().write(true).open(out.join(path)).unwrap()
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_500_000_000)).unwrap();
+        }
+        assert!(extract(&entries, OverwritePolicy::KeepNewer).unwrap().skipped.is_empty());
+        assert_eq!(fs::read(out.join("old")).unwrap(), b"from archive");
+        assert_eq!(fs::metadata(out.join("link")).unwrap().nlink(), 2);
+        fs::write(out.join("old"), b"local edit").unwrap();
+        assert_eq!(extract(&entries, OverwritePolicy::KeepNewer).unwrap().skipped, ["old"]);
+        assert_eq!(fs::read(out.join("old")).unwrap(), b"local edit");
+        // Без времени изменения записи сравнивать не с чем
+        entries[0].mtime = None;
+        assert_eq!(extract(&entries, OverwritePolicy::KeepNewer).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // Ответы на вопросы: нет, да, затем «оставить все»
+        let mut answers = io::Cursor::new(b"\ny\ns\n".to_vec());
+        let mut existing = ExistingFiles::new(OverwritePolicy::Prompt, &mut answers);
+        let keeps: Vec<bool> = ["old", "new", "link", "old", "missing"].iter().map(|path| existing.keeps(&out.join(path), None).unwrap()).collect();
+        assert_eq!(keeps, [true, false, true, true, false]);
+        fs::remove_dir_all(&out).unwrap();
+    }
+
+    #[test]
+    fn test_varint_and_legacy_container() {
+        let mut buffer = Vec::new();
+        for value in [0, 127, 128, u32::MAX as u64 + 1, u64::MAX] {
+            buffer.clear();
+            write_varint(&mut buffer, value);
+            let mut offset = 0;
+            assert_eq!(read_varint(&buffer, &mut offset).unwrap(), value);
+            assert_eq!(offset, buffer.len());
+        }
+        assert!(read_varint(&[0x80, 0x80], &mut 0).is_err());
+        assert!(read_varint(&[0xFF; 10], &mut 0).is_err());
+
+        // Набор старого формата: количество, размер записи и длины в виде u32
+        let mut entry = 0o644u32.to_le_bytes().to_vec();
+        entry.extend_from_slice(&1u32.to_le_bytes());
+        entry.extend_from_slice(b"a");
+        entry.extend_from_slice(&2u32.to_le_bytes());
+        entry.extend_from_slice(b"hi");
+        let mut legacy = 1u32.to_le_bytes().to_vec();
+        legacy.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+        legacy.extend_from_slice(&entry);
+        legacy.extend(archive(&["b"]));
+        let parsed = bytes_to_archive_data(&legacy, true).unwrap();
+        assert_eq!(parsed.entries.len(), 2);
+        assert_eq!(parsed.entries[0].data, b"hi");
+        assert_eq!(parsed.entries[1].path, "b");
+    }
+
+    #[test]
+    fn test_prefix_container() {
+        let paths = ["a/b/c/deep/first.txt", "a/b/c/deep/second.txt", "a/b/c/other.txt", "docs/привет.txt", "docs/приказ.txt", "z"];
+        let data = archive(&paths);
+        assert!(data.starts_with(PREFIX_CONTAINER_MAGIC));
+        let parsed = bytes_to_archive_data(&data, true).unwrap();
+        assert_eq!(parsed.entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), paths);
+        assert!(parsed.entries.iter().all(|e| e.data == e.path.as_bytes()));
+        // Наборы версии 3 с полными путями читаются как прежде
+        let mut full = CONTAINER_MAGIC.to_vec();
+        write_varint(&mut full, parsed.entries.len() as u64);
+        for e in &parsed.entries {
+            let bytes = dir_entry_to_bytes(e);
+            write_varint(&mut full, bytes.len() as u64);
+            full.extend(bytes);
+        }
+        assert_eq!(bytes_to_archive_data(&full, true).unwrap().entries, parsed.entries);
+        assert!(data.len() < full.len());
+        // Содержимое записей совпадает с путями, поэтому каждая строка встречается и в нем.
+        // Общая директория хранится в пути только первой записи, у остальных — окончание
+        // после общего префикса («в» и «к» начинаются с одного байта, но символ не разрывается)
+        let stored = |suffix: &str| data.windows(suffix.len()).filter(|w| *w == suffix.as_bytes()).count();
+        assert_eq!(stored("a/b/c/deep/"), 1 + 2);
+        assert_eq!(stored("second.txt"), 2);
+        assert_eq!((stored("docs/при"), stored("каз.txt")), (1 + 2, 1 + 1));
+
+        // Длина префикса, которая разрывает символ предыдущего пути, отклоняется
+        let mut data = archive(&["é", "éa"]);
+        let first = bytes_to_archive_data(&data, true).unwrap().entries.remove(0);
+        // Сигнатура, число записей, префикс и размер первой записи, затем сама запись
+        let shared = PREFIX_CONTAINER_MAGIC.len() + 3 + dir_entry_to_bytes(&first).len();
+        assert_eq!(data[shared], 2);
+        data[shared] = 1;
+        assert!(bytes_to_archive_data(&data, true).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! Модуль для вывода JSON (`--progress json`, `--format json`).
+//!
+//! Выводятся только плоские значения, объекты и массивы, поэтому полноценная библиотека
+//! сериализации не нужна: объект собирается по полям в порядке добавления, а строки
+//! экранируются по RFC 8259.
+
+use std::fmt::Display;
+
+/// Записывает строку литералом JSON в кавычках.
+pub fn string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Собирает массив JSON из готовых значений.
+pub fn array<I: IntoIterator<Item = String>>(items: I) -> String {
+    format!("[{}]", items.into_iter().collect::<Vec<_>>().join(","))
+}
+
+/// Объект JSON, который собирается по полям.
+#[derive(Debug, Clone, Default)]
+pub struct Object {
+    /// Поля через запятую без фигурных скобок
+    fields: String,
+}
+
+impl Object {
+    /// Создает пустой объект.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Добавляет поле с готовым значением JSON: числом, `true`, `false`, `null`, объектом или массивом.
+    pub fn raw(mut self, key: &str, value: impl Display) -> Self {
+        if !self.fields.is_empty() {
+            self.fields.push(',');
+        }
+        self.fields.push_str(&format!("{}:{}", string(key), value));
+        self
+    }
+
+    /// Добавляет строковое поле.
+    pub fn str(self, key: &str, value: &str) -> Self {
+        self.raw(key, string(value))
+    }
+
+    /// Добавляет поле с числом или `null`, если значения нет.
+    pub fn opt(self, key: &str, value: Option<impl Display>) -> Self {
+        match value {
+            Some(value) => self.raw(key, value),
+            None => self.raw(key, "null"),
+        }
+    }
+
+    /// Добавляет строковое поле или `null`, если значения нет.
+    pub fn opt_str(self, key: &str, value: Option<&str>) -> Self {
+        match value {
+            Some(value) => self.str(key, value),
+            None => self.raw(key, "null"),
+        }
+    }
+
+    /// Возвращает объект одной строкой.
+    pub fn finish(self) -> String {
+        format!("{{{}}}", self.fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object() {
+        assert_eq!(string("dir/\"q\"\\\n\t é"), r#""dir/\"q\"\\\n\u0009 é""#);
+        let object = Object::new().str("path", "a.txt").raw("size", 42).opt("crc32", None::<u32>)
+            .opt_str("link", Some("b")).raw("list", array(["1".to_owned(), "true".to_owned()]));
+        assert_eq!(object.finish(), r#"{"path":"a.txt","size":42,"crc32":null,"link":"b","list":[1,true]}"#);
+        assert_eq!((Object::new().finish(), array(Vec::new())), ("{}".to_owned(), "[]".to_owned()));
+    }
+}
@@ -0,0 +1,55 @@
+//! Библиотека архиватора: алгоритмы сжатия, формат архива и вспомогательные инструменты.
+//!
+//! Модули можно использовать и без командной строки, например для сравнения
+//! содержимого архивов или встраивания сжатия в другие программы.
+pub mod io;
+pub mod rle;
+pub mod lz77;
+pub mod lz4;
+pub mod processing;
+pub mod header;
+pub mod entry_index;
+pub mod lzw;
+pub mod huffman;
+pub mod checksum;
+pub mod crypto;
+pub mod catalog;
+pub mod selftest;
+pub mod bench;
+pub mod archive;
+pub mod filter;
+pub mod glob;
+pub mod owner;
+pub mod info;
+pub mod lock;
+pub mod partial;
+pub mod progress;
+pub mod format;
+pub mod warnings;
+pub mod volume;
+pub mod recovery;
+pub mod server;
+pub mod chunking;
+pub mod frames;
+pub mod blocks;
+pub mod names;
+pub mod nested;
+pub mod tree;
+pub mod dupes;
+pub mod calibrate;
+pub mod json;
+pub mod transform;
+pub mod grep;
+pub mod cache;
+pub mod timestamp;
+#[cfg(test)]
+mod faults;
+#[cfg(feature = "server")]
+pub mod http;
+
+/// Структура для хранения данных архива.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveData {
+    /// Список записей директории.
+    pub entries: Vec<io::DirEntry>,
+}
@@ -0,0 +1,212 @@
+//! Модуль для рекомендательных блокировок файла архива.
+//!
+//! При чтении архива (распаковка, просмотр, сравнение) на него берется разделяемая
+//! блокировка `flock`, а при перезаписи (`--compact`, сжатие в существующий архив) —
+//! исключительная. Так одновременные задания резервного копирования и восстановления
+//! одного файла не видят его в промежуточном состоянии. Если блокировка занята,
+//! программа сообщает об ожидании и ждет ее освобождения. Блокировка рекомендательная:
+//! программы, которые ее не берут, она не останавливает.
+//!
+//! Архив заменяется переименованием нового файла, поэтому после ожидания блокировки
+//! путь может указывать уже на другой файл; тогда файл открывается и блокируется заново.
+//! Запись выходного архива, которого еще нет, согласуется через файл `<архив>.lock`
+//! (`OutputLock`): задания по расписанию, пишущие в одно место, записывают архив и его
+//! тома по очереди, а не вперемешку.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Суффикс файла блокировки записи выходного архива.
+pub const LOCK_SUFFIX: &str = ".lock";
+
+/// Блокировка файла архива; снимается при уничтожении значения.
+#[derive(Debug)]
+pub struct ArchiveLock {
+    /// Открытый файл, на котором удерживается блокировка
+    file: File,
+}
+
+impl ArchiveLock {
+    /// Берет разделяемую блокировку для чтения архива.
+    ///
+    /// # Аргументы
+    ///
+    /// * `path` - Путь к файлу архива.
+    ///
+    /// # Возвращает
+    ///
+    /// Блокировку или ошибку ввода/вывода.
+    pub fn shared(path: &Path) -> io::Result<ArchiveLock> {
+        ArchiveLock::acquire(path, libc::LOCK_SH)
+    }
+
+    /// Берет исключительную блокировку для перезаписи архива.
+    pub fn exclusive(path: &Path) -> io::Result<ArchiveLock> {
+        ArchiveLock::acquire(path, libc::LOCK_EX)
+    }
+
+    /// Открывает файл и берет на нем блокировку `operation`, ожидая ее при необходимости.
+    fn acquire(path: &Path, operation: libc::c_int) -> io::Result<ArchiveLock> {
+        Ok(ArchiveLock { file: lock_current(path, operation, |path| File::open(path))? })
+    }
+
+    /// Читает содержимое заблокированного файла.
+    pub fn read_all(&mut self) -> io::Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        self.file.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// Берет исключительную блокировку на файл, который будет перезаписан, если он существует.
+///
+/// # Возвращает
+///
+/// Блокировку, `None` для еще не созданного файла или ошибку ввода/вывода.
+pub fn lock_existing(path: &Path) -> io::Result<Option<ArchiveLock>> {
+    match ArchiveLock::exclusive(path) {
+        Ok(lock) => Ok(Some(lock)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Блокировка записи выходного архива через файл `<архив>.lock`.
+///
+/// Файл блокировки удаляется при уничтожении значения.
+#[derive(Debug)]
+pub struct OutputLock {
+    /// Открытый файл блокировки
+    file: File,
+    /// Путь к файлу блокировки
+    path: PathBuf,
+}
+
+impl OutputLock {
+    /// Берет блокировку записи архива, создавая файл блокировки и ожидая, пока его
+    /// освободит другой процесс.
+    ///
+    /// # Аргументы
+    ///
+    /// * `archive` - Путь к выходному архиву (для многотомного — без номера тома).
+    ///
+    /// # Возвращает
+    ///
+    /// Блокировку или ошибку ввода/вывода.
+    pub fn acquire(archive: &Path) -> io::Result<OutputLock> {
+        let mut path = archive.as_os_str().to_owned();
+        path.push(LOCK_SUFFIX);
+        let path = PathBuf::from(path);
+        let file = lock_current(&path, libc::LOCK_EX, |path| OpenOptions::new().create(true).append(true).open(path))?;
+        Ok(OutputLock { file, path })
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        // Файл удаляется до снятия блокировки: ожидающий процесс увидит, что путь
+        // больше не указывает на его файл, и создаст новый
+        let _ = fs::remove_file(&self.path);
+        let _ = flock(&self.file, libc::LOCK_UN);
+    }
+}
+
+/// Открывает файл функцией `open` и блокирует его, пока блокировка не будет взята
+/// на файл, на который путь указывает после ожидания.
+fn lock_current(path: &Path, operation: libc::c_int, open: impl Fn(&Path) -> io::Result<File>) -> io::Result<File> {
+    let mut waited = false;
+    loop {
+        let file = open(path)?;
+        if flock(&file, operation | libc::LOCK_NB).is_err() {
+            if !waited {
+                log::warn!("waiting for a lock on {}...", path.display());
+                waited = true;
+            }
+            flock(&file, operation)
+                .map_err(|e| io::Error::new(e.kind(), format!("cannot lock {}: {}", path.display(), e)))?;
+        }
+        let locked = file.metadata()?;
+        match fs::metadata(path) {
+            Ok(current) if (current.dev(), current.ino()) == (locked.dev(), locked.ino()) => return Ok(file),
+            // Пока блокировка ожидалась, файл заменили или удалили
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Вызывает `flock`, повторяя вызов, прерванный сигналом.
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    loop {
+        // SAFETY: дескриптор принадлежит открытому файлу и действителен во время вызова
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Читает архив под разделяемой блокировкой.
+///
+/// # Аргументы
+///
+/// * `path` - Путь к файлу архива.
+///
+/// # Возвращает
+///
+/// Содержимое файла или ошибку ввода/вывода.
+pub fn read_shared(path: &str) -> io::Result<Vec<u8>> {
+    ArchiveLock::shared(Path::new(path))?.read_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_lock_modes() {
+        let path = std::env::temp_dir().join(format!("lock_test_{}.arc", std::process::id()));
+        fs::write(&path, b"archive").unwrap();
+        let busy = |operation| flock(&File::open(&path).unwrap(), operation | libc::LOCK_NB).is_err();
+
+        // Читатели не мешают друг другу, но не дают перезаписать архив
+        let mut reader = ArchiveLock::shared(&path).unwrap();
+        assert_eq!(reader.read_all().unwrap(), b"archive");
+        assert!(!busy(libc::LOCK_SH));
+        assert!(busy(libc::LOCK_EX));
+        drop(reader);
+
+        let writer = ArchiveLock::exclusive(&path).unwrap();
+        assert!(busy(libc::LOCK_SH));
+        drop(writer);
+        assert_eq!(read_shared(path.to_str().unwrap()).unwrap(), b"archive");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_output_lock_serializes_writers() {
+        let archive = std::env::temp_dir().join(format!("output_lock_test_{}.arc", std::process::id()));
+        let lock_file = PathBuf::from(format!("{}{}", archive.display(), LOCK_SUFFIX));
+        let first = OutputLock::acquire(&archive).unwrap();
+        assert!(flock(&File::open(&lock_file).unwrap(), libc::LOCK_EX | libc::LOCK_NB).is_err());
+
+        // Второй писатель ждет первого и после его завершения блокирует новый файл
+        let waiting = std::thread::spawn({
+            let archive = archive.clone();
+            move || OutputLock::acquire(&archive).map(drop)
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        drop(first);
+        waiting.join().unwrap().unwrap();
+        assert!(!lock_file.exists());
+    }
+}
@@ -3,6 +3,8 @@
 //! Этот модуль предоставляет функции для сжатия и распаковки данных с использованием алгоритма LZ4. 
 //! Алгоритм LZ4 используется для быстрого сжатия и разжатия данных.
 
+use std::io;
+
 
 /// Сжимает входные данные с использованием алгоритма LZ4.
 ///
@@ -70,8 +72,8 @@ pub fn compress(input: &[u8]) -> Vec<u8> {
 ///
 /// # Возвращает
 ///
-/// Вектор байтов, представляющий распакованные данные.
-pub fn decompress(input: &[u8]) -> Vec<u8> {
+/// Вектор байтов, представляющий распакованные данные, или ошибку при некорректном потоке.
+pub fn decompress(input: &[u8]) -> io::Result<Vec<u8>> {
     let mut output = Vec::new();
     let mut i = 0;
 
@@ -79,46 +81,41 @@ pub fn decompress(input: &[u8]) -> Vec<u8> {
         if input[i] == 0 {
             // проверка, что достаточно данных для чтения offset и length
             if i + 3 >= input.len() {
-                eprintln!("Error: Unexpected end of input while reading match block.");
-                return Vec::new();
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    "LZ4: оборванный блок совпадения"));
             }
 
             let offset = u16::from_le_bytes([input[i + 1], input[i + 2]]) as usize;
             let length = input[i + 3] as usize;
 
             if offset == 0 || offset > output.len() {
-                eprintln!("Error: Invalid offset ({}) at position {}.", offset, i);
-                return Vec::new();
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("LZ4: неверное смещение {} в позиции {}", offset, i)));
             }
 
-            let mut start = output.len() - offset;
+            let start = output.len() - offset;
 
-            
-            for _ in 0..length {
-                if start >= output.len() {
-                    eprintln!("Error: Out of bounds access during decompression.");
-                    return Vec::new();
-                }
-                let byte = output[start];
+            // Совпадение может перекрываться с копируемой областью, поэтому копируем побайтно
+            for j in 0..length {
+                let byte = output[start + j];
                 output.push(byte);
-                start += 1; 
             }
 
             i += 4;
         } else if input[i] == 1 {
             // Проверка, что достаточно данных для чтения литерала
             if i + 1 >= input.len() {
-                eprintln!("Error: Unexpected end of input while reading literal.");
-                return Vec::new();
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    "LZ4: оборванный литерал"));
             }
 
             output.push(input[i + 1]);
             i += 2;
         } else {
-            eprintln!("Error: Invalid marker ({}) at position {}.", input[i], i);
-            return Vec::new();
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("LZ4: неизвестный маркер {} в позиции {}", input[i], i)));
         }
     }
 
-    output
-}
\ No newline at end of file
+    Ok(output)
+}
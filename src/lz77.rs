@@ -1,24 +1,36 @@
 //! Модуль для сжатия и распаковки данных с использованием алгоритма LZ77.
 //!
-//! Этот модуль предоставляет функции для сжатия и распаковки данных с использованием алгоритма LZ77. 
+//! Этот модуль предоставляет функции для сжатия и распаковки данных с использованием алгоритма LZ77.
 //! Алгоритм LZ77 используется для сжатия данных путем замены повторяющихся последовательностей ссылками на предыдущие вхождения.
+//!
+//! Поток состоит из токенов:
+//!
+//! * `0, distance_hi, distance_lo, length` — совпадение (4 байта);
+//! * `1, byte` — литерал (2 байта);
+//! * `2, distance, length` — короткое совпадение с расстоянием меньше 256 (3 байта).
+//!   Используется только в режиме коротких совпадений, который должен быть включен и при распаковке.
+
+use std::io;
 
 const WINDOW_SIZE: usize = 4096;
 
 /// Размер буфера предварительного просмотра.
 const LOOKAHEAD_BUFFER_SIZE: usize = 18;
 
+/// Максимальное расстояние, которое помещается в короткий токен.
+const SHORT_DISTANCE_LIMIT: usize = 256;
+
 /// Сжимает входные данные с использованием алгоритма LZ77.
 ///
 /// # Аргументы
 ///
 /// * `input` - Срез байтов, которые требуется сжать.
+/// * `short_matches` - Разрешает короткие токены для близких совпадений длиной от 2 байтов.
 ///
 /// # Возвращает
 ///
 /// Вектор байтов, представляющий сжатые данные.
-
-pub fn compress(input: &[u8]) -> Vec<u8> {
+pub fn compress(input: &[u8], short_matches: bool) -> Vec<u8> {
     let mut compressed = Vec::new();
     let mut i = 0;
 
@@ -26,20 +38,26 @@ pub fn compress(input: &[u8]) -> Vec<u8> {
         let mut match_length = 0;
         let mut match_distance = 0;
 
-        let start = if i >= WINDOW_SIZE { i - WINDOW_SIZE } else { 0 };
+        let start = i.saturating_sub(WINDOW_SIZE);
 
         for j in start..i {
             let mut k = 0;
             while k < LOOKAHEAD_BUFFER_SIZE && i + k < input.len() && input[j + k] == input[i + k] {
                 k += 1;
             }
-            if k > match_length {
+            // В режиме коротких совпадений при равной длине выгоднее ближайшее вхождение
+            if k > match_length || (short_matches && k > 0 && k == match_length) {
                 match_length = k;
                 match_distance = i - j;
             }
         }
 
-        if match_length >= 3 {
+        if short_matches && match_distance < SHORT_DISTANCE_LIMIT && match_length >= 2 {
+            compressed.push(2);
+            compressed.push(match_distance as u8);
+            compressed.push(match_length as u8);
+            i += match_length;
+        } else if match_length >= 3 {
             compressed.push(0);
             compressed.push((match_distance >> 8) as u8);
             compressed.push((match_distance & 0xFF) as u8);
@@ -60,29 +78,86 @@ pub fn compress(input: &[u8]) -> Vec<u8> {
 /// # Аргументы
 ///
 /// * `input` - Срез байтов, которые требуется распаковать.
+/// * `short_matches` - Разрешает короткие токены; должен совпадать с режимом сжатия.
+/// * `strict` - Строгий режим: неизвестный тип токена считается ошибкой, а не литералом.
 ///
 /// # Возвращает
 ///
-/// Вектор байтов, представляющий распакованные данные.
-
-pub fn decompress(input: &[u8]) -> Vec<u8> {
+/// Вектор байтов, представляющий распакованные данные, или ошибку при некорректном потоке.
+pub fn decompress(input: &[u8], short_matches: bool, strict: bool) -> io::Result<Vec<u8>> {
     let mut decompressed = Vec::new();
     let mut i = 0;
 
     while i < input.len() {
-        if input[i] == 0 {
-            let distance = ((input[i + 1] as usize) << 8) | (input[i + 2] as usize);
-            let length = input[i + 3] as usize;
+        if input[i] == 0 || (short_matches && input[i] == 2) {
+            let token_len = if input[i] == 0 { 4 } else { 3 };
+            if i + token_len > input.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "LZ77: оборванный токен совпадения"));
+            }
+            let (distance, length) = if input[i] == 0 {
+                (((input[i + 1] as usize) << 8) | (input[i + 2] as usize), input[i + 3] as usize)
+            } else {
+                (input[i + 1] as usize, input[i + 2] as usize)
+            };
+            if distance == 0 || distance > decompressed.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("LZ77: неверное расстояние {} в позиции {}", distance, i)));
+            }
             let start = decompressed.len() - distance;
             for j in 0..length {
                 decompressed.push(decompressed[start + j]);
             }
-            i += 4;
+            i += token_len;
         } else {
+            if strict && input[i] != 1 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("LZ77: неизвестный тип токена {} в позиции {}", input[i], i)));
+            }
+            if i + 1 >= input.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "LZ77: оборванный литерал"));
+            }
             decompressed.push(input[i + 1]);
             i += 2;
         }
     }
 
-    decompressed
-}
\ No newline at end of file
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[u8] = b"fn main() { let a = b; let b = a; if a == b { return; } }";
+
+    #[test]
+    fn test_round_trip() {
+        let compressed = compress(SOURCE, false);
+        assert_eq!(decompress(&compressed, false, false).unwrap(), SOURCE);
+    }
+
+    #[test]
+    fn test_short_matches_round_trip() {
+        let compressed = compress(SOURCE, true);
+        assert_eq!(decompress(&compressed, true, false).unwrap(), SOURCE);
+    }
+
+    #[test]
+    fn test_short_matches_are_smaller() {
+        assert!(compress(SOURCE, true).len() < compress(SOURCE, false).len());
+    }
+
+    #[test]
+    fn test_short_token_encoding() {
+        // "ab" повторяется на расстоянии 2: короткий токен вместо двух литералов
+        assert_eq!(compress(b"abab", true), vec![1, b'a', 1, b'b', 2, 2, 2]);
+        assert_eq!(compress(b"abab", false), vec![1, b'a', 1, b'b', 1, b'a', 1, b'b']);
+    }
+
+    #[test]
+    fn test_unknown_token() {
+        let input = vec![1, b'a', 7, b'b'];
+        assert_eq!(decompress(&input, false, false).unwrap(), b"ab".to_vec());
+        assert!(decompress(&input, false, true).is_err());
+    }
+}
@@ -109,7 +109,6 @@ impl<R: Read> BitReader<R> {
     /// # Возвращает
     ///
     /// Опциональное значение, содержащее прочитанные биты.
-
     fn read_bits(&mut self, num_bits: u8) -> io::Result<Option<u16>> {
         let mut result: u16 = 0;
         for _ in 0..num_bits {
@@ -188,18 +187,29 @@ pub fn compress(input: &[u8]) -> Vec<u8> {
 /// # Аргументы
 ///
 /// * `input` - Срез байтов, которые требуется распаковать.
+/// * `strict` - Строгий режим: ненулевые биты дополнения и лишние байты в конце считаются ошибкой.
 ///
 /// # Возвращает
 ///
-/// Вектор байтов, представляющий распакованные данные.
-pub fn decompress(input: &[u8]) -> Vec<u8> {
-    let mut bit_reader = BitReader::new(&input[..]);
+/// Вектор байтов, представляющий распакованные данные, или ошибку при некорректном потоке.
+pub fn decompress(input: &[u8], strict: bool) -> io::Result<Vec<u8>> {
+    let mut bit_reader = BitReader::new(input);
     let mut codes: Vec<u16> = Vec::new();
 
-    while let Some(code) = bit_reader.read_bits(12).expect("Failed to read bits") {
+    while let Some(code) = bit_reader.read_bits(12)? {
         codes.push(code);
     }
 
+    if strict {
+        // Коды занимают ровно столько байтов, сколько нужно, а дополнение последнего байта нулевое
+        if input.len() != (codes.len() * 12).div_ceil(8) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "LZW: лишние байты после последнего кода"));
+        }
+        if codes.len() % 2 == 1 && input[input.len() - 1] & 0x0F != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "LZW: ненулевые биты дополнения"));
+        }
+    }
+
     let mut dictionary: HashMap<u16, Vec<u8>> = HashMap::new();
     let mut dict_size: u16 = 256;
     for i in 0..256 {
@@ -207,13 +217,15 @@ pub fn decompress(input: &[u8]) -> Vec<u8> {
     }
 
     let mut result: Vec<u8> = Vec::new();
-    let mut w = match codes.get(0) {
+    let mut w = match codes.first() {
         Some(&k) => {
-            let entry = dictionary.get(&k).cloned().unwrap_or_else(Vec::new);
+            let entry = dictionary.get(&k).cloned().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("LZW: неверный первый код {}", k))
+            })?;
             result.extend(&entry);
             entry
         },
-        None => return result,
+        None => return Ok(result),
     };
 
     for &k in codes.iter().skip(1) {
@@ -224,8 +236,7 @@ pub fn decompress(input: &[u8]) -> Vec<u8> {
             e.push(w[0]);
             e
         } else {
-            eprintln!("Error: Invalid LZW code {}", k);
-            return Vec::new();
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("LZW: неверный код {}", k)));
         };
         result.extend(&entry);
 
@@ -239,6 +250,25 @@ pub fn decompress(input: &[u8]) -> Vec<u8> {
         w = entry;
     }
 
-    result
+    Ok(result)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_decompress() {
+        let input = b"TOBEORNOTTOBEORTOBEORNOT";
+        assert_eq!(decompress(&compress(input), true).unwrap(), input);
+    }
+
+    #[test]
+    fn test_padding_bits_set() {
+        // Три кода занимают 36 бит, последние 4 бита дополнения должны быть нулевыми
+        let mut compressed = compress(b"ABC");
+        *compressed.last_mut().unwrap() |= 0x01;
+        assert!(decompress(&compressed, false).is_ok());
+        assert!(decompress(&compressed, true).is_err());
+    }
+}
@@ -1,81 +1,1154 @@
 //! Основной модуль архиватора, отвечающий за сжатие и распаковку файлов с использованием различных алгоритмов.
-mod io;
-mod rle;
-mod lz77;
-mod lz4;
-mod processing;
-mod lzw;
-mod huffman;
-
-use std::time::Instant;
+use rle_archiver::{archive, bench, cache, calibrate, catalog, checksum, crypto, filter, grep, header, info, io, json, lock, nested, owner, partial, processing, progress, recovery, selftest, server, timestamp, transform, tree, dupes, volume, ArchiveData};
+use info::ArchiveInfo;
+
+use std::time::{Duration, Instant};
 use processing::Algorithm;
-use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::ops::Range;
 use std::path::Path;
-
 use std::io::Write;
+
 use std::os::unix::fs::PermissionsExt;
-use clap::{Command, Arg, ArgAction};
-use log::error;
+use clap::{Command, Arg, ArgAction, ArgGroup, ArgMatches};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use log::{error, warn, LevelFilter};
+
+
+/// Завершает программу после прерывания с отдельным кодом возврата.
+///
+/// # Аргументы
+///
+/// * `progress` - Описание того, что успело выполниться.
+fn exit_interrupted(progress: &str) -> ! {
+    eprintln!("Interrupted: {}.", progress);
+    std::process::exit(partial::INTERRUPTED_EXIT_CODE);
+}
+
+/// Разбирает диапазон байтов `START-END` для `--range`; без `END` диапазон продолжается до конца файла.
+fn parse_range(spec: &str) -> Result<Range<u64>, String> {
+    let invalid = || format!("invalid byte range {} (expected START-END)", spec);
+    let (start, end) = spec.split_once('-').ok_or_else(invalid)?;
+    let start = start.parse::<u64>().map_err(|_| invalid())?;
+    let end = if end.is_empty() { u64::MAX } else { end.parse::<u64>().map_err(|_| invalid())? };
+    if end < start {
+        return Err(invalid());
+    }
+    Ok(start..end)
+}
+
+/// Подкоманды: имя, флаг режима, который она заменяет, и описание для справки.
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("compress", "--compress", "Compress files (same as -c)"),
+    ("extract", "--extract", "Extract an archive (same as -d)"),
+    ("list", "--list", "List the entries of an archive (same as -l)"),
+    ("test", "--test", "Verify every entry of an archive (same as -t)"),
+    ("info", "--info", "Show information about an archive (same as --info)"),
+    ("tree", "--tree", "Show the entries of an archive as a tree (same as --tree)"),
+    ("cat", "--cat", "Print one entry of an archive to stdout (same as --cat)"),
+    ("grep", "--grep", "Search the contents of archive entries (same as --grep)"),
+    ("diff", "--diff", "Compare an archive with a directory (same as --diff)"),
+];
+
+/// Заменяет подкоманду в начале командной строки (`rle_archiver compress ...`) флагом ее режима.
+///
+/// Подкоманда может стоять только первым аргументом, где прежде всегда был флаг режима,
+/// поэтому прежняя запись (`rle_archiver -c ...`) разбирается как раньше, а обе записи
+/// проверяются одним набором аргументов.
+fn expand_command(mut args: Vec<OsString>) -> Vec<OsString> {
+    if let Some((_, flag, _)) = args.get(1).and_then(|arg| COMMANDS.iter().find(|(name, _, _)| arg == name)) {
+        args[1] = flag.into();
+    }
+    args
+}
+
+/// Параметры, допустимые только в некоторых режимах: параметр и флаги, хотя бы один
+/// из которых должен быть задан вместе с ним.
+///
+/// `requires` в clap для этого не подходит: у флага режима (`SetTrue`) есть значение
+/// по умолчанию `false`, и clap считает такой флаг заданным всегда.
+const MODE_OPTIONS: &[(&str, &[&str])] = &[
+    ("target_speed", &["compress"]),
+    ("target_ratio", &["compress"]),
+    ("index_only", &["compress"]),
+    ("update", &["compress"]),
+    ("compare_content", &["update"]),
+    ("solid", &["compress"]),
+    ("no_solid", &["compress"]),
+    ("chunk_dedup", &["solid"]),
+    ("block_size", &["compress"]),
+    ("pack_below", &["compress"]),
+    ("recompress", &["compress"]),
+    ("exclude", &["compress"]),
+    ("exclude_from", &["compress"]),
+    ("files_from", &["compress"]),
+    ("one_file_system", &["compress"]),
+    ("exclude_caches", &["compress"]),
+    ("no_ignore_files", &["compress"]),
+    ("gitignore", &["compress"]),
+    ("no_dedup", &["compress"]),
+    ("reproducible", &["compress"]),
+    ("comment", &["compress"]),
+    ("volume_size", &["compress"]),
+    ("recovery", &["compress"]),
+    ("threads", &["decompress"]),
+    ("salvage", &["decompress"]),
+    ("owner_map", &["decompress"]),
+    ("fsync", &["decompress"]),
+    ("extract_order", &["decompress"]),
+    ("report", &["decompress"]),
+    ("recursive_extract", &["decompress"]),
+    ("portable_names", &["decompress"]),
+    ("strip_components", &["decompress"]),
+    ("transform", &["decompress"]),
+    ("overwrite", &["decompress"]),
+    ("type", &["decompress", "list"]),
+    ("perm", &["decompress", "list"]),
+    ("entry", &["cat", "head", "grep"]),
+    ("range", &["cat"]),
+    ("lines", &["head"]),
+    ("link_dupes", &["dupes"]),
+    ("fixed_strings", &["grep"]),
+    ("ignore_case", &["grep"]),
+    ("format", &["list", "info"]),
+    ("time_style", &["list"]),
+    ("no_cache", &["list", "tree"]),
+    ("depth", &["audit"]),
+];
+
+/// Отклоняет параметры из `MODE_OPTIONS`, заданные в командной строке без своего режима.
+///
+/// Значения по умолчанию не проверяются. При ошибке выводит ее как clap и завершает программу.
+///
+/// # Аргументы
+///
+/// * `cli` - Описание аргументов командной строки.
+/// * `matches` - Разобранные аргументы.
+fn check_modes(cli: &mut Command, matches: &ArgMatches) {
+    let name = |cli: &Command, id: &str| {
+        let arg = cli.get_arguments().find(|arg| arg.get_id() == id).expect("unknown argument");
+        match arg.get_long() {
+            Some(long) => format!("--{}", long),
+            None => format!("<{}>", arg.get_value_names().map_or(id.to_owned(), |names| names[0].to_string())),
+        }
+    };
+    for (id, modes) in MODE_OPTIONS {
+        if matches.value_source(id) != Some(ValueSource::CommandLine) || modes.iter().any(|mode| matches.get_flag(mode)) {
+            continue;
+        }
+        let allowed: Vec<String> = modes.iter().map(|mode| format!("'{}'", name(cli, mode))).collect();
+        let message = format!("the argument '{}' can only be used with {}", name(cli, id), allowed.join(" or "));
+        cli.error(ErrorKind::ArgumentConflict, message).exit();
+    }
+}
+
+/// Перечисляет записи архива для `--list` и `--tree`, используя кеш списков (см. `cache`).
+///
+/// Кеш не используется с `--no-cache`, для зашифрованных архивов, чтобы их пути не оказались
+/// на диске открытым текстом, и если не удалось определить директорию кеша.
+/// При ошибке чтения или разбора выводит сообщение и завершает программу с кодом 1.
+///
+/// # Аргументы
+///
+/// * `path` - Путь к файлу архива.
+/// * `strict` - Строгий режим разбора.
+/// * `use_cache` - Читать и сохранять список в кеше.
+///
+/// # Возвращает
+///
+/// Записи архива со сжатыми размерами.
+fn list_entries(path: &str, strict: bool, use_cache: bool) -> Vec<cache::CachedEntry> {
+    let raw = volume::read_raw(path).expect("Failed to read input file");
+    // Зашифрованный файл начинается не с сигнатуры, а с данных шифра
+    let encrypted = !header::has_magic(&raw);
+    let compressed_data = crypto::open(raw).expect("Failed to read input file");
+    let listed = match cache::default_dir().filter(|_| use_cache && !encrypted) {
+        Some(dir) => cache::list_sizes(&dir, &compressed_data, strict),
+        None => processing::list_sizes(&compressed_data, strict),
+    };
+    listed.unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    })
+}
+
+/// Читает архив и распаковывает его содержимое в байты.
+///
+/// При ошибке чтения или распаковки выводит сообщение и завершает программу с кодом 1.
+///
+/// # Аргументы
+///
+/// * `path` - Путь к файлу архива.
+/// * `algorithm` - Алгоритм для архивов без заголовка (`-a`); обычно определяется по заголовку.
+/// * `threads` - Число потоков, которыми распаковываются записи, сжатые по отдельности.
+/// * `short_matches` - Разрешает короткие токены LZ77 для архивов без заголовка.
+/// * `strict` - Строгий режим распаковки.
+/// * `select` - Условие отбора записей по пути и правам; записи, сжатые по отдельности,
+///   не прошедшие отбор, не распаковываются.
+///
+/// # Возвращает
+///
+/// Распакованные байты архива (записи или индекс).
+fn load_payload(path: &str, algorithm: Option<&Algorithm>, threads: usize, short_matches: bool, strict: bool,
+                select: &dyn Fn(&str, u32) -> bool) -> Vec<u8> {
+    let compressed_data = volume::read_archive(path).expect("Failed to read input file");
+    // Без -a распаковать можно только архив с заголовком
+    if algorithm.is_none() && !header::has_magic(&compressed_data) {
+        eprintln!("{}: not an archive (did you mean -c?)", path);
+        std::process::exit(1);
+    }
+    processing::decompress_selected(&compressed_data, algorithm.cloned(), threads, short_matches, strict, select)
+        .unwrap_or_else(|e| {
+            if partial::interrupted() {
+                exit_interrupted("nothing was extracted");
+            }
+            eprintln!("Decompression of {} failed: {}", path, e);
+            std::process::exit(1);
+        })
+}
+
+/// Читает и распаковывает архив с данными.
+///
+/// Аргументы те же, что у `load_payload`, но вместо условия отбора передается фильтр записей.
+/// Архив, созданный с `--index-only`, не содержит данных, поэтому для него программа
+/// завершается с ошибкой.
+///
+/// # Возвращает
+///
+/// Распакованные записи архива, прошедшие фильтр.
+fn load_archive(path: &str, algorithm: Option<&Algorithm>, threads: usize, short_matches: bool, strict: bool,
+                entry_filter: &filter::EntryFilter) -> ArchiveData {
+    let decompressed = load_payload(path, algorithm, threads, short_matches, strict,
+        &|_, mode| entry_filter.matches_mode(mode));
+    if archive::is_index(&decompressed) {
+        eprintln!("{} is an index-only archive and contains no file data.", path);
+        std::process::exit(1);
+    }
+    let mut archive = io::bytes_to_archive_data(&decompressed, strict)
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to deserialize {}: {}", path, e);
+            std::process::exit(1);
+        });
+    // Записи архивов с общим сжатым потоком распаковываются все и отбираются здесь
+    archive.entries.retain(|e| entry_filter.matches(e));
+    archive
+}
 
-/// Структура для хранения данных архива.
-#[derive(Serialize, Deserialize)]
-struct ArchiveData {
-    /// Список записей директории.
-    entries: Vec<io::DirEntry>,
+/// Проверяет, что все архивы потока хранят время изменения записей, с которым
+/// `--overwrite keep-newer` сравнивает существующие файлы.
+///
+/// При ошибке чтения выводит сообщение и завершает программу с кодом 1.
+fn archive_has_mtimes(path: &str) -> bool {
+    let members = volume::read_archive(path).and_then(|data| processing::describe(&data, false)).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    });
+    members.iter().all(|member| member.header.mtimes())
 }
 
+/// Восстанавливает записи поврежденного архива (`--salvage`) и выводит отчет о потерях.
+///
+/// # Аргументы
+///
+/// * `path` - Путь к файлу архива.
+/// * `entry_filter` - Фильтр записей.
+///
+/// # Возвращает
+///
+/// Восстановленные записи, прошедшие фильтр, и признак того, что часть данных потеряна.
+fn salvage_archive(path: &str, entry_filter: &filter::EntryFilter) -> (ArchiveData, bool) {
+    let compressed_data = volume::read_archive(path).expect("Failed to read input file");
+    let mut report = processing::salvage(&compressed_data);
+    for lost in &report.lost {
+        eprintln!("Lost: {}", lost);
+    }
+    eprintln!("Salvage: {} entries recovered, {} problems reported.", report.entries.len(), report.lost.len());
+    report.entries.retain(|e| entry_filter.matches(e));
+    (ArchiveData { entries: report.entries }, !report.lost.is_empty())
+}
+
+/// Читает индекс архива: сохраненный индекс (`--index-only`) или индекс, построенный по записям архива.
+///
+/// Аргументы те же, что у `load_payload`.
+///
+/// # Возвращает
+///
+/// Индекс архива.
+fn load_index(path: &str, algorithm: Option<&Algorithm>, short_matches: bool, strict: bool) -> archive::ArchiveIndex {
+    let decompressed = load_payload(path, algorithm, 1, short_matches, strict, &|_, _| true);
+    let index = if archive::is_index(&decompressed) {
+        archive::ArchiveIndex::from_bytes(&decompressed)
+    } else {
+        io::bytes_to_archive_data(&decompressed, strict).map(|a| archive::ArchiveIndex::from_archive(&a))
+    };
+    index.unwrap_or_else(|e| {
+        eprintln!("Failed to deserialize {}: {}", path, e);
+        std::process::exit(1);
+    })
+}
+
+/// Выводит отчет сравнения `--cmp` или `--diff`: `+` — путь есть только во втором индексе,
+/// `-` — только в первом, `~` — запись отличается.
+///
+/// # Аргументы
+///
+/// * `report` - Отчет сравнения.
+/// * `porcelain` - Вывод в формате `--porcelain`.
+/// * `names` - Имена добавленных и удаленных путей в формате `--porcelain`.
+fn print_diff(report: &archive::DiffReport, porcelain: bool, names: [&str; 2]) {
+    for path in &report.added {
+        if porcelain { println!("{}\t{}", names[0], path) } else { println!("+ {}", path) }
+    }
+    for path in &report.removed {
+        if porcelain { println!("{}\t{}", names[1], path) } else { println!("- {}", path) }
+    }
+    for entry in &report.modified {
+        if porcelain {
+            let names: Vec<&str> = entry.reasons.iter().map(|r| r.name()).collect();
+            println!("modified\t{}\t{}", entry.path, names.join(","));
+        } else {
+            let reasons: Vec<String> = entry.reasons.iter().map(|r| r.to_string()).collect();
+            println!("~ {} ({})", entry.path, reasons.join(", "));
+        }
+    }
+}
+
+/// Записывает созданный архив одним файлом или томами по `volume_size` байтов.
+///
+/// Архив вместе с записью восстановления шифруется установленным шифром (см. `crypto::seal`).
+///
+/// # Аргументы
+///
+/// * `output_file` - Путь к архиву (без номера тома).
+/// * `compressed` - Данные архива.
+/// * `volume_size` - Наибольший размер тома (`--volume-size`) или `None` для одного файла.
+/// * `recovery` - Размер записи восстановления в процентах (`--recovery`) или `None`.
+fn write_archive(output_file: &str, compressed: &[u8], volume_size: Option<u64>, recovery: Option<u32>) {
+    let protected;
+    let compressed = match recovery {
+        Some(percent) => {
+            protected = recovery::protect(compressed, percent);
+            &protected[..]
+        }
+        None => compressed,
+    };
+    let compressed = crypto::seal(compressed.to_vec()).expect("Failed to encrypt archive");
+    let compressed = &compressed[..];
+    if output_file == io::STDIO_PATH {
+        if let Err(err) = partial::write_output(output_file, compressed) {
+            eprintln!("Failed to write archive to standard output: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // Задания, пишущие в один архив, записывают его по очереди
+    let _output_lock = lock::OutputLock::acquire(Path::new(output_file)).expect("Failed to lock output file");
+    match volume_size {
+        Some(volume_size) => {
+            let _lock = lock::lock_existing(Path::new(&volume::volume_path(output_file, 1))).expect("Failed to lock output file");
+            match volume::write_volumes(output_file, compressed, volume_size) {
+                Ok(count) => eprintln!("{}: {} volumes", output_file, count),
+                Err(err) => {
+                    eprintln!("Failed to write output volumes {}: {}", output_file, err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => {
+            let _lock = lock::lock_existing(Path::new(output_file)).expect("Failed to lock output file");
+            // При ошибке записи (например, нехватке места) временный файл уже удален
+            if let Err(err) = partial::write_file(output_file, compressed) {
+                eprintln!("Failed to write output file {}: {}", output_file, err);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Читает стандартный ввод целиком как единственную запись архива (`-i -`).
+fn read_stdin_entry() -> io::DirEntry {
+    io::read_stream(&mut std::io::stdin().lock()).unwrap_or_else(|err| {
+        eprintln!("Failed to read standard input: {}", err);
+        std::process::exit(1);
+    })
+}
+
+/// Записывает отчет о проверке распакованных файлов (`--report`).
+///
+/// Каждая строка отчета: `ok` или `mismatch`, ожидаемая CRC32, CRC32 записанных байтов и путь,
+/// разделенные табуляцией. При несовпадениях выводит их в stderr и завершает программу с кодом 1.
+///
+/// # Аргументы
+///
+/// * `path` - Путь к файлу отчета.
+/// * `checks` - Результаты проверки записанных файлов.
+fn write_report(path: &str, checks: &[io::WriteCheck]) {
+    let mut report = String::new();
+    for check in checks {
+        let state = if check.is_ok() { "ok" } else { "mismatch" };
+        report.push_str(&format!("{}\t{:08x}\t{:08x}\t{}\n", state, check.expected, check.written, check.path));
+    }
+    std::fs::write(path, report).expect("Failed to write report");
+    let mismatches: Vec<_> = checks.iter().filter(|c| !c.is_ok()).collect();
+    if !mismatches.is_empty() {
+        for check in &mismatches {
+            eprintln!("Written data of {} does not match the archive (crc32 {:08x}, expected {:08x})", check.path, check.written, check.expected);
+        }
+        eprintln!("{} of {} extracted files were written incorrectly", mismatches.len(), checks.len());
+        std::process::exit(1);
+    }
+}
+
+/// Выводит сведения об одном архиве потока в формате `--porcelain`: строки `ключ<TAB>значение`.
+///
+/// Набор ключей стабилен: новые ключи только добавляются, а строки неизвестных сведений не выводятся.
+///
+/// # Аргументы
+///
+/// * `number` - Номер архива в потоке, начиная с 1.
+/// * `member` - Сводка об архиве.
+fn print_member_porcelain(number: usize, member: &processing::MemberSummary) {
+    println!("archive\t{}", number);
+    println!("version\t{}", member.header.version);
+    println!("algorithm\t{}", member.header.algorithm.name());
+    println!("layout\t{}", member_layout(member));
+    if let Some(count) = member.entries {
+        println!("entries\t{}", count);
+    }
+    if let Some(block_size) = member.block_size {
+        println!("block_size\t{}", block_size);
+    }
+    println!("compressed\t{}", member.payload_len);
+    if let Some(original) = member.original_size {
+        println!("original\t{}", original);
+    }
+    if let Some(provenance) = &member.info {
+        println!("created\t{}", provenance.created);
+        println!("host\t{}", provenance.hostname);
+        println!("tool\t{}", provenance.tool_version);
+        if let Some(comment) = &provenance.comment {
+            println!("comment\t{}", comment);
+        }
+        if let Some(options) = &provenance.options {
+            println!("options\t{}", options);
+        }
+        if let Some(rate) = provenance.decode_rate {
+            println!("restore_rate\t{}", rate);
+        }
+        if let Some(selection) = &provenance.selection {
+            println!("selection\t{}", selection);
+        }
+    }
+}
+
+/// Возвращает устройство архива для машиночитаемого вывода: `non-solid`, `solid` или `solid-chunked`.
+fn member_layout(member: &processing::MemberSummary) -> &'static str {
+    match member.entries {
+        Some(_) => "non-solid",
+        None if member.header.chunked() => "solid-chunked",
+        None => "solid",
+    }
+}
+
+/// Описывает один архив потока объектом JSON для `--info --format json`.
+///
+/// Ключи те же, что у `--porcelain`; отсутствующие сведения записываются как `null`.
+///
+/// # Аргументы
+///
+/// * `number` - Номер архива в потоке, начиная с 1.
+/// * `member` - Сводка об архиве.
+fn member_json(number: usize, member: &processing::MemberSummary) -> String {
+    let provenance = member.info.as_ref();
+    json::Object::new()
+        .raw("archive", number)
+        .raw("version", member.header.version)
+        .str("algorithm", member.header.algorithm.name())
+        .str("layout", member_layout(member))
+        .opt("entries", member.entries)
+        .opt("block_size", member.block_size)
+        .raw("compressed", member.payload_len)
+        .opt("original", member.original_size)
+        .opt("created", provenance.map(|p| p.created))
+        .opt_str("host", provenance.map(|p| p.hostname.as_str()))
+        .opt_str("tool", provenance.map(|p| p.tool_version.as_str()))
+        .opt_str("comment", provenance.and_then(|p| p.comment.as_deref()))
+        .opt_str("options", provenance.and_then(|p| p.options.as_ref()).map(|o| o.to_string()).as_deref())
+        .opt("restore_rate", provenance.and_then(|p| p.decode_rate))
+        .opt_str("selection", provenance.and_then(|p| p.selection.as_deref()))
+        .finish()
+}
+
+/// Описывает запись архива объектом JSON для `--list --format json`.
+///
+/// Время изменения записывается числом секунд от начала эпохи Unix (`null` для архивов, записанных
+/// без него); CRC32 — шестнадцатеричной строкой, если он известен без распаковки.
+fn entry_json(entry: &processing::ListedEntry, compressed: Option<u64>) -> String {
+    json::Object::new()
+        .str("path", &entry.path)
+        .str("type", filter::EntryType::from_mode(entry.permissions).name())
+        .str("permissions", &format!("{:o}", entry.permissions))
+        .raw("size", entry.size)
+        .opt("compressed", compressed)
+        .opt("mtime", entry.mtime)
+        .opt_str("crc32", entry.crc32.map(|crc| format!("{:08x}", crc)).as_deref())
+        .opt_str("link", entry.link.as_deref())
+        .finish()
+}
+
+/// Устанавливает журнал, который выводит записи `log` в stderr.
+///
+/// Ошибки и предупреждения выводятся с префиксами `Error:` и `Warning:`, как остальные
+/// сообщения программы, а подробные записи — с названием уровня. Если уровень не задан
+/// флагами, он берется из переменной окружения `RUST_LOG` (по умолчанию `warn`).
+///
+/// # Аргументы
+///
+/// * `level` - Уровень из `--log-level`, `-v` или `-q`, если он задан.
+fn init_logging(level: Option<LevelFilter>) {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(level.unwrap_or(LevelFilter::Warn));
+    if level.is_none() {
+        builder.parse_default_env();
+    }
+    builder.format(|buf, record| match record.level() {
+        log::Level::Error => writeln!(buf, "Error: {}", record.args()),
+        log::Level::Warn => writeln!(buf, "Warning: {}", record.args()),
+        level => writeln!(buf, "{}: {}", level.as_str().to_lowercase(), record.args()),
+    });
+    builder.init();
+}
 
 /// Главная функция приложения.
 /// Инициализирует и настраивает команду rle_archiver с различными аргументами.
     ///
     /// ## Аргументы
     ///
-    /// - `compress` (`-c`): Сжимает файлы. Не может использоваться вместе с `decompress`. Обязателен, если не указан `decompress`.
-    /// - `decompress` (`-d`): Распаковывает файлы. Не может использоваться вместе с `compress`. Обязателен, если не указан `compress`.
-    /// - `algorithm`: Выбор алгоритма сжатия. Обязательный аргумент.
-    /// - `input`: Входной файл для обработки. Обязательный аргумент.
-    /// - `output`: Выходной файл. Обязательный аргумент.
-    /// - `multithread` (`-m`): Включает многопоточную обработку.
+    /// Режим задается подкомандой первым аргументом (`compress`, `extract`, `list`, `test`, `info`, `tree`, `cat`, `grep`, `diff`;
+    /// см. `COMMANDS`) или, как прежде, флагом режима (`-c`, `-d` и другие).
+    ///
+    /// - `compress` (`-c`, `--compress`): Сжимает файлы.
+    /// - `decompress` (`-d`, `--extract`, `--decompress`): Распаковывает файлы.
+    /// - `algorithm` (`-a`, `--algorithm`): Выбор алгоритма сжатия. Обязателен для `-c`, если не задана цель `--target-speed`
+    ///   или `--target-ratio`; при распаковке алгоритм определяется по заголовку архива, а `-a` нужен
+    ///   только для архивов старого формата без заголовка.
+    /// - `target_speed` (`--target-speed`), `target_ratio` (`--target-ratio`): Вместо `-a` выбирают алгоритм
+    ///   по замеру на этой машине: самый сжимающий не медленнее заданной скорости или самый быстрый
+    ///   не хуже заданной степени сжатия. Выбор записывается в сведения архива.
+    /// - `input` (`-i`, `--input`): Входной файл для обработки. Обязателен для `-c` и `-d`; `-` — стандартный ввод.
+    /// - `output` (`-o`, `--output`): Выходной файл. Обязателен для `-c` и `-d`; `-` — стандартный вывод.
+    /// - `multithread` (`-m`, `--multithread`): Включает многопоточную обработку.
+    /// - `threads` (`--threads`): Число потоков, которые распаковывают и записывают файлы при распаковке.
+    /// - `short_matches` (`--short-matches`): Разрешает короткие токены LZ77 для близких повторов.
+    /// - `strict` (`--strict`): Строгая распаковка — любая аномалия архива считается ошибкой.
+    /// - `salvage` (`--salvage`): Восстанавливает из поврежденного архива все, что удается, и выводит отчет о потерях.
+    /// - `index_only` (`--index-only`): Записывает архив без данных — только индекс с контрольными суммами.
+    /// - `update` (`-u`, `--update`): Дописывает к существующему архиву только новые и измененные записи
+    ///   (с другим размером, временем изменения или правами).
+    /// - `compare_content` (`--compare-content`): `-u` сравнивает содержимое записей, а не размер и время изменения.
+    /// - `solid` (`--solid`): Сжимает все записи одним потоком вместо сжатия каждой записи отдельно; повторы кадров потока заменяются ссылками.
+    /// - `no_solid` (`--no-solid`): Сжимает каждую запись отдельно (по умолчанию); из `--solid` и `--no-solid`
+    ///   действует последний.
+    /// - `chunk_dedup` (`--chunk-dedup`): Сохраняет повторяющиеся блоки общего потока один раз (только с `--solid`).
+    /// - `block_size` (`--block-size`): Делит файлы на независимо сжатые блоки для чтения диапазонов; длина блока подстраивается под энтропию данных.
+    /// - `pack_below` (`--pack-below`): Упаковывает файлы меньше заданного размера в общие сжатые сегменты.
+    /// - `recompress` (`--recompress`): Разрешает сжимать файл, который уже является архивом.
+    /// - `exclude` (`--exclude`), `exclude_from` (`--exclude-from`): Шаблоны файлов, которые не попадают
+    ///   в архив; флаг можно повторять, а файл содержит по одному шаблону на строку.
+    /// - `no_ignore_files` (`--no-ignore-files`): Не учитывает файлы `.archiverignore` в обходимых директориях.
+    /// - `gitignore` (`--gitignore`): В директориях без `.archiverignore` учитывает `.gitignore`.
+    /// - `files_from` (`--files-from`): Файл со списком путей относительно `-i`, которые сжимаются
+    ///   вместо обхода всего дерева.
+    /// - `null` (`--null`): Пути в списке `--files-from` разделены нулевыми байтами (`find -print0`).
+    /// - `one_file_system` (`--one-file-system`): Не заходит в директории, смонтированные с других файловых систем.
+    /// - `exclude_caches` (`--exclude-caches`): Пропускает директории, помеченные файлом `CACHEDIR.TAG`.
+    /// - `numeric_owner` (`--numeric-owner`): Сохраняет и восстанавливает владельца только по числовым UID и GID.
+    /// - `owner_map` (`--owner-map`): Файл замены владельцев при распаковке: строки `user СТАРЫЙ НОВЫЙ`
+    ///   и `group СТАРЫЙ НОВЫЙ` с именами или числовыми идентификаторами (см. `owner::OwnerMap`).
+    /// - `fsync` (`--fsync`): Когда сбрасывать распакованные данные на диск: `per-file`, `at-end` или `off`.
+    /// - `extract_order` (`--extract-order`): Порядок записи файлов при распаковке: `archive` — по смещению
+    ///   в архиве, `path` — по пути назначения.
+    /// - `strip_components` (`--strip-components`): Убирает первые N компонентов пути каждой записи при распаковке.
+    /// - `transform` (`--transform`): Переименовывает записи при распаковке по правилу `s|шаблон|замена|флаги`
+    ///   (можно указать несколько раз).
+    /// - `overwrite` (`--overwrite`): Что делать при распаковке с существующими файлами: `force` — заменять,
+    ///   `skip` — оставлять, `prompt` — спрашивать, `keep-newer` — оставлять измененные позже записей архива.
+    /// - `report` (`--report`): Перечитывает распакованные файлы, сверяет CRC32 записанных байтов
+    ///   с сохраненной и записывает результат по каждому файлу в отчет.
+    /// - `recursive_extract` (`--recursive-extract`): После распаковки распаковывает на месте вложенные архивы
+    ///   этой программы до заданной глубины вложенности (по умолчанию 4).
+    /// - `portable_names` (`--portable-names`): Переименовывает при распаковке и имена, недопустимые
+    ///   в файловых системах Windows.
+    /// - `skip_special` (`--skip-special`): Не сохраняет и не создает именованные каналы и устройства.
+    /// - `no_dedup` (`--no-dedup`): Сохраняет содержимое каждого файла, даже если оно повторяет другой файл.
+    /// - `reproducible` (`--reproducible`): Создает побайтно воспроизводимый архив для одного и того же дерева.
+    /// - `comment` (`--comment`): Комментарий, который сохраняется в архиве вместе со временем создания,
+    ///   именем компьютера и версией программы.
+    /// - `volume_size` (`--volume-size`): Записывает архив томами `<выход>.001`, `<выход>.002`, ... не больше
+    ///   заданного размера; при распаковке тома читаются по порядку.
+    /// - `recovery` (`--recovery`): Дописывает к архиву данные четности Рида — Соломона размером
+    ///   около заданного процента архива.
+    /// - `status_file` (`--status-file`): Файл, в который раз в секунду записываются стадия операции,
+    ///   текущая запись, обработанные записи и байты и оценка оставшегося времени.
+    /// - `progress` (`--progress json`): Несколько раз в секунду выводит в stderr то же состояние событием JSON
+    ///   в одну строку.
+    /// - `type` (`--type`), `perm` (`--perm`): При распаковке и в списке `--list` остаются только записи
+    ///   указанного типа и с подходящими правами доступа. Директории и символические ссылки в архиве
+    ///   не хранятся, поэтому типы `d` и `l` не принимаются.
+    /// - `catalog` (`--catalog`): Файл каталога, в который записываются сведения о каждом созданном архиве.
+    /// - `locate` (`--locate`): Ищет в каталоге архивы, содержащие файл с указанным путем или именем.
+    /// - `self_test` (`--self-test`): Проверяет все алгоритмы и контейнер на встроенных тестовых данных.
+    /// - `bench_corpus` (`--bench-corpus`): Сравнивает алгоритмы на файлах директории и записывает отчет CSV.
+    ///
+    /// - `cmp` (`--cmp`): Сравнивает два архива и выводит добавленные, удаленные и измененные записи.
+    /// - `diff` (`--diff`): Сравнивает архив `-i` с директорией `-o` и выводит записи, которых нет на диске,
+    ///   лишние файлы и файлы, отличающиеся размером, содержимым, правами или временем изменения.
+    /// - `cat` (`--cat`): Выводит содержимое файла `--path` из архива `-i` или его диапазон байтов `--range`.
+    ///   Путь можно передать и позиционным аргументом: `rle_archiver cat -i backup.arc etc/app.conf`.
+    /// - `head` (`--head`): Выводит первые `-n` (`--lines`) строк файла `--path` из архива `-i`.
+    /// - `compact` (`--compact`): Переписывает архив `-i` без вытесненных записей в `-o` или на место исходного.
+    /// - `info` (`--info`): Выводит сведения об архиве `-i`: шифрование, формат, алгоритм, число записей, исходный
+    ///   и сжатый размер и происхождение.
+    /// - `list` (`-l`, `--list`): Выводит записи архива `-i` с правами, размерами и временем изменения
+    ///   без распаковки содержимого файлов.
+    /// - `tree` (`--tree`): Выводит записи архива `-i` деревом с суммарными исходными и сжатыми размерами
+    ///   каждой директории и ее долей в архиве.
+    /// - `time_style` (`--time-style`): Как `--list` показывает время изменения: `iso` — до минуты, `full` — до секунды
+    ///   со смещением пояса, `relative` — относительно текущего; время местное.
+    /// - `no_cache` (`--no-cache`): `--list` и `--tree` не читают и не сохраняют разобранный список записей
+    ///   в кеше `~/.cache/archiver/` (см. `cache`).
+    /// - `dupes` (`--dupes`): Выводит группы файлов с одинаковым содержимым в архиве или директории `-i`
+    ///   и число байтов, занятых лишними копиями.
+    /// - `link_dupes` (`--link-dupes`): Вместе с `--dupes` заменяет копии в директории жесткими ссылками.
+    /// - `grep` (`--grep`): Ищет шаблон (позиционный аргумент) в содержимом файлов архива `-i`, распаковывая
+    ///   записи по одной, и выводит найденные строки как `путь:номер строки:строка`.
+    /// - `fixed_strings` (`-F`, `--fixed-strings`): Шаблон `--grep` — обычная строка, а не регулярное выражение.
+    /// - `ignore_case` (`--ignore-case`): `--grep` не учитывает регистр.
+    /// - `test` (`-t`, `--test`): Распаковывает архив `-i` в памяти и выводит результат проверки каждой записи.
+    /// - `audit` (`--audit`): Проверяет целостность архива `-i` без распаковки на диск.
+    /// - `porcelain` (`--porcelain`): Выводит результаты `--list`, `--tree`, `--dupes`, `--info`, `--cmp`, `--diff`, `--locate`, `-t`
+    ///   и `--audit` в стабильном формате с разделителями-табуляциями для скриптов.
+    /// - `verbose` (`-v`, `--verbose`), `quiet` (`-q`, `--quiet`), `log_level` (`--log-level`): Уровень журнала в stderr:
+    ///   `-v` — `info`, `-vv` — `debug`, `-vvv` — `trace`, `-q` — только ошибки; без них — `warn` или `RUST_LOG`.
+    /// - `format` (`--format json`): Выводит результаты `--list` и `--info` в формате JSON: массив записей
+    ///   или объект со сведениями о каждом архиве потока.
+    /// - `depth` (`--depth`): Глубина `--audit`: `quick` — заголовки и индексы, `standard` — также хеши SHA-256,
+    ///   `deep` — также распаковка и сверка CRC32 каждой записи.
+    /// - `repair` (`--repair`): Восстанавливает поврежденные части архива `-i` по записи восстановления
+    ///   и записывает результат в `-o` или на место исходного.
+    ///
+    /// - `serve` (`--serve`): Запускает сервер, который принимает запросы на сжатие и распаковку через сокет Unix.
+    /// - `http` (`--http`): Запускает HTTP-сервис архивации (только в сборке с возможностью `server`).
+    ///
+    /// Ровно один из режимов `-c`, `-d`, `--locate`, `--self-test`, `--bench-corpus`, `--cmp`, `--cat`, `--head`,
+    /// `--compact`, `--info`, `-l`, `--tree`, `--dupes`, `--grep`, `--diff`, `-t`, `--audit`, `--repair`, `--serve`, `--http` обязателен.
 fn main() {
     
     
     // Определение аргументов командной строки
-    let matches = Command::new("rle_archiver")
+    let commands: String = COMMANDS.iter().map(|(name, _, help)| format!("\n  {:<10}{}", name, help)).collect();
+    let mut cli = Command::new("rle_archiver")
         .version("1.0")
         .author("Your Name <youremail@example.com>")
         .about("Compresses and decompresses files using various algorithms")
+        .override_usage("rle_archiver <COMMAND> [OPTIONS]\n       rle_archiver <-c|-d|-l|-t|--info|--tree|...> [OPTIONS]")
+        .after_help(format!("Commands:{}", commands))
         .arg(Arg::new("compress")
             .short('c')
-            .conflicts_with("decompress")
+            .long("compress")
             .help("Compress files")
-            .required_unless_present("decompress")
+            .requires("algorithm_choice")
             .action(ArgAction::SetTrue))
         .arg(Arg::new("decompress")
             .short('d')
-            .conflicts_with("compress")
-            .help("Decompress files")
-            .required_unless_present("compress")
+            .long("extract")
+            .visible_alias("decompress")
+            .help("Extract an archive")
             .action(ArgAction::SetTrue))
         .arg(Arg::new("algorithm")
             .short('a')
-            .help("Compression algorithm to use")
-            .required(true)
+            .long("algorithm")
+            .help("Compression algorithm to use (detected from the archive header when decompressing)")
             .num_args(1)) 
+        .arg(Arg::new("target_speed")
+            .long("target-speed")
+            .help("Instead of -a, pick the best-compressing algorithm that compresses at least this fast on this machine (e.g. 200MBps)")
+            .value_name("SPEED")
+            .value_parser(calibrate::parse_speed)
+            .conflicts_with("algorithm")
+            .num_args(1))
+        .arg(Arg::new("target_ratio")
+            .long("target-ratio")
+            .help("Instead of -a, pick the fastest algorithm whose compressed size is at most this share of the original (e.g. 0.5)")
+            .value_name("RATIO")
+            .value_parser(calibrate::parse_ratio)
+            .conflicts_with("algorithm")
+            .num_args(1))
         .arg(Arg::new("input")
             .short('i')
-            .help("Input file to process")
-            .required(true)
+            .long("input")
+            .help("Input file to process (- for standard input)")
+            .required_if_eq_any([("compress", "true"), ("decompress", "true"), ("cat", "true"), ("head", "true"), ("compact", "true"), ("info", "true"), ("list", "true"), ("tree", "true"), ("dupes", "true"), ("grep", "true"), ("diff", "true"), ("test", "true"), ("audit", "true"), ("repair", "true")])
             .num_args(1)) 
         .arg(Arg::new("output")
             .short('o')
-            .help("Output file")
-            .required(true)
+            .long("output")
+            .help("Output file (- for standard output)")
+            .required_if_eq_any([("compress", "true"), ("decompress", "true")])
             .num_args(1))
         .arg(Arg::new("multithread")
             .short('m')
+            .long("multithread")
             .help("Enable multithreading")
             .action(ArgAction::SetTrue))
-        .get_matches();
+        .arg(Arg::new("threads")
+            .long("threads")
+            .help("Number of threads that decode and write extracted entries (default: 4 with -m, otherwise 1)")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .num_args(1))
+        .arg(Arg::new("short_matches")
+            .long("short-matches")
+            .help("Use 3-byte LZ77 tokens for near matches")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("strict")
+            .long("strict")
+            .help("Fail on any anomaly in the archive instead of extracting what is readable")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("salvage")
+            .long("salvage")
+            .help("Extract everything recoverable from a damaged archive and report lost entries instead of failing")
+            .conflicts_with("strict")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("index_only")
+            .long("index-only")
+            .help("Write only paths, sizes, permissions, modification times and checksums (no file data) as a baseline for --cmp")
+            .conflicts_with("catalog")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("update")
+            .short('u')
+            .long("update")
+            .help("Append only new and changed entries to the existing archive (-o) instead of rewriting it")
+            .conflicts_with_all(["index_only", "volume_size", "catalog"])
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("compare_content")
+            .long("compare-content")
+            .help("With -u, compare file contents instead of sizes and modification times (slower: stored entries are decompressed)")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("solid")
+            .long("solid")
+            .help("Compress all entries as one stream (better ratio for many small files, no selective extraction); repeated 1 KiB frames are stored once")
+            .overrides_with("no_solid")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("no_solid")
+            .long("no-solid")
+            .help("Compress every entry independently with a central index for fast partial extraction (default)")
+            .overrides_with("solid")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("chunk_dedup")
+            .long("chunk-dedup")
+            .help("Split the solid stream into content-defined chunks and store repeated chunks once")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("block_size")
+            .long("block-size")
+            .help("Split each file into independently compressed blocks of about this many bytes for random access; from 4096 bytes up, blocks shrink to a quarter in incompressible data and grow up to four times in repetitive data")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .conflicts_with("solid")
+            .num_args(1))
+        .arg(Arg::new("pack_below")
+            .long("pack-below")
+            .help("Pack files smaller than this many bytes into shared data segments compressed together")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .conflicts_with_all(["solid", "block_size"])
+            .num_args(1))
+        .arg(Arg::new("recompress")
+            .long("recompress")
+            .help("Allow compressing a file that is already an archive")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("exclude")
+            .long("exclude")
+            .help("Skip files and directories matching this glob (name, or path if it contains /); repeatable")
+            .action(ArgAction::Append)
+            .num_args(1))
+        .arg(Arg::new("exclude_from")
+            .long("exclude-from")
+            .help("Read exclude globs from a file, one per line; lines starting with # are comments")
+            .action(ArgAction::Append)
+            .num_args(1))
+        .arg(Arg::new("files_from")
+            .long("files-from")
+            .value_name("FILE")
+            .help("Archive only the paths listed in FILE (one per line, relative to the input directory; - for standard input)")
+            .conflicts_with("index_only")
+            .num_args(1))
+        .arg(Arg::new("null")
+            .long("null")
+            .help("Paths in the --files-from list are separated by NUL bytes, as printed by find -print0")
+            .requires("files_from")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("one_file_system")
+            .long("one-file-system")
+            .help("Stay on the file system of the input; skip mount points such as /proc or network shares")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("exclude_caches")
+            .long("exclude-caches")
+            .help("Skip directories containing a valid CACHEDIR.TAG file")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("numeric_owner")
+            .long("numeric-owner")
+            .help("Store and restore owners by numeric UID/GID only, ignoring user and group names")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("owner_map")
+            .long("owner-map")
+            .value_name("FILE")
+            .help("Translate archived owners when extracting as root: lines \"user OLD NEW\" or \"group OLD NEW\" with names or numeric ids")
+            .num_args(1))
+        .arg(Arg::new("fsync")
+            .long("fsync")
+            .help("When to flush extracted data to disk: after each file, once at the end, or not at all")
+            .value_parser(["per-file", "at-end", "off"])
+            .default_value("off")
+            .num_args(1))
+        .arg(Arg::new("extract_order")
+            .long("extract-order")
+            .help("Order of file writes when extracting: by archive offset (sequential reads) or by target path (directory locality)")
+            .value_parser(["archive", "path"])
+            .default_value("archive")
+            .num_args(1))
+        .arg(Arg::new("report")
+            .long("report")
+            .value_name("FILE")
+            .help("Re-read extracted files, compare checksums of the written bytes and write per-file results")
+            .num_args(1))
+        .arg(Arg::new("recursive_extract")
+            .long("recursive-extract")
+            .value_name("DEPTH")
+            .help("Also unpack archives found inside the extracted tree in place, up to DEPTH levels deep")
+            .value_parser(clap::value_parser!(usize))
+            .num_args(0..=1)
+            // Совпадает с nested::DEFAULT_DEPTH
+            .default_missing_value("4"))
+        .arg(Arg::new("portable_names")
+            .long("portable-names")
+            .help("Also rename entries whose names are invalid on Windows file systems when extracting")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("skip_special")
+            .long("skip-special")
+            .help("Do not archive or extract FIFOs and device nodes")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("no_ignore_files")
+            .long("no-ignore-files")
+            .help("Do not honor .archiverignore files (gitignore syntax) in the walked directories")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("gitignore")
+            .long("gitignore")
+            .help("Honor .gitignore in directories that have no .archiverignore")
+            .conflicts_with("no_ignore_files")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("no_dedup")
+            .long("no-dedup")
+            .help("Store every file's content even if another file has identical content")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("reproducible")
+            .long("reproducible")
+            .help("Produce byte-identical archives for the same tree: sort entries, normalize permissions, drop owners and host, take the time from SOURCE_DATE_EPOCH")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("comment")
+            .long("comment")
+            .help("Free-form comment stored in the archive together with its creation time, host and tool version")
+            .num_args(1))
+        .arg(Arg::new("volume_size")
+            .long("volume-size")
+            .help("Split the archive into volumes OUTPUT.001, OUTPUT.002, ... of at most this size (e.g. 100M)")
+            .value_parser(volume::parse_size)
+            .num_args(1))
+        .arg(Arg::new("recovery")
+            .long("recovery")
+            .help("Append Reed-Solomon recovery data of about this share of the archive (e.g. 5%), used by --repair")
+            .value_parser(recovery::parse_percent)
+            .num_args(1))
+        .arg(Arg::new("status_file")
+            .long("status-file")
+            .help("Periodically write the current stage, entry, processed bytes and ETA to this file")
+            .num_args(1))
+        .arg(Arg::new("progress")
+            .long("progress")
+            .value_name("FORMAT")
+            .help("Emit progress events on stderr in this format: json (one object per line with the stage, current entry, processed and total entries and bytes)")
+            .value_parser(["json"])
+            .num_args(1))
+        .arg(Arg::new("type")
+            .long("type")
+            .help("Extract or list only entries of this type: f (file), p (FIFO), c/b (character/block device); directories and symlinks are not stored")
+            .value_parser(["f", "p", "c", "b"])
+            .num_args(1))
+        .arg(Arg::new("perm")
+            .long("perm")
+            .help("Extract or list only entries whose permissions match, as in find -perm: /MODE (any bit), -MODE (all bits), MODE (exact)")
+            .allow_hyphen_values(true)
+            .num_args(1))
+        .arg(Arg::new("catalog")
+            .long("catalog")
+            .help("Catalog file recording every created archive")
+            .num_args(1))
+        .arg(Arg::new("locate")
+            .long("locate")
+            .help("List archives in the catalog that contain the given path or file name")
+            .requires("catalog")
+            .num_args(1))
+        .arg(Arg::new("self_test")
+            .long("self-test")
+            .help("Run built-in round-trip checks for every codec and the container")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("bench_corpus")
+            .long("bench-corpus")
+            .help("Benchmark every codec on the files of a directory and write a CSV report (to -o or stdout)")
+            .num_args(1))
+        .arg(Arg::new("cmp")
+            .long("cmp")
+            .help("Compare two archives or index-only archives and report added, removed and modified entries")
+            .value_names(["OLD", "NEW"])
+            .num_args(2))
+        .arg(Arg::new("diff")
+            .long("diff")
+            .help("Compare the archive (-i) with the directory (-o) and report entries missing on disk, extra files and files whose size, content, permissions or modification time differ")
+            .requires("output")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("cat")
+            .long("cat")
+            .help("Print a file stored in the archive (-i), or a byte range of it, to -o or stdout")
+            .requires("entry_path")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("path")
+            .long("path")
+            .help("Path of the file inside the archive")
+            .num_args(1))
+        .arg(Arg::new("entry")
+            .value_name("PATH|PATTERN")
+            .help("Path of the file inside the archive for --cat and --head (same as --path), or the pattern of --grep")
+            .conflicts_with("path")
+            .index(1))
+        .arg(Arg::new("range")
+            .long("range")
+            .help("Byte range START-END (END exclusive, may be omitted for end of file); only the blocks covering it are decoded in --block-size archives")
+            .value_parser(parse_range)
+            .num_args(1))
+        .arg(Arg::new("head")
+            .long("head")
+            .help("Print the first lines of a file stored in the archive (-i), decoding only as many blocks as needed")
+            .requires("entry_path")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("lines")
+            .short('n')
+            .long("lines")
+            .help("Number of lines printed by --head (default 10)")
+            .value_parser(clap::value_parser!(usize))
+            .num_args(1))
+        .arg(Arg::new("compact")
+            .long("compact")
+            .help("Rewrite the archive (-i) densely, dropping superseded entries of appended archives; written to -o or in place")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("info")
+            .long("info")
+            .help("Show the format, algorithm, entry count, creation time, host, tool version and comment of the archive (-i)")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("list")
+            .short('l')
+            .long("list")
+            .help("List the entries of the archive (-i) with their permissions, sizes and modification times without extracting file data")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("tree")
+            .long("tree")
+            .help("Show the entries of the archive (-i) as a tree with total original and compressed sizes per directory")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("time_style")
+            .long("time-style")
+            .value_name("STYLE")
+            .help("How --list shows modification times in the local time zone: iso (default, to the minute), full (to the second with the UTC offset) or relative (e.g. 3 days ago)")
+            .value_parser(["iso", "full", "relative"])
+            .default_value("iso")
+            .num_args(1))
+        .arg(Arg::new("no_cache")
+            .long("no-cache")
+            .help("Do not read or write the cached listing of the archive in ~/.cache/archiver (used by --list and --tree)")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("dupes")
+            .long("dupes")
+            .help("Report groups of files with identical content and the bytes they waste in the archive or directory (-i)")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("link_dupes")
+            .long("link-dupes")
+            .help("With --dupes over a directory, replace identical copies with hard links to the first copy")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("grep")
+            .long("grep")
+            .help("Print lines of files in the archive (-i) that match the regular expression PATTERN as path:line:text, decompressing one entry at a time")
+            .requires("entry")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("fixed_strings")
+            .short('F')
+            .long("fixed-strings")
+            .help("Treat the --grep pattern as a literal string")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("ignore_case")
+            .long("ignore-case")
+            .help("Ignore case in --grep")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("porcelain")
+            .long("porcelain")
+            .help("Print results of --list, --tree, --dupes, --info, --cmp, --diff, --locate, --test and --audit in a stable tab-separated format for scripts")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .help("Log more details on stderr: -v info, -vv debug, -vvv trace")
+            .action(ArgAction::Count))
+        .arg(Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .help("Log only errors on stderr")
+            .conflicts_with("verbose")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("log_level")
+            .long("log-level")
+            .value_name("LEVEL")
+            .help("Log records of this level and above on stderr (default: warn, or RUST_LOG)")
+            .value_parser(["off", "error", "warn", "info", "debug", "trace"])
+            .conflicts_with_all(["verbose", "quiet"])
+            .num_args(1))
+        .arg(Arg::new("strip_components")
+            .long("strip-components")
+            .value_name("N")
+            .help("Remove the first N path components of each entry before extracting it; entries with no more than N components are skipped")
+            .value_parser(clap::value_parser!(usize))
+            .num_args(1))
+        .arg(Arg::new("transform")
+            .long("transform")
+            .value_name("EXPR")
+            .help("Rename entries before extracting them with a sed-style rule s|regex|replacement|[flags] (flags: g, i); may be given several times, rules are applied in order before --strip-components")
+            .value_parser(transform::Transform::parse)
+            .action(ArgAction::Append)
+            .num_args(1))
+        .arg(Arg::new("overwrite")
+            .long("overwrite")
+            .value_name("POLICY")
+            .help("What to do with files that already exist in the output: force (replace), skip, prompt (ask on standard input) or keep-newer (keep files modified more recently than their archived copies)")
+            .value_parser(["force", "skip", "prompt", "keep-newer"])
+            .default_value("force")
+            .num_args(1))
+        .arg(Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Output format of --list and --info: text (default) or json")
+            .value_parser(["text", "json"])
+            .default_value("text")
+            .conflicts_with("porcelain")
+            .num_args(1))
+        .arg(Arg::new("test")
+            .short('t')
+            .long("test")
+            .help("Decompress the archive (-i) in memory, verify every entry against its checksum and report each one, writing nothing to disk")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("audit")
+            .long("audit")
+            .help("Check the integrity of the archive (-i) without extracting it, as thoroughly as --depth requests")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("depth")
+            .long("depth")
+            .help("How thoroughly --audit checks: headers and indexes (quick), also SHA-256 digests (standard) or also decode every entry and compare its CRC32 (deep)")
+            .value_parser(["quick", "standard", "deep"])
+            .default_value("standard")
+            .num_args(1))
+        .arg(Arg::new("repair")
+            .long("repair")
+            .help("Reconstruct damaged regions of an archive (-i) created with --recovery; written to -o or in place")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("serve")
+            .long("serve")
+            .help("Serve framed compress/decompress requests on this Unix socket until terminated (the algorithm is chosen per request)")
+            .value_name("SOCKET")
+            .num_args(1))
+        .arg(Arg::new("http")
+            .long("http")
+            .help("Serve HTTP endpoints /archive, /list and /extract on this address, e.g. 127.0.0.1:8080 (requires the server feature)")
+            .value_name("ADDR")
+            .num_args(1))
+        .group(ArgGroup::new("mode")
+            .args(["compress", "decompress", "locate", "self_test", "bench_corpus", "cmp", "cat", "head", "compact", "info", "list", "tree", "dupes", "grep", "diff", "test", "audit", "repair", "serve", "http"])
+            .required(true))
+        .group(ArgGroup::new("entry_path")
+            .args(["path", "entry"]))
+        .group(ArgGroup::new("algorithm_choice")
+            .args(["algorithm", "target_speed", "target_ratio"])
+            .multiple(true));
+    let matches = cli.try_get_matches_from_mut(expand_command(std::env::args_os().collect())).unwrap_or_else(|e| e.exit());
+    check_modes(&mut cli, &matches);
+
+    let log_level = match (matches.get_one::<String>("log_level"), matches.get_count("verbose"), matches.get_flag("quiet")) {
+        (Some(level), _, _) => level.parse().ok(),
+        (None, _, true) => Some(LevelFilter::Error),
+        (None, 0, false) => None,
+        (None, 1, false) => Some(LevelFilter::Info),
+        (None, 2, false) => Some(LevelFilter::Debug),
+        (None, _, false) => Some(LevelFilter::Trace),
+    };
+    init_logging(log_level);
+
+    // В конвейере (`-o - | head`) закрытый читатель завершает программу сигналом SIGPIPE,
+    // как cat, а не паникой при выводе. Серверы игнорируют SIGPIPE, чтобы отключение
+    // клиента не завершало их
+    if !matches.contains_id("serve") && !matches.contains_id("http") {
+        unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+    }
+
+    // Незавершенные выходные файлы удаляются при SIGINT/SIGTERM
+    if let Err(e) = partial::install_cleanup_handler() {
+        error!("Failed to install signal handler: {}", e);
+    }
+
+    if matches.get_flag("self_test") {
+        let passed = selftest::run();
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    let catalog_path = matches.get_one::<String>("catalog");
+    // Результаты выводятся в stdout, а сообщения о ходе работы — в stderr
+    let porcelain = matches.get_flag("porcelain");
+    let json_format = matches.get_one::<String>("format").is_some_and(|format| format == "json");
+
+    // Сравнение алгоритмов на корпусе файлов
+    if let Some(corpus) = matches.get_one::<String>("bench_corpus") {
+        let results = bench::run(Path::new(corpus)).expect("Failed to read corpus");
+        let csv = bench::to_csv(&results);
+        match matches.get_one::<String>("output") {
+            Some(output) => {
+                io::write_file(output, csv.as_bytes()).expect("Failed to write report");
+                for line in bench::summary(&results) {
+                    eprintln!("{}", line);
+                }
+            }
+            None => print!("{}", csv),
+        }
+        return;
+    }
+
+    // Поиск по каталогу не требует алгоритма и входного файла
+    if let Some(name) = matches.get_one::<String>("locate") {
+        let records = catalog::read_catalog(catalog_path.unwrap()).expect("Failed to read catalog");
+        let found = catalog::locate(&records, name);
+        if found.is_empty() && !porcelain {
+            println!("{} not found in any archive.", name);
+        }
+        for (record, entry) in found {
+            if porcelain {
+                println!("{}\t{}\t{}\t{}\t{:08x}", record.archive, record.created, entry.path, entry.size, entry.crc32);
+            } else {
+                println!("{}\t{}\t{}\t{} bytes\tcrc32 {:08x}",
+                    record.archive, record.created, entry.path, entry.size, entry.crc32);
+            }
+        }
+        return;
+    }
 
     // Определение команды (сжатие или распаковка)
     let command = if matches.get_flag("compress") {
@@ -85,61 +1158,808 @@ fn main() {
     };
 
     // Извлечение значений аргументов
-    let algorithm_str = matches.get_one::<String>("algorithm").unwrap();
-    let input_file = matches.get_one::<String>("input").unwrap();
-    let output_file = matches.get_one::<String>("output").unwrap();
+    let threads = matches.get_one::<u64>("threads").map(|&n| n as usize)
+        .unwrap_or(if matches.get_flag("multithread") { processing::THREADS } else { 1 });
+    let use_multithreading = matches.get_flag("multithread") || threads > 1;
+    let short_matches = matches.get_flag("short_matches");
+    let strict = matches.get_flag("strict");
+    let numeric_owner = matches.get_flag("numeric_owner");
 
-    let use_multithreading = matches.get_flag("multithread");
-
-    // Определение алгоритма на основе аргумента
-    let algorithm = match algorithm_str.as_str() {
-        "rle" => Algorithm::Rle,
-        "lz77" => Algorithm::Lz77,
-        "lz4" => Algorithm::Lz4,
-        "lzw" => Algorithm::Lzw,
-        "hf" => Algorithm::Hf,
-        _ => {
+    // Определение алгоритма на основе аргумента (при распаковке он берется из заголовка архива)
+    let algorithm = matches.get_one::<String>("algorithm").map(|algorithm_str| {
+        Algorithm::from_name(algorithm_str).unwrap_or_else(|| {
             error!("Неподдерживаемый алгоритм: {}", algorithm_str);
             std::process::exit(1);
+        })
+    });
+
+    // Сравнение двух архивов
+    if let Some(mut paths) = matches.get_many::<String>("cmp") {
+        let (a, b) = (paths.next().unwrap(), paths.next().unwrap());
+        let index_a = load_index(a, algorithm.as_ref(), short_matches, strict);
+        let index_b = load_index(b, algorithm.as_ref(), short_matches, strict);
+        let report = archive::diff(&index_a, &index_b);
+        print_diff(&report, porcelain, ["added", "removed"]);
+        std::process::exit(if report.is_empty() { 0 } else { 1 });
+    }
+
+    // Вывод файла, диапазона байтов или первых строк без распаковки всего архива
+    if matches.get_flag("cat") || matches.get_flag("head") {
+        let input_file = matches.get_one::<String>("input").unwrap();
+        let path = matches.get_one::<String>("path").or(matches.get_one("entry")).unwrap();
+        let compressed_data = volume::read_archive(input_file).expect("Failed to read input file");
+        if !header::has_magic(&compressed_data) {
+            eprintln!("{}: not an archive", input_file);
+            std::process::exit(1);
+        }
+        let data = if matches.get_flag("head") {
+            processing::read_head(&compressed_data, path, matches.get_one::<usize>("lines").copied().unwrap_or(10), strict)
+        } else {
+            let range = matches.get_one::<Range<u64>>("range").cloned().unwrap_or(0..u64::MAX);
+            processing::read_range(&compressed_data, path, range, strict)
+        };
+        let data = data.unwrap_or_else(|e| {
+            eprintln!("{}: {}", input_file, e);
+            std::process::exit(1);
+        });
+        match matches.get_one::<String>("output") {
+            Some(output) => partial::write_output(output, &data).expect("Failed to write output file"),
+            None => std::io::stdout().write_all(&data).expect("Failed to write to stdout"),
+        }
+        return;
+    }
+
+    // Уплотнение архива после дописывания
+    if matches.get_flag("compact") {
+        let input_file = matches.get_one::<String>("input").unwrap();
+        let output_file = matches.get_one::<String>("output").unwrap_or(input_file);
+        if input_file == io::STDIO_PATH {
+            eprintln!("--compact rewrites the archive file and cannot read it from standard input");
+            std::process::exit(1);
+        }
+        // Архив перезаписывается целиком, поэтому блокировка держится до записи результата
+        let mut input_lock = lock::ArchiveLock::exclusive(Path::new(input_file)).expect("Failed to open input file");
+        let _output_lock = if output_file != input_file {
+            lock::lock_existing(Path::new(output_file)).expect("Failed to lock output file")
+        } else {
+            None
+        };
+        let compressed_data = input_lock.read_all().and_then(crypto::open).expect("Failed to read input file");
+        let compacted = processing::compact(&compressed_data, use_multithreading, strict).unwrap_or_else(|e| {
+            eprintln!("{}: {}", input_file, e);
+            std::process::exit(1);
+        });
+        if partial::interrupted() {
+            exit_interrupted(&format!("{} was not changed", output_file));
         }
+        let compacted = crypto::seal(compacted).expect("Failed to encrypt archive");
+        partial::write_output(output_file, &compacted).expect("Failed to write output file");
+        eprintln!("{}: {} -> {} bytes", output_file, compressed_data.len(), compacted.len());
+        return;
+    }
+
+    // Сервер сжатия на локальном сокете
+    if let Some(socket) = matches.get_one::<String>("serve") {
+        let options = server::ServeOptions { use_multithreading, short_matches, strict };
+        eprintln!("Listening on {}", socket);
+        if let Err(e) = server::serve(Path::new(socket), options) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // HTTP-сервис архивации
+    if let Some(addr) = matches.get_one::<String>("http") {
+        #[cfg(feature = "server")]
+        {
+            let options = server::ServeOptions { use_multithreading, short_matches, strict };
+            eprintln!("Listening on http://{}", addr);
+            if let Err(e) = rle_archiver::http::serve(addr, options) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        #[cfg(not(feature = "server"))]
+        {
+            eprintln!("Cannot serve on {}: built without the server feature (cargo build --features server)", addr);
+            std::process::exit(1);
+        }
+    }
+
+    // Починка архива по записи восстановления
+    if matches.get_flag("repair") {
+        let input_file = matches.get_one::<String>("input").unwrap();
+        let output_file = matches.get_one::<String>("output").unwrap_or(input_file);
+        if input_file == io::STDIO_PATH {
+            eprintln!("--repair rewrites the archive file and cannot read it from standard input");
+            std::process::exit(1);
+        }
+        let mut input_lock = lock::ArchiveLock::exclusive(Path::new(input_file)).expect("Failed to open input file");
+        let _output_lock = if output_file != input_file {
+            lock::lock_existing(Path::new(output_file)).expect("Failed to lock output file")
+        } else {
+            None
+        };
+        let damaged = input_lock.read_all().expect("Failed to read input file");
+        let report = recovery::repair(&damaged).unwrap_or_else(|e| {
+            eprintln!("{}: {}", input_file, e);
+            std::process::exit(1);
+        });
+        if report.damaged_data + report.damaged_parity == 0 {
+            eprintln!("{}: no damage found", input_file);
+            if output_file == input_file {
+                return;
+            }
+        }
+        partial::write_output(output_file, &report.archive).expect("Failed to write output file");
+        if report.damaged_data + report.damaged_parity > 0 {
+            eprintln!("{}: repaired {} damaged regions of the archive and {} of the recovery data",
+                output_file, report.damaged_data, report.damaged_parity);
+        }
+        return;
+    }
+
+    // Сведения об архиве без распаковки
+    if matches.get_flag("info") {
+        let input_file = matches.get_one::<String>("input").unwrap();
+        let raw = volume::read_raw(input_file).expect("Failed to read input file");
+        // Зашифрованный файл помечен `crypto::SEALED_MAGIC`; файлы, зашифрованные до появления
+        // метки, начинаются не с сигнатуры, а с данных шифра
+        let encrypted = crypto::is_sealed(&raw) || !header::has_magic(&raw);
+        let print_encrypted = || {
+            if porcelain {
+                println!("encrypted\t{}", if encrypted { "yes" } else { "no" });
+            } else if !json_format {
+                println!("Encrypted:  {}", if encrypted { "yes (file cipher)" } else { "no" });
+            }
+        };
+        // Без шифра о зашифрованном архиве известно только то, что он зашифрован
+        let compressed_data = crypto::open(raw).unwrap_or_else(|e| {
+            if encrypted && json_format {
+                println!("{}", json::Object::new().raw("encrypted", true).raw("archives", "null").finish());
+            } else if encrypted {
+                print_encrypted();
+            }
+            eprintln!("{}: {}", input_file, e);
+            std::process::exit(1);
+        });
+        // Файл без сигнатуры, который не расшифровался в архив, — не архив, а не зашифрованный архив
+        let members = processing::describe(&compressed_data, strict).unwrap_or_else(|e| {
+            eprintln!("{}: {}", input_file, e);
+            std::process::exit(1);
+        });
+        print_encrypted();
+        if json_format {
+            let archives = json::array(members.iter().enumerate().map(|(i, member)| member_json(i + 1, member)));
+            println!("{}", json::Object::new().raw("encrypted", encrypted).raw("archives", archives).finish());
+            return;
+        }
+        for (i, member) in members.iter().enumerate() {
+            if porcelain {
+                print_member_porcelain(i + 1, member);
+                continue;
+            }
+            if members.len() > 1 {
+                println!("Archive {} of {}:", i + 1, members.len());
+            }
+            let layout = match (member.entries, member.block_size) {
+                (Some(count), Some(block_size)) => format!("non-solid, {} entries in blocks of about {} bytes", count, block_size),
+                (Some(count), None) => format!("non-solid, {} entries compressed separately", count),
+                (None, _) if member.header.chunked() => "solid, single compressed stream of deduplicated chunks".to_owned(),
+                (None, _) => "solid, single compressed stream".to_owned(),
+            };
+            println!("Format:     version {}, {}", member.header.version, member.header.algorithm.name());
+            println!("Layout:     {}", layout);
+            println!("Compressed: {} bytes", member.payload_len);
+            match member.original_size {
+                Some(0) => println!("Original:   0 bytes"),
+                Some(original) => println!("Original:   {} bytes (ratio {:.3})", original, member.payload_len as f64 / original as f64),
+                None => println!("Original:   unknown until the solid stream is decompressed"),
+            }
+            if let Some(provenance) = &member.info {
+                println!("Created:    {}", info::format_time(provenance.created));
+                println!("Host:       {}", provenance.hostname);
+                println!("Tool:       {}", provenance.tool_version);
+                if let Some(comment) = &provenance.comment {
+                    println!("Comment:    {}", comment);
+                }
+                if let Some(options) = &provenance.options {
+                    println!("Options:    {}", options);
+                }
+                if let Some(selection) = &provenance.selection {
+                    println!("Selected:   {}", selection);
+                }
+                if let Some(rate) = provenance.decode_rate {
+                    println!("Restore:    {} (measured at creation)", info::format_rate(rate));
+                    if rate < info::SLOW_RESTORE_RATE {
+                        let created_with = provenance.options.as_ref().map_or(member.header.algorithm.name().to_owned(), |o| o.to_string());
+                        warn!("archive {} was created with {}, expect {} restore.", i + 1, created_with, info::format_rate(rate));
+                    }
+                }
+            }
+        }
+        return;
+    }
+
+    // Фильтр записей при перечислении и распаковке
+    let entry_filter = filter::EntryFilter {
+        entry_type: matches.get_one::<String>("type").and_then(|t| filter::EntryType::from_letter(t)),
+        perm: matches.get_one::<String>("perm").map(|spec| filter::PermMatch::parse(spec).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })),
     };
 
+    // Список записей архива
+    if matches.get_flag("list") {
+        let input_file = matches.get_one::<String>("input").unwrap();
+        let mut entries = list_entries(input_file, strict, !matches.get_flag("no_cache"));
+        entries.retain(|(entry, _)| entry_filter.matches_mode(entry.permissions));
+        if json_format {
+            println!("{}", json::array(entries.iter().map(|(entry, compressed)| entry_json(entry, *compressed))));
+            return;
+        }
+        if porcelain {
+            for (entry, _) in entries {
+                println!("{:o}\t{}\t{}\t{}\t{}", entry.permissions, entry.size, entry.path, entry.link.as_deref().unwrap_or(""),
+                    entry.mtime.map(|mtime| mtime.to_string()).unwrap_or_default());
+            }
+            return;
+        }
+        let style = matches.get_one::<String>("time_style").and_then(|name| timestamp::TimeStyle::from_name(name)).unwrap_or_default();
+        let now = timestamp::now();
+        let times: Vec<String> = entries.iter()
+            .map(|(entry, _)| entry.mtime.map_or_else(|| "-".to_owned(), |mtime| style.format(mtime, now)))
+            .collect();
+        let width = times.iter().map(|time| time.chars().count()).max().unwrap_or(0);
+        for ((entry, _), mtime) in entries.into_iter().zip(times) {
+            let link = match &entry.link {
+                Some(target) if filter::EntryType::from_mode(entry.permissions) == filter::EntryType::Symlink => format!(" -> {}", target),
+                Some(target) => format!(" link to {}", target),
+                None => String::new(),
+            };
+            println!("{} {:>12} {:>width$} {}{}", filter::mode_string(entry.permissions), entry.size, mtime, entry.path, link);
+        }
+        return;
+    }
+
+    // Дерево записей с размерами директорий
+    if matches.get_flag("tree") {
+        let input_file = matches.get_one::<String>("input").unwrap();
+        let entries = list_entries(input_file, strict, !matches.get_flag("no_cache"));
+        let root = tree::build(&entries);
+        if !porcelain {
+            println!("{:>12} {:>12} {:>6}  path", "original", "compressed", "share");
+        }
+        for (depth, path, node) in root.flatten() {
+            let slash = if node.is_dir && depth > 0 { "/" } else { "" };
+            if porcelain {
+                let compressed = node.compressed.map(|size| size.to_string()).unwrap_or_default();
+                println!("{}\t{}\t{}{}", node.original, compressed, path, slash);
+                continue;
+            }
+            let compressed = node.compressed.map(|size| size.to_string()).unwrap_or_else(|| "-".to_owned());
+            let share = node.original as f64 * 100.0 / root.original.max(1) as f64;
+            let name = if depth == 0 { input_file.as_str() } else { node.name.as_str() };
+            println!("{:>12} {:>12} {:>5.1}%  {}{}{}", node.original, compressed, share, "  ".repeat(depth), name, slash);
+        }
+        if root.compressed.is_none() && !porcelain {
+            eprintln!("{}: compressed sizes of entries in a solid stream are unknown (-)", input_file);
+        }
+        return;
+    }
+
+    // Проверка каждой записи архива в памяти
+    if matches.get_flag("test") {
+        let input_file = matches.get_one::<String>("input").unwrap();
+        let compressed_data = volume::read_archive(input_file).expect("Failed to read input file");
+        let report = processing::test_archive(&compressed_data, strict).unwrap_or_else(|e| {
+            eprintln!("{}: {}", input_file, e);
+            std::process::exit(1);
+        });
+        for check in &report.entries {
+            match (&check.error, porcelain) {
+                (None, true) => println!("ok\t{}", check.path),
+                (Some(error), true) => println!("failed\t{}\t{}", check.path, error),
+                (None, false) => println!("ok      {}", check.path),
+                (Some(error), false) => println!("FAILED  {}: {}", check.path, error),
+            }
+        }
+        for damage in &report.damaged {
+            eprintln!("{}: {}", input_file, damage);
+        }
+        let failed = report.entries.iter().filter(|c| !c.is_ok()).count();
+        eprintln!("{}: {} entries ok, {} failed", input_file, report.entries.len() - failed, failed);
+        std::process::exit(if report.is_ok() { 0 } else { 1 });
+    }
+
+    // Проверка целостности архива
+    if matches.get_flag("audit") {
+        let input_file = matches.get_one::<String>("input").unwrap();
+        let depth_name = matches.get_one::<String>("depth").unwrap();
+        let depth = processing::AuditDepth::from_name(depth_name).unwrap_or_default();
+        let compressed_data = volume::read_archive(input_file).expect("Failed to read input file");
+        match processing::audit(&compressed_data, depth, strict) {
+            Ok(report) if porcelain => println!("ok\t{}\t{}\t{}\t{}", depth_name, report.members, report.digests, report.entries),
+            Ok(report) => println!("{}: ok ({} audit: {} archives, {} SHA-256 digests, {} entries)",
+                input_file, depth_name, report.members, report.digests, report.entries),
+            Err(e) => {
+                eprintln!("{}: {}", input_file, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Поиск по содержимому файлов архива
+    if matches.get_flag("grep") {
+        let pattern = matches.get_one::<String>("entry").unwrap();
+        let input_file = matches.get_one::<String>("input").unwrap();
+        let pattern = grep::build_pattern(pattern, matches.get_flag("fixed_strings"), matches.get_flag("ignore_case"))
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+        let compressed_data = volume::read_archive(input_file).expect("Failed to read input file");
+        let mut stdout = std::io::stdout().lock();
+        let found = grep::search(&compressed_data, &pattern, strict, &|_, _| true, &mut |m| {
+            writeln!(stdout, "{}:{}:{}", m.path, m.line, m.text).expect("Failed to write to stdout");
+            true
+        });
+        match found {
+            // Как у grep: код выхода 1, если ничего не найдено
+            Ok(count) => std::process::exit(if count > 0 { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("{}: {}", input_file, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Параметры обхода дерева при сжатии
+    let mut walk = io::WalkOptions {
+        one_file_system: matches.get_flag("one_file_system"),
+        exclude_caches: matches.get_flag("exclude_caches"),
+        numeric_owner,
+        skip_special: matches.get_flag("skip_special"),
+        dedup: !matches.get_flag("no_dedup"),
+        ignore_files: !matches.get_flag("no_ignore_files"),
+        gitignore: matches.get_flag("gitignore"),
+        ..Default::default()
+    };
+
+    // Параметры записи файлов при распаковке
+    let mut extract = io::ExtractOptions {
+        numeric_owner,
+        threads,
+        skip_special: matches.get_flag("skip_special"),
+        fsync: matches.get_one::<String>("fsync").and_then(|name| io::FsyncPolicy::from_name(name)).unwrap_or_default(),
+        order: matches.get_one::<String>("extract_order").and_then(|name| io::ExtractOrder::from_name(name)).unwrap_or_default(),
+        verify_written: matches.contains_id("report"),
+        portable_names: matches.get_flag("portable_names"),
+        overwrite: io::OverwritePolicy::Force,
+    };
+    let report_file = matches.get_one::<String>("report");
+    let owner_map = matches.get_one::<String>("owner_map").map(|path| owner::OwnerMap::read(Path::new(path)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }));
+    for path in matches.get_many::<String>("exclude_from").unwrap_or_default() {
+        walk.exclude.read_from(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    }
+    for pattern in matches.get_many::<String>("exclude").unwrap_or_default() {
+        walk.exclude.add(pattern);
+    }
+
+    // Сравнение архива с директорией на диске
+    if matches.get_flag("diff") {
+        let input_file = matches.get_one::<String>("input").unwrap();
+        let output_dir = matches.get_one::<String>("output").unwrap();
+        let index = load_index(input_file, algorithm.as_ref(), short_matches, strict);
+        let on_disk = archive::ArchiveIndex::from_dir(Path::new(output_dir), &walk).unwrap_or_else(|e| {
+            eprintln!("{}: {}", output_dir, e);
+            std::process::exit(1);
+        });
+        let report = archive::diff(&index, &on_disk);
+        print_diff(&report, porcelain, ["extra", "missing"]);
+        std::process::exit(if report.is_empty() { 0 } else { 1 });
+    }
+
+    // Поиск одинаковых файлов в архиве или в директории
+    if matches.get_flag("dupes") {
+        let input_file = matches.get_one::<String>("input").unwrap();
+        let is_dir = Path::new(input_file).is_dir();
+        let entries = if is_dir {
+            // Копии ищутся по содержимому на диске, поэтому обход не заменяет их дубликатами
+            let walk = io::WalkOptions { dedup: false, ..walk.clone() };
+            let mut entries = io::read_dir_recursive(Path::new(input_file), Path::new(input_file), &walk).unwrap_or_else(|e| {
+                eprintln!("{}: {}", input_file, e);
+                std::process::exit(1);
+            });
+            entries.sort_by(|a, b| a.path.cmp(&b.path));
+            entries
+        } else {
+            if matches.get_flag("link_dupes") {
+                eprintln!("--link-dupes needs a directory, {} is not one", input_file);
+                std::process::exit(1);
+            }
+            load_archive(input_file, algorithm.as_ref(), threads, short_matches, strict, &entry_filter).entries
+        };
+        let groups = dupes::find(&entries);
+        for (number, group) in groups.iter().enumerate() {
+            if porcelain {
+                for path in &group.copies {
+                    println!("{}\t{}\tcopy\t{}", number + 1, group.size, path);
+                }
+                for path in &group.references {
+                    println!("{}\t{}\treference\t{}", number + 1, group.size, path);
+                }
+                continue;
+            }
+            println!("{} bytes wasted: {} files of {} bytes, {} stored in full",
+                group.wasted(), group.copies.len() + group.references.len(), group.size, group.copies.len());
+            for path in &group.copies {
+                println!("    {}", path);
+            }
+            for path in &group.references {
+                println!("    {} (stored once)", path);
+            }
+        }
+        let wasted: u64 = groups.iter().map(|g| g.wasted()).sum();
+        if !porcelain {
+            println!("{} groups of identical files, {} bytes wasted", groups.len(), wasted);
+        }
+        if matches.get_flag("link_dupes") {
+            let report = dupes::link_duplicates(Path::new(input_file), &groups).unwrap_or_else(|e| {
+                eprintln!("{}: {}", input_file, e);
+                std::process::exit(1);
+            });
+            for (path, reason) in &report.skipped {
+                warn!("{} was not linked: {}", path, reason);
+            }
+            eprintln!("{}: linked {} copies, freed {} bytes", input_file, report.linked, report.freed);
+        } else if wasted > 0 {
+            if is_dir {
+                eprintln!("Compress without --no-dedup to store identical files once, or pass --link-dupes to hard-link the copies.");
+            } else {
+                eprintln!("Recreate the archive without --no-dedup to store identical files once.");
+            }
+        }
+        return;
+    }
+
+    // Сведения о происхождении, которые записываются в создаваемый архив
+    let reproducible = matches.get_flag("reproducible");
+    let comment = matches.get_one::<String>("comment").cloned();
+    let mut archive_info = if reproducible { ArchiveInfo::reproducible(comment) } else { ArchiveInfo::current(comment) };
+
+    // Без -a алгоритм выбирается по замеру на этой машине; замер зависит от машины,
+    // поэтому в воспроизводимый архив не записывается
+    let target = calibrate::Target {
+        speed: matches.get_one::<u64>("target_speed").copied(),
+        ratio: matches.get_one::<f64>("target_ratio").copied(),
+    };
+    let algorithm = if target.is_set() && matches.get_flag("compress") {
+        let selection = calibrate::choose(&calibrate::measure(), &target);
+        eprintln!("Selected {}", selection);
+        if !selection.met {
+            warn!("no algorithm meets the target on this machine, using the closest one.");
+        }
+        if !reproducible {
+            archive_info.selection = Some(selection.to_string());
+        }
+        Some(selection.chosen.algorithm)
+    } else {
+        algorithm
+    };
+
+    let input_file = matches.get_one::<String>("input").unwrap();
+    let output_file = matches.get_one::<String>("output").unwrap();
+    let volume_size = matches.get_one::<u64>("volume_size").copied();
+    let recovery = matches.get_one::<u32>("recovery").copied();
+
     let input_path = Path::new(input_file);
+    let from_stdin = input_file == io::STDIO_PATH;
+    let to_stdout = output_file == io::STDIO_PATH;
+    // Стандартный вывод — один поток байтов: в него нельзя записать тома, дописать архив
+    // или распаковать дерево файлов
+    if to_stdout {
+        let unsupported = [
+            ("volume-size", volume_size.is_some()),
+            ("update", matches.get_flag("update")),
+            ("catalog", catalog_path.is_some()),
+            ("report", report_file.is_some()),
+            ("recursive-extract", matches.contains_id("recursive_extract")),
+        ];
+        if let Some((flag, _)) = unsupported.iter().find(|(_, used)| *used) {
+            eprintln!("--{} cannot be used with -o - (standard output)", flag);
+            std::process::exit(1);
+        }
+    }
+    // Политика для существующих файлов; keep-newer сравнивает их со временем изменения записей
+    let overwrite_name = matches.get_one::<String>("overwrite").filter(|_| matches.get_flag("decompress"));
+    if let Some(name) = overwrite_name {
+        if from_stdin && (name == "prompt" || name == "keep-newer") {
+            eprintln!("--overwrite {} cannot be used with -i - (standard input)", name);
+            std::process::exit(1);
+        }
+        if name == "keep-newer" && !archive_has_mtimes(input_file) {
+            eprintln!("{}: --overwrite keep-newer needs modification times of the entries, which this archive does not store (written by an older version)", input_file);
+            std::process::exit(1);
+        }
+        extract.overwrite = io::OverwritePolicy::from_name(name).unwrap_or_default();
+    }
     let start_time = Instant::now();
 
+    // Файл состояния и события для наблюдения за ходом длительных операций
+    let status: Vec<progress::StatusWriter> = [
+        matches.get_one::<String>("status_file")
+            .map(|path| progress::StatusWriter::start(Path::new(path), "reading", Duration::from_secs(1))),
+        matches.contains_id("progress")
+            .then(|| progress::StatusWriter::start_events("reading", Duration::from_millis(250))),
+    ].into_iter().flatten().collect();
+    let set_state = |state: &str| status.iter().for_each(|status| status.set_state(state));
+
 
     // Выполнение команды
     match command {
+        "compress" if matches.get_flag("index_only") => {
+            // Индекс без данных: только пути, размеры, права, время изменения и контрольные суммы
+            let mut index = if from_stdin {
+                let mut entries = vec![read_stdin_entry()];
+                io::fill_checksums(&mut entries);
+                archive::ArchiveIndex::from_archive(&ArchiveData { entries })
+            } else {
+                archive::ArchiveIndex::from_dir(input_path, &walk).unwrap_or_else(|e| {
+                    eprintln!("{}: {}", input_file, e);
+                    std::process::exit(1);
+                })
+            };
+            if reproducible {
+                index.entries.iter_mut().for_each(|e| {
+                    e.permissions = io::reproducible_mode(e.permissions);
+                    e.mtime = None;
+                });
+            }
+            let compressed = processing::compress(&index.to_bytes(), algorithm.unwrap(), use_multithreading, short_matches);
+            if partial::interrupted() {
+                exit_interrupted("no index was written");
+            }
+            let compressed = processing::set_info(&compressed, &archive_info).expect("Failed to write archive information");
+            write_archive(output_file, &compressed, volume_size, recovery);
+        },
         "compress" => {
-            // Чтение директории и сериализация данных
-            let entries = io::read_dir_recursive(input_path, input_path).expect("Failed to read path");
-            let serialized = io::archive_data_to_bytes(&ArchiveData { entries });
-            
-            // Сжатие данных и запись в выходной файл
-            let compressed = processing::compress(&serialized, algorithm, use_multithreading);
-            io::write_file(output_file, &compressed).expect("Failed to write output file");
+            // Повторное сжатие архива обычно означает перепутанные -c и -d
+            if !from_stdin && input_path.is_file() && !matches.get_flag("recompress") {
+                let mut magic = [0u8; header::ARCHIVE_MAGIC.len()];
+                let is_archive = std::fs::File::open(input_path)
+                    .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut magic))
+                    .is_ok() && header::has_magic(&magic);
+                if is_archive {
+                    error!("{} is already an archive (did you mean -d?); pass --recompress to wrap it again.", input_file);
+                    std::process::exit(1);
+                }
+            }
+
+            // Чтение директории (стандартного ввода или путей из списка) и сериализация данных
+            let mut entries = if let Some(list) = matches.get_one::<String>("files_from") {
+                if from_stdin {
+                    eprintln!("--files-from needs -i to be the directory the listed paths are relative to");
+                    std::process::exit(1);
+                }
+                let text = if list == io::STDIO_PATH {
+                    std::io::read_to_string(std::io::stdin())
+                } else {
+                    std::fs::read_to_string(list)
+                };
+                let entries = text.and_then(|text| io::parse_file_list(&text, matches.get_flag("null")))
+                    .and_then(|paths| io::read_listed(input_path, &paths, &walk));
+                entries.unwrap_or_else(|e| {
+                    if partial::interrupted() {
+                        exit_interrupted("no archive was written");
+                    }
+                    eprintln!("{}: {}", list, e);
+                    std::process::exit(1);
+                })
+            } else if from_stdin {
+                let entry = read_stdin_entry();
+                if header::has_magic(&entry.data) && !matches.get_flag("recompress") {
+                    error!("standard input is already an archive (did you mean -d?); pass --recompress to wrap it again.");
+                    std::process::exit(1);
+                }
+                vec![entry]
+            } else {
+                io::read_dir_recursive(input_path, input_path, &walk).unwrap_or_else(|e| {
+                    if partial::interrupted() {
+                        exit_interrupted("no archive was written");
+                    }
+                    eprintln!("{}: {}", input_file, e);
+                    std::process::exit(1);
+                })
+            };
+            if reproducible {
+                io::make_reproducible(&mut entries);
+            }
+            // С -u сжимаются только новые и измененные записи, которые дописываются к архиву
+            let existing = if matches.get_flag("update") && Path::new(output_file).exists() {
+                let existing = volume::read_archive(output_file).expect("Failed to read output file");
+                entries = processing::changed_entries(&existing, entries, matches.get_flag("compare_content"), strict).unwrap_or_else(|e| {
+                    eprintln!("{}: {}", output_file, e);
+                    std::process::exit(1);
+                });
+                if entries.is_empty() {
+                    eprintln!("{}: up to date", output_file);
+                    return;
+                }
+                Some(existing)
+            } else {
+                None
+            };
+            progress::begin(entries.len() as u64, entries.iter().map(|e| e.data.len() as u64).sum());
+            set_state("compressing");
+            // Без каталога контрольные суммы считаются при сжатии записей; для каталога они нужны
+            // заранее и вычисляются параллельно, а сжатие использует их повторно
+            if catalog_path.is_some() {
+                io::fill_checksums(&mut entries);
+            }
+            let record = catalog_path.map(|_| catalog::CatalogRecord::new(output_file, &entries));
+            let entries_count = entries.len();
+
+            // Сжатие данных (по записям или общим потоком) и запись в выходной файл
+            let options = processing::CompressionOptions::new(algorithm.unwrap())
+                .multithreading(use_multithreading)
+                .short_matches(short_matches)
+                .solid(matches.get_flag("solid"))
+                .chunk_dedup(matches.get_flag("chunk_dedup"))
+                .block_size(matches.get_one::<u64>("block_size").map(|&size| size as usize))
+                .pack_below(matches.get_one::<u64>("pack_below").map(|&size| size as usize));
+            let total_bytes = entries.iter().map(|e| e.data.len() as u64).sum();
+            // Замер зависит от машины, поэтому в воспроизводимый архив не записывается
+            if !reproducible {
+                archive_info.decode_rate = processing::measure_decode_rate(&entries, &options);
+            }
+            let compressed = processing::compress_archive(ArchiveData { entries }, &options);
+            if options.solid {
+                // Общий поток сжимается целиком, поэтому ход отмечается только по его завершении
+                progress::begin(1, total_bytes);
+                progress::advance(input_file, total_bytes);
+            }
+            if partial::interrupted() {
+                exit_interrupted(&format!("compression of {} entries was stopped, no archive was written", entries_count));
+            }
+            archive_info.options = Some(options);
+            let mut compressed = processing::set_info(&compressed, &archive_info).expect("Failed to write archive information");
+            if let Some(mut existing) = existing {
+                eprintln!("{}: {} new or changed entries", output_file, entries_count);
+                existing.extend(compressed);
+                compressed = existing;
+            }
+            write_archive(output_file, &compressed, volume_size, recovery);
+
+            // Регистрация архива в каталоге
+            if let (Some(catalog_path), Some(mut record)) = (catalog_path, record) {
+                // Многотомный архив регистрируется по первому тому, с которого начинается чтение
+                let written = match volume_size {
+                    Some(_) => volume::volume_path(output_file, 1),
+                    None => output_file.clone(),
+                };
+                if let Ok(abs) = std::fs::canonicalize(written) {
+                    record.archive = abs.to_string_lossy().into_owned();
+                }
+                catalog::append_record(catalog_path, &record).expect("Failed to update catalog");
+            }
         },
         "decompress" => {
-            // Чтение сжатого файла и его распаковка
-            let compressed_data = io::read_file(input_file).expect("Failed to read input file");
-            let decompressed = processing::decompress(&compressed_data, algorithm, use_multithreading);
-            if decompressed.is_empty() {
-                error!("Decompression failed.");
-                return;
+            // Чтение и распаковка архива, затем запись в выходной файл
+            let (mut archive, damaged) = if matches.get_flag("salvage") {
+                salvage_archive(input_file, &entry_filter)
+            } else {
+                (load_archive(input_file, algorithm.as_ref(), threads, short_matches, strict, &entry_filter), false)
+            };
+            // Единственный файл — архив, сжатый из одного файла (с пустым путем), а не дерево,
+            // в котором после --strip-components или --transform осталась одна запись
+            let single_file = archive.entries.len() == 1 && archive.entries[0].path.is_empty();
+            let transforms: Vec<transform::Transform> = matches.get_many("transform").unwrap_or_default().cloned().collect();
+            if let Err(e) = transform::apply_all(&mut archive.entries, &transforms) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            if let Some(&count) = matches.get_one::<usize>("strip_components") {
+                io::strip_components(&mut archive.entries, count);
             }
-            // Десериализация данных и запись в выходной файл
-            let archive: ArchiveData = io::bytes_to_archive_data(&decompressed)
-                .expect("Failed to deserialize data");
-            if archive.entries.len() == 1 { // Обработка единичных файлов
+            if let Some(owner_map) = &owner_map {
+                for owner in archive.entries.iter_mut().filter_map(|e| e.owner.as_mut()) {
+                    *owner = owner_map.apply(owner, numeric_owner);
+                }
+            }
+            progress::begin(archive.entries.len() as u64, archive.entries.iter().map(|e| e.data.len() as u64).sum());
+            set_state("extracting");
+            if single_file { // Обработка единичных файлов
                 let e = &archive.entries[0];
-                let mut file = std::fs::File::create(output_file)
-                    .expect("Failed to create single output file");
-                file.write_all(&e.data).expect("Failed to write data");
-                std::fs::set_permissions(output_file, std::fs::Permissions::from_mode(e.permissions))
-                    .expect("Failed to set permissions");
+                if let Err(err) = e.verify() {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+                if to_stdout {
+                    if let Err(err) = partial::write_output(output_file, &e.data) {
+                        eprintln!("Failed to write to standard output: {}", err);
+                        std::process::exit(1);
+                    }
+                    progress::advance(&e.path, e.data.len() as u64);
+                } else if io::ExistingFiles::new(extract.overwrite, &mut std::io::stdin().lock()).keeps(Path::new(output_file), e.mtime)
+                    .unwrap_or_else(|err| {
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }) {
+                    eprintln!("Skipped {}: the file exists (--overwrite {})", output_file, overwrite_name.map_or("force", String::as_str));
+                } else {
+                    let written = partial::PartialFile::create(Path::new(output_file)).and_then(|mut file| {
+                        file.write_all(&e.data)?;
+                        if let Some(owner) = &e.owner {
+                            if let Err(err) = file.set_owner(owner, numeric_owner) {
+                                warn!("cannot restore owner of {}: {}", output_file, err);
+                            }
+                        }
+                        file.set_permissions(std::fs::Permissions::from_mode(e.permissions))?;
+                        file.commit()
+                    });
+                    // Незавершенный временный файл удаляется до выхода
+                    if let Err(err) = written {
+                        eprintln!("Failed to write output file {}: {}", output_file, err);
+                        std::process::exit(1);
+                    }
+                    progress::advance(&e.path, e.data.len() as u64);
+                    if let Some(report_file) = report_file {
+                        let written = std::fs::File::open(output_file).and_then(|mut f| io::reread_crc32(&mut f))
+                            .expect("Failed to re-read output file");
+                        let expected = e.crc32.unwrap_or_else(|| checksum::crc32(&e.data));
+                        write_report(report_file, &[io::WriteCheck { path: output_file.clone(), expected, written }]);
+                    }
+                }
             } else {
-                io::write_dir_entries(&archive.entries, Path::new(output_file))
-                    .expect("Failed to write directory entries");
+                if to_stdout {
+                    eprintln!("{}: {} entries cannot be written to standard output; pass a directory with -o", input_file, archive.entries.len());
+                    std::process::exit(1);
+                }
+                // Маркер остается рядом с деревом, если распаковка прервется
+                let marker = partial::ExtractionMarker::create(Path::new(output_file))
+                    .expect("Failed to create extraction marker");
+                let report = io::write_dir_entries(&archive.entries, Path::new(output_file), &extract).unwrap_or_else(|err| {
+                    if err.kind() == std::io::ErrorKind::Interrupted {
+                        exit_interrupted(&format!("{}, {}{} marks the incomplete tree", err, output_file, partial::PARTIAL_SUFFIX));
+                    }
+                    eprintln!("Failed to write directory entries: {}", err);
+                    std::process::exit(1);
+                });
+                marker.finish().expect("Failed to remove extraction marker");
+                if let Some(report_file) = report_file {
+                    write_report(report_file, &report.checks);
+                }
+                if !report.skipped.is_empty() {
+                    eprintln!("Skipped {} existing files (--overwrite {}):", report.skipped.len(), overwrite_name.map_or("force", String::as_str));
+                    for path in &report.skipped {
+                        eprintln!("  {}", path);
+                    }
+                }
+            }
+            if let Some(&depth) = matches.get_one::<usize>("recursive_extract") {
+                let expanded = nested::extract_nested(Path::new(output_file), depth, &extract, use_multithreading, strict)
+                    .unwrap_or_else(|err| {
+                        eprintln!("Failed to extract nested archives: {}", err);
+                        std::process::exit(1);
+                    });
+                for path in expanded {
+                    eprintln!("Extracted nested archive {}", path.display());
+                }
+            }
+            if damaged {
+                // Все, что удалось восстановить, записано, но архив был поврежден
+                std::process::exit(1);
             }
         },
         _ => {
@@ -148,8 +1968,12 @@ fn main() {
         }
     };
 
+    for status in status {
+        status.finish("done");
+    }
+
     // Вывод времени выполнения
     let duration = start_time.elapsed();
-    println!("Program executed successfully.");
-    println!("Elapsed time: {:.2?}", duration);
+    eprintln!("Program executed successfully.");
+    eprintln!("Elapsed time: {:.2?}", duration);
 }
\ No newline at end of file
@@ -0,0 +1,121 @@
+//! Модуль для имен файлов, которые нельзя создать в системе назначения.
+//!
+//! Архив может содержать имена, недопустимые там, где он распаковывается: с нулевыми
+//! байтами, длиннее `NAME_MAX`, `.` и `..`, а для файловых систем Windows (`--portable-names`)
+//! также зарезервированные имена (`CON`, `NUL.txt`, `COM1`), имена с точкой или пробелом
+//! в конце и символы `<>:"\|?*`. Вместо того чтобы прерывать распаковку, такое имя
+//! переименовывается детерминированно: недопустимые символы заменяются кодами `%XX`
+//! (и сам `%` в таком имени тоже), а слишком длинное имя укорачивается и дополняется
+//! CRC32 исходного имени. Одно и то же имя всегда получает одну и ту же замену.
+
+use std::borrow::Cow;
+use crate::checksum;
+
+/// Наибольшая длина имени файла в байтах (`NAME_MAX`).
+pub const NAME_MAX: usize = 255;
+
+/// Символы, недопустимые в именах файлов Windows.
+const WINDOWS_FORBIDDEN: &str = "<>:\"\\|?*";
+
+/// Имена устройств, зарезервированные в Windows (в том числе с любым расширением).
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Возвращает имя, под которым файл можно создать в системе назначения.
+///
+/// # Аргументы
+///
+/// * `name` - Имя файла (одна компонента пути).
+/// * `portable` - Применять также ограничения файловых систем Windows.
+///
+/// # Возвращает
+///
+/// Исходное имя, если оно допустимо, или его замену.
+pub fn target_name(name: &str, portable: bool) -> Cow<'_, str> {
+    let reserved = portable && WINDOWS_RESERVED.iter().any(|r| name.split('.').next().is_some_and(|stem| stem.eq_ignore_ascii_case(r)));
+    // Точки и пробелы в конце Windows отбрасывает, поэтому они кодируются
+    let trailing = if portable { name.len() - name.trim_end_matches(['.', ' ']).len() } else { 0 };
+    let forbidden = |c: char| c == '\0' || (portable && (c.is_ascii_control() || WINDOWS_FORBIDDEN.contains(c)));
+    let invalid = name == "." || name == ".." || reserved || trailing > 0 || name.contains(forbidden);
+    if !invalid && name.len() <= NAME_MAX {
+        return Cow::Borrowed(name);
+    }
+    let mut renamed = String::new();
+    if invalid {
+        let encoded_from = if name == "." || name == ".." { 0 } else { name.len() - trailing };
+        for (i, c) in name.char_indices() {
+            if (i == 0 && reserved) || i >= encoded_from || c == '%' || forbidden(c) {
+                renamed.push_str(&format!("%{:02X}", c as u32));
+            } else {
+                renamed.push(c);
+            }
+        }
+    } else {
+        renamed.push_str(name);
+    }
+    if renamed.len() > NAME_MAX {
+        // Начало имени сохраняется, а по CRC32 длинные имена с общим началом различаются
+        let suffix = format!("%{:08x}", checksum::crc32(name.as_bytes()));
+        let mut end = NAME_MAX - suffix.len();
+        while !renamed.is_char_boundary(end) {
+            end -= 1;
+        }
+        renamed.truncate(end);
+        renamed.push_str(&suffix);
+    }
+    Cow::Owned(renamed)
+}
+
+/// Возвращает относительный путь, под которым запись можно создать в системе назначения.
+///
+/// Каждая компонента пути заменяется `target_name`, а пустые компоненты (в том числе
+/// ведущий `/`) отбрасываются, поэтому путь не выходит за пределы директории распаковки.
+///
+/// # Аргументы
+///
+/// * `path` - Путь записи в архиве.
+/// * `portable` - Применять также ограничения файловых систем Windows.
+///
+/// # Возвращает
+///
+/// Исходный путь, если он допустим, или его замену.
+pub fn target_path(path: &str, portable: bool) -> Cow<'_, str> {
+    let components: Vec<_> = path.split('/').filter(|c| !c.is_empty()).map(|c| target_name(c, portable)).collect();
+    let renamed = components.join("/");
+    if renamed == path {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(renamed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_names() {
+        assert_eq!(target_name("report.txt", true), "report.txt");
+        assert!(matches!(target_name("100% done", true), Cow::Borrowed(_)));
+        assert_eq!(target_name("a\0b", false), "a%00b");
+        assert_eq!(target_name("..", false), "%2E%2E");
+        // Ограничения Windows применяются только с `portable`
+        assert_eq!(target_name("con.txt", false), "con.txt");
+        assert_eq!(target_name("con.txt", true), "%63on.txt");
+        assert_eq!(target_name("console", true), "console");
+        assert_eq!(target_name("50%. ", true), "50%25%2E%20");
+        assert_eq!(target_name("what?<>", true), "what%3F%3C%3E");
+
+        let long = "ё".repeat(200);
+        let renamed = target_name(&long, false);
+        assert!(renamed.len() <= NAME_MAX && renamed.starts_with("ёё"));
+        assert_eq!(renamed, target_name(&long, false));
+        assert_ne!(renamed, target_name(&"ё".repeat(201), false));
+
+        assert_eq!(target_path("dir/file", true), "dir/file");
+        assert_eq!(target_path("/etc//../passwd", false), "etc/%2E%2E/passwd");
+        assert_eq!(target_path("docs/aux/notes.", true), "docs/%61ux/notes%2E");
+    }
+}
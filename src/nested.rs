@@ -0,0 +1,138 @@
+//! Модуль для распаковки вложенных архивов (`--recursive-extract`).
+//!
+//! Резервные копии часто хранятся как архив архивов: ежедневные архивы собираются
+//! в недельный и так далее. После распаковки дерево просматривается, и каждый файл,
+//! начинающийся с сигнатуры `RARC`, распаковывается на своем месте: архив из нескольких
+//! записей заменяется директорией с тем же именем, а архив одного файла — этим файлом.
+//! Распакованное содержимое просматривается снова, пока не исчерпана глубина.
+//!
+//! Вложенный архив сначала распаковывается рядом, в `<путь>.partial`, и заменяет
+//! исходный файл только после успешной записи. Архив, который не удалось распаковать
+//! (поврежденный или содержащий только индекс), остается как есть с предупреждением.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use crate::header;
+use crate::io::{self as archive_io, ExtractOptions};
+use crate::partial::PARTIAL_SUFFIX;
+use crate::processing;
+use crate::warnings::{self, Warning};
+
+/// Глубина вложенности по умолчанию для `--recursive-extract` без значения.
+pub const DEFAULT_DEPTH: usize = 4;
+
+/// Распаковывает вложенные архивы в файле или дереве на их месте.
+///
+/// # Аргументы
+///
+/// * `path` - Распакованный файл или директория.
+/// * `depth` - Сколько уровней вложенности распаковывать; 0 ничего не распаковывает.
+/// * `options` - Параметры записи распакованных файлов.
+/// * `use_multithreading` - Флаг многопоточной распаковки.
+/// * `strict` - Строгий режим распаковки.
+///
+/// # Возвращает
+///
+/// Пути распакованных вложенных архивов в порядке распаковки или ошибку ввода/вывода
+/// при обходе дерева либо записи файлов.
+pub fn extract_nested(path: &Path, depth: usize, options: &ExtractOptions, use_multithreading: bool, strict: bool) -> io::Result<Vec<PathBuf>> {
+    let mut expanded = Vec::new();
+    if depth == 0 {
+        return Ok(expanded);
+    }
+    let mut archives = Vec::new();
+    find_archives(path, &mut archives)?;
+    for archive in archives {
+        let data = fs::read(&archive)?;
+        let entries = match processing::decompress(&data, None, use_multithreading, false, strict)
+            .and_then(|bytes| archive_io::bytes_to_archive_data(&bytes, strict))
+        {
+            Ok(archive_data) => archive_data.entries,
+            Err(e) => {
+                warnings::report(Warning::NestedNotExtracted { path: archive.display().to_string(), error: e.to_string() });
+                continue;
+            }
+        };
+        let mut partial = archive.clone().into_os_string();
+        partial.push(PARTIAL_SUFFIX);
+        let partial = PathBuf::from(partial);
+        if let [single] = entries.as_slice() {
+            if single.path.is_empty() {
+                single.verify()?;
+                fs::write(&partial, &single.data)?;
+                fs::set_permissions(&partial, fs::Permissions::from_mode(single.permissions))?;
+                fs::rename(&partial, &archive)?;
+                expanded.push(archive.clone());
+                expanded.extend(extract_nested(&archive, depth - 1, options, use_multithreading, strict)?);
+                continue;
+            }
+        }
+        archive_io::write_dir_entries(&entries, &partial, options)?;
+        fs::remove_file(&archive)?;
+        fs::rename(&partial, &archive)?;
+        expanded.push(archive.clone());
+        expanded.extend(extract_nested(&archive, depth - 1, options, use_multithreading, strict)?);
+    }
+    Ok(expanded)
+}
+
+/// Собирает файлы, начинающиеся с сигнатуры архива, в порядке путей.
+///
+/// Символические ссылки не просматриваются, чтобы не выйти за пределы дерева.
+fn find_archives(path: &Path, archives: &mut Vec<PathBuf>) -> io::Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.is_dir() {
+        let mut children: Vec<_> = fs::read_dir(path)?.map(|entry| entry.map(|e| e.path())).collect::<io::Result<_>>()?;
+        children.sort();
+        for child in children {
+            find_archives(&child, archives)?;
+        }
+    } else if meta.is_file() {
+        let mut magic = [0u8; header::ARCHIVE_MAGIC.len()];
+        let is_archive = File::open(path)?.read_exact(&mut magic).is_ok() && header::has_magic(&magic);
+        if is_archive {
+            archives.push(path.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::DirEntry;
+    use crate::processing::Algorithm;
+    use crate::ArchiveData;
+    use std::collections::BTreeMap;
+
+    fn entry(path: &str, data: &[u8]) -> DirEntry {
+        DirEntry { path: path.to_owned(), data: data.to_vec(), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None }
+    }
+
+    #[test]
+    fn test_extract_nested() {
+        let out = std::env::temp_dir().join(format!("nested_test_{}", std::process::id()));
+        let inner = processing::compress_entries(&[entry("day1/log.txt", b"monday")], Algorithm::Lz4, false, false, None, None);
+        let single = processing::compress(&archive_io::archive_data_to_bytes(&ArchiveData { entries: vec![entry("", b"plain")] }),
+            Algorithm::Rle, false, false);
+        let week = processing::compress_entries(&[entry("week/day1.arc", &inner), entry("notes.arc", &single)],
+            Algorithm::Lz77, false, false, None, None);
+        let outer = vec![entry("month/week.arc", &week), entry("broken.arc", b"RARC but not really")];
+        archive_io::write_dir_entries(&outer, &out, &ExtractOptions::default()).unwrap();
+
+        // Первый уровень: распаковывается только недельный архив
+        let (expanded, reported) = warnings::capture(|| extract_nested(&out, 1, &ExtractOptions::default(), false, true));
+        assert_eq!(expanded.unwrap(), vec![out.join("month/week.arc")]);
+        assert_eq!(reported.len(), 1);
+        assert!(out.join("month/week.arc/week/day1.arc").is_file());
+
+        let (expanded, _) = warnings::capture(|| extract_nested(&out, DEFAULT_DEPTH, &ExtractOptions::default(), false, true));
+        assert_eq!(expanded.unwrap().len(), 2);
+        assert_eq!(fs::read(out.join("month/week.arc/week/day1.arc/day1/log.txt")).unwrap(), b"monday");
+        assert_eq!(fs::read(out.join("month/week.arc/notes.arc")).unwrap(), b"plain");
+        assert_eq!(fs::read(out.join("broken.arc")).unwrap(), b"RARC but not really");
+        let _ = fs::remove_dir_all(&out);
+    }
+}
@@ -0,0 +1,340 @@
+//! Модуль для сохранения и восстановления владельца файлов.
+//!
+//! Для каждой записи сохраняются числовые UID и GID, а также имена пользователя и группы.
+//! При распаковке от имени root владелец восстанавливается: по умолчанию по именам
+//! (если такие пользователь и группа есть в системе), а с `--numeric-owner` — только
+//! по числовым идентификаторам. Без прав root владелец не меняется, и файлы
+//! принадлежат распаковывающему пользователю. При распаковке на другой машине таблица
+//! `--owner-map` заменяет сохраненных пользователей и группы местными (см. `OwnerMap`).
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::{chown, fchown};
+use std::path::Path;
+use std::sync::Mutex;
+use crate::io::{read_varint, write_varint};
+
+/// Имена пользователя и группы, найденные по UID и GID.
+type Names = (Option<String>, Option<String>);
+
+/// Владелец записи архива.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Owner {
+    /// Числовой идентификатор пользователя
+    pub uid: u32,
+    /// Числовой идентификатор группы
+    pub gid: u32,
+    /// Имя пользователя (`None`, если не определено или архив создан с `--numeric-owner`)
+    pub user: Option<String>,
+    /// Имя группы
+    pub group: Option<String>,
+}
+
+impl Owner {
+    /// Определяет владельца по числовым идентификаторам файла.
+    ///
+    /// # Аргументы
+    ///
+    /// * `uid`, `gid` - Идентификаторы из метаданных файла.
+    /// * `numeric` - Не сохранять имена (`--numeric-owner`).
+    pub fn from_ids(uid: u32, gid: u32, numeric: bool) -> Owner {
+        if numeric {
+            return Owner { uid, gid, user: None, group: None };
+        }
+        // Имена запоминаются, чтобы не обращаться к базе пользователей для каждого файла
+        static NAMES: Mutex<Option<HashMap<(u32, u32), Names>>> = Mutex::new(None);
+        let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+        let (user, group) = names.get_or_insert_with(HashMap::new)
+            .entry((uid, gid))
+            .or_insert_with(|| (user_name(uid), group_name(gid)))
+            .clone();
+        Owner { uid, gid, user, group }
+    }
+
+    /// Дописывает владельца в буфер: UID и GID в формате varint, затем имена
+    /// с длиной в формате varint (пустое имя означает его отсутствие).
+    pub fn write(&self, buffer: &mut Vec<u8>) {
+        write_varint(buffer, u64::from(self.uid));
+        write_varint(buffer, u64::from(self.gid));
+        for name in [&self.user, &self.group] {
+            let name = name.as_deref().unwrap_or("");
+            write_varint(buffer, name.len() as u64);
+            buffer.extend_from_slice(name.as_bytes());
+        }
+    }
+
+    /// Читает владельца, записанного `write`, сдвигая позицию.
+    ///
+    /// # Возвращает
+    ///
+    /// Владельца или ошибку при неверном формате.
+    pub fn read(data: &[u8], offset: &mut usize) -> io::Result<Owner> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Неверный формат владельца записи");
+        let uid = u32::try_from(read_varint(data, offset)?).map_err(|_| invalid())?;
+        let gid = u32::try_from(read_varint(data, offset)?).map_err(|_| invalid())?;
+        let mut names = [None, None];
+        for name in names.iter_mut() {
+            let len = usize::try_from(read_varint(data, offset)?).map_err(|_| invalid())?;
+            let bytes = offset.checked_add(len).and_then(|end| data.get(*offset..end)).ok_or_else(invalid)?;
+            *offset += len;
+            if len > 0 {
+                *name = Some(String::from_utf8(bytes.to_vec()).map_err(|_| invalid())?);
+            }
+        }
+        let [user, group] = names;
+        Ok(Owner { uid, gid, user, group })
+    }
+
+    /// Возвращает UID и GID для восстановления в этой системе.
+    ///
+    /// Без `numeric` имена сопоставляются с локальными пользователями и группами;
+    /// если имени нет в системе, используется сохраненный числовой идентификатор.
+    pub fn resolve(&self, numeric: bool) -> (u32, u32) {
+        if numeric {
+            return (self.uid, self.gid);
+        }
+        let uid = self.user.as_deref().and_then(user_id).unwrap_or(self.uid);
+        let gid = self.group.as_deref().and_then(group_id).unwrap_or(self.gid);
+        (uid, gid)
+    }
+}
+
+/// Таблица замены владельцев при распаковке на другой машине (`--owner-map`).
+///
+/// Каждая строка файла задает замену пользователя или группы: `user СТАРЫЙ НОВЫЙ` или
+/// `group СТАРЫЙ НОВЫЙ`. Старый владелец — имя, сохраненное в архиве, или числовой
+/// идентификатор; новый — имя в этой системе или числовой идентификатор. Пустые строки
+/// и строки, начинающиеся с `#`, пропускаются.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OwnerMap {
+    /// Замены пользователей: по имени и по UID
+    users: (HashMap<String, u32>, HashMap<u32, u32>),
+    /// Замены групп: по имени и по GID
+    groups: (HashMap<String, u32>, HashMap<u32, u32>),
+}
+
+impl OwnerMap {
+    /// Разбирает таблицу замены владельцев.
+    ///
+    /// # Аргументы
+    ///
+    /// * `text` - Содержимое файла таблицы.
+    ///
+    /// # Возвращает
+    ///
+    /// Таблицу или ошибку с номером неверной строки, например если нового пользователя нет в системе.
+    pub fn parse(text: &str) -> io::Result<OwnerMap> {
+        let mut map = OwnerMap::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidInput,
+                format!("owner map line {}: {}", number + 1, reason));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [kind, old, new] = fields[..] else {
+                return Err(invalid("expected user|group OLD NEW"));
+            };
+            let (table, lookup): (_, fn(&str) -> Option<u32>) = match kind {
+                "user" => (&mut map.users, user_id),
+                "group" => (&mut map.groups, group_id),
+                _ => return Err(invalid("expected user|group OLD NEW")),
+            };
+            let new = match new.parse::<u32>() {
+                Ok(id) => id,
+                Err(_) => lookup(new).ok_or_else(|| invalid(&format!("no {} named {} on this system", kind, new)))?,
+            };
+            match old.parse::<u32>() {
+                Ok(id) => table.1.insert(id, new),
+                Err(_) => table.0.insert(old.to_owned(), new),
+            };
+        }
+        Ok(map)
+    }
+
+    /// Читает таблицу замены владельцев из файла.
+    pub fn read(path: &Path) -> io::Result<OwnerMap> {
+        let text = std::fs::read_to_string(path)?;
+        OwnerMap::parse(&text).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    /// Заменяет владельца записи по таблице.
+    ///
+    /// Замена по имени проверяется раньше замены по идентификатору и не применяется
+    /// с `numeric` (`--numeric-owner`). У замененного пользователя или группы имя убирается,
+    /// поэтому при восстановлении используется новый числовой идентификатор.
+    ///
+    /// # Аргументы
+    ///
+    /// * `owner` - Владелец, сохраненный в архиве.
+    /// * `numeric` - Владельцы восстанавливаются только по числовым идентификаторам.
+    ///
+    /// # Возвращает
+    ///
+    /// Владельца для восстановления в этой системе.
+    pub fn apply(&self, owner: &Owner, numeric: bool) -> Owner {
+        let find = |table: &(HashMap<String, u32>, HashMap<u32, u32>), name: &Option<String>, id: u32| {
+            name.as_ref().filter(|_| !numeric).and_then(|name| table.0.get(name)).or_else(|| table.1.get(&id)).copied()
+        };
+        let mut mapped = owner.clone();
+        if let Some(uid) = find(&self.users, &owner.user, owner.uid) {
+            mapped.uid = uid;
+            mapped.user = None;
+        }
+        if let Some(gid) = find(&self.groups, &owner.group, owner.gid) {
+            mapped.gid = gid;
+            mapped.group = None;
+        }
+        mapped
+    }
+}
+
+/// Возвращает `true`, если программа запущена с правами root и может менять владельца.
+pub fn can_restore() -> bool {
+    // SAFETY: geteuid не имеет предусловий и не может завершиться ошибкой
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Восстанавливает владельца файла по пути (без прав root ничего не делает).
+///
+/// # Аргументы
+///
+/// * `path` - Путь к распакованному файлу.
+/// * `owner` - Сохраненный владелец.
+/// * `numeric` - Использовать только числовые идентификаторы (`--numeric-owner`).
+///
+/// # Возвращает
+///
+/// Результат операции или ошибку ввода/вывода.
+pub fn restore(path: &Path, owner: &Owner, numeric: bool) -> io::Result<()> {
+    if !can_restore() {
+        return Ok(());
+    }
+    let (uid, gid) = owner.resolve(numeric);
+    chown(path, Some(uid), Some(gid))
+}
+
+/// Восстанавливает владельца открытого файла (без прав root ничего не делает).
+pub fn restore_file(file: &File, owner: &Owner, numeric: bool) -> io::Result<()> {
+    if !can_restore() {
+        return Ok(());
+    }
+    let (uid, gid) = owner.resolve(numeric);
+    fchown(file, Some(uid), Some(gid))
+}
+
+/// Размер буфера для функций `getpw*_r` и `getgr*_r`.
+const LOOKUP_BUFFER_LEN: usize = 16 * 1024;
+
+/// Возвращает имя пользователя по UID.
+fn user_name(uid: u32) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_LEN];
+    // SAFETY: все указатели действительны, а длина буфера передается вместе с ним
+    unsafe {
+        let mut entry: libc::passwd = std::mem::zeroed();
+        let mut result = std::ptr::null_mut();
+        if libc::getpwuid_r(uid, &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result) != 0 || result.is_null() {
+            return None;
+        }
+        CStr::from_ptr(entry.pw_name).to_str().ok().map(str::to_owned)
+    }
+}
+
+/// Возвращает имя группы по GID.
+fn group_name(gid: u32) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_LEN];
+    // SAFETY: все указатели действительны, а длина буфера передается вместе с ним
+    unsafe {
+        let mut entry: libc::group = std::mem::zeroed();
+        let mut result = std::ptr::null_mut();
+        if libc::getgrgid_r(gid, &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result) != 0 || result.is_null() {
+            return None;
+        }
+        CStr::from_ptr(entry.gr_name).to_str().ok().map(str::to_owned)
+    }
+}
+
+/// Возвращает UID пользователя с указанным именем.
+fn user_id(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_LEN];
+    // SAFETY: все указатели действительны, а длина буфера передается вместе с ним
+    unsafe {
+        let mut entry: libc::passwd = std::mem::zeroed();
+        let mut result = std::ptr::null_mut();
+        if libc::getpwnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result) != 0 || result.is_null() {
+            return None;
+        }
+        Some(entry.pw_uid)
+    }
+}
+
+/// Возвращает GID группы с указанным именем.
+fn group_id(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER_LEN];
+    // SAFETY: все указатели действительны, а длина буфера передается вместе с ним
+    unsafe {
+        let mut entry: libc::group = std::mem::zeroed();
+        let mut result = std::ptr::null_mut();
+        if libc::getgrnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result) != 0 || result.is_null() {
+            return None;
+        }
+        Some(entry.gr_gid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owner_round_trip() {
+        for owner in [
+            Owner { uid: 1000, gid: 100, user: Some("alice".into()), group: Some("users".into()) },
+            Owner { uid: 70000, gid: 0, user: None, group: None },
+        ] {
+            let mut buffer = Vec::new();
+            owner.write(&mut buffer);
+            let mut offset = 0;
+            assert_eq!(Owner::read(&buffer, &mut offset).unwrap(), owner);
+            assert_eq!(offset, buffer.len());
+            assert!(Owner::read(&buffer[..buffer.len() - 1], &mut 0).is_err());
+        }
+    }
+
+    #[test]
+    fn test_resolve_names() {
+        // root есть в любой системе и всегда имеет UID и GID 0
+        let root = Owner::from_ids(0, 0, false);
+        assert_eq!(root.user.as_deref(), Some("root"));
+        let renamed = Owner { uid: 4242, gid: 4242, user: root.user.clone(), group: root.group.clone() };
+        assert_eq!(renamed.resolve(false), (0, 0));
+        assert_eq!(renamed.resolve(true), (4242, 4242));
+        let unknown = Owner { uid: 4242, gid: 4243, user: Some("no-such-user-xyz".into()), group: None };
+        assert_eq!(unknown.resolve(false), (4242, 4243));
+        assert_eq!(Owner::from_ids(0, 0, true).user, None);
+    }
+
+    #[test]
+    fn test_owner_map() {
+        let map = OwnerMap::parse("# archived on the old server\nuser alice root\nuser 1001 2001\n\ngroup staff 50\ngroup 100 root\n").unwrap();
+        let alice = Owner { uid: 1000, gid: 100, user: Some("alice".into()), group: Some("users".into()) };
+        assert_eq!(map.apply(&alice, false), Owner { uid: 0, gid: 0, user: None, group: None });
+        assert_eq!(map.apply(&alice, false).resolve(false), (0, 0));
+        // С --numeric-owner имена не сопоставляются, и остаются только замены по идентификаторам
+        assert_eq!(map.apply(&alice, true), Owner { uid: 1000, gid: 0, user: Some("alice".into()), group: None });
+        let bob = Owner { uid: 1001, gid: 20, user: Some("bob".into()), group: Some("staff".into()) };
+        assert_eq!(map.apply(&bob, false), Owner { uid: 2001, gid: 50, user: None, group: None });
+        let other = Owner { uid: 7, gid: 7, user: None, group: None };
+        assert_eq!(map.apply(&other, false), other);
+
+        assert!(OwnerMap::parse("user alice").unwrap_err().to_string().contains("line 1"));
+        assert!(OwnerMap::parse("\nowner alice bob").unwrap_err().to_string().contains("line 2"));
+        assert!(OwnerMap::parse("user alice no-such-user-xyz").unwrap_err().to_string().contains("no user named no-such-user-xyz"));
+        assert_eq!(OwnerMap::parse("# empty\n").unwrap(), OwnerMap::default());
+    }
+}
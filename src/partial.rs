@@ -0,0 +1,281 @@
+//! Модуль для записи результатов через временные файлы.
+//!
+//! Архив сначала записывается во временный файл `<путь>.<pid>.<n>.partial` и переименовывается
+//! в итоговый только после успешного завершения, поэтому прерванное сжатие не оставляет
+//! недописанных `.arc`. Имя временного файла уникально для процесса, поэтому одновременные
+//! задания с одним выходным путем не перезаписывают временные файлы друг друга. Незавершенные временные файлы удаляются при панике (в `Drop`)
+//! и при получении SIGINT/SIGTERM обработчиком из `install_cleanup_handler`.
+//! Распаковка дерева помечается файлом `<директория>.partial`, который удаляется
+//! только после записи всех файлов.
+//!
+//! Первый сигнал только выставляет флаг прерывания: рабочие потоки проверяют его
+//! (`interrupted`) на границах блоков и записей и останавливаются, а программа сообщает,
+//! что успела сделать. Второй сигнал завершает программу немедленно.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use crate::io::STDIO_PATH;
+use crate::owner::{self, Owner};
+
+/// Суффикс временных файлов и маркеров незавершенной распаковки.
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Код завершения программы, прерванной сигналом.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Временные файлы, которые нужно удалить при прерывании.
+static ACTIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Флаг, выставляемый при получении SIGINT/SIGTERM.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Номер следующего временного файла процесса.
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+/// Возвращает `true`, если получен сигнал прерывания и работу нужно остановить.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Возвращает ошибку прерывания с описанием того, что успело выполниться.
+pub fn interrupted_error(progress: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, progress)
+}
+
+/// Возвращает путь с добавленным суффиксом `.partial`.
+fn partial_path(target: &Path) -> PathBuf {
+    let mut name = target.as_os_str().to_owned();
+    name.push(PARTIAL_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Возвращает уникальное в пределах системы имя временного файла рядом с итоговым:
+/// `<путь>.<pid>.<n>.partial`.
+fn temp_path(target: &Path) -> PathBuf {
+    let mut name = target.as_os_str().to_owned();
+    name.push(format!(".{}.{}", std::process::id(), NEXT_TEMP.fetch_add(1, Ordering::Relaxed)));
+    partial_path(Path::new(&name))
+}
+
+/// Удаляет путь из списка временных файлов.
+fn unregister(path: &Path) {
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).retain(|p| p != path);
+}
+
+/// Временный файл, который становится итоговым после вызова `commit`.
+pub struct PartialFile {
+    /// Открытый временный файл
+    file: File,
+    /// Путь к временному файлу
+    temp: PathBuf,
+    /// Итоговый путь
+    target: PathBuf,
+    /// Переименован ли файл в итоговый
+    committed: bool,
+}
+
+impl PartialFile {
+    /// Создает временный файл рядом с итоговым.
+    ///
+    /// # Аргументы
+    ///
+    /// * `target` - Итоговый путь к файлу.
+    ///
+    /// # Возвращает
+    ///
+    /// Временный файл или ошибку ввода/вывода.
+    pub fn create(target: &Path) -> io::Result<Self> {
+        let temp = temp_path(target);
+        // Файл, оставшийся от процесса с тем же номером, не перезаписывается
+        let file = OpenOptions::new().write(true).create_new(true).open(&temp)?;
+        ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).push(temp.clone());
+        Ok(PartialFile { file, temp, target: target.to_path_buf(), committed: false })
+    }
+
+    /// Устанавливает права доступа временного файла (сохраняются после переименования).
+    pub fn set_permissions(&self, permissions: fs::Permissions) -> io::Result<()> {
+        self.file.set_permissions(permissions)
+    }
+
+    /// Восстанавливает владельца временного файла (без прав root ничего не делает).
+    pub fn set_owner(&self, owner: &Owner, numeric: bool) -> io::Result<()> {
+        owner::restore_file(&self.file, owner, numeric)
+    }
+
+    /// Сбрасывает данные на диск и переименовывает временный файл в итоговый.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.temp, &self.target)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+/// Запись идет во временный файл; при ошибке записи (например, ENOSPC) временный файл
+/// удаляется, когда `PartialFile` отбрасывается без `commit`.
+impl Write for PartialFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+        }
+        unregister(&self.temp);
+    }
+}
+
+/// Записывает данные в файл через временный файл.
+///
+/// # Аргументы
+///
+/// * `path` - Итоговый путь к файлу.
+/// * `data` - Срез байтов для записи.
+///
+/// # Возвращает
+///
+/// Результат операции или ошибку ввода/вывода; при ошибке временный файл удаляется.
+pub fn write_file(path: &str, data: &[u8]) -> io::Result<()> {
+    let mut file = PartialFile::create(Path::new(path))?;
+    file.write_all(data)?;
+    file.commit()
+}
+
+/// Записывает данные в файл через временный файл или, если путь равен `-`,
+/// в стандартный вывод.
+///
+/// # Аргументы
+///
+/// * `path` - Итоговый путь к файлу или `-`.
+/// * `data` - Срез байтов для записи.
+///
+/// # Возвращает
+///
+/// Результат операции или ошибку ввода/вывода.
+pub fn write_output(path: &str, data: &[u8]) -> io::Result<()> {
+    if path == STDIO_PATH {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(data)?;
+        return stdout.flush();
+    }
+    write_file(path, data)
+}
+
+/// Маркер незавершенной распаковки дерева файлов.
+///
+/// Пока распаковка не завершена, рядом с выходной директорией лежит файл
+/// `<директория>.partial`. При прерывании он остается и показывает, что дерево неполное.
+pub struct ExtractionMarker {
+    /// Путь к файлу маркера
+    path: PathBuf,
+}
+
+impl ExtractionMarker {
+    /// Создает маркер для выходной директории.
+    pub fn create(output_dir: &Path) -> io::Result<Self> {
+        let path = partial_path(output_dir);
+        fs::write(&path, format!("extraction into {} did not complete\n", output_dir.display()))?;
+        Ok(ExtractionMarker { path })
+    }
+
+    /// Удаляет маркер после успешной распаковки.
+    pub fn finish(self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+}
+
+/// Устанавливает обработчик SIGINT/SIGTERM.
+///
+/// Первый сигнал выставляет флаг `interrupted`. Повторный сигнал удаляет незавершенные
+/// временные файлы и сразу завершает программу с кодом `INTERRUPTED_EXIT_CODE`.
+///
+/// # Возвращает
+///
+/// Результат установки обработчика или ошибку ввода/вывода.
+pub fn install_cleanup_handler() -> io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        let mut received = signals.forever();
+        if received.next().is_some() {
+            INTERRUPTED.store(true, Ordering::Relaxed);
+            log::warn!("interrupt received, stopping at the next block (press Ctrl-C again to abort immediately).");
+        }
+        if received.next().is_some() {
+            for path in ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+                let _ = fs::remove_file(path);
+            }
+            log::error!("aborted: partial output was removed.");
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_file_commit_and_drop() {
+        let dir = std::env::temp_dir().join(format!("partial_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("out.arc");
+
+        let mut file = PartialFile::create(&target).unwrap();
+        file.write_all(b"half").unwrap();
+        let temp = file.temp.clone();
+        assert!(temp.exists() && temp.to_str().unwrap().ends_with(PARTIAL_SUFFIX));
+        // Второй писатель того же файла получает свой временный файл
+        let other = PartialFile::create(&target).unwrap();
+        assert_ne!(other.temp, temp);
+        drop(other);
+        drop(file);
+        assert!(!temp.exists());
+        assert!(!target.exists());
+
+        write_file(target.to_str().unwrap(), b"done").unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"done");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_partial_file_write_failures() {
+        use crate::faults::FailingWriter;
+
+        let dir = std::env::temp_dir().join(format!("partial_fault_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("out.arc");
+        let data = vec![7u8; 10_000];
+
+        // Сбой в начале, в середине и в последнем байте записи
+        for (budget, errno) in [(0, libc::ENOSPC), (4096, libc::EIO), (9_999, libc::ENOSPC)] {
+            let file = PartialFile::create(&target).unwrap();
+            let temp = file.temp.clone();
+            let mut writer = match errno {
+                libc::EIO => FailingWriter::eio(file, budget),
+                _ => FailingWriter::enospc(file, budget),
+            };
+            let error = writer.write_all(&data).unwrap_err();
+            assert_eq!(error.raw_os_error(), Some(errno));
+            drop(writer);
+            assert!(!temp.exists() && !target.exists());
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        assert!(ACTIVE.lock().unwrap().iter().all(|path| !path.starts_with(&dir)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
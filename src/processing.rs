@@ -8,20 +8,55 @@ use crate::lz77;
 use crate::lz4;
 use crate::lzw;
 use crate::huffman;
+use crate::archive::{self, ArchiveIndex};
+use crate::checksum;
+use crate::blocks;
+use crate::chunking;
+use crate::frames;
+use crate::crypto;
+use crate::entry_index::{self, IndexRecord};
+use crate::filter::EntryType;
+use crate::header::{self, ByteOrder, Header};
+use crate::io::{self as archive_io, read_varint, write_varint, DirEntry};
+use crate::info::ArchiveInfo;
+use crate::partial;
+use crate::progress;
+use crate::recovery;
+use crate::warnings::{self, Warning};
+use crate::ArchiveData;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::ops::Range;
 use std::thread;
-use log::error;
-#[derive(PartialEq)]
+
+/// Число потоков, между которыми распределяется работа в многопоточном режиме.
+pub const THREADS: usize = 4;
+
+/// Размер исходных данных, по достижении которого сегмент мелких файлов закрывается.
+pub const SEGMENT_SIZE: usize = 256 * 1024;
+
+/// Способ хранения записи, блока или сегмента: данные сжаты алгоритмом архива.
+const BLOB_COMPRESSED: u8 = 0;
+
+/// Способ хранения записи, блока или сегмента: данные не уменьшились при сжатии и сохранены как есть.
+const BLOB_STORED: u8 = 1;
+
+/// Алгоритм сжатия. Значения вариантов — идентификаторы алгоритмов в заголовке архива
+/// (см. `Algorithm::id`): они записаны в архивы и никогда не меняются.
+#[derive(Debug, PartialEq)]
+#[repr(u8)]
 pub enum Algorithm {
     /// Алгоритм RLE (Run-Length Encoding) для сжатия повторяющихся данных.
-    Rle,
+    Rle = 1,
     /// Алгоритм LZ77 для сжатия данных путем поиска повторяющихся последовательностей.
-    Lz77,
+    Lz77 = 2,
     /// Алгоритм LZ4 для быстрого сжатия и распаковки данных.
-    Lz4,
+    Lz4 = 3,
     /// Алгоритм LZW (Lempel-Ziv-Welch) для сжатия данных.
-    Lzw,
+    Lzw = 4,
     /// Алгоритм Хаффмана для сжатия данных с использованием кодирования Хаффмана.
-    Hf,
+    Hf = 5,
 }
 
 /// Реализация клонирования для перечисления `Algorithm`.
@@ -42,7 +77,267 @@ impl Clone for Algorithm {
     }
 }
 
-/// Сжимает входные данные с использованием выбранного алгоритма.
+impl Algorithm {
+    /// Все поддерживаемые алгоритмы.
+    pub const ALL: [Algorithm; 5] = [Algorithm::Rle, Algorithm::Lz77, Algorithm::Lz4, Algorithm::Lzw, Algorithm::Hf];
+
+    /// Возвращает имя алгоритма, используемое в командной строке.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Rle => "rle",
+            Algorithm::Lz77 => "lz77",
+            Algorithm::Lz4 => "lz4",
+            Algorithm::Lzw => "lzw",
+            Algorithm::Hf => "hf",
+        }
+    }
+
+    /// Определяет алгоритм по имени из командной строки.
+    ///
+    /// # Аргументы
+    ///
+    /// * `name` - Имя алгоритма (`rle`, `lz77`, `lz4`, `lzw`, `hf`).
+    ///
+    /// # Возвращает
+    ///
+    /// Алгоритм или `None`, если имя не поддерживается.
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        Algorithm::ALL.iter().find(|a| a.name() == name).cloned()
+    }
+
+    /// Возвращает идентификатор алгоритма, записываемый в заголовок архива.
+    pub fn id(&self) -> u8 {
+        self.clone() as u8
+    }
+
+    /// Определяет алгоритм по идентификатору из заголовка архива.
+    pub fn from_id(id: u8) -> Option<Algorithm> {
+        Algorithm::ALL.iter().find(|a| a.id() == id).cloned()
+    }
+}
+
+/// Параметры сжатия архива, общие для командной строки и библиотеки.
+///
+/// Параметры собираются цепочкой методов от `CompressionOptions::new` и сохраняются в разделе
+/// сведений архива (см. модуль `info`), поэтому `--info` показывает флаги, с которыми архив
+/// был создан, и по ним его можно пересобрать так же.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionOptions {
+    /// Алгоритм сжатия (`-a`)
+    pub algorithm: Algorithm,
+    /// Сжимать в несколько потоков (`-m`)
+    pub use_multithreading: bool,
+    /// Короткие токены LZ77 (`--short-matches`)
+    pub short_matches: bool,
+    /// Сжимать все записи одним потоком (`--solid`)
+    pub solid: bool,
+    /// Сохранять повторяющиеся блоки общего потока один раз (`--chunk-dedup`, только с `solid`)
+    pub chunk_dedup: bool,
+    /// Размер независимо сжатых блоков (`--block-size`)
+    pub block_size: Option<usize>,
+    /// Порог размера мелких файлов для упаковки в сегменты (`--pack-below`)
+    pub pack_below: Option<usize>,
+}
+
+/// Бит флагов сохраненных параметров: многопоточное сжатие.
+const OPTION_MULTITHREADING: u8 = 0x01;
+
+/// Бит флагов сохраненных параметров: короткие токены LZ77.
+const OPTION_SHORT_MATCHES: u8 = 0x02;
+
+/// Бит флагов сохраненных параметров: общий сжатый поток.
+const OPTION_SOLID: u8 = 0x04;
+
+/// Бит флагов сохраненных параметров: дедупликация блоков общего потока.
+const OPTION_CHUNK_DEDUP: u8 = 0x08;
+
+impl CompressionOptions {
+    /// Создает параметры по умолчанию: записи сжимаются по отдельности в одном потоке.
+    pub fn new(algorithm: Algorithm) -> Self {
+        CompressionOptions {
+            algorithm,
+            use_multithreading: false,
+            short_matches: false,
+            solid: false,
+            chunk_dedup: false,
+            block_size: None,
+            pack_below: None,
+        }
+    }
+
+    /// Включает или выключает многопоточное сжатие.
+    pub fn multithreading(mut self, enabled: bool) -> Self {
+        self.use_multithreading = enabled;
+        self
+    }
+
+    /// Разрешает короткие токены LZ77.
+    pub fn short_matches(mut self, enabled: bool) -> Self {
+        self.short_matches = enabled;
+        self
+    }
+
+    /// Выбирает общий сжатый поток вместо сжатия записей по отдельности.
+    pub fn solid(mut self, enabled: bool) -> Self {
+        self.solid = enabled;
+        self
+    }
+
+    /// Включает дедупликацию блоков общего потока.
+    pub fn chunk_dedup(mut self, enabled: bool) -> Self {
+        self.chunk_dedup = enabled;
+        self
+    }
+
+    /// Задает размер блока для блочного режима.
+    pub fn block_size(mut self, size: Option<usize>) -> Self {
+        self.block_size = size;
+        self
+    }
+
+    /// Задает порог размера мелких файлов для упаковки в сегменты.
+    pub fn pack_below(mut self, size: Option<usize>) -> Self {
+        self.pack_below = size;
+        self
+    }
+
+    /// Преобразует параметры в байты: идентификатор алгоритма, байт флагов, затем размер
+    /// блока и порог упаковки в формате varint (0 — не задан).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        for (enabled, flag) in [(self.use_multithreading, OPTION_MULTITHREADING), (self.short_matches, OPTION_SHORT_MATCHES),
+                                (self.solid, OPTION_SOLID), (self.chunk_dedup, OPTION_CHUNK_DEDUP)] {
+            if enabled {
+                flags |= flag;
+            }
+        }
+        let mut buffer = vec![self.algorithm.id(), flags];
+        write_varint(&mut buffer, self.block_size.unwrap_or(0) as u64);
+        write_varint(&mut buffer, self.pack_below.unwrap_or(0) as u64);
+        buffer
+    }
+
+    /// Разбирает параметры, записанные `to_bytes`.
+    ///
+    /// # Возвращает
+    ///
+    /// Параметры и число прочитанных байтов или ошибку при неверном формате.
+    pub fn from_bytes(data: &[u8]) -> io::Result<(Self, usize)> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Параметры сжатия повреждены");
+        let (&id, &flags) = data.first().zip(data.get(1)).ok_or_else(invalid)?;
+        let algorithm = Algorithm::from_id(id).ok_or_else(invalid)?;
+        let mut offset = 2;
+        let mut size = || -> io::Result<Option<usize>> {
+            let value = usize::try_from(read_varint(data, &mut offset)?).map_err(|_| invalid())?;
+            Ok(Some(value).filter(|&v| v > 0))
+        };
+        let options = CompressionOptions::new(algorithm)
+            .multithreading(flags & OPTION_MULTITHREADING != 0)
+            .short_matches(flags & OPTION_SHORT_MATCHES != 0)
+            .solid(flags & OPTION_SOLID != 0)
+            .chunk_dedup(flags & OPTION_CHUNK_DEDUP != 0)
+            .block_size(size()?)
+            .pack_below(size()?);
+        Ok((options, offset))
+    }
+}
+
+impl fmt::Display for CompressionOptions {
+    /// Выводит параметры флагами командной строки, например `-a lz77 --solid -m`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "-a {}", self.algorithm.name())?;
+        for (enabled, flag) in [(self.solid, "--solid"), (self.chunk_dedup, "--chunk-dedup"),
+                                (self.use_multithreading, "-m"), (self.short_matches, "--short-matches")] {
+            if enabled {
+                write!(f, " {}", flag)?;
+            }
+        }
+        if let Some(size) = self.block_size {
+            write!(f, " --block-size {}", size)?;
+        }
+        if let Some(size) = self.pack_below {
+            write!(f, " --pack-below {}", size)?;
+        }
+        Ok(())
+    }
+}
+
+/// Сжимает записи директории в архив с выбранными параметрами.
+///
+/// Общий поток сжимается `compress_solid` или `compress_chunked`, а записи по отдельности —
+/// `compress_entries`. Сведения о происхождении не записываются (см. `set_info`).
+///
+/// # Аргументы
+///
+/// * `archive` - Записи директории.
+/// * `options` - Параметры сжатия.
+///
+/// # Возвращает
+///
+/// Вектор байтов архива.
+pub fn compress_archive(mut archive: ArchiveData, options: &CompressionOptions) -> Vec<u8> {
+    let algorithm = options.algorithm.clone();
+    if !options.solid {
+        return compress_entries(&archive.entries, algorithm, options.use_multithreading, options.short_matches,
+            options.block_size, options.pack_below);
+    }
+    // Контрольные суммы входят в общий поток до его сжатия
+    archive_io::fill_checksums(&mut archive.entries);
+    let serialized = archive_io::archive_data_to_bytes(&archive);
+    drop(archive);
+    if options.chunk_dedup {
+        compress_chunked(&serialized, algorithm, options.use_multithreading, options.short_matches)
+    } else {
+        compress_solid(&serialized, algorithm, options.use_multithreading, options.short_matches)
+    }
+}
+
+/// Объем содержимого файлов, на котором при сжатии измеряется скорость распаковки.
+const RATE_SAMPLE: usize = 1 << 20;
+
+/// Наименьшая длительность распаковки выборки меньше `RATE_SAMPLE`, при которой замер
+/// сохраняется: распаковку нескольких байтов определяют накладные расходы, а не алгоритм.
+const RATE_MIN_TIME: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// Измеряет скорость распаковки архива, сжимаемого с параметрами `options` (см. `ArchiveInfo::decode_rate`).
+///
+/// Первые `RATE_SAMPLE` байтов содержимого записей сжимаются выбранным алгоритмом одним потоком
+/// и распаковываются с замером времени, поэтому измерение почти не замедляет сжатие большого архива.
+/// Выборка меньшего объема учитывается, только если ее распаковка заняла не меньше `RATE_MIN_TIME`.
+///
+/// # Аргументы
+///
+/// * `entries` - Записи, которые будут сжаты.
+/// * `options` - Параметры сжатия.
+///
+/// # Возвращает
+///
+/// Байты распакованного содержимого в секунду или `None`, если данных нет, их слишком мало
+/// для замера или замер не удался.
+pub fn measure_decode_rate(entries: &[DirEntry], options: &CompressionOptions) -> Option<u64> {
+    let mut sample = Vec::new();
+    for e in entries.iter().filter(|e| e.permissions & (archive_io::HARD_LINK_FLAG | archive_io::DUPLICATE_FLAG) == 0) {
+        let take = e.data.len().min(RATE_SAMPLE - sample.len());
+        sample.extend_from_slice(&e.data[..take]);
+        if sample.len() == RATE_SAMPLE {
+            break;
+        }
+    }
+    if sample.is_empty() {
+        return None;
+    }
+    let compressed = compress(&sample, options.algorithm.clone(), options.use_multithreading, options.short_matches);
+    let start = std::time::Instant::now();
+    let decoded = decompress(&compressed, None, options.use_multithreading, options.short_matches, false).ok()?;
+    let elapsed = start.elapsed();
+    if sample.len() < RATE_SAMPLE && elapsed < RATE_MIN_TIME {
+        return None;
+    }
+    let nanos = elapsed.as_nanos().max(1);
+    u64::try_from(decoded.len() as u128 * 1_000_000_000 / nanos).ok()
+}
+
+/// Сжимает входные данные с использованием выбранного алгоритма и записывает заголовок архива.
 /// 
 /// Если `use_multithreading` установлено в `true`, сжатие выполняется в многопоточном режиме.
 /// 
@@ -51,18 +346,264 @@ impl Clone for Algorithm {
 /// * `input` - Срез байтов, содержащий исходные данные для сжатия.
 /// * `algorithm` - Выбранный алгоритм сжатия.
 /// * `use_multithreading` - Флаг, указывающий использовать ли многопоточность.
+/// * `short_matches` - Разрешает короткие токены совпадений LZ77.
 /// 
 /// # Возвращает
 /// 
-/// Вектор байтов, содержащий сжатые данные.
+/// Вектор байтов: заголовок архива, сжатые данные и трейлер с их SHA-256.
 /// # Примечания
 /// 
 /// При попытке использовать многопоточность для lzw или алгоритма Хаффмена будет использован однопоточный режим.
-pub fn compress(input: &[u8], algorithm: Algorithm, use_multithreading: bool) -> Vec<u8> {
+/// После прерывания (`partial::interrupted`) оставшиеся блоки пропускаются, и результат нужно отбросить.
+pub fn compress(input: &[u8], algorithm: Algorithm, use_multithreading: bool, short_matches: bool) -> Vec<u8> {
+    let payload = encode(input, algorithm.clone(), use_multithreading, short_matches);
+    wrap(payload, algorithm, short_matches, 0, None)
+}
+
+/// Сжимает данные, как `compress`, предварительно сохраняя повторяющиеся блоки один раз.
+///
+/// Данные делятся на блоки по содержимому (см. модуль `chunking`), повторы блоков
+/// заменяются ссылками, и результат сжимается выбранным алгоритмом. Распаковка
+/// (`decompress`) восстанавливает данные по флагу `FLAG_CHUNKED` в заголовке.
+///
+/// # Аргументы
+///
+/// Те же, что у `compress`.
+///
+/// # Возвращает
+///
+/// Вектор байтов: заголовок архива, сжатые данные и трейлер с их SHA-256.
+pub fn compress_chunked(input: &[u8], algorithm: Algorithm, use_multithreading: bool, short_matches: bool) -> Vec<u8> {
+    let payload = encode(&chunking::deduplicate(input), algorithm.clone(), use_multithreading, short_matches);
+    wrap(payload, algorithm, short_matches, header::FLAG_CHUNKED, None)
+}
+
+/// Сжимает общий поток сплошного архива, как `compress`, заменяя кадры, повторяющие ранее
+/// записанные, ссылками на них (см. модуль `frames`).
+///
+/// Так одинаковые файлы в общем потоке сохраняются один раз, даже если их разделяет больше
+/// окна алгоритма сжатия. Если ни один кадр не повторился, результат совпадает с `compress`;
+/// иначе в заголовке ставится флаг `FLAG_CHUNKED`, и `decompress` собирает поток обратно.
+///
+/// # Аргументы
+///
+/// Те же, что у `compress`.
+///
+/// # Возвращает
+///
+/// Вектор байтов: заголовок архива, сжатые данные и трейлер с их SHA-256.
+pub fn compress_solid(input: &[u8], algorithm: Algorithm, use_multithreading: bool, short_matches: bool) -> Vec<u8> {
+    match frames::deduplicate(input) {
+        Some(encoded) => {
+            let payload = encode(&encoded, algorithm.clone(), use_multithreading, short_matches);
+            wrap(payload, algorithm, short_matches, header::FLAG_CHUNKED, None)
+        }
+        None => compress(input, algorithm, use_multithreading, short_matches),
+    }
+}
+
+/// Сжимает каждую запись директории отдельно и записывает центральный индекс.
+///
+/// Такой архив позволяет распаковывать отдельные записи, не распаковывая остальные.
+/// В блочном режиме содержимое каждой записи дополнительно делится на блоки около `block_size`
+/// байтов (длина подбирается по энтропии, см. `blocks::block_lengths`), которые сжимаются
+/// независимо, что позволяет читать произвольные диапазоны (см. `read_range`). С `pack_below` записи с содержимым меньше заданного размера
+/// упаковываются в общие сегменты до `SEGMENT_SIZE` байтов, которые сжимаются одним
+/// потоком: это уменьшает накладные расходы на запись и улучшает сжатие деревьев из
+/// множества мелких файлов. Сегмент записывается по заполнении, поэтому мелкие файлы
+/// в индексе могут идти после следующих за ними крупных. Записи, блоки и сегменты, которые
+/// при сжатии не уменьшились, сохраняются как есть. В многопоточном режиме записи
+/// и сегменты распределяются между потоками. Недостающие контрольные суммы записей
+/// вычисляются в той же задаче, которая сжимает запись. После прерывания (`partial::interrupted`)
+/// оставшиеся записи пропускаются, и результат нужно отбросить.
+///
+/// # Аргументы
+///
+/// * `entries` - Записи директории.
+/// * `algorithm` - Выбранный алгоритм сжатия.
+/// * `use_multithreading` - Флаг, указывающий использовать ли многопоточность.
+/// * `short_matches` - Разрешает короткие токены совпадений LZ77.
+/// * `block_size` - Базовый размер блока для блочного режима или `None`.
+/// * `pack_below` - Порог размера мелких файлов для упаковки в сегменты или `None`
+///   (в блочном режиме не используется).
+///
+/// # Возвращает
+///
+/// Вектор байтов: заголовок архива, сжатые записи с индексом и трейлер с SHA-256.
+pub fn compress_entries(entries: &[DirEntry], algorithm: Algorithm, use_multithreading: bool, short_matches: bool,
+                        block_size: Option<usize>, pack_below: Option<usize>) -> Vec<u8> {
+    let new_record = |e: &DirEntry| IndexRecord {
+        path: e.path.clone(),
+        permissions: e.permissions,
+        offset: 0,
+        compressed_size: 0,
+        size: e.data.len() as u64,
+        crc32: None,
+        blocks: Vec::new(),
+        block_lengths: Vec::new(),
+        owner: None,
+        metadata: BTreeMap::new(),
+        mtime: e.mtime,
+        segment: None,
+    };
+    let encode_unit = |unit: &Unit| {
+        // После прерывания оставшиеся записи не сжимаются
+        if partial::interrupted() {
+            return (unit.entries.iter().map(|e| new_record(e)).collect(), Vec::new());
+        }
+        let (records, blob) = match block_size {
+            Some(block_size) => {
+                let e = unit.entries[0];
+                let mut record = new_record(e);
+                record.crc32 = Some(e.crc32.unwrap_or_else(|| checksum::crc32(&e.data)));
+                record.owner = e.owner.clone();
+                record.metadata = e.metadata.clone();
+                let mut blob = Vec::new();
+                let mut start = 0;
+                for len in blocks::block_lengths(&e.data, block_size) {
+                    let compressed = encode_blob(&e.data[start..start + len], algorithm.clone(), short_matches);
+                    record.blocks.push(compressed.len() as u64);
+                    record.block_lengths.push(len as u64);
+                    blob.extend(compressed);
+                    start += len;
+                }
+                (vec![record], blob)
+            }
+            None if unit.packed => {
+                // Байты записей сегмента идут подряд, а их место запоминается в индексе
+                let mut segment = Vec::new();
+                let mut records = Vec::new();
+                for e in &unit.entries {
+                    let bytes = archive_io::dir_entry_to_bytes_with_crc(e);
+                    records.push(IndexRecord { segment: Some((segment.len() as u64, bytes.len() as u64)), ..new_record(e) });
+                    segment.extend(bytes);
+                }
+                (records, encode_blob(&segment, algorithm.clone(), short_matches))
+            }
+            None => {
+                let e = unit.entries[0];
+                (vec![new_record(e)], encode_blob(&archive_io::dir_entry_to_bytes_with_crc(e), algorithm.clone(), short_matches))
+            }
+        };
+        for e in &unit.entries {
+            progress::advance(&e.path, e.data.len() as u64);
+        }
+        (records, blob)
+    };
+    let units = match pack_below.filter(|_| block_size.is_none()) {
+        Some(pack_below) => pack_units(entries, pack_below),
+        None => entries.iter().map(|e| Unit { entries: vec![e], packed: false }).collect(),
+    };
+    let blobs: Vec<_> = if use_multithreading {
+        let chunk_size = units.len().div_ceil(THREADS).max(1);
+        thread::scope(|s| {
+            let handles: Vec<_> = units.chunks(chunk_size)
+                .map(|chunk| s.spawn(move || chunk.iter().map(encode_unit).collect::<Vec<_>>()))
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().expect("Thread failed")).collect()
+        })
+    } else {
+        units.iter().map(encode_unit).collect()
+    };
+    let mut flags = if block_size.is_some() {
+        header::FLAG_PER_ENTRY | header::FLAG_BLOCKS | header::FLAG_OWNERS | header::FLAG_PACKED_INDEX
+    } else {
+        header::FLAG_PER_ENTRY | header::FLAG_PACKED_INDEX
+    };
+    if units.iter().any(|unit| unit.packed) {
+        flags |= header::FLAG_SEGMENTS;
+    }
+    let payload = entry_index::build_payload(blobs, block_size.unwrap_or(0) as u64);
+    wrap(payload, algorithm, short_matches, flags, None)
+}
+
+/// Записи, которые сжимаются одним потоком: отдельная запись или сегмент мелких файлов.
+struct Unit<'a> {
+    /// Записи в порядке следования
+    entries: Vec<&'a DirEntry>,
+    /// Записи упакованы в сегмент
+    packed: bool,
+}
+
+/// Делит записи на отдельно сжимаемые записи и сегменты мелких файлов.
+///
+/// Записи с содержимым меньше `pack_below` байтов добавляются в открытый сегмент, который
+/// закрывается, когда его размер достигает `SEGMENT_SIZE`.
+fn pack_units(entries: &[DirEntry], pack_below: usize) -> Vec<Unit<'_>> {
+    let mut units = Vec::new();
+    let mut open = Unit { entries: Vec::new(), packed: true };
+    let mut open_size = 0;
+    for e in entries {
+        if e.data.len() >= pack_below {
+            units.push(Unit { entries: vec![e], packed: false });
+            continue;
+        }
+        open.entries.push(e);
+        open_size += e.path.len() + e.data.len();
+        if open_size >= SEGMENT_SIZE {
+            units.push(std::mem::replace(&mut open, Unit { entries: Vec::new(), packed: true }));
+            open_size = 0;
+        }
+    }
+    if !open.entries.is_empty() {
+        units.push(open);
+    }
+    units
+}
+
+/// Добавляет к сжатым данным заголовок, блок полей заголовка, раздел сведений (если он задан)
+/// и трейлер с хешем (см. `crypto`).
+fn wrap(payload: Vec<u8>, algorithm: Algorithm, short_matches: bool, mut flags: u8, info: Option<&ArchiveInfo>) -> Vec<u8> {
+    // Флаг коротких токенов имеет смысл только для LZ77
+    if short_matches && algorithm == Algorithm::Lz77 {
+        flags |= header::FLAG_SHORT_MATCHES;
+    }
+    let mut region = header::write_fields(&[]);
+    match info {
+        Some(info) => {
+            flags |= header::FLAG_INFO;
+            region.extend(info.to_bytes());
+        }
+        None => flags &= !header::FLAG_INFO,
+    }
+    region.extend(payload);
+    let layout = header::LAYOUT_LITTLE_ENDIAN;
+    let mut output = Header { version: header::FORMAT_VERSION, algorithm, flags, layout, payload_len: region.len() as u64 }.to_bytes();
+    output.extend_from_slice(&region);
+    output.extend_from_slice(&crypto::digest(&region));
+    output
+}
+
+/// Записывает в архив сведения о его происхождении (см. модуль `info`).
+///
+/// # Аргументы
+///
+/// * `archive` - Архив из одного члена, созданный `compress` или `compress_entries`.
+/// * `info` - Сведения об архиве.
+///
+/// # Возвращает
+///
+/// Архив с разделом сведений (прежние сведения заменяются) или ошибку, если данные
+/// не являются одним целым архивом текущей версии формата: архив переписывается
+/// с текущей версией, а устройство данных старых версий отличается.
+pub fn set_info(archive: &[u8], info: &ArchiveInfo) -> io::Result<Vec<u8>> {
+    let mut members = read_members(archive, None, true)?;
+    if members.len() != 1 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "archive information can only be set on a single archive"));
+    }
+    let (header, _, payload) = members.remove(0);
+    if header.version != header::FORMAT_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("archive information can only be set on a version {} archive", header::FORMAT_VERSION)));
+    }
+    Ok(wrap(payload.to_vec(), header.algorithm, false, header.flags, Some(info)))
+}
+
+/// Сжимает данные выбранным алгоритмом без заголовка.
+fn encode(input: &[u8], algorithm: Algorithm, use_multithreading: bool, short_matches: bool) -> Vec<u8> {
     if use_multithreading && (algorithm!=Algorithm::Hf && algorithm!=Algorithm::Lzw) {
 
-        let num_threads = 4;
-        let chunk_size = (input.len() + num_threads - 1) / num_threads;
+        let chunk_size = input.len().div_ceil(THREADS).max(1);
 
         let mut handles = Vec::new();
 
@@ -71,12 +612,16 @@ pub fn compress(input: &[u8], algorithm: Algorithm, use_multithreading: bool) ->
             let chunk = chunk.to_vec();
             let algo = algorithm.clone();
             let handle = thread::spawn(move || {
+                // После прерывания оставшиеся блоки не сжимаются
+                if partial::interrupted() {
+                    return Vec::new();
+                }
                 match algo {
                     Algorithm::Rle => rle::compress(&chunk),
-                    Algorithm::Lz77 => lz77::compress(&chunk),
+                    Algorithm::Lz77 => lz77::compress(&chunk, short_matches),
                     Algorithm::Lz4 => lz4::compress(&chunk),
                     Algorithm::Lzw => lzw::compress(&chunk), 
-                    Algorithm::Hf => huffman::compress(&chunk), 
+                    Algorithm::Hf => huffman::compress(&chunk, true), 
                 }
             });
             handles.push(handle);
@@ -92,50 +637,1882 @@ pub fn compress(input: &[u8], algorithm: Algorithm, use_multithreading: bool) ->
     } else {
         match algorithm {
             Algorithm::Rle => rle::compress(input),
-            Algorithm::Lz77 => lz77::compress(input),
+            Algorithm::Lz77 => lz77::compress(input, short_matches),
             Algorithm::Lz4 => lz4::compress(input),
             Algorithm::Lzw => lzw::compress(input), 
-            Algorithm::Hf => huffman::compress(input), 
+            Algorithm::Hf => huffman::compress(input, true), 
         }
     }
 }
 
-/// Распаковывает сжатые данные с использованием выбранного алгоритма.
+/// Сжимает запись, блок или сегмент архива с посжатыми записями.
+///
+/// Если сжатые данные не меньше исходных (например, RLE или LZ77 на случайных данных),
+/// сохраняются исходные байты.
+///
+/// # Возвращает
+///
+/// Байт способа хранения и сжатые или исходные данные.
+fn encode_blob(input: &[u8], algorithm: Algorithm, short_matches: bool) -> Vec<u8> {
+    let compressed = encode(input, algorithm, false, short_matches);
+    let (method, data) = if compressed.len() < input.len() { (BLOB_COMPRESSED, &compressed[..]) } else { (BLOB_STORED, input) };
+    let mut blob = Vec::with_capacity(1 + data.len());
+    blob.push(method);
+    blob.extend_from_slice(data);
+    blob
+}
+
+/// Распаковывает архив, определяя алгоритм по заголовку.
+/// 
+/// Если в данных записано несколько архивов подряд, они распаковываются по очереди, а результаты
+/// объединяются. Данные без заголовка (архивы старого формата) распаковываются алгоритмом `fallback`.
+///
+/// До начала распаковки проверяются длины и хеши SHA-256 всех архивов, поэтому обрезанный
+/// или поврежденный файл отклоняется сразу.
 /// 
-/// Если `use_multithreading` установлено в `true`, распаковка выполняется в многопоточном режиме.
-/// Однако в текущей реализации многопоточность для распаковки не поддерживается.
+/// Если `use_multithreading` установлено в `true`, записи архивов, сжатых по отдельности,
+/// распаковываются параллельно; общий сжатый поток распаковывается в одном потоке.
 /// 
 ///
 /// # Аргументы
 /// 
-/// * `input` - Срез байтов, содержащий сжатые данные для распаковки.
-/// * `algorithm` - Выбранный алгоритм распаковки.
+/// * `input` - Срез байтов, содержащий архив.
+/// * `fallback` - Алгоритм для данных без заголовка; если он задан и не совпадает с заголовком,
+///   используется алгоритм из заголовка.
 /// * `use_multithreading` - Флаг, указывающий использовать ли многопоточность.
+/// * `short_matches` - Разрешает короткие токены совпадений LZ77 для данных без заголовка;
+///   для архивов с заголовком режим берется из флагов.
+/// * `strict` - Строгий режим: любая аномалия потока (лишние байты, ненулевые биты дополнения,
+///   неизвестные токены) считается ошибкой.
 /// 
 /// # Возвращает
 /// 
-/// Вектор байтов, содержащий распакованные данные.
-/// 
-/// # Примечания
-/// 
-/// При попытке использовать многопоточность для распаковки будет записано сообщение об ошибке в лог.
-pub fn decompress(input: &[u8], algorithm: Algorithm, use_multithreading: bool) -> Vec<u8> {
-    if use_multithreading {
-        error!("Multithreading not supported for decompression.");
-        match algorithm {
-            Algorithm::Rle => rle::decompress(input),
-            Algorithm::Lz77 => lz77::decompress(input),
-            Algorithm::Lz4 => lz4::decompress(input),
-            Algorithm::Lzw => lzw::decompress(input), 
-            Algorithm::Hf => huffman::decompress(input), 
+/// Вектор байтов, содержащий распакованные данные, или ошибку, если данные не являются архивом
+/// или поток поврежден.
+pub fn decompress(input: &[u8], fallback: Option<Algorithm>, use_multithreading: bool, short_matches: bool, strict: bool) -> io::Result<Vec<u8>> {
+    let threads = if use_multithreading { THREADS } else { 1 };
+    decompress_selected(input, fallback, threads, short_matches, strict, &|_, _| true)
+}
+
+/// Распаковывает архив, пропуская записи, не прошедшие отбор.
+///
+/// Для архивов с посжатыми записями отбор выполняется по центральному индексу, и
+/// пропущенные записи не распаковываются. Архивы с общим сжатым потоком распаковываются
+/// целиком, поэтому вызывающий код должен дополнительно отфильтровать их записи.
+///
+/// # Аргументы
+///
+/// Те же, что у `decompress`, но вместо флага многопоточности:
+///
+/// * `threads` - Число потоков, которыми распаковываются записи, сжатые по отдельности (`--threads`).
+/// * `select` - Условие отбора по пути и правам доступа записи.
+///
+/// # Возвращает
+///
+/// Распакованные данные или ошибку, как у `decompress`.
+pub fn decompress_selected(input: &[u8], fallback: Option<Algorithm>, threads: usize, short_matches: bool, strict: bool,
+                           select: &dyn Fn(&str, u32) -> bool) -> io::Result<Vec<u8>> {
+    if !header::has_magic(input) {
+        return match fallback {
+            Some(algorithm) => decode(input, algorithm, short_matches, ByteOrder::Big, strict),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData,
+                "not an archive (missing RARC signature); pass -a to read a headerless archive")),
+        };
+    }
+
+    let mut output = Vec::new();
+    for (header, payload) in split_members(input, fallback.as_ref(), strict)? {
+        if header.per_entry() {
+            output.extend(decode_entries(payload, &header, strict, select, threads)?);
+        } else {
+            output.extend(decode_stream(payload, &header, strict)?);
         }
-    } else {
-        match algorithm {
-            Algorithm::Rle => rle::decompress(input),
-            Algorithm::Lz77 => lz77::decompress(input),
-            Algorithm::Lz4 => lz4::decompress(input),
-            Algorithm::Lzw => lzw::decompress(input),
-            Algorithm::Hf => huffman::decompress(input), 
+    }
+    Ok(output)
+}
+
+/// Разбирает архивы, записанные подряд, и проверяет их длины и хеши SHA-256.
+///
+/// # Возвращает
+///
+/// Заголовки и сжатые данные всех архивов (без разделов сведений) или ошибку, если
+/// какой-либо из них обрезан или поврежден.
+fn split_members<'a>(input: &'a [u8], fallback: Option<&Algorithm>, strict: bool) -> io::Result<Vec<(Header, &'a [u8])>> {
+    Ok(read_members(input, fallback, strict)?.into_iter().map(|(header, _, payload)| (header, payload)).collect())
+}
+
+/// Архив из потока: заголовок, сведения о происхождении и сжатые данные.
+type Member<'a> = (Header, Option<ArchiveInfo>, &'a [u8]);
+
+/// Разбирает архивы, записанные подряд, как `split_members`, сохраняя сведения об архивах.
+fn read_members<'a>(input: &'a [u8], fallback: Option<&Algorithm>, strict: bool) -> io::Result<Vec<Member<'a>>> {
+    scan_members(input, fallback, strict, true)
+}
+
+/// Разбирает архивы, записанные подряд, проверяя хеши SHA-256, только если задан `verify_digests`.
+fn scan_members<'a>(input: &'a [u8], fallback: Option<&Algorithm>, strict: bool, verify_digests: bool) -> io::Result<Vec<Member<'a>>> {
+    frame_members(input, fallback, strict, verify_digests)?.into_iter()
+        .map(|(member, intact)| if intact { Ok(member) } else { Err(digest_mismatch()) })
+        .collect()
+}
+
+/// Ошибка несовпадения хеша сжатых данных архива.
+fn digest_mismatch() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "archive SHA-256 mismatch: the file is corrupted")
+}
+
+/// Разбирает архивы, записанные подряд, как `scan_members`, но не останавливается на неверном хеше:
+/// рядом с каждым архивом возвращается `false`, если его хеш проверялся и не совпал.
+fn frame_members<'a>(input: &'a [u8], fallback: Option<&Algorithm>, strict: bool, verify_digests: bool) -> io::Result<Vec<(Member<'a>, bool)>> {
+    let mut members = Vec::new();
+    let mut offset = 0;
+    while offset < input.len() {
+        let rest = &input[offset..];
+        if let Some(len) = recovery::record_len(rest) {
+            // Запись восстановления (`--recovery`) не содержит данных архива
+            offset += len;
+            continue;
+        }
+        if !header::has_magic(rest) {
+            if strict {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("{} trailing bytes after the last archive", rest.len())));
+            }
+            warnings::report(Warning::TrailingBytes { bytes: rest.len() });
+            break;
+        }
+        let header = Header::parse(rest)?;
+        if let Some(algorithm) = fallback.filter(|a| **a != header.algorithm) {
+            warnings::report(Warning::AlgorithmMismatch { stored: header.algorithm.name(), requested: algorithm.name() });
         }
+        let digest_len = if header.has_digest() { header::DIGEST_LEN } else { 0 };
+        let payload_end = usize::try_from(header.payload_len).ok()
+            .and_then(|len| header.encoded_len().checked_add(len))
+            .filter(|end| end.checked_add(digest_len).is_some_and(|total| total <= rest.len()))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "archive data is truncated"))?;
+        let payload = &rest[header.encoded_len()..payload_end];
+        let intact = !verify_digests || !header.has_digest() || crypto::digest(payload)[..] == rest[payload_end..payload_end + digest_len];
+        offset += payload_end + digest_len;
+        // Поврежденный раздел сведений архива с неверным хешем описывается несовпадением хеша
+        let (info, payload) = split_info(payload, &header).map_err(|e| if intact { e } else { digest_mismatch() })?;
+        members.push(((header, info, payload), intact));
     }
-}
\ No newline at end of file
+    Ok(members)
+}
+
+/// Отделяет блок полей заголовка и раздел сведений от сжатых данных архива.
+///
+/// Поля неизвестных типов пропускаются (см. `header::read_fields`).
+fn split_info<'a>(region: &'a [u8], header: &Header) -> io::Result<(Option<ArchiveInfo>, &'a [u8])> {
+    let region = if header.has_fields() { &region[header::read_fields(region, header::KNOWN_FIELDS)?.1..] } else { region };
+    if !header.has_info() {
+        return Ok((None, region));
+    }
+    let (info, len) = ArchiveInfo::from_bytes(region)?;
+    Ok((Some(info), &region[len..]))
+}
+
+/// Сводка об одном архиве из потока (команда `--info`).
+#[derive(Debug)]
+pub struct MemberSummary {
+    /// Заголовок архива
+    pub header: Header,
+    /// Сведения о происхождении, если они записаны
+    pub info: Option<ArchiveInfo>,
+    /// Размер сжатых данных в байтах
+    pub payload_len: u64,
+    /// Число записей по центральному индексу (`None` для общего сжатого потока)
+    pub entries: Option<usize>,
+    /// Размер блока в блочном режиме
+    pub block_size: Option<u64>,
+    /// Исходный размер записей по центральному индексу (`None` для общего сжатого потока,
+    /// размер которого известен только после распаковки)
+    pub original_size: Option<u64>,
+}
+
+/// Описывает архивы в потоке, не распаковывая их.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов архива.
+/// * `strict` - Строгая проверка архива.
+///
+/// # Возвращает
+///
+/// Сводку по каждому архиву или ошибку, если архив поврежден.
+pub fn describe(input: &[u8], strict: bool) -> io::Result<Vec<MemberSummary>> {
+    if !header::has_magic(input) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an archive (missing RARC signature)"));
+    }
+    let mut summaries = Vec::new();
+    for (header, info, payload) in read_members(input, None, strict)? {
+        let index = if header.per_entry() {
+            Some(entry_index::read_index(payload, &header)?)
+        } else {
+            None
+        };
+        summaries.push(MemberSummary {
+            entries: index.as_ref().map(|index| index.records.len()),
+            original_size: index.as_ref().map(|index| index.records.iter().map(|r| r.size).sum()),
+            block_size: index.map(|index| index.block_size).filter(|&size| size > 0),
+            payload_len: payload.len() as u64,
+            header,
+            info,
+        });
+    }
+    Ok(summaries)
+}
+
+/// Запись в списке содержимого архива (`-l`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedEntry {
+    /// Относительный путь
+    pub path: String,
+    /// Режим файла без служебных битов жесткой ссылки и дубликата
+    pub permissions: u32,
+    /// Размер содержимого в байтах; у жесткой ссылки и дубликата — размер исходной записи
+    pub size: u64,
+    /// Цель символической или жесткой ссылки
+    pub link: Option<String>,
+    /// Контрольная сумма CRC32 содержимого, если она известна без распаковки файлов
+    /// (у записей, сжатых по отдельности, — только в блочном режиме)
+    pub crc32: Option<u32>,
+    /// Время изменения в секундах от начала эпохи Unix (`None`, если оно не сохранено)
+    pub mtime: Option<u64>,
+}
+
+/// Перечисляет записи архива, не распаковывая содержимое файлов, если это возможно.
+///
+/// В архивах с центральным индексом сведения берутся из индекса; распаковываются только
+/// записи ссылок и дубликатов, содержимое которых — путь. Общий сжатый поток распаковывается
+/// целиком. Если путь встречается несколько раз (в склеенных архивах), остается последняя
+/// запись на месте первой.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов архива.
+/// * `strict` - Строгая проверка архива.
+///
+/// # Возвращает
+///
+/// Записи в порядке архива или ошибку, если архив поврежден.
+pub fn list(input: &[u8], strict: bool) -> io::Result<Vec<ListedEntry>> {
+    Ok(list_sizes(input, strict)?.into_iter().map(|(entry, _)| entry).collect())
+}
+
+/// Запись участника архива при перечислении: путь, режим, размер, содержимое записи,
+/// если оно является путем, сжатый размер, CRC32 и время изменения.
+type MemberEntry = (String, u32, u64, Option<Vec<u8>>, Option<u64>, Option<u32>, Option<u64>);
+
+/// Перечисляет записи архива как `list` вместе с их сжатыми размерами.
+///
+/// Сжатый размер берется из центрального индекса; записи, упакованные в общий сегмент
+/// (`--pack-below`), делят его сжатый размер пропорционально исходным размерам.
+/// У записей общего сжатого потока сжатый размер неизвестен (`None`).
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов архива.
+/// * `strict` - Строгая проверка архива.
+///
+/// # Возвращает
+///
+/// Записи и их сжатые размеры в порядке архива или ошибку, если архив поврежден.
+pub fn list_sizes(input: &[u8], strict: bool) -> io::Result<Vec<(ListedEntry, Option<u64>)>> {
+    list_members(input, strict, true)
+}
+
+/// Перечисляет записи архива для `list_sizes`; без `allow_index` архив, содержащий только
+/// индекс (`--index-only`), отклоняется.
+fn list_members(input: &[u8], strict: bool, allow_index: bool) -> io::Result<Vec<(ListedEntry, Option<u64>)>> {
+    if !header::has_magic(input) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an archive (missing RARC signature)"));
+    }
+    let reference = archive_io::HARD_LINK_FLAG | archive_io::DUPLICATE_FLAG;
+    let symlink = |mode: u32| EntryType::from_mode(mode) == EntryType::Symlink;
+    let mut listed: Vec<(ListedEntry, Option<u64>)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for (header, payload) in split_members(input, None, strict)? {
+        let mut entries: Vec<MemberEntry> = Vec::new();
+        if header.per_entry() {
+            let records = entry_index::read_index(payload, &header)?.records;
+            // Исходный размер каждого сегмента, на который делится его сжатый размер
+            let mut segments: HashMap<u64, u64> = HashMap::new();
+            for record in &records {
+                if let Some((_, len)) = record.segment {
+                    *segments.entry(record.offset).or_default() += len;
+                }
+            }
+            for record in records {
+                let content = if record.permissions & reference != 0 || symlink(record.permissions) {
+                    Some(record_content(payload, &header, &record, strict)?)
+                } else {
+                    None
+                };
+                let compressed = match record.segment {
+                    Some((_, len)) => (record.compressed_size as u128 * len as u128
+                        / segments[&record.offset].max(1) as u128) as u64,
+                    None => record.compressed_size,
+                };
+                entries.push((record.path, record.permissions, record.size, content, Some(compressed), record.crc32, record.mtime));
+            }
+        } else {
+            let decoded = decode_stream(payload, &header, strict)?;
+            if archive::is_index(&decoded) {
+                if !allow_index {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "index-only archive contains no file data to compare"));
+                }
+                entries.extend(ArchiveIndex::from_bytes(&decoded)?.entries.into_iter().map(|e| (e.path, e.permissions, e.size, None, None, Some(e.crc32), e.mtime)));
+            } else {
+                entries.extend(archive_io::bytes_to_archive_data(&decoded, strict)?.entries.into_iter()
+                    .map(|e| (e.path, e.permissions, e.data.len() as u64, Some(e.data), None, e.crc32, e.mtime)));
+            }
+        }
+        for (path, mode, mut size, content, compressed, mut crc32, mtime) in entries {
+            let content = content.map(|data| String::from_utf8_lossy(&data).into_owned());
+            if mode & reference != 0 {
+                // Жесткая ссылка и дубликат показываются с размером и CRC32 исходной записи
+                if let Some(&i) = content.as_ref().and_then(|source| positions.get(source)) {
+                    size = listed[i].0.size;
+                    crc32 = listed[i].0.crc32;
+                }
+            }
+            let link = content.filter(|_| mode & archive_io::HARD_LINK_FLAG != 0 || symlink(mode));
+            let entry = ListedEntry { path, permissions: mode & !reference, size, link, crc32, mtime };
+            match positions.get(&entry.path) {
+                Some(&i) => listed[i] = (entry, compressed),
+                None => {
+                    positions.insert(entry.path.clone(), listed.len());
+                    listed.push((entry, compressed));
+                }
+            }
+        }
+    }
+    Ok(listed)
+}
+
+/// Распаковывает содержимое записи по ее описанию в центральном индексе.
+fn record_content(payload: &[u8], header: &Header, record: &IndexRecord, strict: bool) -> io::Result<Vec<u8>> {
+    if header.blocks() {
+        let blocks = (0..record.blocks.len())
+            .map(|block| decode_block(payload, header, record, block, strict))
+            .collect::<io::Result<Vec<_>>>()?;
+        return Ok(blocks.concat());
+    }
+    let decoded = decode_blob(entry_index::entry_blob(payload, record), header, strict)?;
+    Ok(parse_entry(entry_index::segment_bytes(&decoded, record)?, header)?.data)
+}
+
+/// Глубина проверки архива (`--audit --depth`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
+pub enum AuditDepth {
+    /// Заголовки, длины, разделы сведений и центральные индексы
+    Quick,
+    /// Также хеши SHA-256 сжатых данных каждого архива
+    #[default]
+    Standard,
+    /// Также полная распаковка и сверка CRC32 каждой записи
+    Deep,
+}
+
+impl AuditDepth {
+    /// Определяет глубину по имени из командной строки (`quick`, `standard`, `deep`).
+    pub fn from_name(name: &str) -> Option<AuditDepth> {
+        match name {
+            "quick" => Some(AuditDepth::Quick),
+            "standard" => Some(AuditDepth::Standard),
+            "deep" => Some(AuditDepth::Deep),
+            _ => None,
+        }
+    }
+}
+
+/// Итог успешной проверки архива (`--audit`).
+#[derive(Debug, Default, PartialEq)]
+pub struct AuditReport {
+    /// Число архивов в потоке
+    pub members: usize,
+    /// Число проверенных хешей SHA-256
+    pub digests: usize,
+    /// Число записей в центральных индексах, а при глубокой проверке — распакованных записей
+    pub entries: usize,
+}
+
+/// Проверяет целостность архива, не записывая файлов.
+///
+/// Быстрая проверка читает только заголовки и индексы, поэтому подходит для частых
+/// плановых проверок больших архивов; глубокая распаковывает все данные.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов архива.
+/// * `depth` - Глубина проверки.
+/// * `strict` - Строгая проверка архива.
+///
+/// # Возвращает
+///
+/// Итог проверки или ошибку, описывающую первое найденное повреждение.
+pub fn audit(input: &[u8], depth: AuditDepth, strict: bool) -> io::Result<AuditReport> {
+    if !header::has_magic(input) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an archive (missing RARC signature)"));
+    }
+    let mut report = AuditReport::default();
+    for (header, _, payload) in scan_members(input, None, strict, depth >= AuditDepth::Standard)? {
+        report.members += 1;
+        if depth >= AuditDepth::Standard && header.has_digest() {
+            report.digests += 1;
+        }
+        if depth < AuditDepth::Deep {
+            if header.per_entry() {
+                report.entries += entry_index::read_index(payload, &header)?.records.len();
+            }
+            continue;
+        }
+        let decoded = if header.per_entry() {
+            decode_entries(payload, &header, strict, &|_, _| true, 1)?
+        } else {
+            decode_stream(payload, &header, strict)?
+        };
+        if archive::is_index(&decoded) {
+            report.entries += ArchiveIndex::from_bytes(&decoded)?.entries.len();
+            continue;
+        }
+        let entries = archive_io::bytes_to_archive_data(&decoded, strict)?.entries;
+        for entry in &entries {
+            entry.verify()?;
+        }
+        report.entries += entries.len();
+    }
+    Ok(report)
+}
+
+/// Результат проверки одной записи архива (`-t`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryCheck {
+    /// Путь записи
+    pub path: String,
+    /// Описание ошибки распаковки или контрольной суммы; `None`, если запись цела
+    pub error: Option<String>,
+}
+
+impl EntryCheck {
+    /// Возвращает `true`, если запись распаковалась и совпала с контрольной суммой.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Итог проверки архива по записям (`-t`).
+#[derive(Debug, Default)]
+pub struct TestReport {
+    /// Результаты записей в порядке архива
+    pub entries: Vec<EntryCheck>,
+    /// Повреждения архивов потока, которые не относятся к одной записи
+    pub damaged: Vec<String>,
+}
+
+impl TestReport {
+    /// Возвращает `true`, если повреждений нет и все записи целы.
+    pub fn is_ok(&self) -> bool {
+        self.damaged.is_empty() && self.entries.iter().all(EntryCheck::is_ok)
+    }
+}
+
+/// Проверяет архив по записям в памяти, ничего не записывая (`-t`).
+///
+/// Каждая запись распаковывается и сверяется с CRC32. В отличие от `audit`, неверный хеш
+/// SHA-256 архива не прерывает проверку: он попадает в отчет, а записи проверяются дальше,
+/// чтобы было видно, какие из них повреждены. В архиве с посжатыми записями ошибка одной записи
+/// не мешает проверке остальных; в общем сжатом потоке поврежденный поток — повреждение архива,
+/// после которого его записи не проверяются.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов архива.
+/// * `strict` - Строгая проверка архива.
+///
+/// # Возвращает
+///
+/// Отчет о записях или ошибку, если разобрать архив невозможно (сигнатура, заголовок, длины,
+/// центральный индекс). Архив, содержащий только индекс, отмечается в отчете как поврежденный:
+/// проверять в нем нечего.
+pub fn test_archive(input: &[u8], strict: bool) -> io::Result<TestReport> {
+    if !header::has_magic(input) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an archive (missing RARC signature)"));
+    }
+    let mut report = TestReport::default();
+    let members = frame_members(input, None, strict, true)?;
+    let count = members.len();
+    for (number, ((header, _, payload), intact)) in members.into_iter().enumerate() {
+        let member = if count > 1 { format!("archive {} of {}", number + 1, count) } else { "archive".to_owned() };
+        if !intact {
+            report.damaged.push(format!("{}: SHA-256 mismatch", member));
+        }
+        let check = |path: &str, entry: io::Result<DirEntry>| EntryCheck {
+            path: path.to_owned(),
+            error: entry.and_then(|e| e.verify()).err().map(|e| e.to_string()),
+        };
+        if header.per_entry() {
+            for record in entry_index::read_index(payload, &header)?.records {
+                report.entries.push(check(&record.path, decode_record(payload, &header, &record, strict)));
+            }
+            continue;
+        }
+        let entries = decode_stream(payload, &header, strict).and_then(|decoded| {
+            if archive::is_index(&decoded) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "index-only archive contains no file data"));
+            }
+            archive_io::bytes_to_archive_data(&decoded, strict)
+        });
+        match entries {
+            Ok(archive) => report.entries.extend(archive.entries.into_iter().map(|e| check(&e.path.clone(), Ok(e)))),
+            Err(e) => report.damaged.push(format!("{}: {}", member, e)),
+        }
+    }
+    Ok(report)
+}
+
+/// Переписывает архив плотно: архивы, дописанные подряд, объединяются в один,
+/// вытесненные записи (с путем, который встречается позже) отбрасываются, а индекс
+/// строится заново.
+///
+/// Если все архивы текущей версии записаны по записям с одинаковыми алгоритмом, флагами и размером
+/// блока, сжатые записи переносятся без перекодирования. Иначе записи распаковываются
+/// и сжимаются заново алгоритмом и режимом последнего архива. Сведения о происхождении
+/// берутся из последнего архива, в котором они записаны.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов архива.
+/// * `use_multithreading` - Флаг многопоточности для повторного сжатия.
+/// * `strict` - Строгая проверка архива.
+///
+/// # Возвращает
+///
+/// Новый архив или ошибку, если исходный архив поврежден.
+pub fn compact(input: &[u8], use_multithreading: bool, strict: bool) -> io::Result<Vec<u8>> {
+    if !header::has_magic(input) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an archive (missing RARC signature)"));
+    }
+    let (info, members): (Vec<_>, Vec<_>) = read_members(input, None, strict)?.into_iter()
+        .map(|(header, info, payload)| (info, (header, payload)))
+        .unzip();
+    // Сохраняются сведения последнего архива, в котором они есть
+    let info = info.into_iter().flatten().next_back();
+    let (last, last_payload) = members.last()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "archive is empty"))?;
+    let mut indexes = Vec::new();
+    for (member, payload) in &members {
+        if member.per_entry() {
+            indexes.push(entry_index::read_index(payload, member)?);
+        }
+    }
+    // Сведения, индекс и признак сегментов записываются заново, поэтому эти флаги могут различаться
+    const RECODED_FLAGS: u8 = header::FLAG_INFO | header::FLAG_PACKED_INDEX | header::FLAG_SEGMENTS;
+    let uniform = indexes.len() == members.len()
+        && members.iter().all(|(h, _)| h.stored_blobs() && h.byte_order() == ByteOrder::Little && h.algorithm == last.algorithm
+            && h.flags & !RECODED_FLAGS == last.flags & !RECODED_FLAGS)
+        && indexes.iter().all(|index| index.block_size == indexes[0].block_size);
+
+    if uniform {
+        // Перенос сжатых записей без перекодирования
+        let records = indexes.iter().enumerate()
+            .flat_map(|(member, index)| index.records.iter().map(move |record| (member, record.clone())))
+            .collect();
+        let records: Vec<_> = keep_latest(records, |(_, record)| &record.path);
+        // Оставшиеся записи одного сегмента идут подряд и переносятся вместе с сегментом;
+        // байты вытесненных записей остаются в сегменте
+        let blobs = records
+            .chunk_by(|(ma, a), (mb, b)| ma == mb && a.segment.is_some() && b.segment.is_some() && a.offset == b.offset)
+            .map(|group| {
+                let (member, first) = &group[0];
+                let blob = entry_index::entry_blob(members[*member].1, first).to_vec();
+                (group.iter().map(|(_, record)| record.clone()).collect(), blob)
+            })
+            .collect();
+        let payload = entry_index::build_payload(blobs, indexes[0].block_size);
+        // Индекс записывается заново: сжатым и, в блочном режиме, с владельцами
+        let mut flags = (last.flags & !header::FLAG_SEGMENTS) | header::FLAG_PACKED_INDEX;
+        if last.blocks() {
+            flags |= header::FLAG_OWNERS;
+        }
+        if records.iter().any(|(_, record)| record.segment.is_some()) {
+            flags |= header::FLAG_SEGMENTS;
+        }
+        return Ok(wrap(payload, last.algorithm.clone(), false, flags, info.as_ref()));
+    }
+
+    let decompressed = decompress(input, None, false, false, strict)?;
+    let entries = keep_latest(archive_io::bytes_to_archive_data(&decompressed, strict)?.entries, |e| &e.path);
+    let compacted = if last.chunked() {
+        let serialized = archive_io::archive_data_to_bytes(&ArchiveData { entries });
+        compress_chunked(&serialized, last.algorithm.clone(), use_multithreading, last.short_matches())
+    } else if !last.per_entry() {
+        let serialized = archive_io::archive_data_to_bytes(&ArchiveData { entries });
+        compress_solid(&serialized, last.algorithm.clone(), use_multithreading, last.short_matches())
+    } else {
+        let index = entry_index::read_index(last_payload, last)?;
+        let block_size = Some(index.block_size as usize).filter(|&size| size > 0);
+        // Порог упаковки не хранится в архиве и восстанавливается по самой крупной упакованной записи
+        let pack_below = index.records.iter().filter(|r| r.segment.is_some()).map(|r| r.size as usize + 1).max();
+        compress_entries(&entries, last.algorithm.clone(), use_multithreading, last.short_matches(), block_size, pack_below)
+    };
+    match info {
+        Some(info) => set_info(&compacted, &info),
+        None => Ok(compacted),
+    }
+}
+
+/// Заменяет содержимое одной записи архива, не пересжимая остальные.
+///
+/// Новое содержимое сжимается отдельным архивом с тем же алгоритмом и режимом, что
+/// у последнего архива в потоке, и дописывается в конец, после чего поток уплотняется
+/// (`compact`): в архиве с посжатыми записями остальные записи переносятся без
+/// перекодирования, а архив с общим сжатым потоком пересжимается целиком. Права, владелец
+/// и метаданные записи сохраняются, а временем изменения становится время замены; если заменяется исходная запись дубликатов, дубликаты
+/// сохраняются с прежним содержимым. Запись восстановления (`--recovery`) после замены
+/// не сохраняется и должна быть создана заново.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов архива.
+/// * `path` - Путь заменяемой записи.
+/// * `data` - Новое содержимое записи.
+/// * `use_multithreading` - Флаг многопоточности для сжатия.
+/// * `strict` - Строгая проверка архива.
+///
+/// # Возвращает
+///
+/// Новый архив или ошибку, если записи нет или архив поврежден.
+pub fn replace_entry(input: &[u8], path: &str, data: &[u8], use_multithreading: bool, strict: bool) -> io::Result<Vec<u8>> {
+    let (located, attributes) = find_entry(input, path, strict)?;
+    let mut located = Some(located);
+    let reference = archive_io::HARD_LINK_FLAG | archive_io::DUPLICATE_FLAG;
+    let mut replaced = vec![DirEntry {
+        data: data.to_vec(),
+        permissions: attributes.permissions & !reference,
+        crc32: Some(checksum::crc32(data)),
+        mtime: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok().map(|d| d.as_secs()),
+        ..attributes.clone()
+    }];
+
+    // Дубликаты заменяемой записи получают ее прежнее содержимое
+    if attributes.permissions & reference == 0 {
+        let is_duplicate = |_: &str, mode: u32| mode & archive_io::DUPLICATE_FLAG != 0;
+        let candidates = decompress_selected(input, None, 1, false, strict, &is_duplicate)?;
+        let candidates = archive_io::bytes_to_archive_data(&candidates, strict)?.entries;
+        let mut previous: Option<DirEntry> = None;
+        for candidate in candidates.iter().filter(|e| e.duplicate_of() == Some(path)) {
+            // Дубликат мог быть вытеснен записью с тем же путем в следующем архиве потока
+            let (found, latest) = find_entry(input, &candidate.path, strict)?;
+            let latest = DirEntry { data: located_bytes(found, strict)?, ..latest };
+            if latest.duplicate_of() != Some(path) || replaced.iter().any(|e| e.path == latest.path) {
+                continue;
+            }
+            if let (None, Some(located)) = (&previous, located.take()) {
+                previous = Some(DirEntry { data: located_bytes(located, strict)?, ..attributes.clone() });
+            }
+            if let Some(source) = &previous {
+                replaced.push(latest.resolve_duplicate(source));
+            }
+        }
+    }
+
+    let members = read_members(input, None, strict)?;
+    let (last, _, last_payload) = members.last()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "archive is empty"))?;
+    let appended = if last.per_entry() {
+        let index = entry_index::read_index(last_payload, last)?;
+        let block_size = Some(index.block_size as usize).filter(|&size| size > 0);
+        compress_entries(&replaced, last.algorithm.clone(), use_multithreading, last.short_matches(), block_size, None)
+    } else if last.chunked() {
+        let serialized = archive_io::archive_data_to_bytes(&ArchiveData { entries: replaced });
+        compress_chunked(&serialized, last.algorithm.clone(), use_multithreading, last.short_matches())
+    } else {
+        let serialized = archive_io::archive_data_to_bytes(&ArchiveData { entries: replaced });
+        compress_solid(&serialized, last.algorithm.clone(), use_multithreading, last.short_matches())
+    };
+    let mut output = input.to_vec();
+    output.extend(appended);
+    compact(&output, use_multithreading, strict)
+}
+
+/// Отбирает записи, которых нет в архиве или которые отличаются от сохраненных (`-u`).
+///
+/// По умолчанию записи сравниваются, как в `tar -u`, по размеру, времени изменения, правам
+/// и цели ссылки из списка записей (см. `list`), и содержимое файлов не распаковывается.
+/// Записи без сохраненного времени изменения (из архивов старых версий) считаются
+/// измененными. С `compare_content` записи сравниваются по правам и содержимому, а также
+/// по владельцу и метаданным, если они сохранены; из архива распаковываются только записи
+/// с теми же путями (общий сжатый поток — целиком). Если путь встречается в нескольких
+/// склеенных архивах, сравнение идет с последним вхождением. Отобранные записи сжимаются
+/// и дописываются к архиву, и при распаковке вытесняют прежние.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов архива.
+/// * `entries` - Текущие записи, например прочитанные `io::read_dir_recursive`.
+/// * `compare_content` - Сравнивать содержимое записей вместо размера и времени изменения
+///   (`--compare-content`).
+/// * `strict` - Строгая проверка архива.
+///
+/// # Возвращает
+///
+/// Новые и измененные записи в прежнем порядке или ошибку, если архив поврежден
+/// или содержит только индекс.
+pub fn changed_entries(input: &[u8], entries: Vec<DirEntry>, compare_content: bool, strict: bool) -> io::Result<Vec<DirEntry>> {
+    if !compare_content {
+        let stored: HashMap<String, ListedEntry> = list_members(input, strict, false)?.into_iter()
+            .map(|(e, _)| (e.path.clone(), e))
+            .collect();
+        // Жесткая ссылка и дубликат перечисляются с размером исходной записи
+        let sizes: HashMap<&str, u64> = entries.iter().map(|e| (e.path.as_str(), e.data.len() as u64)).collect();
+        let reference = archive_io::HARD_LINK_FLAG | archive_io::DUPLICATE_FLAG;
+        let unchanged = |e: &DirEntry| stored.get(&e.path).is_some_and(|s| {
+            let source = e.hard_link_target().or_else(|| e.duplicate_of());
+            let size = source.map_or(Some(e.data.len() as u64), |source| sizes.get(source).copied());
+            let link = if EntryType::from_mode(e.permissions) == EntryType::Symlink {
+                Some(String::from_utf8_lossy(&e.data).into_owned())
+            } else {
+                e.hard_link_target().map(str::to_owned)
+            };
+            s.mtime.is_some() && s.mtime == e.mtime && Some(s.size) == size
+                && s.permissions == e.permissions & !reference && s.link == link
+        });
+        let changed: Vec<bool> = entries.iter().map(|e| !unchanged(e)).collect();
+        return Ok(entries.into_iter().zip(changed).filter(|(_, changed)| *changed).map(|(e, _)| e).collect());
+    }
+    let paths: HashSet<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+    let stored = decompress_selected(input, None, 1, false, strict, &|path, _| paths.contains(path))?;
+    if archive::is_index(&stored) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "index-only archive contains no file data to compare"));
+    }
+    let stored: HashMap<String, DirEntry> = archive_io::bytes_to_archive_data(&stored, strict)?.entries.into_iter()
+        .map(|e| (e.path.clone(), e))
+        .collect();
+    let unchanged = |e: &DirEntry| stored.get(&e.path).is_some_and(|s| s.permissions == e.permissions && s.data == e.data
+        && (s.owner.is_none() || s.owner == e.owner) && (s.metadata.is_empty() || s.metadata == e.metadata));
+    Ok(entries.into_iter().filter(|e| !unchanged(e)).collect())
+}
+
+/// Оставляет для каждого пути только последнее вхождение, сохраняя порядок.
+fn keep_latest<T>(items: Vec<T>, path: impl Fn(&T) -> &String) -> Vec<T> {
+    let mut latest = HashMap::new();
+    for (i, item) in items.iter().enumerate() {
+        latest.insert(path(item).clone(), i);
+    }
+    items.into_iter().enumerate()
+        .filter(|(i, item)| latest[path(item)] == *i)
+        .map(|(_, item)| item)
+        .collect()
+}
+
+/// Результат восстановления поврежденного архива (`--salvage`).
+#[derive(Debug, Default)]
+pub struct SalvageReport {
+    /// Восстановленные записи, прошедшие проверку контрольной суммы
+    pub entries: Vec<DirEntry>,
+    /// Описания потерянных записей и поврежденных участков архива
+    pub lost: Vec<String>,
+}
+
+/// Извлекает из поврежденного архива все, что удается восстановить.
+///
+/// Архивы, записанные подряд, находятся по сигнатуре `RARC`: после поврежденного участка
+/// поиск продолжается со следующей сигнатуры. Хеш SHA-256 не прерывает разбор: в архиве
+/// с центральным индексом каждая запись распаковывается и проверяется по CRC32 отдельно,
+/// а в распакованном общем потоке чтение продолжается после поврежденных записей
+/// (см. `io::salvage_archive_data`).
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов поврежденного архива.
+///
+/// # Возвращает
+///
+/// Восстановленные записи и отчет о потерях.
+pub fn salvage(input: &[u8]) -> SalvageReport {
+    let mut report = SalvageReport::default();
+    let mut offset = 0;
+    // Конец последнего разобранного архива: байты до него уже учтены в отчете
+    let mut member_end = 0;
+    while offset < input.len() {
+        if let Some(len) = recovery::record_len(&input[offset..]) {
+            offset += len;
+            member_end = offset;
+            continue;
+        }
+        // Поиск следующей сигнатуры архива
+        let start = input[offset..].windows(header::ARCHIVE_MAGIC.len())
+            .position(|w| w == header::ARCHIVE_MAGIC)
+            .map_or(input.len(), |found| offset + found);
+        let gap_start = offset.max(member_end);
+        if start > gap_start {
+            report.lost.push(format!("{} unrecognized bytes at offset {}", start - gap_start, gap_start));
+        }
+        if start == input.len() {
+            break;
+        }
+        let rest = &input[start..];
+        let header = match Header::parse(rest) {
+            Ok(header) => header,
+            Err(e) => {
+                report.lost.push(format!("damaged archive header at offset {}: {}", start, e));
+                offset = start + 1;
+                continue;
+            }
+        };
+        let digest_len = if header.has_digest() { header::DIGEST_LEN } else { 0 };
+        let declared = usize::try_from(header.payload_len).unwrap_or(usize::MAX);
+        let header_len = header.encoded_len();
+        let payload = &rest[header_len..header_len + declared.min(rest.len() - header_len)];
+        let digest = rest.get(header_len + payload.len()..).and_then(|d| d.get(..digest_len));
+        let intact = match digest {
+            Some(digest) if payload.len() == declared => {
+                let matches = !header.has_digest() || crypto::digest(payload)[..] == *digest;
+                if !matches {
+                    report.lost.push(format!("archive at offset {}: SHA-256 mismatch, checking entries one by one", start));
+                }
+                matches
+            }
+            _ => {
+                report.lost.push(format!("archive at offset {} is truncated", start));
+                false
+            }
+        };
+        match split_info(payload, &header) {
+            Ok((_, payload)) => salvage_member(payload, &header, start, &mut report),
+            Err(e) => report.lost.push(format!("archive at offset {}: {}", start, e)),
+        }
+        member_end = start + header_len + payload.len() + digest.map_or(0, |d| d.len());
+        // После поврежденного архива следующая сигнатура ищется сразу за его заголовком:
+        // длина данных в заголовке тоже может быть испорчена
+        offset = if intact { member_end } else { start + header_len };
+    }
+    report
+}
+
+/// Восстанавливает записи одного архива, добавляя потери в отчет.
+fn salvage_member(payload: &[u8], header: &Header, start: usize, report: &mut SalvageReport) {
+    let keep = |entry: DirEntry, report: &mut SalvageReport| match entry.verify() {
+        Ok(()) => report.entries.push(entry),
+        Err(e) => report.lost.push(e.to_string()),
+    };
+    if !header.per_entry() {
+        let data = match decode_stream(payload, header, false) {
+            Ok(data) => data,
+            Err(e) => {
+                report.lost.push(format!("archive at offset {}: compressed stream is damaged ({}), all its entries are lost", start, e));
+                return;
+            }
+        };
+        let (entries, damaged) = archive_io::salvage_archive_data(&data);
+        for entry in entries {
+            keep(entry, report);
+        }
+        for region in damaged {
+            report.lost.push(format!("archive at offset {}: {}", start, region));
+        }
+        return;
+    }
+    let index = match entry_index::read_index(payload, header) {
+        Ok(index) => index,
+        Err(e) => {
+            report.lost.push(format!("archive at offset {}: central index is damaged ({}), all its entries are lost", start, e));
+            return;
+        }
+    };
+    for record in &index.records {
+        match decode_record(payload, header, record, false) {
+            Ok(entry) => keep(entry, report),
+            Err(e) => report.lost.push(format!("{}: {}", record.path, e)),
+        }
+    }
+}
+
+/// Распаковывает одну запись архива с посжатыми записями по ее описанию в центральном индексе.
+///
+/// Упакованная запись распаковывается вместе со своим сегментом, поэтому для множества записей
+/// одного сегмента `decode_entries` быстрее.
+fn decode_record(payload: &[u8], header: &Header, record: &IndexRecord, strict: bool) -> io::Result<DirEntry> {
+    if header.blocks() {
+        let blocks = (0..record.blocks.len())
+            .map(|block| decode_block(payload, header, record, block, strict))
+            .collect::<io::Result<Vec<Vec<u8>>>>()?;
+        return Ok(DirEntry {
+            path: record.path.clone(),
+            data: blocks.concat(),
+            permissions: record.permissions,
+            crc32: record.crc32,
+            owner: record.owner.clone(),
+            metadata: record.metadata.clone(),
+            mtime: record.mtime,
+        });
+    }
+    let bytes = decode_blob(entry_index::entry_blob(payload, record), header, strict)?;
+    parse_entry(entry_index::segment_bytes(&bytes, record)?, header)
+}
+
+/// Запись архива, найденная для частичного чтения.
+enum Located<'a> {
+    /// Содержимое записи, распакованное целиком
+    Data(Vec<u8>),
+    /// Запись из блоков, которые распаковываются по мере необходимости
+    Blocks { payload: &'a [u8], header: Header, record: Box<IndexRecord> },
+}
+
+/// Находит запись в архиве; если запись встречается несколько раз, берется последняя.
+/// Для жесткой ссылки или дубликата возвращается запись, на которую они указывают.
+///
+/// Записи блочного режима не распаковываются, целиком сжатые записи распаковываются по
+/// отдельности, а для архивов с общим сжатым потоком распаковывается весь поток.
+fn locate_entry<'a>(input: &'a [u8], path: &str, strict: bool) -> io::Result<Located<'a>> {
+    let reference = archive_io::HARD_LINK_FLAG | archive_io::DUPLICATE_FLAG;
+    let (located, attributes) = find_entry(input, path, strict)?;
+    if attributes.permissions & reference == 0 {
+        return Ok(located);
+    }
+    let target = String::from_utf8(located_bytes(located, strict)?)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{}: invalid link or duplicate target", path)))?;
+    match find_entry(input, &target, strict)? {
+        (_, attributes) if attributes.permissions & reference != 0 => Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("{}: target {} is a link or duplicate itself", path, target))),
+        (located, _) => Ok(located),
+    }
+}
+
+/// Распаковывает содержимое найденной записи целиком.
+fn located_bytes(located: Located, strict: bool) -> io::Result<Vec<u8>> {
+    match located {
+        Located::Data(data) => Ok(data),
+        Located::Blocks { payload, header, record, .. } => {
+            let mut data = Vec::new();
+            for block in 0..record.blocks.len() {
+                data.extend(decode_block(payload, &header, &record, block, strict)?);
+            }
+            Ok(data)
+        }
+    }
+}
+
+/// Находит запись в архиве, как `locate_entry`, не следуя жестким ссылкам.
+///
+/// # Возвращает
+///
+/// Запись и ее атрибуты (путь, права, владелец, метаданные, без содержимого) или ошибку,
+/// если записи нет.
+fn find_entry<'a>(input: &'a [u8], path: &str, strict: bool) -> io::Result<(Located<'a>, DirEntry)> {
+    if !header::has_magic(input) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an archive (missing RARC signature)"));
+    }
+    for (header, payload) in split_members(input, None, strict)?.into_iter().rev() {
+        if !header.per_entry() {
+            let mut archive = archive_io::bytes_to_archive_data(&decode_stream(payload, &header, strict)?, strict)?;
+            match archive.entries.iter().rposition(|e| e.path == path) {
+                Some(i) => {
+                    let mut entry = archive.entries.swap_remove(i);
+                    return Ok((Located::Data(std::mem::take(&mut entry.data)), entry));
+                }
+                None => continue,
+            }
+        }
+        let mut index = entry_index::read_index(payload, &header)?;
+        let Some(i) = index.records.iter().rposition(|r| r.path == path) else {
+            continue;
+        };
+        let record = index.records.swap_remove(i);
+        if !header.blocks() {
+            let decoded = decode_blob(entry_index::entry_blob(payload, &record), &header, strict)?;
+            let mut entry = parse_entry(entry_index::segment_bytes(&decoded, &record)?, &header)?;
+            return Ok((Located::Data(std::mem::take(&mut entry.data)), entry));
+        }
+        let attributes = DirEntry {
+            path: record.path.clone(),
+            data: Vec::new(),
+            permissions: record.permissions,
+            crc32: record.crc32,
+            owner: record.owner.clone(),
+            metadata: record.metadata.clone(),
+            mtime: record.mtime,
+        };
+        return Ok((Located::Blocks { payload, header, record: Box::new(record) }, attributes));
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, format!("{} not found in the archive", path)))
+}
+
+/// Распаковывает блок записи с номером `block`.
+fn decode_block(payload: &[u8], header: &Header, record: &IndexRecord, block: usize, strict: bool) -> io::Result<Vec<u8>> {
+    decode_blob(entry_index::block_blob(payload, record, block), header, strict)
+}
+
+/// Читает диапазон байтов содержимого записи, не распаковывая архив целиком.
+///
+/// В блочном режиме распаковываются только блоки, покрывающие диапазон. Для архивов
+/// с целиком сжатыми записями распаковывается одна запись, для архивов с общим
+/// сжатым потоком — весь поток. Если запись встречается несколько раз, берется последняя.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов, содержащий архив с заголовком.
+/// * `path` - Путь к записи внутри архива.
+/// * `range` - Диапазон байтов; часть за концом файла отбрасывается.
+/// * `strict` - Строгий режим распаковки.
+///
+/// # Возвращает
+///
+/// Байты из диапазона или ошибку, если записи нет или архив поврежден.
+pub fn read_range(input: &[u8], path: &str, range: Range<u64>, strict: bool) -> io::Result<Vec<u8>> {
+    let (payload, header, record) = match locate_entry(input, path, strict)? {
+        Located::Data(data) => {
+            let end = range.end.min(data.len() as u64) as usize;
+            return Ok(data[(range.start as usize).min(end)..end].to_vec());
+        }
+        Located::Blocks { payload, header, record } => (payload, header, record),
+    };
+    let end = range.end.min(record.size);
+    if range.start >= end {
+        return Ok(Vec::new());
+    }
+    let (first, last, start) = entry_index::covering_blocks(&record, range.start..end);
+    let mut data = Vec::new();
+    for block in first..=last {
+        data.extend(decode_block(payload, &header, &record, block, strict)?);
+    }
+    let skip = (range.start - start) as usize;
+    let take = (end - range.start) as usize;
+    data.get(skip..skip + take).map(|d| d.to_vec())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "block decoded to fewer bytes than recorded"))
+}
+
+/// Передает содержимое записи по частям, пока `sink` не вернет `false`.
+///
+/// В блочном режиме блоки распаковываются по одному, поэтому чтение начала большого файла
+/// останавливается, не распаковывая остальные блоки. Иначе запись распаковывается целиком
+/// и передается одной частью.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов, содержащий архив с заголовком.
+/// * `path` - Путь к записи внутри архива.
+/// * `strict` - Строгий режим распаковки.
+/// * `sink` - Получатель очередной части; `false` прекращает распаковку.
+///
+/// # Возвращает
+///
+/// Результат операции или ошибку, если записи нет или архив поврежден.
+pub fn stream_entry(input: &[u8], path: &str, strict: bool, sink: &mut dyn FnMut(&[u8]) -> bool) -> io::Result<()> {
+    match locate_entry(input, path, strict)? {
+        Located::Data(data) => {
+            sink(&data);
+        }
+        Located::Blocks { payload, header, record, .. } => {
+            for block in 0..record.blocks.len() {
+                if !sink(&decode_block(payload, &header, &record, block, strict)?) {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Возвращает первые `lines` строк записи вместе с символами перевода строки.
+///
+/// Распаковка останавливается, как только набрано нужное число строк (см. `stream_entry`).
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов, содержащий архив с заголовком.
+/// * `path` - Путь к записи внутри архива.
+/// * `lines` - Количество строк.
+/// * `strict` - Строгий режим распаковки.
+///
+/// # Возвращает
+///
+/// Начало содержимого записи или ошибку, если записи нет или архив поврежден.
+pub fn read_head(input: &[u8], path: &str, lines: usize, strict: bool) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut remaining = lines;
+    stream_entry(input, path, strict, &mut |chunk| {
+        for (i, &byte) in chunk.iter().enumerate() {
+            if remaining == 0 {
+                output.extend_from_slice(&chunk[..i]);
+                return false;
+            }
+            if byte == b'\n' {
+                remaining -= 1;
+            }
+        }
+        output.extend_from_slice(chunk);
+        remaining > 0
+    })?;
+    Ok(output)
+}
+
+/// Передает содержимое файлов архива по частям, распаковывая записи по одной (`--grep`).
+///
+/// В блочном режиме блоки распаковываются по одному, записи, сжатые по отдельности, —
+/// каждая отдельно, а общий сжатый поток распаковывается целиком, как при `-d`. Из записей
+/// с одним путем (в склеенных архивах) передается последняя, а жесткая ссылка и дубликат
+/// передаются с содержимым своей исходной записи. Директории, символические ссылки
+/// и специальные файлы пропускаются.
+///
+/// # Аргументы
+///
+/// * `input` - Срез байтов, содержащий архив с заголовком.
+/// * `strict` - Строгий режим распаковки.
+/// * `select` - Условие отбора записей по пути и режиму.
+/// * `sink` - Получатель пути записи и очередной части ее содержимого. После всех частей
+///   записи он получает пустую часть; `false` прекращает передачу текущей записи.
+///
+/// # Возвращает
+///
+/// Результат операции или ошибку, если архив поврежден или содержит только индекс.
+pub fn stream_files(input: &[u8], strict: bool, select: &dyn Fn(&str, u32) -> bool,
+                    sink: &mut dyn FnMut(&str, &[u8]) -> bool) -> io::Result<()> {
+    if !header::has_magic(input) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an archive (missing RARC signature)"));
+    }
+    let reference = archive_io::HARD_LINK_FLAG | archive_io::DUPLICATE_FLAG;
+    let members = split_members(input, None, strict)?;
+    // Общие потоки распаковываются один раз, а путь запоминает участника с последней записью
+    let mut streams: Vec<Option<ArchiveData>> = Vec::new();
+    let mut latest: HashMap<String, usize> = HashMap::new();
+    for (i, (header, payload)) in members.iter().enumerate() {
+        let paths: Vec<String> = if header.per_entry() {
+            streams.push(None);
+            entry_index::read_index(payload, header)?.records.into_iter().map(|r| r.path).collect()
+        } else {
+            let decoded = decode_stream(payload, header, strict)?;
+            if archive::is_index(&decoded) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "index-only archive contains no file data"));
+            }
+            let archive = archive_io::bytes_to_archive_data(&decoded, strict)?;
+            let paths = archive.entries.iter().map(|e| e.path.clone()).collect();
+            streams.push(Some(archive));
+            paths
+        };
+        for path in paths {
+            latest.insert(path, i);
+        }
+    }
+
+    let wanted = |i: usize, path: &str, mode: u32| {
+        latest[path] == i && EntryType::from_mode(mode & !reference) == EntryType::File && select(path, mode & !reference)
+    };
+    // Передает части записи и завершающую пустую часть, если получатель не остановился раньше
+    let mut send = |path: &str, chunks: &mut dyn Iterator<Item = io::Result<Vec<u8>>>| -> io::Result<()> {
+        for chunk in chunks {
+            if !sink(path, &chunk?) {
+                return Ok(());
+            }
+        }
+        sink(path, &[]);
+        Ok(())
+    };
+    // Содержимое исходной записи ссылки или дубликата, найденной по всему архиву
+    let source = |target: &[u8]| -> io::Result<Vec<u8>> {
+        located_bytes(locate_entry(input, &String::from_utf8_lossy(target), strict)?, strict)
+    };
+    for (i, ((header, payload), stream)) in members.iter().zip(streams).enumerate() {
+        if let Some(archive) = stream {
+            let by_path: HashMap<&str, &DirEntry> = archive.entries.iter().map(|e| (e.path.as_str(), e)).collect();
+            for entry in archive.entries.iter().filter(|e| wanted(i, &e.path, e.permissions)) {
+                let data = if entry.permissions & reference == 0 {
+                    Ok(entry.data.clone())
+                } else {
+                    match by_path.get(String::from_utf8_lossy(&entry.data).as_ref()) {
+                        Some(original) if original.permissions & reference == 0 => Ok(original.data.clone()),
+                        _ => source(&entry.data),
+                    }
+                };
+                send(&entry.path, &mut std::iter::once(data))?;
+            }
+            continue;
+        }
+        for record in entry_index::read_index(payload, header)?.records {
+            if !wanted(i, &record.path, record.permissions) {
+                continue;
+            }
+            if record.permissions & reference != 0 {
+                let data = record_content(payload, header, &record, strict).and_then(|target| source(&target));
+                send(&record.path, &mut std::iter::once(data))?;
+            } else if header.blocks() {
+                let mut blocks = (0..record.blocks.len()).map(|block| decode_block(payload, header, &record, block, strict));
+                send(&record.path, &mut blocks)?;
+            } else {
+                let data = decode_record(payload, header, &record, strict).map(|entry| entry.data);
+                send(&record.path, &mut std::iter::once(data))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Распаковывает отобранные записи архива с центральным индексом.
+///
+/// # Возвращает
+///
+/// Записи в том же виде, что и `io::archive_data_to_bytes`, чтобы дальнейший разбор
+/// не зависел от способа сжатия.
+fn decode_entries(payload: &[u8], header: &Header, strict: bool, select: &dyn Fn(&str, u32) -> bool,
+                  threads: usize) -> io::Result<Vec<u8>> {
+    let index = entry_index::read_index(payload, header)?;
+    let records: Vec<_> = index.records.iter().filter(|r| select(&r.path, r.permissions)).collect();
+    // Отобранные записи одного сегмента распаковываются вместе, одним потоком
+    let units: Vec<_> = records
+        .chunk_by(|a, b| a.segment.is_some() && b.segment.is_some() && a.offset == b.offset)
+        .collect();
+    let decode_unit = |unit: &[&IndexRecord]| -> io::Result<Vec<DirEntry>> {
+        if partial::interrupted() {
+            return Err(partial::interrupted_error("decompression was stopped".to_owned()));
+        }
+        let record = unit[0];
+        if header.blocks() {
+            // Запись собирается из блоков, а путь, права, CRC32, владелец, метаданные и время изменения берутся из индекса
+            let mut data = Vec::with_capacity(record.size as usize);
+            for block in 0..record.blocks.len() {
+                data.extend(decode_blob(entry_index::block_blob(payload, record, block), header, strict)?);
+            }
+            Ok(vec![DirEntry {
+                path: record.path.clone(),
+                data,
+                permissions: record.permissions,
+                crc32: record.crc32,
+                owner: record.owner.clone(),
+                metadata: record.metadata.clone(),
+                mtime: record.mtime,
+            }])
+        } else {
+            let decoded = decode_blob(entry_index::entry_blob(payload, record), header, strict)?;
+            unit.iter().map(|r| parse_entry(entry_index::segment_bytes(&decoded, r)?, header)).collect()
+        }
+    };
+    // Записи независимы, поэтому распаковываются параллельно так же, как сжимаются
+    let threads = threads.clamp(1, units.len().max(1));
+    log::debug!("decoding {} entries, threads: {}", records.len(), threads);
+    let entries = if threads > 1 {
+        let chunk_size = units.len().div_ceil(threads).max(1);
+        thread::scope(|s| {
+            let handles: Vec<_> = units.chunks(chunk_size)
+                .map(|chunk| s.spawn(move || chunk.iter().map(|unit| decode_unit(unit)).collect::<io::Result<Vec<_>>>()))
+                .collect();
+            handles.into_iter()
+                .map(|h| h.join().expect("Thread failed"))
+                .collect::<io::Result<Vec<_>>>()
+                .map(|chunks| chunks.concat().concat())
+        })?
+    } else {
+        units.iter().map(|unit| decode_unit(unit)).collect::<io::Result<Vec<_>>>()?.concat()
+    };
+    Ok(archive_io::archive_data_to_bytes(&ArchiveData { entries }))
+}
+
+/// Распаковывает сжатую запись, блок или сегмент архива с посжатыми записями.
+///
+/// Начиная с версии 7 первый байт определяет способ хранения (`BLOB_COMPRESSED` или
+/// `BLOB_STORED`); в прежних версиях данные всегда сжаты.
+fn decode_blob(blob: &[u8], header: &Header, strict: bool) -> io::Result<Vec<u8>> {
+    if !header.stored_blobs() {
+        return decode(blob, header.algorithm.clone(), header.short_matches(), header.byte_order(), strict);
+    }
+    match blob.split_first() {
+        Some((&BLOB_COMPRESSED, data)) => decode(data, header.algorithm.clone(), header.short_matches(), header.byte_order(), strict),
+        Some((&BLOB_STORED, data)) => Ok(data.to_vec()),
+        Some((method, _)) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown storage method {}", method))),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "compressed data is empty")),
+    }
+}
+
+/// Распаковывает общий сжатый поток архива, восстанавливая повторы блоков (`FLAG_CHUNKED`).
+fn decode_stream(payload: &[u8], header: &Header, strict: bool) -> io::Result<Vec<u8>> {
+    let decoded = decode(payload, header.algorithm.clone(), header.short_matches(), header.byte_order(), strict)?;
+    if header.chunked() {
+        chunking::reassemble(&decoded)
+    } else {
+        Ok(decoded)
+    }
+}
+
+/// Разбирает распакованную запись в формате, соответствующем версии архива.
+fn parse_entry(bytes: &[u8], header: &Header) -> io::Result<DirEntry> {
+    if header.varint_lengths() {
+        archive_io::bytes_to_dir_entry(bytes)
+    } else {
+        archive_io::bytes_to_dir_entry_legacy(bytes)
+    }
+}
+
+/// Распаковывает данные без заголовка выбранным алгоритмом.
+fn decode(input: &[u8], algorithm: Algorithm, short_matches: bool, order: ByteOrder, strict: bool) -> io::Result<Vec<u8>> {
+    match algorithm {
+        Algorithm::Rle => rle::decompress(input, strict),
+        Algorithm::Lz77 => lz77::decompress(input, short_matches, strict),
+        Algorithm::Lz4 => lz4::decompress(input),
+        Algorithm::Lzw => lzw::decompress(input, strict),
+        Algorithm::Hf => huffman::decompress_ordered(input, order, strict),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_algorithm_from_header() {
+        let data = b"abracadabra abracadabra abracadabra".to_vec();
+        for algorithm in Algorithm::ALL.iter() {
+            let compressed = compress(&data, algorithm.clone(), false, true);
+            assert_eq!(decompress(&compressed, None, false, false, true).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_rejects_non_archive() {
+        assert!(decompress(b"plain text file", None, false, false, false).is_err());
+        let legacy = encode(b"aaaa", Algorithm::Rle, false, false);
+        assert_eq!(decompress(&legacy, Some(Algorithm::Rle), false, false, true).unwrap(), b"aaaa");
+    }
+
+    #[test]
+    fn test_concatenated_members() {
+        let mut data = compress(b"first ", Algorithm::Lzw, false, false);
+        data.extend(compress(b"second", Algorithm::Hf, false, false));
+        assert_eq!(decompress(&data, None, false, false, true).unwrap(), b"first second");
+        data.extend_from_slice(b"junk");
+        assert!(decompress(&data, None, false, false, true).is_err());
+        assert_eq!(decompress(&data, None, false, false, false).unwrap(), b"first second");
+    }
+
+    #[test]
+    fn test_per_entry_selection() {
+        let entries: Vec<DirEntry> = ["a.txt", "b.txt", "c.bin"].iter().map(|p| DirEntry {
+            path: p.to_string(),
+            data: p.repeat(20).into_bytes(),
+            permissions: 0o100644,
+            crc32: None,
+            owner: None,
+            metadata: BTreeMap::new(),
+            mtime: None,
+        }).collect();
+        for use_multithreading in [false, true] {
+            let compressed = compress_entries(&entries, Algorithm::Lz77, use_multithreading, true, None, None);
+            let all = archive_io::bytes_to_archive_data(&decompress(&compressed, None, false, false, true).unwrap(), true).unwrap();
+            assert_eq!(all.entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), ["a.txt", "b.txt", "c.bin"]);
+            assert_eq!(all.entries[2].data, entries[2].data);
+
+            let selected = decompress_selected(&compressed, None, 1, false, true, &|path, _| path.ends_with(".txt")).unwrap();
+            let selected = archive_io::bytes_to_archive_data(&selected, true).unwrap();
+            assert_eq!(selected.entries.len(), 2);
+            assert_eq!(selected.entries[1].data, entries[1].data);
+        }
+    }
+
+    #[test]
+    fn test_packed_segments() {
+        let mut entries: Vec<DirEntry> = (0..200).map(|i| DirEntry {
+            path: format!("node_modules/pkg{}/index.js", i),
+            data: format!("module.exports = require('./lib/pkg{}');\n", i).into_bytes(),
+            permissions: 0o100644,
+            crc32: None,
+            owner: None,
+            metadata: BTreeMap::new(),
+            mtime: None,
+        }).collect();
+        entries.insert(100, DirEntry { path: "big.bin".into(), data: vec![7; 5000], ..entries[0].clone() });
+        let plain = compress_entries(&entries, Algorithm::Lz77, false, true, None, None);
+        // Контрольные суммы вычисляются при сжатии и сохраняются вместе с записями
+        let mut checked = entries.clone();
+        archive_io::fill_checksums(&mut checked);
+        for use_multithreading in [false, true] {
+            let packed = compress_entries(&entries, Algorithm::Lz77, use_multithreading, true, None, Some(1024));
+            assert!(Header::parse(&packed).unwrap().segments());
+            assert!(packed.len() < plain.len() / 2, "{} vs {}", packed.len(), plain.len());
+            let mut all = archive_io::bytes_to_archive_data(&decompress(&packed, None, use_multithreading, false, true).unwrap(), true).unwrap();
+            // Крупный файл записывается сразу, а мелкие — сегментом после него
+            assert_eq!(all.entries[0].path, "big.bin");
+            all.entries.sort_by(|a, b| a.path.cmp(&b.path));
+            let mut expected = checked.clone();
+            expected.sort_by(|a, b| a.path.cmp(&b.path));
+            assert_eq!(all.entries, expected);
+
+            let selected = decompress_selected(&packed, None, 1, false, true, &|path, _| path.contains("pkg42/")).unwrap();
+            assert_eq!(archive_io::bytes_to_archive_data(&selected, true).unwrap().entries, vec![checked[42].clone()]);
+            assert_eq!(read_range(&packed, "node_modules/pkg7/index.js", 0..6, true).unwrap(), b"module");
+            assert_eq!(salvage(&packed).entries.len(), entries.len());
+        }
+    }
+
+    #[test]
+    fn test_packed_index() {
+        let entries: Vec<DirEntry> = (0..1000).map(|i| DirEntry {
+            path: format!("var/log/archive/2024/app-{:04}.log", i),
+            data: format!("{}\n", i).into_bytes(),
+            permissions: 0o100644,
+            crc32: None,
+            owner: None,
+            metadata: BTreeMap::new(),
+            mtime: None,
+        }).collect();
+        for block_size in [None, Some(4096)] {
+            // Индекс посжатого архива сжимается LZ4 независимо от алгоритма данных
+            let compressed = compress_entries(&entries, Algorithm::Hf, false, false, block_size, None);
+            let (header, payload) = split_members(&compressed, None, true).unwrap().remove(0);
+            assert!(header.packed_index(), "{:?}", block_size);
+            let index = entry_index::read_index(payload, &header).unwrap();
+            assert_eq!(index.records.len(), entries.len());
+            let index_offset = u64::from_le_bytes(payload[payload.len() - 8..].try_into().unwrap()) as usize;
+            assert!(payload.len() - 8 - index_offset < index.to_bytes().len() / 2, "{:?}", block_size);
+            assert_eq!(list(&compressed, true).unwrap()[999].path, "var/log/archive/2024/app-0999.log");
+        }
+        // Индекс есть только у посжатых архивов
+        let solid = compress(&archive_io::archive_data_to_bytes(&ArchiveData { entries }), Algorithm::Lz4, false, false);
+        assert!(!Header::parse(&solid).unwrap().packed_index());
+    }
+
+    #[test]
+    fn test_checksums_while_compressing() {
+        let entries: Vec<DirEntry> = (0..50).map(|i| DirEntry {
+            path: format!("dir/file{}.txt", i),
+            data: format!("contents of file {}\n", i).repeat(i * 40).into_bytes(),
+            permissions: 0o100644,
+            crc32: None,
+            owner: None,
+            metadata: BTreeMap::new(),
+            mtime: None,
+        }).collect();
+        let layouts = [
+            CompressionOptions::new(Algorithm::Lz4),
+            CompressionOptions::new(Algorithm::Lz4).pack_below(Some(2048)),
+            CompressionOptions::new(Algorithm::Lz4).block_size(Some(4096)),
+            CompressionOptions::new(Algorithm::Lz4).solid(true),
+        ];
+        for options in layouts {
+            for use_multithreading in [false, true] {
+                let options = options.clone().multithreading(use_multithreading);
+                // Записи без контрольных сумм получают их в задачах сжатия, в том числе на разных потоках
+                let compressed = compress_archive(ArchiveData { entries: entries.clone() }, &options);
+                let restored = archive_io::bytes_to_archive_data(&decompress(&compressed, None, true, false, true).unwrap(), true).unwrap();
+                assert_eq!(restored.entries.len(), entries.len(), "{}", options);
+                for e in &restored.entries {
+                    assert_eq!(e.crc32, Some(checksum::crc32(&e.data)), "{} {}", options, e.path);
+                }
+                assert!(test_archive(&compressed, true).unwrap().is_ok(), "{}", options);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_range() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let entries = vec![DirEntry { path: "big.bin".into(), data: data.clone(), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None }];
+        for block_size in [Some(1000), None] {
+            let compressed = compress_entries(&entries, Algorithm::Lz4, false, false, block_size, None);
+            assert_eq!(read_range(&compressed, "big.bin", 1500..2600, true).unwrap(), &data[1500..2600]);
+            assert_eq!(read_range(&compressed, "big.bin", 4990..9000, true).unwrap(), &data[4990..]);
+            assert!(read_range(&compressed, "big.bin", 6000..7000, true).unwrap().is_empty());
+            assert!(read_range(&compressed, "missing", 0..1, true).is_err());
+            let all = archive_io::bytes_to_archive_data(&decompress(&compressed, None, false, false, true).unwrap(), true).unwrap();
+            assert_eq!(all.entries[0].data, data);
+            assert!(all.entries[0].verify().is_ok());
+        }
+        let solid = compress(&archive_io::archive_data_to_bytes(&ArchiveData { entries }), Algorithm::Rle, false, false);
+        assert_eq!(read_range(&solid, "big.bin", 10..20, true).unwrap(), &data[10..20]);
+
+        // Случайное начало делится на мелкие блоки, а повторяющийся хвост — на крупные
+        let mut state = 7u32;
+        let mut mixed: Vec<u8> = (0..8192).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        mixed.extend(b"backup ".repeat(4000));
+        let entries = vec![DirEntry { path: "mixed.bin".into(), data: mixed.clone(), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None }];
+        let compressed = compress_entries(&entries, Algorithm::Lz4, false, false, Some(4096), None);
+        let members = split_members(&compressed, None, true).unwrap();
+        let (header, payload) = &members[0];
+        let index = entry_index::read_index(payload, header).unwrap();
+        assert_eq!(index.block_size, 4096);
+        let lengths = &index.records[0].block_lengths;
+        assert_eq!(lengths.iter().sum::<u64>(), mixed.len() as u64);
+        assert_eq!((lengths[0], *lengths.iter().max().unwrap()), (1024, 16384));
+        for range in [0..10, 1000..1100, 8000..9000, 8192..30_000, 20_000..40_000] {
+            assert_eq!(read_range(&compressed, "mixed.bin", range.clone(), true).unwrap(), &mixed[range.start as usize..(range.end as usize).min(mixed.len())]);
+        }
+        assert!(test_archive(&compressed, true).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_read_head() {
+        let text: Vec<u8> = (0..200).flat_map(|i| format!("line {}\n", i).into_bytes()).collect();
+        let entries = vec![DirEntry { path: "log".into(), data: text.clone(), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None }];
+        for block_size in [Some(16), None] {
+            let compressed = compress_entries(&entries, Algorithm::Lzw, false, false, block_size, None);
+            assert_eq!(read_head(&compressed, "log", 3, true).unwrap(), b"line 0\nline 1\nline 2\n");
+            assert!(read_head(&compressed, "log", 0, true).unwrap().is_empty());
+            assert_eq!(read_head(&compressed, "log", 1000, true).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn test_salvage() {
+        let entries: Vec<DirEntry> = ["a.txt", "b.txt", "c.txt"].iter().map(|p| DirEntry {
+            path: p.to_string(),
+            data: p.repeat(50).into_bytes(),
+            permissions: 0o100644,
+            crc32: Some(checksum::crc32(p.repeat(50).as_bytes())),
+            owner: None,
+            metadata: BTreeMap::new(),
+            mtime: None,
+        }).collect();
+        let first = compress_entries(&entries, Algorithm::Rle, false, false, None, None);
+        let second = compress(&archive_io::archive_data_to_bytes(&ArchiveData { entries: entries[..1].to_vec() }), Algorithm::Lz4, false, false);
+
+        // Поврежден первый блок первого архива и заголовок второго архива
+        let mut input = first.clone();
+        input[header::HEADER_LEN + 60] ^= 0xFF;
+        input.extend_from_slice(b"garbage");
+        input.extend(&second);
+        input.extend(&second);
+        let damaged = input.len() - second.len();
+        input[damaged + 4] = 99;
+        assert!(decompress(&input, None, false, false, false).is_err());
+
+        let report = salvage(&input);
+        let paths: Vec<&str> = report.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["b.txt", "c.txt", "a.txt"]);
+        assert!(report.lost.iter().any(|l| l.contains("a.txt")));
+        assert!(report.lost.iter().any(|l| l.contains("garbage") || l.contains("unrecognized")));
+        assert!(report.lost.iter().any(|l| l.contains("damaged archive header")));
+    }
+
+    #[test]
+    fn test_compact() {
+        let entry = |path: &str, text: &str| DirEntry {
+            path: path.to_owned(),
+            data: text.repeat(30).into_bytes(),
+            permissions: 0o100644,
+            crc32: None,
+            owner: None,
+            metadata: BTreeMap::new(),
+            mtime: None,
+        };
+        let paths = |data: &[u8]| -> Vec<(String, Vec<u8>)> {
+            let archive = archive_io::bytes_to_archive_data(&decompress(data, None, false, false, true).unwrap(), true).unwrap();
+            archive.entries.into_iter().map(|e| (e.path, e.data)).collect()
+        };
+        let old = vec![entry("a", "old a "), entry("b", "b ")];
+        let new = vec![entry("a", "new a "), entry("c", "c ")];
+        let expected = vec![
+            ("b".to_owned(), old[1].data.clone()),
+            ("a".to_owned(), new[0].data.clone()),
+            ("c".to_owned(), new[1].data.clone()),
+        ];
+        for (block_size, pack_below) in [(None, None), (Some(64), None), (None, Some(1000))] {
+            let mut input = compress_entries(&old, Algorithm::Lz77, false, true, block_size, pack_below);
+            input.extend(compress_entries(&new, Algorithm::Lz77, false, true, block_size, pack_below));
+            let compacted = compact(&input, false, true).unwrap();
+            assert!(compacted.len() < input.len());
+            assert_eq!(split_members(&compacted, None, true).unwrap().len(), 1);
+            assert_eq!(paths(&compacted), expected);
+        }
+
+        // Архивы в разных режимах сжимаются заново режимом последнего архива
+        let mut mixed = compress(&archive_io::archive_data_to_bytes(&ArchiveData { entries: old }), Algorithm::Rle, false, false);
+        mixed.extend(compress_entries(&new, Algorithm::Hf, false, false, Some(50), None));
+        let compacted = compact(&mixed, false, true).unwrap();
+        let members = split_members(&compacted, None, true).unwrap();
+        assert_eq!(members.len(), 1);
+        assert!(members[0].0.blocks() && members[0].0.algorithm == Algorithm::Hf);
+        assert_eq!(paths(&compacted), expected);
+    }
+
+    #[test]
+    fn test_replace_entry() {
+        let entry = |path: &str, data: &[u8], permissions: u32| DirEntry {
+            path: path.to_owned(),
+            data: data.to_vec(),
+            permissions,
+            crc32: None,
+            owner: None,
+            metadata: BTreeMap::from([("kind".to_owned(), path.to_owned())]),
+            mtime: None,
+        };
+        let config = b"setting = 1\n".repeat(20);
+        let mut entries = vec![
+            entry("etc/app.conf", &config, 0o100600),
+            entry("etc/copy.conf", b"etc/app.conf", 0o100644 | archive_io::DUPLICATE_FLAG),
+            entry("bin/tool", &b"tool ".repeat(100), 0o100755),
+        ];
+        archive_io::fill_checksums(&mut entries);
+        for (solid, block_size) in [(false, None), (false, Some(64)), (true, None)] {
+            let archive = if solid {
+                compress(&archive_io::archive_data_to_bytes(&ArchiveData { entries: entries.clone() }), Algorithm::Lz4, false, false)
+            } else {
+                compress_entries(&entries, Algorithm::Lz4, false, false, block_size, None)
+            };
+            let replaced = replace_entry(&archive, "etc/app.conf", b"setting = 2\n", false, true).unwrap();
+            assert_eq!(split_members(&replaced, None, true).unwrap().len(), 1);
+            assert_eq!(read_range(&replaced, "etc/app.conf", 0..u64::MAX, true).unwrap(), b"setting = 2\n");
+            // Дубликат прежнего содержимого остается с ним
+            assert_eq!(read_range(&replaced, "etc/copy.conf", 0..u64::MAX, true).unwrap(), config);
+            let restored = archive_io::bytes_to_archive_data(&decompress(&replaced, None, false, false, true).unwrap(), true).unwrap();
+            let app = restored.entries.iter().find(|e| e.path == "etc/app.conf").unwrap();
+            assert_eq!((app.permissions, app.metadata["kind"].as_str()), (0o100600, "etc/app.conf"));
+            assert!(restored.entries.iter().all(|e| e.duplicate_of().is_none() && e.verify().is_ok()));
+            assert_eq!(restored.entries.len(), 3);
+            assert!(replace_entry(&archive, "missing", b"", false, true).is_err());
+        }
+    }
+
+    #[test]
+    fn test_changed_entries() {
+        let entry = |path: &str, text: &str, permissions: u32, mtime: u64| DirEntry {
+            path: path.to_owned(),
+            data: text.repeat(20).into_bytes(),
+            permissions,
+            crc32: None,
+            owner: None,
+            metadata: BTreeMap::new(),
+            mtime: Some(mtime),
+        };
+        let link = DirEntry { data: b"a".to_vec(), permissions: 0o100644 | archive_io::HARD_LINK_FLAG, ..entry("l", "", 0, 100) };
+        let mut stored = vec![entry("a", "a ", 0o100644, 100), entry("b", "b ", 0o100644, 100), entry("c", "c ", 0o100644, 100),
+            entry("e", "e ", 0o100644, 100), entry("f", "f ", 0o100644, 100), entry("g", "g ", 0o100644, 100), link.clone()];
+        // Архив старой версии без времени изменения
+        stored[5].mtime = None;
+        let current = vec![entry("a", "a ", 0o100644, 100), entry("b", "new b ", 0o100644, 200), entry("c", "c ", 0o100755, 100),
+            entry("d", "d ", 0o100644, 200), entry("e", "E ", 0o100644, 100), entry("f", "f ", 0o100644, 200),
+            entry("g", "g ", 0o100644, 100), link];
+        for solid in [false, true] {
+            let mut archive = if solid {
+                compress(&archive_io::archive_data_to_bytes(&ArchiveData { entries: stored.clone() }), Algorithm::Lz77, false, false)
+            } else {
+                compress_entries(&stored, Algorithm::Lz77, false, false, None, None)
+            };
+            // По размеру и времени изменения не видна правка без изменения размера (e),
+            // а по содержимому — новое время изменения (f)
+            let paths = |changed: Vec<DirEntry>| changed.into_iter().map(|e| e.path).collect::<Vec<_>>();
+            assert_eq!(paths(changed_entries(&archive, current.clone(), false, true).unwrap()), ["b", "c", "d", "f", "g"]);
+            assert_eq!(paths(changed_entries(&archive, current.clone(), true, true).unwrap()), ["b", "c", "d", "e"]);
+            // После дописывания измененных записей архив совпадает с текущим деревом
+            let changed = changed_entries(&archive, current.clone(), false, true).unwrap();
+            archive.extend(compress_entries(&changed, Algorithm::Lz77, false, false, None, None));
+            assert_eq!(paths(changed_entries(&archive, current.clone(), false, true).unwrap()), Vec::<String>::new());
+        }
+        let index = compress(&ArchiveIndex::from_archive(&ArchiveData { entries: stored }).to_bytes(), Algorithm::Lz77, false, false);
+        assert!(changed_entries(&index, current, false, true).is_err());
+    }
+
+    #[test]
+    fn test_test_archive() {
+        let mut entries = vec![
+            DirEntry { path: "a.txt".into(), data: b"alpha ".repeat(50), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None },
+            DirEntry { path: "b.txt".into(), data: b"beta ".repeat(50), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None },
+        ];
+        archive_io::fill_checksums(&mut entries);
+        let solid = compress(&archive_io::archive_data_to_bytes(&ArchiveData { entries: entries.clone() }), Algorithm::Lz4, false, false);
+        let report = test_archive(&solid, true).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.entries.len(), 2);
+
+        // Испорченная первая запись не мешает проверить вторую
+        let mut damaged = compress_entries(&entries, Algorithm::Rle, false, false, None, None);
+        let alpha = damaged.windows(4).position(|w| w == b"alph").unwrap();
+        damaged[alpha] ^= 0x55;
+        assert!(audit(&damaged, AuditDepth::Standard, true).is_err());
+        let report = test_archive(&damaged, false).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.damaged, vec!["archive: SHA-256 mismatch"]);
+        assert_eq!(report.entries.iter().map(|c| (c.path.as_str(), c.is_ok())).collect::<Vec<_>>(), [("a.txt", false), ("b.txt", true)]);
+        assert!(test_archive(b"not an archive", true).is_err());
+    }
+
+    #[test]
+    fn test_stored_fallback() {
+        let mut state = 99u32;
+        let noise: Vec<u8> = (0..50_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        let entries = vec![
+            DirEntry { path: "noise.bin".into(), data: noise.clone(), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None },
+            DirEntry { path: "text.txt".into(), data: b"text ".repeat(1000), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None },
+        ];
+        for block_size in [None, Some(4096)] {
+            // RLE удвоил бы случайные данные, а сохраненные как есть почти не растут
+            let archive = compress_entries(&entries, Algorithm::Rle, false, false, block_size, None);
+            assert!(archive.len() < noise.len() + entries[1].data.len() + 1000, "{} bytes", archive.len());
+            assert_eq!(read_range(&archive, "noise.bin", 100..200, true).unwrap(), &noise[100..200]);
+            let restored = archive_io::bytes_to_archive_data(&decompress(&archive, None, true, false, true).unwrap(), true).unwrap();
+            assert_eq!(restored.entries[0].data, noise);
+            assert_eq!(restored.entries[1].data, entries[1].data);
+        }
+        let header = Header { version: header::FORMAT_VERSION, algorithm: Algorithm::Rle, flags: header::FLAG_PER_ENTRY, layout: header::LAYOUT_LITTLE_ENDIAN, payload_len: 0 };
+        assert!(decode_blob(&[7], &header, true).is_err());
+        assert!(decode_blob(&[], &header, true).is_err());
+    }
+
+    #[test]
+    fn test_chunked_stream() {
+        let mut state = 7u32;
+        let image: Vec<u8> = (0..100_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        let mut dump = b"header v2\n".to_vec();
+        dump.extend_from_slice(&image);
+        let entries = vec![
+            DirEntry { path: "image.v1".into(), data: image, permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None },
+            DirEntry { path: "image.v2".into(), data: dump.clone(), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None },
+        ];
+        let serialized = archive_io::archive_data_to_bytes(&ArchiveData { entries });
+        let archive = compress_chunked(&serialized, Algorithm::Lz4, false, false);
+        assert!(archive.len() * 3 < compress(&serialized, Algorithm::Lz4, false, false).len() * 2);
+        assert_eq!(decompress(&archive, None, true, false, true).unwrap(), serialized);
+
+        // Замена записи и уплотнение сохраняют режим потока
+        let replaced = replace_entry(&archive, "image.v1", b"gone", false, true).unwrap();
+        assert!(split_members(&replaced, None, true).unwrap()[0].0.chunked());
+        assert_eq!(read_range(&replaced, "image.v2", 0..u64::MAX, true).unwrap(), dump);
+    }
+
+    #[test]
+    fn test_solid_frames() {
+        let mut state = 3u32;
+        let image: Vec<u8> = (0..100_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        // Копии, не отмеченные дубликатами (например, из потока tar), дальше окна алгоритма сжатия
+        let entry = |path: &str, data: &[u8]| DirEntry { path: path.into(), data: data.to_vec(), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None };
+        let archive = ArchiveData { entries: vec![entry("a/image", &image), entry("b/note", b"note"), entry("c/image", &image)] };
+        let compressed = compress_archive(archive.clone(), &CompressionOptions::new(Algorithm::Lz4).solid(true));
+        assert!(split_members(&compressed, None, true).unwrap()[0].0.chunked());
+        let serialized = archive_io::archive_data_to_bytes(&archive);
+        assert!(compressed.len() * 3 < compress(&serialized, Algorithm::Lz4, false, false).len() * 2);
+        let restored = archive_io::bytes_to_archive_data(&decompress(&compressed, None, false, false, true).unwrap(), true).unwrap();
+        assert_eq!(restored.entries.iter().map(|e| &e.data).collect::<Vec<_>>(), [&image, &b"note".to_vec(), &image]);
+
+        // Без повторов кадров общий поток записывается как раньше
+        let single = ArchiveData { entries: vec![entry("a/image", &image)] };
+        let plain = compress_archive(single, &CompressionOptions::new(Algorithm::Lz4).solid(true));
+        assert!(!split_members(&plain, None, true).unwrap()[0].0.chunked());
+    }
+
+    #[test]
+    fn test_archive_info() {
+        let entries = vec![DirEntry { path: "a".into(), data: b"abcabcabc".to_vec(), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None }];
+        let info = ArchiveInfo { created: 1790000000, hostname: "host".into(), tool_version: "test 1".into(), comment: Some("note".into()), options: None, decode_rate: None, selection: None };
+        let plain = compress_entries(&entries, Algorithm::Lz77, false, true, None, None);
+        let archive = set_info(&plain, &info).unwrap();
+        assert!(Header::parse(&archive).unwrap().short_matches());
+        assert_eq!(decompress(&archive, None, false, false, true).unwrap(), decompress(&plain, None, false, false, true).unwrap());
+        assert_eq!(read_range(&archive, "a", 3..6, true).unwrap(), b"abc");
+        assert_eq!(salvage(&archive).entries.len(), 1);
+
+        let mut appended = archive.clone();
+        appended.extend(&plain);
+        let summary = describe(&appended, true).unwrap();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[0].info, Some(info.clone()));
+        assert_eq!((summary[0].entries, summary[1].info.as_ref()), (Some(1), None));
+        assert_eq!(summary[0].original_size, Some(9));
+        assert!(set_info(&appended, &info).is_err());
+        assert_eq!(describe(&compact(&appended, false, true).unwrap(), true).unwrap()[0].info, Some(info));
+
+        // Скорость измеряется на выборке в `RATE_SAMPLE` байтов, а по нескольким байтам не измеряется
+        let options = CompressionOptions::new(Algorithm::Lz4);
+        let large = vec![DirEntry { data: b"restore rate sample ".repeat(RATE_SAMPLE / 10), ..entries[0].clone() }];
+        assert!(measure_decode_rate(&large, &options).is_some_and(|rate| rate > 0));
+        assert_eq!(measure_decode_rate(&entries, &options), None);
+        assert_eq!(measure_decode_rate(&[], &options), None);
+    }
+
+    #[test]
+    fn test_unknown_header_fields() {
+        // Архив более новой версии с полями, которых эта версия не знает
+        let entries = vec![DirEntry { path: "a".into(), data: b"newer archive".to_vec(), permissions: 0o100644, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None }];
+        let plain = compress_entries(&entries, Algorithm::Lz4, false, false, None, None);
+        let header = Header::parse(&plain).unwrap();
+        let body = &plain[header.encoded_len()..plain.len() - header::DIGEST_LEN];
+        let with_fields = |fields: &[header::Field]| {
+            let mut region = header::write_fields(fields);
+            region.extend_from_slice(&body[header::read_fields(body, header::KNOWN_FIELDS).unwrap().1..]);
+            let mut archive = Header { payload_len: region.len() as u64, ..Header::parse(&plain).unwrap() }.to_bytes();
+            archive.extend_from_slice(&region);
+            archive.extend_from_slice(&crypto::digest(&region));
+            archive
+        };
+        let optional = with_fields(&[header::Field { kind: 1 << 20, value: vec![0xAB; 40] }]);
+        assert_eq!(decompress(&optional, None, false, false, true).unwrap(), decompress(&plain, None, false, false, true).unwrap());
+        assert_eq!(list(&optional, true).unwrap().len(), 1);
+        let critical = with_fields(&[header::Field { kind: (1 << 20) | header::FIELD_CRITICAL, value: vec![] }]);
+        assert!(decompress(&critical, None, false, false, true).unwrap_err().to_string().contains("unknown to this version"));
+    }
+
+    #[test]
+    fn test_list() {
+        let entry = |path: &str, permissions, data: &[u8]| DirEntry { path: path.into(), data: data.to_vec(), permissions, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None };
+        let entries = vec![
+            entry("a.txt", 0o100644, b"hello world"),
+            entry("copy.txt", 0o100600 | archive_io::DUPLICATE_FLAG, b"a.txt"),
+            entry("hard", 0o100644 | archive_io::HARD_LINK_FLAG, b"a.txt"),
+            entry("soft", 0o120777, b"a.txt"),
+        ];
+        let listed = |path: &str, permissions, size, link: Option<&str>| ListedEntry { path: path.into(), permissions, size, link: link.map(String::from), crc32: None, mtime: None };
+        let expected = vec![
+            listed("a.txt", 0o100644, 11, None),
+            listed("copy.txt", 0o100600, 11, None),
+            listed("hard", 0o100644, 11, Some("a.txt")),
+            listed("soft", 0o120777, 5, Some("a.txt")),
+        ];
+        let separate = compress_entries(&entries, Algorithm::Lz4, false, false, None, None);
+        assert_eq!(list(&separate, true).unwrap(), expected);
+        let solid = compress(&archive_io::archive_data_to_bytes(&ArchiveData { entries: entries.clone() }), Algorithm::Rle, false, false);
+        assert_eq!(list(&solid, true).unwrap(), expected);
+        assert!(list_sizes(&separate, true).unwrap().iter().all(|(_, compressed)| compressed.is_some_and(|size| size > 0)));
+        assert!(list_sizes(&solid, true).unwrap().iter().all(|(_, compressed)| compressed.is_none()));
+        // Упакованные записи делят сжатый размер сегмента
+        let packed = compress_entries(&entries, Algorithm::Lz4, false, false, None, Some(1024));
+        let sizes = list_sizes(&packed, true).unwrap();
+        let payload = split_members(&packed, None, true).unwrap()[0].1.len() as u64;
+        assert!(sizes.iter().map(|(_, compressed)| compressed.unwrap()).sum::<u64>() <= payload);
+
+        // Запись из дописанного архива заменяет прежнюю на ее месте
+        let mut appended = separate.clone();
+        appended.extend(compress_entries(&[entry("a.txt", 0o100755, b"hi")], Algorithm::Rle, false, false, Some(4), None));
+        assert_eq!(list(&appended, true).unwrap()[0], ListedEntry { crc32: Some(checksum::crc32(b"hi")), ..listed("a.txt", 0o100755, 2, None) });
+        // В блочном режиме CRC32 хранится в индексе, а ссылки и дубликаты показываются с CRC32 исходной записи
+        let blocks = compress_entries(&entries, Algorithm::Lz4, false, false, Some(4), None);
+        let sums: Vec<Option<u32>> = list(&blocks, true).unwrap().iter().map(|e| e.crc32).collect();
+        assert_eq!(&sums[..3], [Some(checksum::crc32(b"hello world")); 3]);
+    }
+
+    #[test]
+    fn test_audit_depths() {
+        let entry = |path: &str, crc32| DirEntry { path: path.into(), data: b"audited ".repeat(40), permissions: 0o100644, crc32, owner: None, metadata: BTreeMap::new(), mtime: None };
+        let good = compress_entries(&[entry("a", None), entry("b", None)], Algorithm::Lz77, false, false, None, None);
+        assert_eq!(audit(&good, AuditDepth::Deep, true).unwrap(), AuditReport { members: 1, digests: 1, entries: 2 });
+
+        // Поврежденные сжатые данные находит проверка хешей, но не быстрая проверка
+        let mut damaged = good.clone();
+        damaged[header::HEADER_LEN + 2] ^= 0x40;
+        assert_eq!(audit(&damaged, AuditDepth::Quick, true).unwrap().entries, 2);
+        assert!(audit(&damaged, AuditDepth::Standard, true).is_err());
+
+        // Неверную сумму записи при верном хеше архива находит только распаковка
+        let wrong = compress(&archive_io::archive_data_to_bytes(&ArchiveData { entries: vec![entry("c", Some(1))] }), Algorithm::Rle, false, false);
+        assert_eq!(audit(&wrong, AuditDepth::Standard, true).unwrap(), AuditReport { members: 1, digests: 1, entries: 0 });
+        assert!(audit(&wrong, AuditDepth::Deep, true).unwrap_err().to_string().contains("CRC32"));
+    }
+
+    #[test]
+    fn test_detects_corruption_before_decoding() {
+        let data = compress(b"some data to protect", Algorithm::Lz4, false, false);
+        assert!(decompress(&data[..data.len() - 1], None, false, false, false).is_err());
+        let mut damaged = data.clone();
+        damaged[header::HEADER_LEN] ^= 0x40;
+        let err = decompress(&damaged, None, false, false, false).unwrap_err();
+        assert!(err.to_string().contains("SHA-256"));
+    }
+}
@@ -0,0 +1,274 @@
+//! Модуль для отслеживания хода длительных операций.
+//!
+//! Сжатие и распаковка отмечают обработанные записи (`advance`) в глобальных счетчиках,
+//! которые почти ничего не стоят, если за ходом никто не следит. С `--status-file`
+//! фоновый поток (`StatusWriter`) раз в интервал перезаписывает файл состояния: операцию,
+//! текущую запись, число обработанных записей и байтов, прошедшее время и оценку
+//! оставшегося. Файл заменяется переименованием, поэтому система мониторинга всегда
+//! читает его целиком, а не в момент записи. С `--progress json` тот же поток выводит
+//! состояние в stderr событиями — объектами JSON по одному на строку.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::json;
+
+/// Число обработанных записей.
+static ENTRIES_DONE: AtomicU64 = AtomicU64::new(0);
+/// Число обработанных байтов исходных данных.
+static BYTES_DONE: AtomicU64 = AtomicU64::new(0);
+/// Общее число записей операции.
+static ENTRIES_TOTAL: AtomicU64 = AtomicU64::new(0);
+/// Общий объем исходных данных операции.
+static BYTES_TOTAL: AtomicU64 = AtomicU64::new(0);
+/// Путь последней обработанной записи.
+static CURRENT: Mutex<String> = Mutex::new(String::new());
+
+/// Начинает отсчет новой стадии с известным объемом работы.
+///
+/// # Аргументы
+///
+/// * `entries` - Общее число записей.
+/// * `bytes` - Общий объем данных записей в байтах.
+pub fn begin(entries: u64, bytes: u64) {
+    ENTRIES_DONE.store(0, Ordering::Relaxed);
+    BYTES_DONE.store(0, Ordering::Relaxed);
+    ENTRIES_TOTAL.store(entries, Ordering::Relaxed);
+    BYTES_TOTAL.store(bytes, Ordering::Relaxed);
+    CURRENT.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Отмечает, что запись обработана.
+///
+/// # Аргументы
+///
+/// * `path` - Путь записи.
+/// * `bytes` - Размер данных записи.
+pub fn advance(path: &str, bytes: u64) {
+    ENTRIES_DONE.fetch_add(1, Ordering::Relaxed);
+    BYTES_DONE.fetch_add(bytes, Ordering::Relaxed);
+    let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+    current.clear();
+    current.push_str(path);
+}
+
+/// Снимок хода операции.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Обработано записей
+    pub entries_done: u64,
+    /// Всего записей (0, если объем работы еще неизвестен)
+    pub entries_total: u64,
+    /// Обработано байтов
+    pub bytes_done: u64,
+    /// Всего байтов
+    pub bytes_total: u64,
+    /// Последняя обработанная запись
+    pub current: String,
+}
+
+impl Snapshot {
+    /// Оценивает оставшееся время по доле обработанных байтов.
+    ///
+    /// # Возвращает
+    ///
+    /// Оценку или `None`, пока ничего не обработано.
+    pub fn eta(&self, elapsed: Duration) -> Option<Duration> {
+        if self.bytes_done == 0 || self.bytes_total < self.bytes_done {
+            return None;
+        }
+        let remaining = (self.bytes_total - self.bytes_done) as f64 / self.bytes_done as f64;
+        Some(elapsed.mul_f64(remaining))
+    }
+
+    /// Форматирует состояние строками `ключ: значение`.
+    ///
+    /// # Аргументы
+    ///
+    /// * `state` - Текущая стадия (например, `compressing` или `done`).
+    /// * `elapsed` - Время с начала операции.
+    pub fn format(&self, state: &str, elapsed: Duration) -> String {
+        let eta = self.eta(elapsed).map_or_else(|| "unknown".to_owned(), |eta| eta.as_secs().to_string());
+        format!("state: {}\nentries: {}/{}\nbytes: {}/{}\ncurrent: {}\nelapsed: {}\neta: {}\n",
+            state, self.entries_done, self.entries_total, self.bytes_done, self.bytes_total,
+            self.current, elapsed.as_secs(), eta)
+    }
+
+    /// Форматирует состояние событием — объектом JSON в одну строку без перевода строки.
+    ///
+    /// # Аргументы
+    ///
+    /// * `state` - Текущая стадия.
+    /// * `elapsed` - Время с начала операции.
+    pub fn to_json(&self, state: &str, elapsed: Duration) -> String {
+        json::Object::new()
+            .str("state", state)
+            .str("current", &self.current)
+            .raw("entries_done", self.entries_done)
+            .raw("entries_total", self.entries_total)
+            .raw("bytes_done", self.bytes_done)
+            .raw("bytes_total", self.bytes_total)
+            .raw("elapsed_ms", elapsed.as_millis())
+            .opt("eta_ms", self.eta(elapsed).map(|eta| eta.as_millis()))
+            .finish()
+    }
+}
+
+/// Возвращает текущий снимок хода операции.
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        entries_done: ENTRIES_DONE.load(Ordering::Relaxed),
+        entries_total: ENTRIES_TOTAL.load(Ordering::Relaxed),
+        bytes_done: BYTES_DONE.load(Ordering::Relaxed),
+        bytes_total: BYTES_TOTAL.load(Ordering::Relaxed),
+        current: CURRENT.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+    }
+}
+
+/// Куда записывается состояние.
+#[derive(Debug, Clone)]
+enum Sink {
+    /// Файл состояния (`--status-file`)
+    File(PathBuf),
+    /// События JSON в stderr (`--progress json`)
+    Events,
+}
+
+impl Sink {
+    /// Записывает текущее состояние; ошибку записи файла выводит предупреждением.
+    fn write(&self, state: &str, elapsed: Duration) {
+        match self {
+            Sink::File(path) => if let Err(e) = write_status(path, &snapshot().format(state, elapsed)) {
+                log::warn!("cannot update status file {}: {}", path.display(), e);
+            },
+            // Если stderr закрыт, сообщить об ошибке некуда
+            Sink::Events => {
+                let _ = writeln!(io::stderr().lock(), "{}", snapshot().to_json(state, elapsed));
+            }
+        }
+    }
+}
+
+/// Фоновый поток, периодически записывающий файл состояния (`--status-file`)
+/// или события хода операции (`--progress json`).
+pub struct StatusWriter {
+    /// Куда записывается состояние
+    sink: Sink,
+    /// Время начала операции
+    started: Instant,
+    /// Текущая стадия операции
+    state: Arc<Mutex<String>>,
+    /// Флаг остановки потока
+    stop: Arc<AtomicBool>,
+    /// Поток записи
+    handle: Option<JoinHandle<()>>,
+}
+
+impl StatusWriter {
+    /// Запускает поток, который раз в `interval` перезаписывает файл состояния.
+    ///
+    /// # Аргументы
+    ///
+    /// * `path` - Путь к файлу состояния.
+    /// * `state` - Начальная стадия.
+    /// * `interval` - Период обновления.
+    pub fn start(path: &Path, state: &str, interval: Duration) -> StatusWriter {
+        Self::spawn(Sink::File(path.to_owned()), state, interval)
+    }
+
+    /// Запускает поток, который раз в `interval` выводит в stderr событие JSON с состоянием.
+    ///
+    /// # Аргументы
+    ///
+    /// * `state` - Начальная стадия.
+    /// * `interval` - Период между событиями.
+    pub fn start_events(state: &str, interval: Duration) -> StatusWriter {
+        Self::spawn(Sink::Events, state, interval)
+    }
+
+    /// Запускает поток записи состояния в `sink`.
+    fn spawn(sink: Sink, state: &str, interval: Duration) -> StatusWriter {
+        let state = Arc::new(Mutex::new(state.to_owned()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let started = Instant::now();
+        let handle = {
+            let (sink, state, stop) = (sink.clone(), Arc::clone(&state), Arc::clone(&stop));
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let current = state.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                    sink.write(&current, started.elapsed());
+                    thread::park_timeout(interval);
+                }
+            })
+        };
+        StatusWriter { sink, started, state, stop, handle: Some(handle) }
+    }
+
+    /// Меняет стадию операции, показываемую в файле состояния или событиях.
+    pub fn set_state(&self, state: &str) {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner()) = state.to_owned();
+    }
+
+    /// Останавливает поток и записывает итоговое состояние (например, `done` или `failed`).
+    pub fn finish(mut self, state: &str) {
+        self.stop_thread();
+        self.sink.write(state, self.started.elapsed());
+    }
+
+    /// Останавливает поток записи, если он еще работает.
+    fn stop_thread(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.stop.store(true, Ordering::Relaxed);
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for StatusWriter {
+    /// Останавливает поток, если `finish` не был вызван.
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
+
+/// Атомарно заменяет файл состояния: записывает временный файл и переименовывает его.
+fn write_status(path: &Path, status: &str) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    fs::write(&temp, status)?;
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_file() {
+        let snapshot = Snapshot { entries_done: 1, entries_total: 4, bytes_done: 250, bytes_total: 1000, current: "a.txt".into() };
+        assert_eq!(snapshot.eta(Duration::from_secs(10)), Some(Duration::from_secs(30)));
+        let text = snapshot.format("compressing", Duration::from_secs(10));
+        assert!(text.contains("entries: 1/4\n") && text.contains("current: a.txt\n") && text.ends_with("eta: 30\n"));
+        assert_eq!(snapshot.to_json("compressing", Duration::from_secs(10)),
+            r#"{"state":"compressing","current":"a.txt","entries_done":1,"entries_total":4,"bytes_done":250,"bytes_total":1000,"elapsed_ms":10000,"eta_ms":30000}"#);
+        let odd = Snapshot { current: "dir/\"q\"\\\n\t".into(), bytes_done: 0, ..snapshot };
+        assert!(odd.to_json("done", Duration::ZERO).contains(r#""current":"dir/\"q\"\\\n\u0009","#));
+        assert!(odd.to_json("done", Duration::ZERO).ends_with(r#""eta_ms":null}"#));
+
+        // Счетчики общие для всего процесса, поэтому другие тесты могут менять их параллельно
+        let path = std::env::temp_dir().join(format!("status_test_{}", std::process::id()));
+        let writer = StatusWriter::start(&path, "reading", Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(30));
+        assert!(fs::read_to_string(&path).unwrap().starts_with("state: reading\n"));
+        writer.set_state("compressing");
+        writer.finish("done");
+        let status = fs::read_to_string(&path).unwrap();
+        assert!(status.starts_with("state: done\nentries: "), "{}", status);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,307 @@
+//! Модуль для записей восстановления (`--recovery`) и починки архива (`--repair`).
+//!
+//! Запись восстановления дописывается после архива и хранит данные четности Рида — Соломона.
+//! Архив делится на `data_shards` частей одинакового размера (последняя дополняется нулями),
+//! по которым вычисляется `parity_shards` частей четности. В записи хранятся CRC32 всех частей,
+//! поэтому при починке поврежденные части находятся по несовпадению контрольной суммы
+//! и восстанавливаются, если их не больше, чем частей четности. Один испорченный байт
+//! на ненадежном носителе портит одну часть, а не весь остаток архива.
+//!
+//! Устройство записи (все числа в порядке little-endian):
+//!
+//! | Поле | Размер | Содержимое |
+//! |------|--------|------------|
+//! | сигнатура | 4 | `RREC` (`RECOVERY_MAGIC`) |
+//! | версия | 1 | версия записи, сейчас 1 |
+//! | размер части | 4 | `u32` |
+//! | части данных | 4 | `u32` |
+//! | части четности | 4 | `u32` |
+//! | длина архива | 8 | `u64`, число байтов архива перед записью |
+//! | таблица CRC32 | 4 на часть | CRC32 частей данных, затем частей четности |
+//! | CRC32 заголовка | 4 | контрольная сумма всех предыдущих полей записи |
+//! | четность | размер части на часть | части четности |
+//! | длина записи | 8 | `u64`, размер всей записи, включая эти поля |
+//! | сигнатура | 4 | `RREC`, по ней запись находится с конца файла |
+//!
+//! Распаковка и просмотр пропускают запись восстановления, как и в склеенных архивах.
+
+use std::convert::TryInto;
+use std::io;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use crate::checksum;
+
+/// Сигнатура, с которой начинается и которой заканчивается запись восстановления.
+pub const RECOVERY_MAGIC: &[u8; 4] = b"RREC";
+
+/// Текущая версия записи восстановления.
+const RECOVERY_VERSION: u8 = 1;
+
+/// Размер заголовка записи без таблицы CRC32: сигнатура, версия, три `u32` и `u64`.
+const HEADER_LEN: usize = 4 + 1 + 4 + 4 + 4 + 8;
+
+/// Размер окончания записи: длина записи и сигнатура.
+const FOOTER_LEN: usize = 8 + 4;
+
+/// Наибольшее число частей данных: вместе с четностью частей не больше 256.
+const MAX_DATA_SHARDS: usize = 128;
+
+/// Наименьший размер части, чтобы небольшие архивы не делились на крошечные части.
+const MIN_SHARD_SIZE: usize = 4096;
+
+/// Заголовок записи восстановления.
+#[derive(Debug, Clone, PartialEq)]
+struct Layout {
+    /// Размер одной части в байтах
+    shard_size: usize,
+    /// Число частей данных
+    data_shards: usize,
+    /// Число частей четности
+    parity_shards: usize,
+    /// Длина защищенного архива
+    data_len: usize,
+    /// CRC32 частей данных и четности
+    checksums: Vec<u32>,
+}
+
+impl Layout {
+    /// Размер заголовка вместе с таблицей CRC32 и ее контрольной суммой.
+    fn header_len(&self) -> usize {
+        HEADER_LEN + 4 * (self.data_shards + self.parity_shards) + 4
+    }
+
+    /// Размер всей записи восстановления.
+    fn record_len(&self) -> usize {
+        self.header_len() + self.parity_shards * self.shard_size + FOOTER_LEN
+    }
+
+    /// Преобразует заголовок в байты.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.header_len());
+        buffer.extend_from_slice(RECOVERY_MAGIC);
+        buffer.push(RECOVERY_VERSION);
+        buffer.extend_from_slice(&(self.shard_size as u32).to_le_bytes());
+        buffer.extend_from_slice(&(self.data_shards as u32).to_le_bytes());
+        buffer.extend_from_slice(&(self.parity_shards as u32).to_le_bytes());
+        buffer.extend_from_slice(&(self.data_len as u64).to_le_bytes());
+        for crc in &self.checksums {
+            buffer.extend_from_slice(&crc.to_le_bytes());
+        }
+        let crc = checksum::crc32(&buffer);
+        buffer.extend_from_slice(&crc.to_le_bytes());
+        buffer
+    }
+
+    /// Разбирает заголовок в начале данных.
+    ///
+    /// # Возвращает
+    ///
+    /// Заголовок или `None`, если данные не начинаются с целого заголовка записи.
+    fn parse(data: &[u8]) -> Option<Layout> {
+        if !data.starts_with(RECOVERY_MAGIC) || data.len() < HEADER_LEN || data[4] != RECOVERY_VERSION {
+            return None;
+        }
+        let u32_at = |pos: usize| u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let (shard_size, data_shards, parity_shards) = (u32_at(5), u32_at(9), u32_at(13));
+        let data_len = usize::try_from(u64::from_le_bytes(data[17..25].try_into().unwrap())).ok()?;
+        if shard_size == 0 || data_shards == 0 || parity_shards == 0 || data_shards + parity_shards > 256
+            || data_len > shard_size * data_shards {
+            return None;
+        }
+        let table_end = HEADER_LEN + 4 * (data_shards + parity_shards);
+        let stored = data.get(table_end..table_end + 4)?;
+        if checksum::crc32(&data[..table_end]).to_le_bytes() != stored {
+            return None;
+        }
+        let checksums = data[HEADER_LEN..table_end].chunks(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        Some(Layout { shard_size, data_shards, parity_shards, data_len, checksums })
+    }
+}
+
+/// Разбирает долю данных четности для `--recovery`: процент от 1 до 100 со знаком `%` или без него.
+pub fn parse_percent(spec: &str) -> Result<u32, String> {
+    spec.strip_suffix('%').unwrap_or(spec).parse::<u32>().ok()
+        .filter(|percent| (1..=100).contains(percent))
+        .ok_or_else(|| format!("invalid recovery size {} (expected a percentage from 1% to 100%)", spec))
+}
+
+/// Дописывает к архиву запись восстановления.
+///
+/// # Аргументы
+///
+/// * `archive` - Байты архива.
+/// * `percent` - Размер данных четности в процентах от размера архива (округляется вверх
+///   до целого числа частей).
+///
+/// # Возвращает
+///
+/// Архив с записью восстановления.
+pub fn protect(archive: &[u8], percent: u32) -> Vec<u8> {
+    let data_shards = archive.len().div_ceil(MIN_SHARD_SIZE).clamp(1, MAX_DATA_SHARDS);
+    let shard_size = archive.len().div_ceil(data_shards).max(1);
+    let parity_shards = (data_shards * percent as usize).div_ceil(100).clamp(1, 256 - data_shards);
+    let mut shards: Vec<Vec<u8>> = (0..data_shards + parity_shards).map(|i| {
+        let start = (i * shard_size).min(archive.len());
+        let mut shard = archive[start..((i + 1) * shard_size).min(archive.len())].to_vec();
+        shard.resize(shard_size, 0);
+        shard
+    }).collect();
+    ReedSolomon::new(data_shards, parity_shards)
+        .and_then(|codec| codec.encode(&mut shards))
+        .expect("shard counts are within the codec limits");
+    let layout = Layout {
+        shard_size,
+        data_shards,
+        parity_shards,
+        data_len: archive.len(),
+        checksums: shards.iter().map(|shard| checksum::crc32(shard)).collect(),
+    };
+    let mut output = archive.to_vec();
+    append_record(&mut output, &layout, &shards[data_shards..]);
+    output
+}
+
+/// Дописывает запись восстановления с частями четности `parity`.
+fn append_record(output: &mut Vec<u8>, layout: &Layout, parity: &[Vec<u8>]) {
+    output.extend(layout.to_bytes());
+    for shard in parity {
+        output.extend_from_slice(shard);
+    }
+    output.extend_from_slice(&(layout.record_len() as u64).to_le_bytes());
+    output.extend_from_slice(RECOVERY_MAGIC);
+}
+
+/// Возвращает длину записи восстановления в начале данных, чтобы читатели архива
+/// могли ее пропустить, или `None`, если данные не начинаются с записи.
+pub fn record_len(data: &[u8]) -> Option<usize> {
+    Layout::parse(data).map(|layout| layout.record_len()).filter(|&len| len <= data.len())
+}
+
+/// Находит запись восстановления в конце файла.
+///
+/// Сначала запись ищется по окончанию; если оно повреждено, файл просматривается с конца
+/// в поисках заголовка с верной контрольной суммой.
+///
+/// # Возвращает
+///
+/// Смещение записи и ее заголовок или `None`, если записи нет.
+fn locate(data: &[u8]) -> Option<(usize, Layout)> {
+    let with_layout = |start: usize| Layout::parse(&data[start..]).map(|layout| (start, layout));
+    if data.ends_with(RECOVERY_MAGIC) && data.len() >= FOOTER_LEN {
+        let len = u64::from_le_bytes(data[data.len() - FOOTER_LEN..data.len() - 4].try_into().unwrap());
+        let found = usize::try_from(len).ok()
+            .and_then(|len| data.len().checked_sub(len))
+            .and_then(with_layout);
+        if found.is_some() {
+            return found;
+        }
+    }
+    data.windows(RECOVERY_MAGIC.len()).enumerate().rev()
+        .filter(|(_, window)| window == RECOVERY_MAGIC)
+        .find_map(|(start, _)| with_layout(start))
+}
+
+/// Результат починки архива.
+#[derive(Debug)]
+pub struct RepairReport {
+    /// Починенный архив вместе с заново записанной записью восстановления
+    pub archive: Vec<u8>,
+    /// Число восстановленных частей архива
+    pub damaged_data: usize,
+    /// Число восстановленных частей четности
+    pub damaged_parity: usize,
+}
+
+/// Чинит архив по его записи восстановления.
+///
+/// # Аргументы
+///
+/// * `data` - Байты архива с записью восстановления, возможно поврежденные.
+///
+/// # Возвращает
+///
+/// Починенный архив и число восстановленных частей или ошибку, если записи восстановления нет
+/// либо поврежденных частей больше, чем частей четности.
+pub fn repair(data: &[u8]) -> io::Result<RepairReport> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let (start, layout) = locate(data).ok_or_else(|| invalid("no recovery record found (was the archive created with --recovery?)".to_owned()))?;
+    if start != layout.data_len {
+        return Err(invalid(format!("archive is {} bytes instead of {}: bytes were inserted or removed, not damaged",
+            start, layout.data_len)));
+    }
+    let parity_start = start + layout.header_len();
+    let mut shards: Vec<Option<Vec<u8>>> = (0..layout.data_shards + layout.parity_shards).map(|i| {
+        let (region, offset) = if i < layout.data_shards {
+            (&data[..start], i * layout.shard_size)
+        } else {
+            (&data[parity_start..], (i - layout.data_shards) * layout.shard_size)
+        };
+        let available = region.get(offset.min(region.len())..(offset + layout.shard_size).min(region.len())).unwrap_or_default();
+        let mut shard = available.to_vec();
+        if i >= layout.data_shards && shard.len() < layout.shard_size {
+            // Обрезанная часть четности считается поврежденной
+            return None;
+        }
+        shard.resize(layout.shard_size, 0);
+        Some(shard).filter(|shard| checksum::crc32(shard) == layout.checksums[i])
+    }).collect();
+    let damaged_data = shards[..layout.data_shards].iter().filter(|s| s.is_none()).count();
+    let damaged_parity = shards[layout.data_shards..].iter().filter(|s| s.is_none()).count();
+    if damaged_data + damaged_parity > layout.parity_shards {
+        return Err(invalid(format!("{} of {} regions are damaged, only {} can be repaired",
+            damaged_data + damaged_parity, layout.data_shards + layout.parity_shards, layout.parity_shards)));
+    }
+    ReedSolomon::new(layout.data_shards, layout.parity_shards)
+        .and_then(|codec| codec.reconstruct(&mut shards))
+        .map_err(|e| invalid(format!("cannot reconstruct damaged regions: {:?}", e)))?;
+    let shards: Vec<Vec<u8>> = shards.into_iter().map(|shard| shard.unwrap_or_default()).collect();
+    let mut archive: Vec<u8> = shards[..layout.data_shards].concat();
+    archive.truncate(layout.data_len);
+    append_record(&mut archive, &layout, &shards[layout.data_shards..]);
+    Ok(RepairReport { archive, damaged_data, damaged_parity })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::{self, Algorithm};
+
+    #[test]
+    fn test_repair_damaged_archive() {
+        assert_eq!(parse_percent("5%"), Ok(5));
+        assert!(parse_percent("0%").is_err() && parse_percent("150").is_err());
+
+        // Псевдослучайные данные почти не сжимаются, поэтому архив делится на много частей
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..100_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 24) as u8
+        }).collect();
+        let archive = processing::compress(&data, Algorithm::Lz4, false, false);
+        let protected = protect(&archive, 20);
+        assert_eq!(&protected[..archive.len()], &archive[..]);
+        // Запись восстановления не мешает распаковке
+        assert_eq!(processing::decompress(&protected, None, false, false, true).unwrap(), data);
+
+        let layout = locate(&protected).unwrap().1;
+        assert!(layout.data_shards > 16 && layout.parity_shards >= 3);
+        let mut damaged = protected.clone();
+        damaged[100] ^= 0xFF;
+        damaged[archive.len() / 2] ^= 0x01;
+        let parity = archive.len() + layout.header_len();
+        damaged[parity + 3] ^= 0x10;
+        assert!(processing::decompress(&damaged, None, false, false, true).is_err());
+        let report = repair(&damaged).unwrap();
+        assert_eq!((report.damaged_data, report.damaged_parity), (2, 1));
+        assert_eq!(report.archive, protected);
+        assert_eq!(repair(&protected).unwrap().damaged_data, 0);
+
+        // Повреждений больше, чем частей четности
+        for shard in 0..=layout.parity_shards {
+            damaged[shard * layout.shard_size] ^= 0xFF;
+        }
+        assert!(repair(&damaged).is_err());
+        assert!(repair(&archive).is_err());
+    }
+}
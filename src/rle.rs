@@ -3,6 +3,8 @@
 //! Этот модуль предоставляет функции для сжатия и распаковки данных с использованием алгоритма RLE. 
 //! Алгоритм RLE используется для сжатия данных путем замены повторяющихся последовательностей байтов на пары (длина, значение).
 
+use std::io;
+
 /// Сжимает входные данные с использованием алгоритма RLE.
 ///
 /// # Аргументы
@@ -37,14 +39,12 @@ pub fn compress(input: &[u8]) -> Vec<u8> {
                     compressed.push(1);
                     compressed.push(input[i]);
                     i += 1;
-                } else
-                {
+                } else {
                     compressed.push(128+2);
-                compressed.push(input[i]);
-                compressed.push(input[i+1]);
-                i += 2;
-                
-            }
+                    compressed.push(input[i]);
+                    compressed.push(input[i+1]);
+                    i += 2;
+                }
             continue;
             }
              // Сборка неповторяющихся байтов
@@ -60,10 +60,15 @@ pub fn compress(input: &[u8]) -> Vec<u8> {
                 i += 1;
             }
             distinct_count = distinct_count.saturating_sub(2);
-            compressed.push(128 + distinct_count as u8);
-            compressed.extend_from_slice(&input[distinct_start..distinct_start + distinct_count]);
-            
-                i = i.saturating_sub(2);
+            if distinct_count == 1 {
+                // Одиночный байт записывается как серия длины 1
+                compressed.push(1);
+                compressed.push(input[distinct_start]);
+            } else {
+                compressed.push(128 + distinct_count as u8);
+                compressed.extend_from_slice(&input[distinct_start..distinct_start + distinct_count]);
+            }
+            i = i.saturating_sub(2);
 
         }
     }
@@ -77,11 +82,13 @@ pub fn compress(input: &[u8]) -> Vec<u8> {
 /// # Аргументы
 ///
 /// * `input` - Срез байтов, которые требуется распаковать.
+/// * `strict` - Строгий режим: оборванная в конце последовательность считается ошибкой,
+///   а не отбрасывается.
 ///
 /// # Возвращает
 ///
-/// Вектор байтов, представляющий распакованные данные.
-pub fn decompress(input: &[u8]) -> Vec<u8> {
+/// Вектор байтов, представляющий распакованные данные, или ошибку при некорректном потоке.
+pub fn decompress(input: &[u8], strict: bool) -> io::Result<Vec<u8>> {
     let mut decompressed = Vec::new();
     let mut i = 0;
 
@@ -92,20 +99,24 @@ pub fn decompress(input: &[u8]) -> Vec<u8> {
             if i < input.len() {
                 let value = input[i];
                 i += 1;
-                decompressed.extend(std::iter::repeat(value).take(count as usize));
+                decompressed.extend(std::iter::repeat_n(value, count as usize));
+            } else if strict {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "RLE: серия без значения в конце потока"));
             }
         } else {
             let distinct_count = (count - 128) as usize;
             if i + distinct_count <= input.len() {
                 decompressed.extend_from_slice(&input[i..i + distinct_count]);
                 i += distinct_count;
+            } else if strict {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "RLE: оборванная последовательность литералов"));
             } else {
                 break;
             }
         }
     }
 
-    decompressed
+    Ok(decompressed)
 }
 #[cfg(test)]
 mod tests {
@@ -122,7 +133,13 @@ mod tests {
     fn test_decompress() {
         let input = vec![4, b'A', 3, b'B', 2, b'C', 1, b'D', 2, b'A'];
         let expected = b"AAAABBBCCDAA".to_vec();
-        assert_eq!(decompress(&input), expected);
+        assert_eq!(decompress(&input, false).unwrap(), expected);
     }
-}
 
+    #[test]
+    fn test_truncated_input() {
+        let input = vec![4, b'A', 3];
+        assert_eq!(decompress(&input, false).unwrap(), b"AAAA".to_vec());
+        assert!(decompress(&input, true).is_err());
+    }
+}
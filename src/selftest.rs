@@ -0,0 +1,151 @@
+//! Модуль самопроверки сборки.
+//!
+//! Прогоняет встроенные тестовые векторы через все алгоритмы сжатия и через контейнер архива,
+//! чтобы перед использованием сборки (в том числе кросс-компилированной) убедиться,
+//! что данные восстанавливаются без искажений.
+
+use std::collections::BTreeMap;
+use std::panic;
+use crate::io::{self, DirEntry};
+use crate::processing::{self, Algorithm};
+use crate::ArchiveData;
+
+/// Формирует набор тестовых векторов.
+///
+/// # Возвращает
+///
+/// Пары (название, данные), покрывающие пустой ввод, одиночный байт, длинные серии,
+/// текст и псевдослучайные двоичные данные.
+fn vectors() -> Vec<(&'static str, Vec<u8>)> {
+    // Линейный конгруэнтный генератор дает одинаковые «случайные» данные на любой платформе
+    let mut state = 0x1234_5678u32;
+    let random: Vec<u8> = (0..8192).map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (state >> 16) as u8
+    }).collect();
+
+    let text = b"The quick brown fox jumps over the lazy dog. ".repeat(50);
+    let mut mixed = vec![0u8; 300];
+    mixed.extend_from_slice(&text[..200]);
+    mixed.extend(std::iter::repeat_n(0xFF, 1000));
+    mixed.extend_from_slice(&random[..500]);
+
+    vec![
+        ("empty", Vec::new()),
+        ("single byte", vec![42]),
+        ("long run", vec![b'A'; 5000]),
+        ("text", text),
+        ("random", random),
+        ("mixed", mixed),
+    ]
+}
+
+/// Выполняет проверку, превращая панику в ошибку.
+fn guarded<F: FnOnce() -> Result<(), String> + panic::UnwindSafe>(check: F) -> Result<(), String> {
+    // Стандартный обработчик печатает трассировку, а здесь паника — лишь результат проверки
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(check);
+    panic::set_hook(default_hook);
+    result.unwrap_or_else(|e| {
+        let message = e.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| e.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(format!("panic: {}", message))
+    })
+}
+
+/// Проверяет сжатие и распаковку одного вектора выбранным алгоритмом.
+fn check_codec(algorithm: &Algorithm, data: &[u8], use_multithreading: bool, short_matches: bool) -> Result<(), String> {
+    let compressed = processing::compress(data, algorithm.clone(), use_multithreading, short_matches);
+    let restored = processing::decompress(&compressed, None, false, short_matches, true)
+        .map_err(|e| e.to_string())?;
+    if restored != data {
+        return Err(format!("round trip mismatch ({} bytes in, {} bytes out)", data.len(), restored.len()));
+    }
+    Ok(())
+}
+
+/// Проверяет полный путь архива: сериализацию записей, сжатие, распаковку и разбор.
+///
+/// При `per_entry` записи сжимаются по отдельности с центральным индексом.
+fn check_container(algorithm: &Algorithm, per_entry: bool) -> Result<(), String> {
+    let metadata = BTreeMap::from([("content-type".to_owned(), "text/plain".to_owned())]);
+    let mut entries = vec![
+        DirEntry { path: "readme.txt".to_owned(), data: b"hello, archive".to_vec(), permissions: 0o100644, crc32: None, owner: None, metadata, mtime: None },
+        DirEntry { path: "bin/tool".to_owned(), data: vec![0x7F, b'E', b'L', b'F', 0, 0, 1], permissions: 0o100755, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None },
+        DirEntry { path: "empty".to_owned(), data: Vec::new(), permissions: 0o100600, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None },
+    ];
+    io::fill_checksums(&mut entries);
+    let serialized = io::archive_data_to_bytes(&ArchiveData { entries });
+    let original = io::bytes_to_archive_data(&serialized, true).map_err(|e| e.to_string())?;
+    let compressed = if per_entry {
+        processing::compress_entries(&original.entries, algorithm.clone(), false, false, None, None)
+    } else {
+        processing::compress(&serialized, algorithm.clone(), false, false)
+    };
+    let decompressed = processing::decompress(&compressed, None, false, false, true)
+        .map_err(|e| e.to_string())?;
+    let archive = io::bytes_to_archive_data(&decompressed, true).map_err(|e| e.to_string())?;
+
+    let same = archive.entries.len() == original.entries.len()
+        && archive.entries.iter().zip(&original.entries).all(|(a, b)| {
+            a.path == b.path && a.data == b.data && a.permissions == b.permissions && a.crc32 == b.crc32
+                && a.metadata == b.metadata
+        }) && archive.entries.iter().all(|e| e.crc32.is_some() && e.verify().is_ok());
+    if !same {
+        return Err("restored entries differ".to_owned());
+    }
+    Ok(())
+}
+
+/// Запускает все проверки и выводит результат каждой из них.
+///
+/// # Возвращает
+///
+/// `true`, если все проверки пройдены.
+pub fn run() -> bool {
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut report = |name: String, result: Result<(), String>| {
+        match result {
+            Ok(()) => {
+                passed += 1;
+                println!("ok      {}", name);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {}", name, e);
+            }
+        }
+    };
+
+    for algorithm in Algorithm::ALL.iter() {
+        for (vector, data) in vectors() {
+            report(format!("{} / {}", algorithm.name(), vector),
+                guarded(|| check_codec(algorithm, &data, false, false)));
+            report(format!("{} / {} / multithreaded", algorithm.name(), vector),
+                guarded(|| check_codec(algorithm, &data, true, false)));
+            if *algorithm == Algorithm::Lz77 {
+                report(format!("{} / {} / short matches", algorithm.name(), vector),
+                    guarded(|| check_codec(algorithm, &data, false, true)));
+            }
+        }
+        report(format!("{} / container", algorithm.name()), guarded(|| check_container(algorithm, false)));
+        report(format!("{} / per-entry container", algorithm.name()), guarded(|| check_container(algorithm, true)));
+    }
+
+    println!();
+    println!("{} checks passed, {} failed.", passed, failed);
+    failed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test_passes() {
+        assert!(run());
+    }
+}
@@ -0,0 +1,213 @@
+//! Модуль для режима сервера сжатия (`--serve`).
+//!
+//! Сервер слушает локальный сокет Unix и выполняет запросы на сжатие и распаковку, поэтому
+//! другие процессы могут сжимать данные без запуска программы на каждый файл. Каждое
+//! подключение обслуживается отдельным потоком и может передать сколько угодно запросов подряд.
+//!
+//! Запрос (все числа в порядке little-endian):
+//!
+//! | Поле | Размер | Содержимое |
+//! |------|--------|------------|
+//! | операция | 1 | `C` — сжатие, `D` — распаковка |
+//! | алгоритм | 1 | идентификатор алгоритма, как в заголовке архива; при распаковке 0 — по заголовку |
+//! | длина | 8 | `u64`, число байтов данных |
+//! | данные | длина | сжимаемые данные или архив |
+//!
+//! Ответ:
+//!
+//! | Поле | Размер | Содержимое |
+//! |------|--------|------------|
+//! | состояние | 1 | 0 — успех, 1 — ошибка |
+//! | длина | 8 | `u64`, число байтов результата |
+//! | результат | длина | архив, распакованные данные или текст ошибки в UTF-8 |
+
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use crate::partial;
+use crate::processing::{self, Algorithm};
+
+/// Код операции сжатия.
+pub const OP_COMPRESS: u8 = b'C';
+
+/// Код операции распаковки.
+pub const OP_DECOMPRESS: u8 = b'D';
+
+/// Состояние успешно выполненного запроса.
+const STATUS_OK: u8 = 0;
+
+/// Состояние запроса, завершившегося ошибкой.
+const STATUS_ERROR: u8 = 1;
+
+/// Наибольший размер данных запроса, чтобы поврежденная длина не привела к огромному выделению памяти.
+const MAX_REQUEST_LEN: u64 = 1 << 32;
+
+/// Как часто сервер проверяет, не пора ли остановиться, пока нет новых подключений.
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+
+/// Параметры обработки запросов.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ServeOptions {
+    /// Сжимать и распаковывать в несколько потоков (`-m`)
+    pub use_multithreading: bool,
+    /// Разрешить короткие токены LZ77 при сжатии (`--short-matches`)
+    pub short_matches: bool,
+    /// Строгая распаковка (`--strict`)
+    pub strict: bool,
+}
+
+/// Запускает сервер на сокете `path` и обслуживает подключения до SIGINT/SIGTERM.
+///
+/// Оставшийся от прежнего запуска файл сокета удаляется; любой другой файл по этому пути
+/// считается ошибкой, чтобы не удалить чужие данные. При остановке файл сокета удаляется.
+///
+/// # Аргументы
+///
+/// * `path` - Путь к сокету Unix.
+/// * `options` - Параметры обработки запросов.
+///
+/// # Возвращает
+///
+/// `Ok(())` после остановки или ошибку ввода/вывода, если сокет не удалось создать.
+pub fn serve(path: &Path, options: ServeOptions) -> io::Result<()> {
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot listen on {}: {}", path.display(), e)))?;
+    // Подключения принимаются без блокировки, чтобы сервер замечал сигнал остановки
+    listener.set_nonblocking(true)?;
+    while !partial::interrupted() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, options) {
+                        log::warn!("connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+            Err(e) => log::warn!("failed to accept a connection: {}", e),
+        }
+    }
+    fs::remove_file(path)
+}
+
+/// Обслуживает запросы одного подключения, пока клиент не закроет его.
+///
+/// # Возвращает
+///
+/// `Ok(())` после закрытия подключения клиентом или ошибку чтения/записи сокета.
+pub fn handle_connection<S: Read + Write>(mut stream: S, options: ServeOptions) -> io::Result<()> {
+    loop {
+        let mut head = [0u8; 10];
+        match stream.read_exact(&mut head[..1]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        stream.read_exact(&mut head[1..])?;
+        let len = u64::from_le_bytes(head[2..10].try_into().unwrap());
+        if len > MAX_REQUEST_LEN {
+            // Граница следующего запроса неизвестна, поэтому подключение закрывается
+            let message = format!("request of {} bytes exceeds the limit of {} bytes", len, MAX_REQUEST_LEN);
+            write_frame(&mut stream, STATUS_ERROR, message.as_bytes())?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        let mut data = vec![0u8; len as usize];
+        stream.read_exact(&mut data)?;
+        match execute(head[0], head[1], &data, options) {
+            Ok(result) => write_frame(&mut stream, STATUS_OK, &result)?,
+            Err(e) => write_frame(&mut stream, STATUS_ERROR, e.to_string().as_bytes())?,
+        }
+    }
+}
+
+/// Выполняет один запрос.
+fn execute(op: u8, algorithm_id: u8, data: &[u8], options: ServeOptions) -> io::Result<Vec<u8>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let algorithm = match algorithm_id {
+        0 => None,
+        id => Some(Algorithm::from_id(id).ok_or_else(|| invalid(format!("unknown algorithm id {}", id)))?),
+    };
+    match op {
+        OP_COMPRESS => {
+            let algorithm = algorithm.ok_or_else(|| invalid("compression requires an algorithm".to_owned()))?;
+            Ok(processing::compress(data, algorithm, options.use_multithreading, options.short_matches))
+        }
+        OP_DECOMPRESS => processing::decompress(data, algorithm, options.use_multithreading, options.short_matches, options.strict),
+        op => Err(invalid(format!("unknown operation {:#04x}", op))),
+    }
+}
+
+/// Записывает кадр ответа.
+fn write_frame<W: Write>(stream: &mut W, status: u8, body: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(9 + body.len());
+    frame.push(status);
+    frame.extend_from_slice(&(body.len() as u64).to_le_bytes());
+    frame.extend_from_slice(body);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Отправляет запрос серверу и ждет ответа.
+///
+/// # Аргументы
+///
+/// * `stream` - Подключение к серверу.
+/// * `op` - `OP_COMPRESS` или `OP_DECOMPRESS`.
+/// * `algorithm` - Алгоритм сжатия; при распаковке `None` означает алгоритм из заголовка.
+/// * `data` - Данные запроса.
+///
+/// # Возвращает
+///
+/// Результат запроса или ошибку: ошибки сервера возвращаются с их текстом.
+pub fn request(stream: &mut UnixStream, op: u8, algorithm: Option<Algorithm>, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut frame = vec![op, algorithm.map_or(0, |a| a.id())];
+    frame.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    frame.extend_from_slice(data);
+    stream.write_all(&frame)?;
+    let mut head = [0u8; 9];
+    stream.read_exact(&mut head)?;
+    let len = u64::from_le_bytes(head[1..9].try_into().unwrap());
+    let mut body = Vec::new();
+    Read::by_ref(stream).take(len).read_to_end(&mut body)?;
+    if body.len() as u64 != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "server response is truncated"));
+    }
+    match head[0] {
+        STATUS_OK => Ok(body),
+        _ => Err(io::Error::other(String::from_utf8_lossy(&body).into_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_over_socket() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let worker = thread::spawn(move || handle_connection(server, ServeOptions::default()));
+
+        let data = b"server request server request server request".repeat(20);
+        let archive = request(&mut client, OP_COMPRESS, Some(Algorithm::Lz77), &data).unwrap();
+        assert_eq!(archive, processing::compress(&data, Algorithm::Lz77, false, false));
+        assert_eq!(request(&mut client, OP_DECOMPRESS, None, &archive).unwrap(), data);
+
+        // Ошибка запроса не закрывает подключение
+        let err = request(&mut client, OP_DECOMPRESS, None, b"garbage").unwrap_err();
+        assert!(err.to_string().contains("not an archive"));
+        assert!(request(&mut client, OP_COMPRESS, None, &data).is_err());
+        assert_eq!(request(&mut client, OP_DECOMPRESS, None, &archive).unwrap(), data);
+
+        drop(client);
+        worker.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+//! Модуль для вывода времени изменения записей в списке (`--time-style`).
+//!
+//! Время показывается в часовом поясе компьютера (переменная `TZ` или системные настройки),
+//! чтобы содержимое резервной копии можно было сверять с датами изменения файлов на месте.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Как показывать время изменения записи.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TimeStyle {
+    /// Дата и время до минуты: `2024-05-01 12:30`
+    #[default]
+    Iso,
+    /// Дата и время до секунды со смещением пояса: `2024-05-01 12:30:45 +0300`
+    Full,
+    /// Время относительно текущего: `3 days ago`
+    Relative,
+}
+
+impl TimeStyle {
+    /// Определяет стиль по имени из командной строки (`iso`, `full`, `relative`).
+    pub fn from_name(name: &str) -> Option<TimeStyle> {
+        match name {
+            "iso" => Some(TimeStyle::Iso),
+            "full" => Some(TimeStyle::Full),
+            "relative" => Some(TimeStyle::Relative),
+            _ => None,
+        }
+    }
+
+    /// Форматирует время изменения.
+    ///
+    /// # Аргументы
+    ///
+    /// * `secs` - Время в секундах от начала эпохи Unix.
+    /// * `now` - Текущее время в секундах (для `Relative`).
+    ///
+    /// # Возвращает
+    ///
+    /// Строку в местном часовом поясе или относительное время.
+    pub fn format(self, secs: u64, now: u64) -> String {
+        match self {
+            TimeStyle::Relative => relative(secs, now),
+            TimeStyle::Iso | TimeStyle::Full => {
+                let Some(tm) = local_time(secs) else {
+                    return secs.to_string();
+                };
+                let date = format!("{:04}-{:02}-{:02} {:02}:{:02}", tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min);
+                if self == TimeStyle::Iso {
+                    return date;
+                }
+                let offset = tm.tm_gmtoff / 60;
+                let sign = if offset < 0 { '-' } else { '+' };
+                format!("{}:{:02} {}{:02}{:02}", date, tm.tm_sec, sign, offset.abs() / 60, offset.abs() % 60)
+            }
+        }
+    }
+}
+
+/// Возвращает текущее время в секундах от начала эпохи Unix.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Раскладывает время на поля календаря в местном часовом поясе.
+fn local_time(secs: u64) -> Option<libc::tm> {
+    let time = libc::time_t::try_from(secs).ok()?;
+    // SAFETY: обе ссылки действительны во время вызова; `localtime_r` заполняет `tm` целиком
+    // и, в отличие от `localtime`, не использует общий буфер
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return None;
+        }
+        Some(tm)
+    }
+}
+
+/// Описывает время относительно текущего крупнейшей целой единицей.
+fn relative(secs: u64, now: u64) -> String {
+    const UNITS: [(u64, &str); 5] = [(365 * 86400, "year"), (30 * 86400, "month"), (86400, "day"), (3600, "hour"), (60, "minute")];
+    let (distance, future) = if secs > now { (secs - now, true) } else { (now - secs, false) };
+    let Some(&(size, unit)) = UNITS.iter().find(|(size, _)| distance >= *size) else {
+        return "just now".to_owned();
+    };
+    let count = distance / size;
+    let plural = if count == 1 { "" } else { "s" };
+    if future {
+        format!("in {} {}{}", count, unit, plural)
+    } else {
+        format!("{} {}{} ago", count, unit, plural)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_styles() {
+        let now = 1_700_000_000;
+        assert_eq!(TimeStyle::Relative.format(now - 30, now), "just now");
+        assert_eq!(TimeStyle::Relative.format(now - 60, now), "1 minute ago");
+        assert_eq!(TimeStyle::Relative.format(now - 3 * 86400 - 5, now), "3 days ago");
+        assert_eq!(TimeStyle::Relative.format(now - 400 * 86400, now), "1 year ago");
+        assert_eq!(TimeStyle::Relative.format(now + 7200, now), "in 2 hours");
+        assert_eq!(TimeStyle::from_name("full"), Some(TimeStyle::Full));
+        assert_eq!(TimeStyle::from_name("long"), None);
+
+        // Поле смещения соответствует стилю в любом поясе, где запущен тест
+        let full = TimeStyle::Full.format(now, now);
+        assert_eq!(full.len(), "2023-11-14 22:13:20 +0000".len(), "{}", full);
+        assert!(full.starts_with(&TimeStyle::Iso.format(now, now)));
+    }
+}
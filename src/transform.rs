@@ -0,0 +1,167 @@
+//! Модуль для переименования записей при распаковке (`--transform`).
+//!
+//! Правило записывается как в `sed` и `tar --transform`: `s|шаблон|замена|флаги`, где вместо
+//! `|` может стоять любой знак, кроме букв, цифр и `\`; внутри правила он экранируется
+//! как `\|`. Шаблон — регулярное выражение в синтаксисе крейта `regex`. В замене `&` означает
+//! найденный текст, `\1`…`\9` — группы шаблона, а `\&` и `\\` — сами знаки. Флаг `g` заменяет
+//! все совпадения, а не только первое, флаг `i` не учитывает регистр. Несколько правил
+//! применяются к пути по очереди.
+
+use std::io;
+use regex::{Regex, RegexBuilder};
+use crate::io::{rename_entries, DirEntry};
+
+/// Правило переименования `s|шаблон|замена|флаги`.
+#[derive(Debug, Clone)]
+pub struct Transform {
+    /// Шаблон
+    regex: Regex,
+    /// Замена в синтаксисе `Regex::replace` (`${1}`, `$$`)
+    replacement: String,
+    /// Заменять все совпадения (флаг `g`)
+    global: bool,
+}
+
+/// Читает часть правила до неэкранированного разделителя.
+///
+/// # Возвращает
+///
+/// Знаки части с отметкой, был ли знак экранирован, или `None`, если разделителя нет.
+fn take_part(chars: &mut std::str::Chars, delimiter: char) -> Option<Vec<(char, bool)>> {
+    let mut part = Vec::new();
+    loop {
+        match chars.next()? {
+            '\\' => part.push((chars.next()?, true)),
+            c if c == delimiter => return Some(part),
+            c => part.push((c, false)),
+        }
+    }
+}
+
+impl Transform {
+    /// Разбирает правило для `--transform`, например `s|^old/|new/|`.
+    pub fn parse(spec: &str) -> Result<Transform, String> {
+        let invalid = |reason: &str| format!("invalid transform {} ({})", spec, reason);
+        let mut chars = spec.chars();
+        let delimiter = match (chars.next(), chars.next()) {
+            (Some('s'), Some(d)) if d != '\\' && !d.is_alphanumeric() => d,
+            _ => return Err(invalid("expected s|regex|replacement|[flags]")),
+        };
+        let unterminated = || invalid("unterminated expression");
+        let pattern = take_part(&mut chars, delimiter).ok_or_else(unterminated)?;
+        let replacement = take_part(&mut chars, delimiter).ok_or_else(unterminated)?;
+
+        let pattern: String = pattern.into_iter().map(|(c, escaped)| match (c, escaped) {
+            (c, true) if c == delimiter => regex::escape(&c.to_string()),
+            (c, true) => format!("\\{}", c),
+            (c, false) => c.to_string(),
+        }).collect();
+        let replacement: String = replacement.into_iter().map(|(c, escaped)| match (c, escaped) {
+            ('&', false) => "${0}".to_owned(),
+            (c, true) if c.is_ascii_digit() => format!("${{{}}}", c),
+            ('$', _) => "$$".to_owned(),
+            (c, _) => c.to_string(),
+        }).collect();
+
+        let (mut global, mut insensitive) = (false, false);
+        for flag in chars {
+            match flag {
+                'g' => global = true,
+                'i' => insensitive = true,
+                _ => return Err(invalid(&format!("unknown flag {}", flag))),
+            }
+        }
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(insensitive)
+            .build()
+            .map_err(|e| invalid(&e.to_string()))?;
+        Ok(Transform { regex, replacement, global })
+    }
+
+    /// Применяет правило к пути.
+    pub fn apply(&self, path: &str) -> String {
+        if self.global {
+            self.regex.replace_all(path, self.replacement.as_str()).into_owned()
+        } else {
+            self.regex.replace(path, self.replacement.as_str()).into_owned()
+        }
+    }
+}
+
+/// Применяет правила к путям записей по очереди и нормализует результат.
+///
+/// Пустые компоненты и `.` убираются, а записи, путь которых стал пустым, удаляются
+/// (см. `io::rename_entries`).
+///
+/// # Возвращает
+///
+/// Новый путь или ошибку, если он выходит за директорию распаковки.
+fn transform_path(transforms: &[Transform], path: &str) -> Result<String, String> {
+    let path = transforms.iter().fold(path.to_owned(), |path, transform| transform.apply(&path));
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+    if components.contains(&"..") {
+        return Err(format!("transformed path {} leaves the output directory", path));
+    }
+    Ok(components.join("/"))
+}
+
+/// Переименовывает записи архива по правилам перед распаковкой.
+///
+/// Жесткие ссылки и дубликаты указывают на переименованные пути, а цели символических ссылок
+/// не меняются. Архив одного файла не переименовывается.
+///
+/// # Аргументы
+///
+/// * `entries` - Записи архива.
+/// * `transforms` - Правила в порядке применения.
+///
+/// # Возвращает
+///
+/// `Ok(())` или ошибку `InvalidInput`, если новый путь выходит за директорию распаковки.
+pub fn apply_all(entries: &mut Vec<DirEntry>, transforms: &[Transform]) -> io::Result<()> {
+    if transforms.is_empty() {
+        return Ok(());
+    }
+    let mut error = None;
+    rename_entries(entries, |path| transform_path(transforms, path)
+        .map_err(|e| error.get_or_insert(e).clone())
+        .ok());
+    match error {
+        Some(e) => Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::io::HARD_LINK_FLAG;
+
+    #[test]
+    fn test_transform() {
+        let rule = |spec| Transform::parse(spec).unwrap();
+        assert_eq!(rule("s|^old/|new/|").apply("old/old/a.txt"), "new/old/a.txt");
+        assert_eq!(rule("s/o/0/g").apply("foo/bar.log"), "f00/bar.l0g");
+        assert_eq!(rule(r"s,\((\d+)\)\.LOG$,[&] \1.$log,i").apply("run(42).log"), r"run[(42).log] 42.$log");
+        assert_eq!(rule(r"s|a\|b|c\|d|").apply("xa|by"), "xc|dy");
+        assert_eq!(rule(r"s#^(\w+)/(\w+)$#\2/\1#").apply("one/two"), "two/one");
+        for spec in ["x|a|b|", "s|a|b", "s|a", "sa|b|", "s|a|b|q", "s|(|b|"] {
+            assert!(Transform::parse(spec).is_err(), "{}", spec);
+        }
+
+        let entry = |path: &str, permissions, data: &[u8]| DirEntry { path: path.into(), data: data.to_vec(), permissions, crc32: None, owner: None, metadata: BTreeMap::new(), mtime: None };
+        let mut entries = vec![
+            entry("src", 0o40755, b""),
+            entry("src/a.rs", 0o100644, b"fn main() {}"),
+            entry("src/b.rs", 0o100644 | HARD_LINK_FLAG, b"src/a.rs"),
+            entry("src/link", 0o120777, b"a.rs"),
+        ];
+        apply_all(&mut entries, &[rule("s|^src|lib|"), rule("s|^lib$||")]).unwrap();
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["lib/a.rs", "lib/b.rs", "lib/link"]);
+        assert_eq!((entries[1].data.as_slice(), entries[2].data.as_slice()), (&b"lib/a.rs"[..], &b"a.rs"[..]));
+        let error = apply_all(&mut entries, &[rule("s|^|../|")]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,123 @@
+//! Модуль для вывода содержимого архива деревом (`--tree`).
+//!
+//! Записи архива собираются в иерархию директорий, и для каждой директории суммируются
+//! исходные и сжатые размеры вложенных записей, чтобы сразу было видно, какие поддиректории
+//! занимают большую часть архива. Вложенные узлы выводятся по убыванию исходного размера.
+
+use std::collections::BTreeMap;
+use crate::filter::EntryType;
+use crate::processing::ListedEntry;
+
+/// Узел дерева: запись архива или директория, в которой есть записи.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeNode {
+    /// Имя узла (последний компонент пути; у корня — `.`)
+    pub name: String,
+    /// Директория ли это (записанная в архив или только содержащая записи)
+    pub is_dir: bool,
+    /// Суммарный исходный размер записей узла в байтах
+    pub original: u64,
+    /// Суммарный сжатый размер; `None`, если размер хотя бы одной записи неизвестен
+    pub compressed: Option<u64>,
+    /// Число записей внутри узла, включая сам узел
+    pub entries: usize,
+    /// Вложенные узлы по имени
+    pub children: BTreeMap<String, TreeNode>,
+}
+
+impl TreeNode {
+    /// Создает пустой узел.
+    fn new(name: &str, is_dir: bool) -> Self {
+        TreeNode { name: name.to_owned(), is_dir, original: 0, compressed: Some(0), entries: 0, children: BTreeMap::new() }
+    }
+
+    /// Добавляет к узлу размеры одной записи.
+    fn add(&mut self, original: u64, compressed: Option<u64>) {
+        self.original += original;
+        self.compressed = self.compressed.zip(compressed).map(|(a, b)| a + b);
+        self.entries += 1;
+    }
+
+    /// Возвращает узлы дерева в порядке вывода: глубину, путь от корня и сам узел.
+    ///
+    /// Вложенные узлы идут сразу после родителя по убыванию исходного размера,
+    /// при равных размерах — по имени. Путь корня — `.`.
+    pub fn flatten(&self) -> Vec<(usize, String, &TreeNode)> {
+        fn visit<'a>(node: &'a TreeNode, depth: usize, path: String, out: &mut Vec<(usize, String, &'a TreeNode)>) {
+            let mut children: Vec<&TreeNode> = node.children.values().collect();
+            children.sort_by(|a, b| b.original.cmp(&a.original).then_with(|| a.name.cmp(&b.name)));
+            out.push((depth, path.clone(), node));
+            for child in children {
+                let child_path = if depth == 0 { child.name.clone() } else { format!("{}/{}", path, child.name) };
+                visit(child, depth + 1, child_path, out);
+            }
+        }
+        let mut out = Vec::new();
+        visit(self, 0, ".".to_owned(), &mut out);
+        out
+    }
+}
+
+/// Строит дерево из записей архива.
+///
+/// # Аргументы
+///
+/// * `entries` - Записи и их сжатые размеры, как их возвращает `processing::list_sizes`.
+///
+/// # Возвращает
+///
+/// Корень дерева с суммарными размерами всего архива.
+pub fn build(entries: &[(ListedEntry, Option<u64>)]) -> TreeNode {
+    let mut root = TreeNode::new(".", true);
+    for (entry, compressed) in entries {
+        let is_dir = EntryType::from_mode(entry.permissions) == EntryType::Dir;
+        let components: Vec<&str> = entry.path.split('/').filter(|c| !c.is_empty()).collect();
+        // Запись с пустым путем — единственный файл архива
+        root.is_dir &= !components.is_empty() || is_dir;
+        let mut node = &mut root;
+        node.add(entry.size, *compressed);
+        for (i, component) in components.iter().enumerate() {
+            let last = i + 1 == components.len();
+            node = node.children.entry(component.to_string())
+                .or_insert_with(|| TreeNode::new(component, !last || is_dir));
+            node.is_dir |= !last || is_dir;
+            node.add(entry.size, *compressed);
+        }
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_tree() {
+        let entry = |path: &str, permissions, size, compressed| {
+            (ListedEntry { path: path.into(), permissions, size, link: None, crc32: None, mtime: None }, compressed)
+        };
+        let entries = vec![
+            entry("docs", 0o40755, 0, Some(2)),
+            entry("docs/a.txt", 0o100644, 100, Some(40)),
+            entry("src/lib/x.rs", 0o100644, 500, Some(120)),
+            entry("src/main.rs", 0o100644, 300, None),
+        ];
+        let root = build(&entries);
+        assert_eq!((root.original, root.compressed, root.entries), (900, None, 4));
+        let docs = &root.children["docs"];
+        assert!(docs.is_dir);
+        assert_eq!((docs.original, docs.compressed, docs.entries), (100, Some(42), 2));
+        assert_eq!(root.children["src"].children["lib"].compressed, Some(120));
+
+        let order: Vec<(usize, String)> = root.flatten().into_iter().map(|(depth, path, _)| (depth, path)).collect();
+        assert_eq!(order, [
+            (0, ".".to_owned()), (1, "src".to_owned()), (2, "src/lib".to_owned()), (3, "src/lib/x.rs".to_owned()),
+            (2, "src/main.rs".to_owned()), (1, "docs".to_owned()), (2, "docs/a.txt".to_owned()),
+        ]);
+
+        // Архив одного файла — дерево из одного узла
+        let single = build(&[entry("", 0o100644, 7, Some(5))]);
+        assert!(!single.is_dir && single.children.is_empty());
+        assert_eq!((single.original, single.compressed), (7, Some(5)));
+    }
+}
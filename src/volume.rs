@@ -0,0 +1,174 @@
+//! Модуль для многотомных архивов.
+//!
+//! С `--volume-size` архив записывается не одним файлом, а последовательными томами
+//! `archive.001`, `archive.002`, ... не больше заданного размера, например для переноса
+//! на носители с ограниченным объемом. Тома — это просто части одного потока байтов:
+//! при распаковке они читаются по порядку и склеиваются, поэтому заголовки и трейлеры
+//! разбираются так же, как в архиве одним файлом.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use crate::crypto;
+use crate::io::STDIO_PATH;
+use crate::lock::ArchiveLock;
+use crate::partial;
+
+/// Разбирает размер тома: число байтов с необязательным суффиксом `K`, `M`, `G` или `T`
+/// (степени 1024), например `100M`.
+pub fn parse_size(spec: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid volume size {} (expected a number with an optional K, M, G or T suffix)", spec);
+    let (digits, multiplier) = match spec.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&spec[..spec.len() - 1], 1u64 << 10),
+        Some('M') => (&spec[..spec.len() - 1], 1 << 20),
+        Some('G') => (&spec[..spec.len() - 1], 1 << 30),
+        Some('T') => (&spec[..spec.len() - 1], 1 << 40),
+        _ => (spec, 1),
+    };
+    let size = digits.parse::<u64>().ok().and_then(|n| n.checked_mul(multiplier)).ok_or_else(invalid)?;
+    if size == 0 {
+        return Err(invalid());
+    }
+    Ok(size)
+}
+
+/// Возвращает путь тома с номером `number` (начиная с 1): `archive.001`.
+pub fn volume_path(base: &str, number: usize) -> String {
+    format!("{}.{:03}", base, number)
+}
+
+/// Записывает данные архива томами не больше `volume_size` байтов.
+///
+/// Тома с большими номерами, оставшиеся от прежнего архива с тем же именем, удаляются,
+/// чтобы при распаковке они не были приняты за продолжение нового архива.
+///
+/// # Аргументы
+///
+/// * `base` - Путь архива без номера тома.
+/// * `data` - Данные архива.
+/// * `volume_size` - Наибольший размер тома в байтах.
+///
+/// # Возвращает
+///
+/// Число записанных томов или ошибку ввода/вывода. При ошибке уже записанные тома
+/// удаляются: неполный набор томов вместе с томами прежнего архива не читался бы.
+pub fn write_volumes(base: &str, data: &[u8], volume_size: u64) -> io::Result<usize> {
+    let mut chunks: Vec<&[u8]> = data.chunks(volume_size.min(usize::MAX as u64) as usize).collect();
+    if chunks.is_empty() {
+        // Пустые данные все равно образуют один (пустой) том
+        chunks.push(data);
+    }
+    let mut count = 0;
+    for chunk in chunks {
+        if let Err(e) = partial::write_file(&volume_path(base, count + 1), chunk) {
+            for number in 1..=count {
+                let _ = fs::remove_file(volume_path(base, number));
+            }
+            return Err(e);
+        }
+        count += 1;
+    }
+    let mut stale = count + 1;
+    while Path::new(&volume_path(base, stale)).exists() {
+        fs::remove_file(volume_path(base, stale))?;
+        stale += 1;
+    }
+    Ok(count)
+}
+
+/// Определяет путь архива без номера тома, если `path` указывает на многотомный архив.
+///
+/// Многотомным считается путь первого тома (`archive.001`) или отсутствующий файл,
+/// рядом с которым есть первый том (`archive`).
+pub fn volume_base(path: &str) -> Option<&str> {
+    match path.strip_suffix(".001") {
+        Some(base) if !base.is_empty() => Some(base),
+        _ if !Path::new(path).exists() && Path::new(&volume_path(path, 1)).exists() => Some(path),
+        _ => None,
+    }
+}
+
+/// Читает архив одним файлом или все его тома по порядку под разделяемыми блокировками.
+///
+/// Прочитанные данные расшифровываются установленным шифром (см. `crypto::open`).
+///
+/// # Аргументы
+///
+/// * `path` - Путь к файлу архива, к первому тому или к архиву без номера тома.
+///
+/// # Возвращает
+///
+/// Содержимое архива или ошибку ввода/вывода либо шифра.
+pub fn read_archive(path: &str) -> io::Result<Vec<u8>> {
+    crypto::open(read_raw(path)?)
+}
+
+/// Читает файл архива или его тома как `read_archive`, но не расшифровывает их.
+///
+/// # Аргументы
+///
+/// * `path` - Путь к файлу архива, к первому тому или к архиву без номера тома;
+///   `-` — чтение архива из стандартного ввода.
+///
+/// # Возвращает
+///
+/// Байты в том виде, в каком они записаны на диск, или ошибку ввода/вывода.
+pub fn read_raw(path: &str) -> io::Result<Vec<u8>> {
+    if path == STDIO_PATH {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data)?;
+        return Ok(data);
+    }
+    let Some(base) = volume_base(path) else {
+        return ArchiveLock::shared(Path::new(path))?.read_all();
+    };
+    // Все тома блокируются до чтения, чтобы не склеить части разных архивов
+    let mut locks = Vec::new();
+    loop {
+        match ArchiveLock::shared(Path::new(&volume_path(base, locks.len() + 1))) {
+            Ok(lock) => locks.push(lock),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e),
+        }
+    }
+    let mut data = Vec::new();
+    for lock in &mut locks {
+        data.extend(lock.read_all()?);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volumes_round_trip() {
+        assert_eq!(parse_size("100M"), Ok(100 << 20));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("2k"), Ok(2048));
+        assert!(parse_size("0").is_err() && parse_size("M").is_err() && parse_size("1.5G").is_err());
+
+        let dir = std::env::temp_dir().join(format!("volume_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("archive.arc").to_str().unwrap().to_owned();
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(write_volumes(&base, &data, 100).unwrap(), 3);
+        assert_eq!(fs::metadata(volume_path(&base, 3)).unwrap().len(), 56);
+        assert_eq!(read_archive(&base).unwrap(), data);
+        assert_eq!(read_archive(&volume_path(&base, 1)).unwrap(), data);
+
+        // Лишние тома прежнего архива удаляются
+        assert_eq!(write_volumes(&base, &data[..150], 100).unwrap(), 2);
+        assert!(!Path::new(&volume_path(&base, 3)).exists());
+        assert_eq!(read_archive(&base).unwrap(), &data[..150]);
+
+        // Если второй том записать нельзя, первый удаляется и временных файлов не остается
+        fs::remove_file(volume_path(&base, 2)).unwrap();
+        fs::create_dir(volume_path(&base, 2)).unwrap();
+        assert!(write_volumes(&base, &data, 100).is_err());
+        assert!(!Path::new(&volume_path(&base, 1)).exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
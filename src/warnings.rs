@@ -0,0 +1,154 @@
+//! Модуль для некритичных предупреждений библиотеки.
+//!
+//! Распаковка и запись файлов могут столкнуться с проблемами, которые не прерывают
+//! операцию: лишние байты после архива, неверно указанный алгоритм, невосстановленный
+//! владелец файла. Такие проблемы сообщаются через `report`. По умолчанию предупреждение
+//! записывается в журнал (`log::warn!`), а внутри `capture` собирается в список, который
+//! вызывающий код получает после операции и может разобрать программно.
+
+use std::cell::RefCell;
+use std::fmt;
+
+/// Некритичная проблема, обнаруженная во время операции.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// После последнего архива остались нераспознанные байты, которые были отброшены
+    TrailingBytes {
+        /// Число отброшенных байтов
+        bytes: usize,
+    },
+    /// После данных потока алгоритма остались байты, которые были отброшены
+    TrailingStreamBytes {
+        /// Название алгоритма
+        algorithm: &'static str,
+        /// Число отброшенных байтов
+        bytes: usize,
+    },
+    /// Алгоритм, указанный явно, не совпал с алгоритмом из заголовка и был проигнорирован
+    AlgorithmMismatch {
+        /// Алгоритм из заголовка архива
+        stored: &'static str,
+        /// Указанный алгоритм
+        requested: &'static str,
+    },
+    /// Владельца распакованного файла не удалось восстановить
+    OwnerNotRestored {
+        /// Путь записи
+        path: String,
+        /// Описание ошибки
+        error: String,
+    },
+    /// Канал или устройство не удалось создать, и запись была пропущена
+    SpecialNotRestored {
+        /// Путь записи
+        path: String,
+        /// Описание ошибки
+        error: String,
+    },
+    /// Имя записи недопустимо в системе назначения, и запись распакована под другим именем
+    PathRenamed {
+        /// Путь записи в архиве
+        path: String,
+        /// Путь, под которым запись распакована
+        renamed: String,
+    },
+    /// Вложенный архив не удалось распаковать, и он оставлен как есть (`--recursive-extract`)
+    NestedNotExtracted {
+        /// Путь вложенного архива
+        path: String,
+        /// Описание ошибки
+        error: String,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::TrailingBytes { bytes } =>
+                write!(f, "{} trailing bytes after the last archive were ignored.", bytes),
+            Warning::TrailingStreamBytes { algorithm, bytes } =>
+                write!(f, "{} trailing bytes after {} data were ignored.", bytes, algorithm),
+            Warning::AlgorithmMismatch { stored, requested } =>
+                write!(f, "archive was compressed with {}, ignoring -a {}.", stored, requested),
+            Warning::OwnerNotRestored { path, error } =>
+                write!(f, "cannot restore owner of {}: {}", path, error),
+            Warning::SpecialNotRestored { path, error } =>
+                write!(f, "cannot create special file {}: {}, skipped", path, error),
+            Warning::PathRenamed { path, renamed } =>
+                write!(f, "{:?} is not a valid name here, extracted as {}", path, renamed),
+            Warning::NestedNotExtracted { path, error } =>
+                write!(f, "nested archive {} was left as is: {}", path, error),
+        }
+    }
+}
+
+thread_local! {
+    /// Список, в который собираются предупреждения текущего потока внутри `capture`.
+    static COLLECTED: RefCell<Option<Vec<Warning>>> = const { RefCell::new(None) };
+}
+
+/// Сообщает о предупреждении: добавляет его в список `capture` или записывает в журнал.
+pub fn report(warning: Warning) {
+    let unclaimed = COLLECTED.with(|collected| match collected.borrow_mut().as_mut() {
+        Some(list) => {
+            list.push(warning);
+            None
+        }
+        None => Some(warning),
+    });
+    if let Some(warning) = unclaimed {
+        log::warn!("{}", warning);
+    }
+}
+
+/// Выполняет операцию, собирая предупреждения вместо вывода в stderr.
+///
+/// Собираются предупреждения, сообщенные в текущем потоке; вложенный вызов собирает
+/// свои предупреждения отдельно от внешнего.
+///
+/// # Аргументы
+///
+/// * `operation` - Выполняемая операция.
+///
+/// # Возвращает
+///
+/// Результат операции и предупреждения в порядке их появления.
+pub fn capture<T>(operation: impl FnOnce() -> T) -> (T, Vec<Warning>) {
+    /// Восстанавливает внешний список и при панике внутри операции.
+    struct Restore(Option<Vec<Warning>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            COLLECTED.with(|collected| *collected.borrow_mut() = self.0.take());
+        }
+    }
+
+    let outer = COLLECTED.with(|collected| collected.borrow_mut().replace(Vec::new()));
+    let restore = Restore(outer);
+    let result = operation();
+    let warnings = COLLECTED.with(|collected| collected.borrow_mut().take()).unwrap_or_default();
+    drop(restore);
+    (result, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io;
+    use crate::ArchiveData;
+
+    #[test]
+    fn test_capture_warnings() {
+        let mut data = io::archive_data_to_bytes(&ArchiveData { entries: Vec::new() });
+        data.extend_from_slice(b"garbage");
+        let (archive, warnings) = capture(|| io::bytes_to_archive_data(&data, false));
+        assert!(archive.unwrap().entries.is_empty());
+        assert_eq!(warnings, vec![Warning::TrailingBytes { bytes: 7 }]);
+
+        let (_, outer) = capture(|| {
+            report(Warning::TrailingBytes { bytes: 1 });
+            let (_, inner) = capture(|| report(Warning::TrailingBytes { bytes: 2 }));
+            assert_eq!(inner, vec![Warning::TrailingBytes { bytes: 2 }]);
+        });
+        assert_eq!(outer, vec![Warning::TrailingBytes { bytes: 1 }]);
+    }
+}
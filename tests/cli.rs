@@ -0,0 +1,990 @@
+//! Сквозные тесты командной строки на настоящих деревьях файлов.
+//!
+//! Каждый тест строит во временной директории дерево (вложенные директории, пустые
+//! и большие файлы, разные права, одинаковые файлы), сжимает его бинарником программы
+//! каждым алгоритмом с разными флагами, проверяет архив (`-t`), распаковывает и сравнивает
+//! файлы с исходными. Пустые директории и права директорий в архиве не хранятся, поэтому
+//! сравниваются только файлы: путь, содержимое и права.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Путь к собранному бинарнику программы.
+const BIN: &str = env!("CARGO_BIN_EXE_rle_archiver");
+
+/// Наборы флагов сжатия, с которыми проверяется каждый алгоритм.
+const FLAG_SETS: &[&[&str]] = &[
+    &[],
+    &["--solid"],
+    &["--solid", "--chunk-dedup"],
+    &["--block-size", "4096"],
+    &["--pack-below", "1024"],
+    &["-m"],
+    &["--no-dedup"],
+    &["--short-matches"],
+];
+
+/// Создает пустую временную директорию для теста.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rle_archiver_cli_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Псевдослучайные байты, одинаковые при каждом запуске.
+fn noise(len: usize, mut state: u32) -> Vec<u8> {
+    (0..len).map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (state >> 16) as u8
+    }).collect()
+}
+
+/// Записывает файл с правами `mode`, создавая родительские директории.
+fn put(root: &Path, path: &str, data: &[u8], mode: u32) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, data).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+}
+
+/// Устанавливает время изменения файла в секундах от начала эпохи Unix.
+fn set_mtime(path: &Path, secs: u64) {
+    let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+    fs::File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+}
+
+/// Строит дерево для сжатия.
+fn build_tree(root: &Path) {
+    // Размеры подобраны так, чтобы файлы занимали несколько блоков `--block-size`, а тесты
+    // отладочной сборки оставались быстрыми: LZ77 без оптимизаций сжимает около 100 КБ/с
+    let text: Vec<u8> = (0..1500).flat_map(|i| format!("line {} of the log\n", i % 700).into_bytes()).collect();
+    put(root, "readme.txt", b"hello, archive\n", 0o644);
+    put(root, "empty", b"", 0o644);
+    put(root, "bin/tool", &b"#!/bin/sh\necho tool\n".repeat(40), 0o755);
+    put(root, "secret/key", b"0123456789abcdef", 0o600);
+    put(root, "docs/readonly.md", b"# read only\n", 0o444);
+    put(root, "a/b/c/d/deep.txt", b"deeply nested", 0o640);
+    put(root, "a/b/empty-too", b"", 0o600);
+    put(root, "logs/app.log", &text, 0o644);
+    put(root, "logs/app.log.1", &text, 0o644);
+    put(root, "data/noise.bin", &noise(12_000, 7), 0o644);
+    put(root, "data/mixed.bin", &[noise(4_000, 11), text[..8_000].to_vec(), vec![0; 8_000]].concat(), 0o664);
+    for i in 0..40 {
+        put(root, &format!("many/file{:02}.txt", i), format!("small file {}\n", i).repeat(i + 1).as_bytes(), 0o644);
+    }
+    fs::create_dir_all(root.join("empty-dir")).unwrap();
+}
+
+/// Собирает файлы дерева: относительный путь, права и содержимое.
+fn snapshot(root: &Path) -> BTreeMap<String, (u32, Vec<u8>)> {
+    fn walk(root: &Path, dir: &Path, files: &mut BTreeMap<String, (u32, Vec<u8>)>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let meta = fs::symlink_metadata(&path).unwrap();
+            if meta.is_dir() {
+                walk(root, &path, files);
+            } else {
+                let rel = path.strip_prefix(root).unwrap().to_string_lossy().into_owned();
+                files.insert(rel, (meta.permissions().mode() & 0o7777, fs::read(&path).unwrap()));
+            }
+        }
+    }
+    let mut files = BTreeMap::new();
+    walk(root, root, &mut files);
+    files
+}
+
+/// Запускает программу и проверяет, что она завершилась успешно.
+fn run(args: &[&str]) -> String {
+    let output = Command::new(BIN).args(args).output().unwrap();
+    assert!(output.status.success(), "rle_archiver {:?} failed:\n{}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Запускает программу с данными на стандартном вводе.
+fn pipe(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(BIN).args(args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    // Ввод пишется из отдельного потока, чтобы заполненный канал вывода не блокировал программу
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().unwrap();
+    // Программа может завершиться, не дочитав ввод
+    let _ = writer.join().unwrap();
+    output
+}
+
+/// Запускает программу с данными на стандартном вводе и проверяет, что она завершилась успешно.
+fn pipe_ok(args: &[&str], input: &[u8]) -> Vec<u8> {
+    let output = pipe(args, input);
+    assert!(output.status.success(), "rle_archiver {:?} failed:\n{}", args, String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+/// Сжимает дерево алгоритмом со всеми наборами флагов, проверяет и распаковывает архивы.
+fn round_trip(algorithm: &str) {
+    let dir = scratch(algorithm);
+    let src = dir.join("src");
+    build_tree(&src);
+    let expected = snapshot(&src);
+    for (i, flags) in FLAG_SETS.iter().enumerate() {
+        let archive = dir.join(format!("tree{}.arc", i)).to_string_lossy().into_owned();
+        let out = dir.join(format!("out{}", i)).to_string_lossy().into_owned();
+        let mut args = vec!["-c", "-a", algorithm, "-i", src.to_str().unwrap(), "-o", &archive];
+        args.extend_from_slice(flags);
+        run(&args);
+        let tested = run(&["-t", "--porcelain", "-i", &archive]);
+        assert_eq!(tested.lines().count(), expected.len(), "{} {:?}", algorithm, flags);
+        assert!(tested.lines().all(|line| line.starts_with("ok\t")), "{} {:?}:\n{}", algorithm, flags, tested);
+        run(&["-d", "-m", "-i", &archive, "-o", &out]);
+        assert!(snapshot(Path::new(&out)) == expected, "{} {:?}: extracted tree differs", algorithm, flags);
+    }
+
+    // Одиночный файл распаковывается в файл с тем же содержимым
+    let single = dir.join("single.arc").to_string_lossy().into_owned();
+    let restored = dir.join("single.out").to_string_lossy().into_owned();
+    run(&["-c", "-a", algorithm, "-i", src.join("logs/app.log").to_str().unwrap(), "-o", &single]);
+    run(&["-d", "-i", &single, "-o", &restored]);
+    assert_eq!(fs::read(&restored).unwrap(), expected["logs/app.log"].1);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_round_trip_rle() {
+    round_trip("rle");
+}
+
+#[test]
+fn test_round_trip_lz77() {
+    round_trip("lz77");
+}
+
+#[test]
+fn test_round_trip_lz4() {
+    round_trip("lz4");
+}
+
+#[test]
+fn test_round_trip_lzw() {
+    round_trip("lzw");
+}
+
+#[test]
+fn test_round_trip_hf() {
+    round_trip("hf");
+}
+
+#[test]
+fn test_stdin_stdout_pipeline() {
+    let data = [noise(3_000, 3), b"pipeline ".repeat(2_000)].concat();
+    let archive = pipe_ok(&["-c", "-a", "lz4", "-i", "-", "-o", "-"], &data);
+    assert!(archive.starts_with(b"RARC"));
+    assert_eq!(pipe_ok(&["-d", "-i", "-", "-o", "-"], &archive), data);
+    assert!(String::from_utf8(pipe_ok(&["-t", "--porcelain", "-i", "-"], &archive)).unwrap().starts_with("ok\t"));
+
+    // Дерево из нескольких файлов нельзя распаковать в стандартный вывод
+    let dir = scratch("pipeline");
+    put(&dir, "src/a", b"first", 0o644);
+    put(&dir, "src/b", b"second", 0o644);
+    let tree = pipe_ok(&["-c", "-a", "lz4", "-i", dir.join("src").to_str().unwrap(), "-o", "-"], b"");
+    let output = pipe(&["-d", "-i", "-", "-o", "-"], &tree);
+    assert!(!output.status.success() && output.stdout.is_empty());
+    // Ничего, кроме исходного дерева, не записано
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_subcommands() {
+    let dir = scratch("subcommands");
+    let src = dir.join("src");
+    build_tree(&src);
+    let expected = snapshot(&src);
+    let archive = dir.join("tree.arc").to_string_lossy().into_owned();
+    let out = dir.join("out").to_string_lossy().into_owned();
+    run(&["compress", "--algorithm", "lz4", "--input", src.to_str().unwrap(), "--output", &archive, "--multithread"]);
+    let tested = run(&["test", "--porcelain", "--input", &archive]);
+    assert_eq!(tested.lines().filter(|line| line.starts_with("ok\t")).count(), expected.len());
+    assert_eq!(run(&["list", "--input", &archive]), run(&["-l", "-i", &archive]));
+    let listed = run(&["list", "--format", "json", "--input", &archive]);
+    assert!(listed.starts_with("[{\"path\":") && listed.trim_end().ends_with("}]"), "{}", listed);
+    assert_eq!(listed.matches("\"type\":\"file\"").count(), expected.len());
+    assert!(run(&["info", "--format", "json", "--input", &archive]).starts_with("{\"encrypted\":false,\"archives\":[{\"archive\":1,"));
+    run(&["extract", "--input", &archive, "--output", &out]);
+    assert!(snapshot(Path::new(&out)) == expected);
+    assert_eq!(run(&["cat", "--input", &archive, "readme.txt"]), "hello, archive\n");
+    assert_eq!(run(&["cat", "-i", &archive, "--path", "secret/key"]), "0123456789abcdef");
+    assert_eq!(run(&["grep", "-i", &archive, "-F", "deeply"]), "a/b/c/d/deep.txt:1:deeply nested\n");
+    let found = run(&["grep", "--input", &archive, r"^line 69\d of"]);
+    assert_eq!(found.lines().count(), 2 * 20);
+    assert!(found.contains("logs/app.log:691:line 690 of the log\n"), "{}", found);
+    assert_eq!(Command::new(BIN).args(["grep", "-i", &archive, "no such text"]).output().unwrap().status.code(), Some(1));
+    assert_eq!(run(&["diff", "-i", &archive, "-o", &out]), "");
+    fs::write(Path::new(&out).join("readme.txt"), b"hello, changed\n").unwrap();
+    set_mtime(&Path::new(&out).join("readme.txt"), 1_600_000_000);
+    fs::remove_file(Path::new(&out).join("secret/key")).unwrap();
+    fs::write(Path::new(&out).join("extra.txt"), b"extra").unwrap();
+    let diff = Command::new(BIN).args(["diff", "--porcelain", "-i", &archive, "-o", &out]).output().unwrap();
+    assert_eq!(diff.status.code(), Some(1));
+    assert_eq!(String::from_utf8(diff.stdout).unwrap(), "extra\textra.txt\nmissing\tsecret/key\nmodified\treadme.txt\tcontent,mtime\n");
+
+    // Прежняя запись флагами остается псевдонимом подкоманд
+    let legacy = dir.join("legacy").to_string_lossy().into_owned();
+    run(&["--decompress", "-i", &archive, "-o", &legacy]);
+    assert!(snapshot(Path::new(&legacy)) == expected);
+    // Подкоманда вместе с другим режимом отклоняется
+    assert!(!Command::new(BIN).args(["extract", "-c", "-i", &archive]).output().unwrap().status.success());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_log_levels() {
+    let mut archive = pipe_ok(&["-c", "-a", "rle", "-i", "-", "-o", "-"], b"logged data");
+    archive.extend_from_slice(b"junk");
+    // Предупреждение библиотеки выводится журналом, а -q оставляет только ошибки
+    let output = pipe(&["-d", "-i", "-", "-o", "-"], &archive);
+    assert_eq!(output.stdout, b"logged data");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Warning: 4 trailing bytes after the last archive were ignored."));
+    let quiet = pipe(&["-d", "-q", "-i", "-", "-o", "-"], &archive);
+    assert!(quiet.status.success() && !String::from_utf8_lossy(&quiet.stderr).contains("Warning"));
+    let silent = pipe(&["-d", "--log-level", "off", "-i", "-", "-o", "-"], &archive);
+    assert!(!String::from_utf8_lossy(&silent.stderr).contains("Warning"));
+    // Предупреждения библиотеки о файле состояния тоже идут через журнал
+    let status = ["-c", "-a", "rle", "-i", "-", "-o", "-", "--status-file", "/nonexistent/status"];
+    assert!(String::from_utf8_lossy(&pipe(&status, b"x").stderr).contains("Warning: cannot update status file"));
+    assert!(!String::from_utf8_lossy(&pipe(&[&status[..], &["-q"]].concat(), b"x").stderr).contains("status file"));
+    // Причина отказа — ошибка, поэтому -q ее не скрывает
+    let refused = pipe(&["-c", "-q", "-a", "rle", "-i", "-", "-o", "-"], &archive);
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("Error: standard input is already an archive"));
+}
+
+#[test]
+fn test_strip_components() {
+    let dir = scratch("strip");
+    put(&dir, "src/top/nums.txt", b"1 2 3\n", 0o644);
+    put(&dir, "src/other.txt", b"other\n", 0o644);
+    let archive = dir.join("tree.arc").to_string_lossy().into_owned();
+    run(&["-c", "-a", "lz4", "-i", dir.join("src").to_str().unwrap(), "-o", &archive]);
+    // После отбрасывания первого компонента остается одна запись, но архив — дерево,
+    // поэтому она распаковывается в директорию, а не в файл по пути -o
+    let out = dir.join("st");
+    run(&["-d", "--strip-components", "1", "-i", &archive, "-o", out.to_str().unwrap()]);
+    assert_eq!(snapshot(&out), BTreeMap::from([("nums.txt".to_owned(), (0o644, b"1 2 3\n".to_vec()))]));
+    // Дерево из одного файла тоже остается деревом
+    let one = dir.join("one.arc").to_string_lossy().into_owned();
+    run(&["-c", "-a", "lz4", "-i", dir.join("src/top").to_str().unwrap(), "-o", &one]);
+    let out = dir.join("one");
+    run(&["-d", "-i", &one, "-o", out.to_str().unwrap()]);
+    assert!(out.join("nums.txt").is_file());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_mode_options() {
+    let dir = scratch("modes");
+    put(&dir, "src/a.txt", b"mode options\n", 0o644);
+    let src = dir.join("src").to_string_lossy().into_owned();
+    let archive = dir.join("tree.arc").to_string_lossy().into_owned();
+    let out = dir.join("out").to_string_lossy().into_owned();
+    // Параметры другого режима отклоняются до начала работы, и архив не создается
+    let rejected: &[&[&str]] = &[
+        &["compress", "--salvage"],
+        &["compress", "--threads", "2"],
+        &["compress", "--strip-components", "1"],
+        &["compress", "--overwrite", "skip"],
+        &["compress", "--type", "f"],
+        &["compress", "--report", "r.txt"],
+        &["compress", "--transform", "s|a|b|"],
+        &["compress", "--link-dupes"],
+        &["compress", "--chunk-dedup"],
+        &["compress", "--format", "json"],
+        &["compress", "readme.txt"],
+    ];
+    for args in rejected {
+        let mut full = args.to_vec();
+        full.extend_from_slice(&["-a", "lz4", "-i", &src, "-o", &archive]);
+        let output = Command::new(BIN).args(&full).output().unwrap();
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("can only be used with"), "{:?}", args);
+        assert!(!Path::new(&archive).exists(), "{:?}", args);
+    }
+    run(&["compress", "-a", "lz4", "--solid", "--chunk-dedup", "-i", &src, "-o", &archive]);
+    let info = Command::new(BIN).args(["info", "--depth", "deep", "-i", &archive]).output().unwrap();
+    assert_eq!(info.status.code(), Some(2));
+    // Значения по умолчанию и параметры своего режима принимаются
+    run(&["extract", "--overwrite", "force", "--fsync", "off", "--threads", "2", "-i", &archive, "-o", &out]);
+    run(&["--audit", "--depth", "deep", "-i", &archive]);
+    assert!(run(&["info", "--format", "json", "-i", &archive]).starts_with('{'));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_list_filters() {
+    let dir = scratch("list_filters");
+    let src = dir.join("src");
+    build_tree(&src);
+    let archive = dir.join("tree.arc").to_string_lossy().into_owned();
+    run(&["-c", "-a", "lz4", "-i", src.to_str().unwrap(), "-o", &archive]);
+    let paths = |listed: String| listed.lines().map(|line| line.split('\t').nth(2).unwrap().to_owned()).collect::<Vec<_>>();
+    assert_eq!(paths(run(&["list", "--porcelain", "--perm", "-0100", "-i", &archive])), ["bin/tool"]);
+    let mut private = paths(run(&["list", "--porcelain", "--type", "f", "--perm", "600", "-i", &archive]));
+    private.sort();
+    assert_eq!(private, ["a/b/empty-too", "secret/key"]);
+    let json = run(&["list", "--format", "json", "--perm", "444", "-i", &archive]);
+    assert!(json.starts_with("[{\"path\":\"docs/readonly.md\"") && json.matches("\"path\"").count() == 1, "{}", json);
+    // Директории и ссылки в архиве не хранятся, поэтому такие типы отклоняются
+    for kind in ["d", "l"] {
+        let output = Command::new(BIN).args(["list", "--type", kind, "-i", &archive]).output().unwrap();
+        assert_eq!(output.status.code(), Some(2));
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_extract_threads() {
+    let dir = scratch("threads");
+    let src = dir.join("src");
+    build_tree(&src);
+    let expected = snapshot(&src);
+    let archive = dir.join("tree.arc").to_string_lossy().into_owned();
+    run(&["-c", "-a", "lz4", "-i", src.to_str().unwrap(), "-o", &archive]);
+    // Число потоков из --threads доходит до распаковки записей, а не только до их записи
+    for (flags, threads) in [(&["--threads", "3"][..], 3), (&["-m"][..], 4), (&[][..], 1)] {
+        let out = dir.join(format!("out{}", threads));
+        let mut args = vec!["extract", "-vv", "-i", &archive, "-o", out.to_str().unwrap()];
+        args.extend_from_slice(flags);
+        let output = Command::new(BIN).args(&args).output().unwrap();
+        assert!(output.status.success());
+        let log = String::from_utf8_lossy(&output.stderr);
+        assert!(log.contains(&format!("decoding {} entries, threads: {}", expected.len(), threads)), "{:?}:\n{}", flags, log);
+        assert!(snapshot(&out) == expected, "{:?}", flags);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_restore_rate() {
+    let dir = scratch("restore_rate");
+    put(&dir, "tiny.txt", b"tiny", 0o644);
+    put(&dir, "large.txt", &b"restore rate of a large file\n".repeat(40_000), 0o644);
+    // Скорость распаковки нескольких байтов не измеряется, поэтому медленный алгоритм
+    // не вызывает предупреждения о восстановлении
+    let tiny = dir.join("tiny.arc").to_string_lossy().into_owned();
+    run(&["-c", "-a", "hf", "-i", dir.join("tiny.txt").to_str().unwrap(), "-o", &tiny]);
+    let output = Command::new(BIN).args(["info", "-i", &tiny]).output().unwrap();
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Restore:"));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("Warning"));
+    // Для мегабайта данных скорость записывается
+    let large = dir.join("large.arc").to_string_lossy().into_owned();
+    run(&["-c", "-a", "lz4", "-i", dir.join("large.txt").to_str().unwrap(), "-o", &large]);
+    assert!(run(&["info", "-i", &large]).contains("Restore:    ~"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_list_cache() {
+    let dir = scratch("list_cache");
+    let src = dir.join("src");
+    build_tree(&src);
+    let cache = dir.join("cache");
+    let archive = dir.join("tree.arc").to_string_lossy().into_owned();
+    run(&["-c", "-a", "lz4", "--solid", "-i", src.to_str().unwrap(), "-o", &archive]);
+    let list = |extra: &[&str]| {
+        let output = Command::new(BIN).args(["list", "--porcelain", "-i", &archive]).args(extra)
+            .env("XDG_CACHE_HOME", &cache).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let cached_files = || fs::read_dir(cache.join("archiver")).map_or(0, |files| files.count());
+    let uncached = list(&["--no-cache"]);
+    assert_eq!(cached_files(), 0);
+    // Первый вызов сохраняет список, повторный выводит то же из кеша
+    assert_eq!(list(&[]), uncached);
+    assert_eq!(cached_files(), 1);
+    assert_eq!(list(&[]), uncached);
+    assert_eq!(list(&["--perm", "755"]).lines().count(), 1);
+    // Измененный архив получает новый ключ, и прежний список не используется
+    fs::write(src.join("added.txt"), b"added").unwrap();
+    run(&["-c", "-a", "lz4", "--solid", "-i", src.to_str().unwrap(), "-o", &archive]);
+    assert!(list(&[]).contains("\tadded.txt\t"));
+    assert_eq!(cached_files(), 2);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_owner_map() {
+    use std::os::unix::fs::MetadataExt;
+    let dir = scratch("owner_map");
+    put(&dir, "src/data.txt", b"owned", 0o644);
+    put(&dir, "src/other.txt", b"kept", 0o644);
+    // Владельца можно сменить и восстановить только от имени root
+    let root = std::os::unix::fs::chown(dir.join("src/data.txt"), Some(4321), Some(4322)).is_ok();
+    let archive = dir.join("tree.arc").to_string_lossy().into_owned();
+    run(&["-c", "-a", "lz4", "-i", dir.join("src").to_str().unwrap(), "-o", &archive]);
+    let map = dir.join("owners.map");
+    fs::write(&map, "# old server\nuser 4321 1234\ngroup 4322 1235\n").unwrap();
+    let out = dir.join("out");
+    run(&["extract", "--owner-map", map.to_str().unwrap(), "-i", &archive, "-o", out.to_str().unwrap()]);
+    assert!(snapshot(&out).len() == 2);
+    if root {
+        let data = fs::metadata(out.join("data.txt")).unwrap();
+        assert_eq!((data.uid(), data.gid()), (1234, 1235));
+        let other = fs::metadata(out.join("other.txt")).unwrap();
+        assert_eq!((other.uid(), other.gid()), (0, 0));
+    }
+    // Неверная таблица отклоняется до распаковки
+    fs::write(&map, "user 4321\n").unwrap();
+    let output = Command::new(BIN).args(["extract", "--owner-map", map.to_str().unwrap(), "-i", &archive, "-o", dir.join("bad").to_str().unwrap()]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("owner map line 1"));
+    assert!(!dir.join("bad").exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_bench_corpus() {
+    let dir = scratch("bench_corpus");
+    put(&dir, "corpus/a,b.TXT", b"comma in the name\n", 0o644);
+    put(&dir, "corpus/sub/noise", &noise(2_000, 5), 0o644);
+    let corpus = dir.join("corpus").to_string_lossy().into_owned();
+    // Без -o отчет выводится в стандартный вывод: строка на каждую пару файла и алгоритма
+    let csv = run(&["--bench-corpus", &corpus]);
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("file,type,algorithm,original_size,compressed_size,ratio,compress_mb_s,decompress_mb_s,round_trip"));
+    let rows: Vec<_> = lines.collect();
+    assert_eq!(rows.len(), 2 * 5);
+    for algorithm in ["rle", "lz77", "lz4", "lzw", "hf"] {
+        assert!(rows.iter().any(|row| row.starts_with(&format!("\"a,b.TXT\",txt,{},18,", algorithm))), "{}", csv);
+        assert!(rows.iter().any(|row| row.starts_with(&format!("sub/noise,-,{},2000,", algorithm))), "{}", csv);
+    }
+    assert!(rows.iter().all(|row| row.ends_with(",ok")), "{}", csv);
+    // С -o отчет записывается в файл, а в stderr выводится сводка по типам
+    let report = dir.join("report.csv");
+    let output = Command::new(BIN).args(["--bench-corpus", &corpus, "-o", report.to_str().unwrap()]).output().unwrap();
+    assert!(output.status.success() && output.stdout.is_empty());
+    assert_eq!(fs::read_to_string(&report).unwrap().lines().count(), 1 + 2 * 5);
+    let summary = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(summary.lines().count(), 2 * 5);
+    assert!(summary.lines().any(|line| line.starts_with("txt      lz4 ")), "{}", summary);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_archive_input_checks() {
+    let dir = scratch("input_checks");
+    put(&dir, "plain.txt", b"not an archive\n", 0o644);
+    let plain = dir.join("plain.txt").to_string_lossy().into_owned();
+    let archive = dir.join("plain.arc").to_string_lossy().into_owned();
+    let twice = dir.join("twice.arc").to_string_lossy().into_owned();
+    run(&["-c", "-a", "lz4", "-i", &plain, "-o", &archive]);
+    // Сжатие архива, скорее всего, означает перепутанные -c и -d
+    let output = Command::new(BIN).args(["-c", "-a", "lz4", "-i", &archive, "-o", &twice]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is already an archive (did you mean -d?)"));
+    assert!(!Path::new(&twice).exists());
+    // С --recompress архив упаковывается еще раз и распаковывается обратно в исходный архив
+    run(&["-c", "-a", "lz4", "--recompress", "-i", &archive, "-o", &twice]);
+    let back = dir.join("back.arc");
+    run(&["-d", "-i", &twice, "-o", back.to_str().unwrap()]);
+    assert_eq!(fs::read(&back).unwrap(), fs::read(&archive).unwrap());
+    // Распаковка файла без заголовка объясняет, что это не архив
+    let output = Command::new(BIN).args(["-d", "-i", &plain, "-o", dir.join("out").to_str().unwrap()]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("plain.txt: not an archive (did you mean -c?)"));
+    assert!(!dir.join("out").exists());
+    // Ошибка чтения входа печатается с путем, без паники
+    let failed = dir.join("failed.arc");
+    let output = Command::new(BIN).args(["-c", "-a", "lz4", "-i", "/proc/self/mem", "-o", failed.to_str().unwrap()]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("/proc/self/mem: "), "{}", stderr);
+    assert!(!stderr.contains("panicked"));
+    assert!(!failed.exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_interrupt() {
+    let dir = scratch("interrupt");
+    // Отладочная сборка сжимает LZ77 медленно, поэтому сигнал приходит задолго до конца.
+    // С --reproducible скорость распаковки не замеряется, и сигнал застает сжатие записей
+    for i in 0..20 {
+        put(&dir, &format!("src/file{:02}", i), &noise(20_000, i), 0o644);
+    }
+    let archive = dir.join("tree.arc");
+    let child = Command::new(BIN).args(["-c", "-a", "lz77", "--reproducible", "-i", dir.join("src").to_str().unwrap(), "-o", archive.to_str().unwrap()])
+        .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    // Первый сигнал останавливает работу на границе записи, а не обрывает программу
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("stopping at the next block"), "{}", stderr);
+    assert!(stderr.contains("Interrupted: compression of 20 entries was stopped, no archive was written."), "{}", stderr);
+    // Ни архива, ни временного файла не остается
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_one_file_system() {
+    let dir = scratch("one_file_system");
+    put(&dir, "src/keep.txt", b"keep\n", 0o644);
+    // Ссылки при обходе разыменовываются, поэтому файл /proc попадает в дерево с другой файловой системы
+    std::os::unix::fs::symlink("/proc/version", dir.join("src/kernel")).unwrap();
+    let src = dir.join("src").to_string_lossy().into_owned();
+    let archive = dir.join("tree.arc").to_string_lossy().into_owned();
+    let paths = |flags: &[&str]| {
+        let mut args = vec!["-c", "-a", "lz4", "-i", &src, "-o", &archive];
+        args.extend_from_slice(flags);
+        run(&args);
+        run(&["list", "--porcelain", "--no-cache", "-i", &archive]).lines()
+            .map(|line| line.split('\t').nth(2).unwrap().to_owned()).collect::<Vec<_>>()
+    };
+    assert_eq!(paths(&[]), ["keep.txt", "kernel"]);
+    assert_eq!(paths(&["--one-file-system"]), ["keep.txt"]);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cat_range() {
+    let dir = scratch("cat_range");
+    let data: Vec<u8> = (1..=20_000).flat_map(|i| format!("{}\n", i).into_bytes()).collect();
+    put(&dir, "src/nums.txt", &data, 0o644);
+    put(&dir, "src/other.txt", b"other", 0o644);
+    let src = dir.join("src").to_string_lossy().into_owned();
+    for (i, flags) in [&["--block-size", "4096"][..], &["--solid"][..], &[][..]].iter().enumerate() {
+        let archive = dir.join(format!("tree{}.arc", i)).to_string_lossy().into_owned();
+        let mut args = vec!["-c", "-a", "lz4", "-i", &src, "-o", &archive];
+        args.extend_from_slice(flags);
+        run(&args);
+        let cat = |range: &str| run(&["cat", "-i", &archive, "--path", "nums.txt", "--range", range]).into_bytes();
+        assert_eq!(cat("10-20"), &data[10..20], "{:?}", flags);
+        // Диапазон на границе блоков собирается из двух блоков
+        assert_eq!(cat("4090-4100"), &data[4090..4100], "{:?}", flags);
+        assert_eq!(cat("108880-"), &data[108_880..], "{:?}", flags);
+        // Конец за пределами файла обрезается, а диапазон целиком за концом пуст
+        assert_eq!(cat("108890-999999"), &data[108_890..], "{:?}", flags);
+        assert!(cat("200000-200010").is_empty(), "{:?}", flags);
+        let part = dir.join(format!("part{}", i));
+        run(&["--cat", "-i", &archive, "--path", "nums.txt", "--range", "0-6", "-o", part.to_str().unwrap()]);
+        assert_eq!(fs::read(&part).unwrap(), b"1\n2\n3\n");
+    }
+    let archive = dir.join("tree0.arc").to_string_lossy().into_owned();
+    for range in ["5-3", "5", "a-b"] {
+        let output = Command::new(BIN).args(["cat", "-i", &archive, "nums.txt", "--range", range]).output().unwrap();
+        assert_eq!(output.status.code(), Some(2), "{}", range);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("invalid byte range"), "{}", range);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_concatenated_archives() {
+    let dir = scratch("concatenated");
+    put(&dir, "v1/conf", b"old\n", 0o600);
+    put(&dir, "v1/only1", b"keep\n", 0o644);
+    put(&dir, "v2/conf", b"new\n", 0o644);
+    put(&dir, "v2/only2", b"two\n", 0o644);
+    let first = dir.join("1.arc").to_string_lossy().into_owned();
+    let second = dir.join("2.arc").to_string_lossy().into_owned();
+    run(&["-c", "-a", "lz4", "-i", dir.join("v1").to_str().unwrap(), "-o", &first]);
+    run(&["-c", "-a", "hf", "--solid", "-i", dir.join("v2").to_str().unwrap(), "-o", &second]);
+    // Склеенные архивы (`cat 1.arc 2.arc`) распаковываются как одно дерево с последними версиями путей
+    let all = dir.join("all.arc").to_string_lossy().into_owned();
+    fs::write(&all, [fs::read(&first).unwrap(), fs::read(&second).unwrap()].concat()).unwrap();
+    let expected = BTreeMap::from([
+        ("conf".to_owned(), (0o644, b"new\n".to_vec())),
+        ("only1".to_owned(), (0o644, b"keep\n".to_vec())),
+        ("only2".to_owned(), (0o644, b"two\n".to_vec())),
+    ]);
+    for flags in [&[][..], &["-m"][..], &["--threads", "4"][..]] {
+        let out = dir.join(format!("out{}", flags.len()));
+        let mut args = vec!["-d", "-i", &all, "-o", out.to_str().unwrap()];
+        args.extend_from_slice(flags);
+        run(&args);
+        assert_eq!(snapshot(&out), expected, "{:?}", flags);
+    }
+    assert_eq!(run(&["cat", "-i", &all, "conf"]), "new\n");
+    let listed = run(&["list", "--porcelain", "--no-cache", "-i", &all]);
+    assert_eq!(listed.matches("\tconf\t").count(), 1, "{}", listed);
+    assert!(listed.contains("100644\t4\tconf\t"), "{}", listed);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_no_solid() {
+    let dir = scratch("no_solid");
+    let src = dir.join("src");
+    build_tree(&src);
+    let expected = snapshot(&src);
+    let archive = dir.join("tree.arc").to_string_lossy().into_owned();
+    let layout = |flags: &[&str]| {
+        let mut args = vec!["-c", "-a", "lz4", "-i", src.to_str().unwrap(), "-o", &archive];
+        args.extend_from_slice(flags);
+        run(&args);
+        let out = dir.join("out");
+        let _ = fs::remove_dir_all(&out);
+        run(&["-d", "-i", &archive, "-o", out.to_str().unwrap()]);
+        assert!(snapshot(&out) == expected, "{:?}", flags);
+        let info = run(&["info", "-i", &archive]);
+        info.lines().find_map(|line| line.strip_prefix("Layout:     ")).unwrap().to_owned()
+    };
+    let separate = format!("non-solid, {} entries compressed separately", expected.len());
+    assert_eq!(layout(&[]), separate);
+    assert_eq!(layout(&["--no-solid"]), separate);
+    // Из --solid и --no-solid действует последний
+    assert_eq!(layout(&["--solid", "--no-solid"]), separate);
+    // Кадры журнала повторяются в data/mixed.bin и заменяются в общем потоке ссылками
+    assert_eq!(layout(&["--no-solid", "--solid"]), "solid, single compressed stream of deduplicated chunks");
+    assert_eq!(layout(&["--no-solid", "--block-size", "4096"]), format!("non-solid, {} entries in blocks of about 4096 bytes", expected.len()));
+    let output = Command::new(BIN).args(["-d", "--no-solid", "-i", &archive, "-o", dir.join("bad").to_str().unwrap()]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_stored_options() {
+    let dir = scratch("stored_options");
+    let src = dir.join("src");
+    build_tree(&src);
+    let src = src.to_string_lossy().into_owned();
+    let flag_sets: &[&[&str]] = &[
+        &["-a", "lz4"],
+        &["-a", "lz77", "--short-matches", "-m", "--pack-below", "1024"],
+        &["-a", "hf", "--solid", "--chunk-dedup"],
+        &["-a", "rle", "--block-size", "4096"],
+    ];
+    for (i, flags) in flag_sets.iter().enumerate() {
+        let archive = dir.join(format!("tree{}.arc", i)).to_string_lossy().into_owned();
+        let mut args = vec!["-c", "--reproducible", "-i", &src, "-o", &archive];
+        args.extend_from_slice(flags);
+        run(&args);
+        // --info показывает флаги, с которыми архив был создан, и по ним он пересобирается так же
+        let info = run(&["info", "-i", &archive]);
+        let options = info.lines().find_map(|line| line.strip_prefix("Options:    ")).unwrap().to_owned();
+        let mut expected = flags.to_vec();
+        expected.sort();
+        let mut shown: Vec<&str> = options.split(' ').collect();
+        shown.sort();
+        assert_eq!(shown, expected, "{}", options);
+        let json = run(&["info", "--format", "json", "-i", &archive]);
+        assert!(json.contains(&format!("\"options\":\"{}\"", options)), "{}", json);
+        let rebuilt = dir.join(format!("rebuilt{}.arc", i)).to_string_lossy().into_owned();
+        let mut args = vec!["-c", "--reproducible", "-i", &src, "-o", &rebuilt];
+        args.extend(options.split(' '));
+        run(&args);
+        assert!(fs::read(&rebuilt).unwrap() == fs::read(&archive).unwrap(), "{}", options);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_porcelain_and_streams() {
+    let dir = scratch("porcelain");
+    put(&dir, "a/x", b"one\n", 0o644);
+    put(&dir, "a/y", b"two\n", 0o644);
+    put(&dir, "b/x", b"ONE\n", 0o600);
+    put(&dir, "b/z", b"new\n", 0o644);
+    set_mtime(&dir.join("a/x"), 1_600_000_000);
+    set_mtime(&dir.join("a/y"), 1_700_000_000);
+    let old = dir.join("a.arc").to_string_lossy().into_owned();
+    let new = dir.join("b.arc").to_string_lossy().into_owned();
+    let catalog = dir.join("catalog").to_string_lossy().into_owned();
+    // Сообщения о ходе работы идут в stderr, а stdout остается пустым
+    let output = Command::new(BIN).args(["-c", "-a", "lz4", "-i", dir.join("a").to_str().unwrap(), "-o", &old, "--catalog", &catalog]).output().unwrap();
+    assert!(output.status.success() && output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Program executed successfully."));
+    run(&["-c", "-a", "lz4", "-i", dir.join("b").to_str().unwrap(), "-o", &new]);
+    let output = Command::new(BIN).args(["-d", "-i", &old, "-o", dir.join("out").to_str().unwrap()]).output().unwrap();
+    assert!(output.status.success() && output.stdout.is_empty() && !output.stderr.is_empty());
+    // Распакованные файлы получают сохраненное время изменения
+    let modified = fs::metadata(dir.join("out/x")).unwrap().modified().unwrap();
+    assert_eq!(modified.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(), 1_600_000_000);
+    let output = Command::new(BIN).args(["cat", "-i", &old, "x"]).output().unwrap();
+    assert_eq!(output.stdout, b"one\n");
+
+    // Стабильный формат с табуляциями для скриптов
+    assert_eq!(run(&["-l", "--porcelain", "-i", &old]), "100644\t4\tx\t\t1600000000\n100644\t4\ty\t\t1700000000\n");
+    // Время изменения выводится в часовом поясе из TZ
+    let list = |style: &str, tz: &str| {
+        let output = Command::new(BIN).args(["-l", "-i", &old, "--time-style", style]).env("TZ", tz).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap().lines().next().unwrap().to_owned()
+    };
+    assert_eq!(list("iso", "UTC0"), "-rw-r--r--            4 2020-09-13 12:26 x");
+    assert_eq!(list("iso", "XYZ-3"), "-rw-r--r--            4 2020-09-13 15:26 x");
+    assert_eq!(list("full", "XYZ-3"), "-rw-r--r--            4 2020-09-13 15:26:40 +0300 x");
+    assert!(list("relative", "UTC0").ends_with(" years ago x"));
+    assert!(run(&["-l", "--format", "json", "-i", &old]).contains("\"path\":\"y\",\"type\":\"file\",\"permissions\":\"100644\",\"size\":4,\"compressed\":"));
+    assert!(run(&["-l", "--format", "json", "-i", &old]).contains(",\"mtime\":1700000000,"));
+    let cmp = Command::new(BIN).args(["--cmp", &old, &new, "--porcelain"]).output().unwrap();
+    assert_eq!(cmp.status.code(), Some(1));
+    assert_eq!(String::from_utf8(cmp.stdout).unwrap(), "added\tz\nremoved\ty\nmodified\tx\tcontent,permissions,mtime\n");
+    let info = run(&["info", "--porcelain", "-i", &old]);
+    let keys: Vec<&str> = info.lines().map(|line| line.split('\t').next().unwrap()).collect();
+    assert_eq!(&keys[..8], ["encrypted", "archive", "version", "algorithm", "layout", "entries", "compressed", "original"]);
+    assert!(info.contains("\nlayout\tnon-solid\nentries\t2\n") && info.contains("\noriginal\t8\n"), "{}", info);
+    assert_eq!(run(&["--audit", "--porcelain", "-i", &old]), "ok\tstandard\t1\t1\t2\n");
+    let located = run(&["--locate", "x", "--catalog", &catalog, "--porcelain"]);
+    let fields: Vec<&str> = located.trim_end().split('\t').collect();
+    assert_eq!((fields[0], fields[2], fields[3], fields[4]), (old.as_str(), "x", "4", "f817a89f"), "{}", located);
+    assert_eq!(run(&["--locate", "nothing", "--catalog", &catalog, "--porcelain"]), "");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_info_sizes() {
+    let dir = scratch("info_sizes");
+    put(&dir, "src/text.txt", &b"hello hello hello hello\n".repeat(100), 0o644);
+    put(&dir, "src/empty", b"", 0o644);
+    let info_of = |name: &str, input: &str, flags: &[&str]| {
+        let archive = dir.join(name).to_string_lossy().into_owned();
+        let mut args = vec!["-c", "-a", "lz4", "-i", input, "-o", &archive];
+        args.extend_from_slice(flags);
+        run(&args);
+        (run(&["info", "-i", &archive]), run(&["info", "--porcelain", "-i", &archive]), run(&["info", "--format", "json", "-i", &archive]))
+    };
+    let field = |info: &str, key: &str| info.lines().find_map(|line| line.strip_prefix(key)).unwrap().to_owned();
+
+    let (text, porcelain, json) = info_of("tree.arc", dir.join("src").to_str().unwrap(), &[]);
+    assert_eq!(field(&text, "Encrypted:  "), "no");
+    let compressed: u64 = field(&porcelain, "compressed\t").parse().unwrap();
+    assert_eq!(field(&porcelain, "original\t"), "2400");
+    assert_eq!(field(&text, "Original:   "), format!("2400 bytes (ratio {:.3})", compressed as f64 / 2400.0));
+    assert!(porcelain.starts_with("encrypted\tno\n"));
+    assert!(json.starts_with("{\"encrypted\":false,") && json.contains(&format!("\"compressed\":{},\"original\":2400,", compressed)), "{}", json);
+    // Зашифрованный файл без установленного шифра распознается по метке, но не читается
+    let sealed = dir.join("sealed.arc");
+    fs::write(&sealed, b"RENC\x00encrypted bytes").unwrap();
+    let info = |flags: &[&str]| {
+        let output = Command::new(BIN).args(["info", "-i", sealed.to_str().unwrap()]).args(flags).output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("no cipher is installed"));
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(info(&[]), "Encrypted:  yes (file cipher)\n");
+    assert_eq!(info(&["--porcelain"]), "encrypted\tyes\n");
+    assert_eq!(info(&["--format", "json"]), "{\"encrypted\":true,\"archives\":null}\n");
+    // Исходный размер сплошного архива без распаковки неизвестен
+    let (text, porcelain, json) = info_of("solid.arc", dir.join("src").to_str().unwrap(), &["--solid"]);
+    assert_eq!(field(&text, "Original:   "), "unknown until the solid stream is decompressed");
+    assert!(!porcelain.contains("\noriginal\t"));
+    assert!(json.contains("\"original\":null"), "{}", json);
+    let (text, _, _) = info_of("empty.arc", dir.join("src/empty").to_str().unwrap(), &[]);
+    assert_eq!(field(&text, "Original:   "), "0 bytes");
+    // Файл без сигнатуры — не архив, а не зашифрованный архив
+    let output = Command::new(BIN).args(["info", "-i", dir.join("src/text.txt").to_str().unwrap()]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not an archive"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_files_from_null() {
+    let dir = scratch("files_from_null");
+    put(&dir, "src/two\nlines.log", b"newline in the name", 0o644);
+    put(&dir, "src/with space.log", b"space", 0o644);
+    put(&dir, "src/skipped.log", b"not listed", 0o644);
+    let src = dir.join("src").to_string_lossy().into_owned();
+    let archive = dir.join("list.arc").to_string_lossy().into_owned();
+    // Список в формате `find -print0` читается из стандартного ввода
+    pipe_ok(&["-c", "-a", "lz4", "-i", &src, "--files-from", "-", "--null", "-o", &archive], b"./two\nlines.log\0with space.log\0");
+    let out = dir.join("out");
+    run(&["-d", "-i", &archive, "-o", out.to_str().unwrap()]);
+    assert_eq!(snapshot(&out), BTreeMap::from([
+        ("two\nlines.log".to_owned(), (0o644, b"newline in the name".to_vec())),
+        ("with space.log".to_owned(), (0o644, b"space".to_vec())),
+    ]));
+    // Без --null перевод строки разделяет пути, и половины имени не существуют
+    let output = pipe(&["-c", "-a", "lz4", "-i", &src, "--files-from", "-", "-o", &archive], b"two\nlines.log\n");
+    assert_eq!(output.status.code(), Some(1));
+    let output = Command::new(BIN).args(["-c", "-a", "lz4", "-i", &src, "--null", "-o", &archive]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_exclude_tree() {
+    let dir = scratch("exclude_tree");
+    put(&dir, "src/keep/a.txt", b"kept", 0o644);
+    put(&dir, "src/target/debug/deps/lib.rlib", b"build output", 0o644);
+    put(&dir, "src/target/release/app", b"binary", 0o755);
+    put(&dir, "src/docs/target/notes.txt", b"nested target", 0o644);
+    let src = dir.join("src");
+    let archive = dir.join("tree.arc").to_string_lossy().into_owned();
+    let output = Command::new(BIN).args(["-c", "-vv", "-a", "lz4", "--exclude", "target/**", "-i", src.to_str().unwrap(), "-o", &archive]).output().unwrap();
+    assert!(output.status.success());
+    // Директория под `target/**` пропускается целиком: ее содержимое даже не обходится
+    let log = String::from_utf8_lossy(&output.stderr);
+    let skipped: Vec<&str> = log.lines().filter_map(|line| line.strip_prefix("debug: skipping ")).collect();
+    assert_eq!(skipped, [src.join("target").to_str().unwrap()], "{}", log);
+    let out = dir.join("out");
+    run(&["-d", "-i", &archive, "-o", out.to_str().unwrap()]);
+    // Шаблон с `/` сравнивается с путем от корня, поэтому вложенная `docs/target` остается
+    assert_eq!(snapshot(&out).into_keys().collect::<Vec<_>>(), ["docs/target/notes.txt", "keep/a.txt"]);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_progress_json() {
+    let dir = scratch("progress_json");
+    let src = dir.join("src");
+    build_tree(&src);
+    let expected = snapshot(&src);
+    let total_bytes: usize = expected.values().map(|(_, data)| data.len()).sum();
+    let archive = dir.join("tree.arc").to_string_lossy().into_owned();
+    let events = |args: &[&str]| {
+        let output = Command::new(BIN).args(args).args(["--progress", "json"]).output().unwrap();
+        assert!(output.status.success() && output.stdout.is_empty(), "{:?}", args);
+        // События — строки JSON в stderr; прочие сообщения выводятся как обычно
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("Program executed successfully."));
+        stderr.lines().filter(|line| line.starts_with('{')).map(String::from).collect::<Vec<_>>()
+    };
+    let number = |event: &str, key: &str| -> u64 {
+        let start = event.find(&format!("\"{}\":", key)).unwrap() + key.len() + 3;
+        event[start..].split([',', '}']).next().unwrap().parse().unwrap()
+    };
+    // Без дедупликации объем событий совпадает с объемом файлов дерева
+    for args in [
+        vec!["-c", "-a", "lz4", "--no-dedup", "-i", src.to_str().unwrap(), "-o", &archive],
+        vec!["-d", "-i", &archive, "-o", dir.join("out").to_str().unwrap()],
+    ] {
+        let events = events(&args);
+        assert!(events.iter().all(|e| e.starts_with("{\"state\":\"") && e.contains(",\"elapsed_ms\":") && e.ends_with('}')), "{:?}", events);
+        let done: Vec<u64> = events.iter().map(|e| number(e, "entries_done")).collect();
+        assert!(done.windows(2).all(|w| w[0] <= w[1]), "{:?}", events);
+        let last = events.last().unwrap();
+        assert!(last.starts_with("{\"state\":\"done\","), "{}", last);
+        assert_eq!((number(last, "entries_done"), number(last, "entries_total")), (expected.len() as u64, expected.len() as u64));
+        assert_eq!((number(last, "bytes_done"), number(last, "bytes_total")), (total_bytes as u64, total_bytes as u64));
+        assert!(last.ends_with(",\"eta_ms\":0}"), "{}", last);
+    }
+    assert!(snapshot(&dir.join("out")) == expected);
+    let output = Command::new(BIN).args(["-c", "-a", "lz4", "-i", src.to_str().unwrap(), "-o", &archive, "--progress", "xml"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cat_entry() {
+    let dir = scratch("cat_entry");
+    let src = dir.join("src");
+    build_tree(&src);
+    let expected = snapshot(&src);
+    for (i, flags) in [&[][..], &["--solid"][..], &["--block-size", "4096"][..], &["--pack-below", "1024"][..]].iter().enumerate() {
+        let archive = dir.join(format!("tree{}.arc", i)).to_string_lossy().into_owned();
+        let mut args = vec!["-c", "-a", "lz4", "-i", src.to_str().unwrap(), "-o", &archive];
+        args.extend_from_slice(flags);
+        run(&args);
+        // В stdout выводятся только байты записи, в том числе двоичные, пустые и дубликаты
+        for path in ["data/noise.bin", "empty", "a/b/c/d/deep.txt", "logs/app.log.1", "many/file07.txt"] {
+            let output = Command::new(BIN).args(["cat", "-i", &archive, path]).output().unwrap();
+            assert!(output.status.success(), "{:?} {}", flags, path);
+            assert!(output.stdout == expected[path].1, "{:?} {}", flags, path);
+        }
+        // Отсутствующая запись — ошибка с кодом 1 и пустым выводом, файл -o не создается
+        let missing = dir.join("missing.out");
+        for extra in [&[][..], &["-o", missing.to_str().unwrap()][..]] {
+            let output = Command::new(BIN).args(["cat", "-i", &archive, "no/such/file"]).args(extra).output().unwrap();
+            assert_eq!(output.status.code(), Some(1), "{:?}", flags);
+            assert!(output.stdout.is_empty());
+            assert_eq!(String::from_utf8(output.stderr).unwrap(), format!("{}: no/such/file not found in the archive\n", archive));
+            assert!(!missing.exists());
+        }
+        // Директория — не файл записи
+        assert_eq!(Command::new(BIN).args(["cat", "-i", &archive, "a/b"]).output().unwrap().status.code(), Some(1));
+    }
+    let archive = dir.join("tree0.arc").to_string_lossy().into_owned();
+    assert_eq!(Command::new(BIN).args(["cat", "-i", &archive]).output().unwrap().status.code(), Some(2));
+    let output = Command::new(BIN).args(["cat", "-i", src.join("readme.txt").to_str().unwrap(), "readme.txt"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not an archive"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_diff_directory() {
+    let dir = scratch("diff_directory");
+    put(&dir, "src/a.txt", b"a\n", 0o644);
+    put(&dir, "src/sub/b.txt", b"bb\n", 0o644);
+    put(&dir, "src/sub/c.txt", b"same size\n", 0o644);
+    for path in ["src/a.txt", "src/sub/b.txt", "src/sub/c.txt"] {
+        set_mtime(&dir.join(path), 1_600_000_000);
+    }
+    let src = dir.join("src").to_string_lossy().into_owned();
+    let copy = dir.join("copy");
+    let diff = |archive: &str, porcelain: bool| {
+        let mut args = vec!["diff", "-i", archive, "-o", copy.to_str().unwrap()];
+        if porcelain {
+            args.push("--porcelain");
+        }
+        let output = Command::new(BIN).args(&args).env("TZ", "UTC0").output().unwrap();
+        (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap())
+    };
+    for (i, flags) in [&[][..], &["--solid"][..], &["--block-size", "4096"][..]].iter().enumerate() {
+        let archive = dir.join(format!("tree{}.arc", i)).to_string_lossy().into_owned();
+        let mut args = vec!["-c", "-a", "lz4", "-i", &src, "-o", &archive];
+        args.extend_from_slice(flags);
+        run(&args);
+        // Распакованная копия совпадает с архивом
+        let _ = fs::remove_dir_all(&copy);
+        run(&["-d", "-i", &archive, "-o", copy.to_str().unwrap()]);
+        assert_eq!(diff(&archive, false), (0, String::new()), "{:?}", flags);
+        // Отсутствующий, лишний и измененные файлы
+        fs::remove_file(copy.join("sub/b.txt")).unwrap();
+        fs::write(copy.join("new.txt"), b"new").unwrap();
+        fs::write(copy.join("a.txt"), b"aaa\n").unwrap();
+        fs::write(copy.join("sub/c.txt"), b"SAME SIZE\n").unwrap();
+        fs::set_permissions(copy.join("sub/c.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+        set_mtime(&copy.join("a.txt"), 1_600_000_000);
+        set_mtime(&copy.join("sub/c.txt"), 1_700_000_000);
+        assert_eq!(diff(&archive, false), (1, "+ new.txt\n- sub/b.txt\n~ a.txt (size 2 -> 4)\n~ sub/c.txt (content, permissions 644 -> 600, mtime 2020-09-13 12:26 -> 2023-11-14 22:13)\n".to_owned()), "{:?}", flags);
+        assert_eq!(diff(&archive, true), (1, "extra\tnew.txt\nmissing\tsub/b.txt\nmodified\ta.txt\tsize\nmodified\tsub/c.txt\tcontent,permissions,mtime\n".to_owned()), "{:?}", flags);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_update() {
+    let dir = scratch("update");
+    put(&dir, "src/a.txt", b"one\n", 0o644);
+    put(&dir, "src/b.txt", b"two\n", 0o644);
+    set_mtime(&dir.join("src/a.txt"), 1_600_000_000);
+    set_mtime(&dir.join("src/b.txt"), 1_600_000_000);
+    let src = dir.join("src").to_string_lossy().into_owned();
+    let archive = dir.join("a.arc").to_string_lossy().into_owned();
+    let update = |flags: &[&str]| {
+        let mut args = vec!["-c", "-u", "-a", "lz4", "-i", &src, "-o", &archive];
+        args.extend_from_slice(flags);
+        let output = Command::new(BIN).args(&args).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stderr).unwrap().contains("up to date")
+    };
+    assert!(!update(&[]));
+    assert!(update(&[]));
+    // Правка без изменения размера и времени видна только при сравнении содержимого
+    fs::write(dir.join("src/b.txt"), b"TWO\n").unwrap();
+    set_mtime(&dir.join("src/b.txt"), 1_600_000_000);
+    assert!(update(&[]));
+    assert!(!update(&["--compare-content"]));
+    assert_eq!(run(&["cat", "-i", &archive, "b.txt"]), "TWO\n");
+    // Новое время изменения — повод дописать запись
+    set_mtime(&dir.join("src/a.txt"), 1_700_000_000);
+    assert!(!update(&[]));
+    assert!(update(&[]));
+    assert!(run(&["-l", "--porcelain", "-i", &archive]).contains("\ta.txt\t\t1700000000\n"));
+    let _ = fs::remove_dir_all(&dir);
+}